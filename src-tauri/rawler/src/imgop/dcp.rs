@@ -0,0 +1,925 @@
+// SPDX-License-Identifier: LGPL-2.1
+
+//! DNG camera profiles (DCP)
+//!
+//! Implements the color model of the DNG specification: dual-illuminant
+//! color/forward matrices interpolated by the correlated color temperature
+//! of the scene white, followed by the optional HueSatMap, LookTable and
+//! ProfileToneCurve rendering stages.
+
+use std::{collections::HashMap, path::Path};
+
+use crate::{
+  formats::tiff::{reader::TiffReader, GenericTiffReader, IFD},
+  tags::DngTag,
+  Result,
+};
+
+use super::{
+  matrix::{multiply, pseudo_inverse},
+  xyz::{FlatColorMatrix, Illuminant, CIE_1931_WHITE_POINT_D50, XYZ_TO_SRGB_D50},
+};
+
+pub type Matrix3 = [[f32; 3]; 3];
+
+const BRADFORD: Matrix3 = [[0.8951, 0.2664, -0.1614], [-0.7502, 1.7135, 0.0367], [0.0389, -0.0685, 1.0296]];
+
+const PROPHOTO_TO_XYZ_D50: Matrix3 = [[0.7976749, 0.1351917, 0.0313534], [0.2880402, 0.7118741, 0.0000857], [0.0, 0.0, 0.82521]];
+
+/// Scale factor between Robertson isotherm distance and DNG tint units.
+const TINT_SCALE: f32 = -3000.0;
+
+/// Robertson isotemperature lines: (mired, u, v, slope)
+const ROBERTSON: [(f32, f32, f32, f32); 31] = [
+  (0.0, 0.18006, 0.26352, -0.24341),
+  (10.0, 0.18066, 0.26589, -0.25479),
+  (20.0, 0.18133, 0.26846, -0.26876),
+  (30.0, 0.18208, 0.27119, -0.28539),
+  (40.0, 0.18293, 0.27407, -0.30470),
+  (50.0, 0.18388, 0.27709, -0.32675),
+  (60.0, 0.18494, 0.28021, -0.35156),
+  (70.0, 0.18611, 0.28342, -0.37915),
+  (80.0, 0.18740, 0.28668, -0.40955),
+  (90.0, 0.18880, 0.28997, -0.44278),
+  (100.0, 0.19032, 0.29326, -0.47888),
+  (125.0, 0.19462, 0.30141, -0.58204),
+  (150.0, 0.19962, 0.30921, -0.70471),
+  (175.0, 0.20525, 0.31647, -0.84901),
+  (200.0, 0.21142, 0.32312, -1.0182),
+  (225.0, 0.21807, 0.32909, -1.2168),
+  (250.0, 0.22511, 0.33439, -1.4512),
+  (275.0, 0.23247, 0.33904, -1.7298),
+  (300.0, 0.24010, 0.34308, -2.0637),
+  (325.0, 0.24792, 0.34655, -2.4681),
+  (350.0, 0.25591, 0.34951, -2.9641),
+  (375.0, 0.26400, 0.35200, -3.5814),
+  (400.0, 0.27218, 0.35407, -4.3633),
+  (425.0, 0.28039, 0.35577, -5.3762),
+  (450.0, 0.28863, 0.35714, -6.7262),
+  (475.0, 0.29685, 0.35823, -8.5955),
+  (500.0, 0.30505, 0.35907, -11.324),
+  (525.0, 0.31320, 0.35968, -15.628),
+  (550.0, 0.32129, 0.36011, -23.325),
+  (575.0, 0.32931, 0.36038, -40.770),
+  (600.0, 0.33724, 0.36051, -116.45),
+];
+
+/// Convert a CIE 1931 xy chromaticity to color temperature (Kelvin) and tint.
+pub fn xy_to_temperature(xy: (f32, f32)) -> (f32, f32) {
+  let (x, y) = xy;
+  let denom = 1.5 - x + 6.0 * y;
+  let u = 2.0 * x / denom;
+  let v = 3.0 * y / denom;
+
+  let (mut last_dt, mut last_du, mut last_dv) = (0.0, 0.0, 0.0);
+  for index in 1..ROBERTSON.len() {
+    let (r0, u0, v0, _) = ROBERTSON[index - 1];
+    let (r1, u1, v1, slope) = ROBERTSON[index];
+    let len = (1.0 + slope * slope).sqrt();
+    let (mut du, mut dv) = (1.0 / len, slope / len);
+    let dt = -(u - u1) * dv + (v - v1) * du;
+
+    if dt <= 0.0 || index == ROBERTSON.len() - 1 {
+      let dt = -dt.min(0.0);
+      let f = if index == 1 { 0.0 } else { dt / (last_dt + dt) };
+      let temperature = 1.0e6 / (r0 * f + r1 * (1.0 - f));
+
+      let uu = u - (u0 * f + u1 * (1.0 - f));
+      let vv = v - (v0 * f + v1 * (1.0 - f));
+      du = du * (1.0 - f) + last_du * f;
+      dv = dv * (1.0 - f) + last_dv * f;
+      let len = (du * du + dv * dv).sqrt();
+      let tint = (uu * du / len + vv * dv / len) * TINT_SCALE;
+      return (temperature, tint);
+    }
+    last_dt = dt;
+    last_du = du;
+    last_dv = dv;
+  }
+  unreachable!()
+}
+
+/// Convert a color temperature (Kelvin) and tint to CIE 1931 xy chromaticity.
+pub fn temperature_to_xy(temperature: f32, tint: f32) -> (f32, f32) {
+  let r = 1.0e6 / temperature.max(1.0);
+  let offset = tint / TINT_SCALE;
+  let last = ROBERTSON.len() - 2;
+  for index in 0..=last {
+    let (r0, u0, v0, s0) = ROBERTSON[index];
+    let (r1, u1, v1, s1) = ROBERTSON[index + 1];
+    if r < r1 || index == last {
+      let f = (r1 - r) / (r1 - r0);
+      let mut u = u0 * f + u1 * (1.0 - f);
+      let mut v = v0 * f + v1 * (1.0 - f);
+
+      let len0 = (1.0 + s0 * s0).sqrt();
+      let len1 = (1.0 + s1 * s1).sqrt();
+      let du = f / len0 + (1.0 - f) / len1;
+      let dv = f * s0 / len0 + (1.0 - f) * s1 / len1;
+      let len = (du * du + dv * dv).sqrt();
+      u += du / len * offset;
+      v += dv / len * offset;
+
+      let denom = u - 4.0 * v + 2.0;
+      return (1.5 * u / denom, v / denom);
+    }
+  }
+  unreachable!()
+}
+
+/// Nominal color temperature of an EXIF light source, if it has one.
+pub fn illuminant_temperature(illuminant: Illuminant) -> Option<f32> {
+  Some(match illuminant {
+    Illuminant::A | Illuminant::Tungsten => 2850.0,
+    Illuminant::IsoStudioTungsten => 3200.0,
+    Illuminant::D50 => 5000.0,
+    Illuminant::D55 | Illuminant::Daylight | Illuminant::FineWeather | Illuminant::Flash | Illuminant::B => 5500.0,
+    Illuminant::D65 | Illuminant::C | Illuminant::CloudyWeather => 6500.0,
+    Illuminant::D75 | Illuminant::Shade => 7500.0,
+    Illuminant::DaylightFluorescent => 6400.0,
+    Illuminant::DaylightWhiteFluorescent => 5050.0,
+    Illuminant::CoolWhiteFluorescent | Illuminant::Fluorescent => 4150.0,
+    Illuminant::WhiteFluorescent => 3525.0,
+    Illuminant::Unknown => return None,
+  })
+}
+
+/// Hue/saturation/value lookup table (ProfileHueSatMap or ProfileLookTable).
+///
+/// Entries are stored value-major, then hue, then saturation. Each entry
+/// holds a hue shift in degrees, a saturation scale and a value scale.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HueSatMap {
+  pub hue_divisions: usize,
+  pub sat_divisions: usize,
+  pub val_divisions: usize,
+  pub srgb_gamma: bool,
+  pub data: Vec<[f32; 3]>,
+}
+
+impl HueSatMap {
+  fn read(ifd: &IFD, dims: DngTag, data: DngTag, encoding: DngTag) -> Option<Self> {
+    let dims = ifd.get_entry(dims)?;
+    let data = ifd.get_entry(data)?;
+    let hue_divisions = dims.force_u32(0) as usize;
+    let sat_divisions = dims.force_u32(1) as usize;
+    let val_divisions = if dims.count() > 2 { dims.force_u32(2) as usize } else { 1 }.max(1);
+    let entries = hue_divisions * sat_divisions * val_divisions;
+    if hue_divisions < 1 || sat_divisions < 2 || (data.count() as usize) < entries * 3 {
+      log::warn!("Ignoring malformed hue/sat table ({}x{}x{})", hue_divisions, sat_divisions, val_divisions);
+      return None;
+    }
+    let srgb_gamma = ifd.get_entry(encoding).map(|e| e.force_u32(0) == 1).unwrap_or(false);
+    Some(Self {
+      hue_divisions,
+      sat_divisions,
+      val_divisions,
+      srgb_gamma,
+      data: (0..entries).map(|i| [data.force_f32(i * 3), data.force_f32(i * 3 + 1), data.force_f32(i * 3 + 2)]).collect(),
+    })
+  }
+
+  fn blend(&self, other: &Self, weight: f32) -> Self {
+    if self.data.len() != other.data.len() || self.hue_divisions != other.hue_divisions || self.sat_divisions != other.sat_divisions {
+      return if weight >= 0.5 { self.clone() } else { other.clone() };
+    }
+    let mut blended = self.clone();
+    for (a, b) in blended.data.iter_mut().zip(other.data.iter()) {
+      for c in 0..3 {
+        a[c] = a[c] * weight + b[c] * (1.0 - weight);
+      }
+    }
+    blended
+  }
+
+  fn entry(&self, v: usize, h: usize, s: usize) -> [f32; 3] {
+    self.data[(v * self.hue_divisions + h) * self.sat_divisions + s]
+  }
+
+  /// Apply the table to a linear ProPhoto pixel.
+  pub fn apply(&self, rgb: [f32; 3]) -> [f32; 3] {
+    let (h, s, v) = rgb_to_hsv(rgb);
+    if v <= 0.0 {
+      return rgb;
+    }
+
+    let h_scaled = h * self.hue_divisions as f32 / 6.0;
+    let s_scaled = s.clamp(0.0, 1.0) * (self.sat_divisions - 1) as f32;
+    let mut h0 = h_scaled.floor() as usize;
+    let s0 = (s_scaled.floor() as usize).min(self.sat_divisions - 2);
+    let mut h1 = h0 + 1;
+    if h0 >= self.hue_divisions - 1 {
+      h0 = self.hue_divisions - 1;
+      h1 = 0;
+    }
+    let hf = h_scaled - h0 as f32;
+    let sf = s_scaled - s0 as f32;
+
+    let (v0, v1, vf) = if self.val_divisions > 1 {
+      let encoded = if self.srgb_gamma { super::srgb::srgb_apply_gamma(v.min(1.0)) } else { v.min(1.0) };
+      let v_scaled = encoded * (self.val_divisions - 1) as f32;
+      let v0 = (v_scaled.floor() as usize).min(self.val_divisions - 2);
+      (v0, v0 + 1, v_scaled - v0 as f32)
+    } else {
+      (0, 0, 0.0)
+    };
+
+    let mut delta = [0.0; 3];
+    for (vi, vw) in [(v0, 1.0 - vf), (v1, vf)] {
+      if vw == 0.0 {
+        continue;
+      }
+      for (hi, hw) in [(h0, 1.0 - hf), (h1, hf)] {
+        for (si, sw) in [(s0, 1.0 - sf), (s0 + 1, sf)] {
+          let e = self.entry(vi, hi, si);
+          let w = vw * hw * sw;
+          for c in 0..3 {
+            delta[c] += e[c] * w;
+          }
+        }
+      }
+    }
+
+    let h = (h + delta[0] * 6.0 / 360.0).rem_euclid(6.0);
+    let s = (s * delta[1]).clamp(0.0, 1.0);
+    let v = if self.srgb_gamma && self.val_divisions > 1 && v <= 1.0 {
+      super::srgb::srgb_invert_gamma((super::srgb::srgb_apply_gamma(v) * delta[2]).clamp(0.0, 1.0))
+    } else {
+      (v * delta[2]).max(0.0)
+    };
+    hsv_to_rgb(h, s, v)
+  }
+}
+
+/// Scene white as derived from the camera neutral.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProfileWhite {
+  pub xy: (f32, f32),
+  pub temperature: f32,
+  pub tint: f32,
+  /// Interpolation weight of the first calibration illuminant.
+  pub weight: f32,
+}
+
+/// Parsed DNG camera profile.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DcpProfile {
+  pub name: Option<String>,
+  pub camera_model: Option<String>,
+  pub illuminant1: Option<Illuminant>,
+  pub illuminant2: Option<Illuminant>,
+  pub color_matrix1: Option<Matrix3>,
+  pub color_matrix2: Option<Matrix3>,
+  pub forward_matrix1: Option<Matrix3>,
+  pub forward_matrix2: Option<Matrix3>,
+  pub hue_sat_map1: Option<HueSatMap>,
+  pub hue_sat_map2: Option<HueSatMap>,
+  pub look_table: Option<HueSatMap>,
+  pub tone_curve: Option<Vec<(f32, f32)>>,
+  pub baseline_exposure_offset: f32,
+}
+
+impl DcpProfile {
+  /// Parse a DCP file (TIFF structure with "IIRC" magic).
+  pub fn from_bytes(buf: &[u8]) -> Result<Self> {
+    let tiff = GenericTiffReader::new_with_buffer(buf, 0, 0, Some(0))?;
+    let ifd = tiff.root_ifd();
+
+    let matrix = |tag: DngTag| -> Option<Matrix3> {
+      let entry = ifd.get_entry(tag)?;
+      if entry.count() != 9 {
+        return None;
+      }
+      let mut m = [[0.0; 3]; 3];
+      for (i, row) in m.iter_mut().enumerate() {
+        for (j, v) in row.iter_mut().enumerate() {
+          *v = entry.force_f32(i * 3 + j);
+        }
+      }
+      Some(m)
+    };
+    let illuminant = |tag: DngTag| ifd.get_entry(tag).and_then(|e| Illuminant::try_from(e.force_u16(0)).ok());
+    let string = |tag: DngTag| ifd.get_entry(tag).and_then(|e| e.as_string().cloned());
+
+    let profile = Self {
+      name: string(DngTag::ProfileName),
+      camera_model: string(DngTag::UniqueCameraModel),
+      illuminant1: illuminant(DngTag::CalibrationIlluminant1),
+      illuminant2: illuminant(DngTag::CalibrationIlluminant2),
+      color_matrix1: matrix(DngTag::ColorMatrix1),
+      color_matrix2: matrix(DngTag::ColorMatrix2),
+      forward_matrix1: matrix(DngTag::ForwardMatrix1),
+      forward_matrix2: matrix(DngTag::ForwardMatrix2),
+      hue_sat_map1: HueSatMap::read(ifd, DngTag::ProfileHueSatMapDims, DngTag::ProfileHueSatMapData1, DngTag::ProfileHueSatMapEncoding),
+      hue_sat_map2: HueSatMap::read(ifd, DngTag::ProfileHueSatMapDims, DngTag::ProfileHueSatMapData2, DngTag::ProfileHueSatMapEncoding),
+      look_table: HueSatMap::read(ifd, DngTag::ProfileLookTableDims, DngTag::ProfileLookTableData, DngTag::ProfileLookTableEncoding),
+      tone_curve: ifd.get_entry(DngTag::ProfileToneCurve).and_then(|e| {
+        let points: Vec<(f32, f32)> = (0..e.count() as usize / 2).map(|i| (e.force_f32(i * 2), e.force_f32(i * 2 + 1))).collect();
+        (points.len() >= 2).then_some(points)
+      }),
+      baseline_exposure_offset: ifd.get_entry(DngTag::BaselineExposureOffset).map(|e| e.force_f32(0)).unwrap_or(0.0),
+    };
+
+    if profile.color_matrix1.is_none() {
+      return Err("DCP profile has no ColorMatrix1".into());
+    }
+    Ok(profile)
+  }
+
+  /// Serialize as a little-endian DCP file.
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let mut writer = DcpWriter::default();
+    let matrix = |m: &Matrix3| DcpValue::SRational(m.iter().flatten().copied().collect());
+    let table = |map: &HueSatMap| DcpValue::Float(map.data.iter().flatten().copied().collect());
+    let dims = |map: &HueSatMap| DcpValue::Long(vec![map.hue_divisions as u32, map.sat_divisions as u32, map.val_divisions as u32]);
+
+    if let Some(model) = &self.camera_model {
+      writer.add(DngTag::UniqueCameraModel, DcpValue::Ascii(model.clone()));
+    }
+    if let Some(m) = &self.color_matrix1 {
+      writer.add(DngTag::ColorMatrix1, matrix(m));
+    }
+    if let Some(m) = &self.color_matrix2 {
+      writer.add(DngTag::ColorMatrix2, matrix(m));
+    }
+    if let Some(illuminant) = self.illuminant1 {
+      writer.add(DngTag::CalibrationIlluminant1, DcpValue::Short(vec![illuminant.into()]));
+    }
+    if let Some(illuminant) = self.illuminant2 {
+      writer.add(DngTag::CalibrationIlluminant2, DcpValue::Short(vec![illuminant.into()]));
+    }
+    if let Some(name) = &self.name {
+      writer.add(DngTag::ProfileName, DcpValue::Ascii(name.clone()));
+    }
+    if let Some(map) = self.hue_sat_map1.as_ref().or(self.hue_sat_map2.as_ref()) {
+      writer.add(DngTag::ProfileHueSatMapDims, dims(map));
+      if map.srgb_gamma {
+        writer.add(DngTag::ProfileHueSatMapEncoding, DcpValue::Long(vec![1]));
+      }
+    }
+    if let Some(map) = &self.hue_sat_map1 {
+      writer.add(DngTag::ProfileHueSatMapData1, table(map));
+    }
+    if let Some(map) = &self.hue_sat_map2 {
+      writer.add(DngTag::ProfileHueSatMapData2, table(map));
+    }
+    if let Some(curve) = &self.tone_curve {
+      writer.add(DngTag::ProfileToneCurve, DcpValue::Float(curve.iter().flat_map(|(x, y)| [*x, *y]).collect()));
+    }
+    writer.add(DngTag::ProfileEmbedPolicy, DcpValue::Long(vec![3]));
+    if let Some(m) = &self.forward_matrix1 {
+      writer.add(DngTag::ForwardMatrix1, matrix(m));
+    }
+    if let Some(m) = &self.forward_matrix2 {
+      writer.add(DngTag::ForwardMatrix2, matrix(m));
+    }
+    if let Some(map) = &self.look_table {
+      writer.add(DngTag::ProfileLookTableDims, dims(map));
+      writer.add(DngTag::ProfileLookTableData, table(map));
+      if map.srgb_gamma {
+        writer.add(DngTag::ProfileLookTableEncoding, DcpValue::Long(vec![1]));
+      }
+    }
+    if self.baseline_exposure_offset != 0.0 {
+      writer.add(DngTag::BaselineExposureOffset, DcpValue::SRational(vec![self.baseline_exposure_offset]));
+    }
+    writer.finish()
+  }
+
+  pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+    let buf = std::fs::read(path.as_ref())?;
+    let mut profile = Self::from_bytes(&buf)?;
+    if profile.name.is_none() {
+      profile.name = path.as_ref().file_stem().map(|s| s.to_string_lossy().into_owned());
+    }
+    Ok(profile)
+  }
+
+  /// Build a matrix-only profile from the calibration matrices of a raw file
+  /// or camera definition. Requires at least two 3-color matrices with known
+  /// color temperatures; the two furthest apart are used.
+  pub fn from_color_matrices(matrices: &HashMap<Illuminant, FlatColorMatrix>) -> Option<Self> {
+    let mut candidates: Vec<(f32, Illuminant, Matrix3)> = matrices
+      .iter()
+      .filter(|(_, m)| m.len() == 9)
+      .filter_map(|(illuminant, m)| {
+        let temperature = illuminant_temperature(*illuminant)?;
+        Some((temperature, *illuminant, [[m[0], m[1], m[2]], [m[3], m[4], m[5]], [m[6], m[7], m[8]]]))
+      })
+      .collect();
+    candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let (low, high) = (candidates.first()?, candidates.last()?);
+    if low.0 == high.0 {
+      return None;
+    }
+    Some(Self {
+      name: Some("Embedded".into()),
+      illuminant1: Some(low.1),
+      illuminant2: Some(high.1),
+      color_matrix1: Some(low.2),
+      color_matrix2: Some(high.2),
+      ..Default::default()
+    })
+  }
+
+  /// Check the profile's UniqueCameraModel against a camera make and model.
+  pub fn matches_camera(&self, make: &str, model: &str) -> bool {
+    match &self.camera_model {
+      Some(unique) => {
+        let unique = unique.to_lowercase();
+        let model = model.to_lowercase();
+        unique == model || unique == format!("{} {}", make.to_lowercase(), model)
+      }
+      None => false,
+    }
+  }
+
+  /// True if the profile carries rendering stages that have to be applied
+  /// to scene-referred data after the color matrix.
+  pub fn has_rendering(&self) -> bool {
+    self.hue_sat_map1.is_some() || self.look_table.is_some() || self.tone_curve.is_some() || self.baseline_exposure_offset != 0.0
+  }
+
+  fn temperatures(&self) -> Option<(f32, f32)> {
+    let t1 = illuminant_temperature(self.illuminant1?)?;
+    let t2 = illuminant_temperature(self.illuminant2?)?;
+    (t1 != t2).then_some((t1, t2))
+  }
+
+  /// Weight of the first calibration illuminant for a scene temperature,
+  /// interpolated linearly in inverse temperature as the DNG spec requires.
+  pub fn interpolation_weight(&self, temperature: f32) -> f32 {
+    let Some((t1, t2)) = self.temperatures() else {
+      return 1.0;
+    };
+    if self.color_matrix2.is_none() {
+      return 1.0;
+    }
+    ((1.0 / temperature - 1.0 / t2) / (1.0 / t1 - 1.0 / t2)).clamp(0.0, 1.0)
+  }
+
+  fn blend_matrices(a: Option<Matrix3>, b: Option<Matrix3>, weight: f32) -> Option<Matrix3> {
+    match (a, b) {
+      (Some(a), Some(b)) => {
+        let mut m = a;
+        for i in 0..3 {
+          for j in 0..3 {
+            m[i][j] = a[i][j] * weight + b[i][j] * (1.0 - weight);
+          }
+        }
+        Some(m)
+      }
+      (a, b) => a.or(b),
+    }
+  }
+
+  fn xyz_to_camera(&self, weight: f32) -> Matrix3 {
+    Self::blend_matrices(self.color_matrix1, self.color_matrix2, weight).expect("profile has a color matrix")
+  }
+
+  /// Find the scene white chromaticity for a camera neutral (reciprocal of
+  /// the white balance coefficients) by iterating matrix interpolation.
+  pub fn white_for_neutral(&self, neutral: [f32; 3]) -> ProfileWhite {
+    let mut last = CIE_1931_WHITE_POINT_D50;
+    for pass in 0..30 {
+      let (temperature, _) = xy_to_temperature(last);
+      let camera_to_xyz = pseudo_inverse(self.xyz_to_camera(self.interpolation_weight(temperature)));
+      let mut next = xyz_to_xy(mul_vec(&camera_to_xyz, neutral)).unwrap_or(last);
+      if (next.0 - last.0).abs() + (next.1 - last.1).abs() < 1.0e-7 {
+        last = next;
+        break;
+      }
+      // Oscillation guard
+      if pass == 29 {
+        next = ((last.0 + next.0) / 2.0, (last.1 + next.1) / 2.0);
+      }
+      last = next;
+    }
+    let (temperature, tint) = xy_to_temperature(last);
+    ProfileWhite {
+      xy: last,
+      temperature,
+      tint,
+      weight: self.interpolation_weight(temperature),
+    }
+  }
+
+  /// Camera neutral for a scene white given as temperature and tint, the
+  /// inverse of `white_for_neutral`. Normalized to green.
+  pub fn neutral_for_white(&self, temperature: f32, tint: f32) -> [f32; 3] {
+    let xyz = xy_to_xyz_unit(temperature_to_xy(temperature, tint));
+    let neutral = mul_vec(&self.xyz_to_camera(self.interpolation_weight(temperature)), xyz);
+    if neutral.iter().all(|&n| n > 0.0) {
+      neutral.map(|n| n / neutral[1])
+    } else {
+      [1.0, 1.0, 1.0]
+    }
+  }
+
+  /// Camera to XYZ (D50) matrix for the given neutral, scaled so that the
+  /// neutral maps to the D50 white with Y = 1.
+  pub fn camera_to_xyz_d50(&self, neutral: [f32; 3], white: &ProfileWhite) -> Matrix3 {
+    let d50 = xy_to_xyz_unit(CIE_1931_WHITE_POINT_D50);
+    let mut m = match Self::blend_matrices(self.forward_matrix1, self.forward_matrix2, white.weight) {
+      Some(forward) => {
+        // Normalize so that camera (1, 1, 1) maps to D50, then undo the white balance.
+        let one = mul_vec(&forward, [1.0, 1.0, 1.0]);
+        let mut forward = forward;
+        for (row, (d, o)) in forward.iter_mut().zip(d50.iter().zip(one.iter())) {
+          let scale = if o.abs() > 1.0e-6 { d / o } else { 1.0 };
+          row.iter_mut().for_each(|v| *v *= scale);
+        }
+        multiply(&forward, &diagonal(neutral.map(|n| if n > 0.0 { 1.0 / n } else { 1.0 })))
+      }
+      None => {
+        let adapt = bradford_adaptation(CIE_1931_WHITE_POINT_D50, white.xy);
+        pseudo_inverse(multiply(&self.xyz_to_camera(white.weight), &adapt))
+      }
+    };
+
+    let y = mul_vec(&m, neutral)[1];
+    if y.abs() > 1.0e-6 {
+      m.iter_mut().flatten().for_each(|v| *v /= y);
+    }
+    m
+  }
+
+  /// Camera to linear sRGB (D65) matrix for the given neutral.
+  pub fn camera_to_srgb(&self, neutral: [f32; 3]) -> (Matrix3, ProfileWhite) {
+    let white = self.white_for_neutral(neutral);
+    let cam2xyz = self.camera_to_xyz_d50(neutral, &white);
+    (multiply(&XYZ_TO_SRGB_D50, &cam2xyz), white)
+  }
+
+  /// Prepare the rendering stages for a scene white.
+  pub fn renderer(&self, white: &ProfileWhite) -> ProfileRenderer {
+    let hue_sat_map = match (&self.hue_sat_map1, &self.hue_sat_map2) {
+      (Some(a), Some(b)) => Some(a.blend(b, white.weight)),
+      (a, b) => a.clone().or_else(|| b.clone()),
+    };
+    let prophoto_to_srgb = multiply(&XYZ_TO_SRGB_D50, &PROPHOTO_TO_XYZ_D50);
+    ProfileRenderer {
+      hue_sat_map,
+      exposure: self.baseline_exposure_offset.exp2(),
+      look_table: self.look_table.clone(),
+      tone_curve: self.tone_curve.as_ref().map(|points| ToneCurve::new(points)),
+      srgb_to_prophoto: pseudo_inverse(prophoto_to_srgb),
+      prophoto_to_srgb,
+    }
+  }
+}
+
+enum DcpValue {
+  Ascii(String),
+  Short(Vec<u16>),
+  Long(Vec<u32>),
+  SRational(Vec<f32>),
+  Float(Vec<f32>),
+}
+
+impl DcpValue {
+  fn type_and_count(&self) -> (u16, usize) {
+    match self {
+      Self::Ascii(v) => (2, v.len() + 1),
+      Self::Short(v) => (3, v.len()),
+      Self::Long(v) => (4, v.len()),
+      Self::SRational(v) => (10, v.len()),
+      Self::Float(v) => (11, v.len()),
+    }
+  }
+
+  fn encode(&self) -> Vec<u8> {
+    match self {
+      Self::Ascii(v) => v.bytes().chain(std::iter::once(0)).collect(),
+      Self::Short(v) => v.iter().flat_map(|x| x.to_le_bytes()).collect(),
+      Self::Long(v) => v.iter().flat_map(|x| x.to_le_bytes()).collect(),
+      Self::SRational(v) => v
+        .iter()
+        .flat_map(|x| {
+          let numerator = (x * 10000.0).round() as i32;
+          numerator.to_le_bytes().into_iter().chain(10000_i32.to_le_bytes())
+        })
+        .collect(),
+      Self::Float(v) => v.iter().flat_map(|x| x.to_le_bytes()).collect(),
+    }
+  }
+}
+
+/// Minimal single-IFD writer for the DCP container ("IIRC" magic).
+#[derive(Default)]
+struct DcpWriter {
+  entries: Vec<(u16, DcpValue)>,
+}
+
+impl DcpWriter {
+  fn add(&mut self, tag: DngTag, value: DcpValue) {
+    self.entries.push((tag.into(), value));
+  }
+
+  fn finish(mut self) -> Vec<u8> {
+    self.entries.sort_by_key(|(tag, _)| *tag);
+    let ifd_size = 2 + self.entries.len() * 12 + 4;
+    let mut out = Vec::new();
+    out.extend_from_slice(b"IIRC");
+    out.extend_from_slice(&8_u32.to_le_bytes());
+
+    let mut data = Vec::new();
+    let data_start = 8 + ifd_size;
+    out.extend_from_slice(&(self.entries.len() as u16).to_le_bytes());
+    for (tag, value) in &self.entries {
+      let (typ, count) = value.type_and_count();
+      let bytes = value.encode();
+      out.extend_from_slice(&tag.to_le_bytes());
+      out.extend_from_slice(&typ.to_le_bytes());
+      out.extend_from_slice(&(count as u32).to_le_bytes());
+      if bytes.len() <= 4 {
+        let mut inline = [0_u8; 4];
+        inline[..bytes.len()].copy_from_slice(&bytes);
+        out.extend_from_slice(&inline);
+      } else {
+        out.extend_from_slice(&((data_start + data.len()) as u32).to_le_bytes());
+        data.extend_from_slice(&bytes);
+        if data.len() % 2 == 1 {
+          data.push(0);
+        }
+      }
+    }
+    out.extend_from_slice(&0_u32.to_le_bytes());
+    out.extend_from_slice(&data);
+    out
+  }
+}
+
+/// Convert white balance coefficients to a camera neutral normalized to green.
+pub fn neutral_from_wb(wb: &[f32; 4]) -> [f32; 3] {
+  if wb.iter().take(3).any(|c| !c.is_normal() || *c <= 0.0) {
+    return [1.0, 1.0, 1.0];
+  }
+  [wb[1] / wb[0], 1.0, wb[1] / wb[2]]
+}
+
+/// Densely sampled ProfileToneCurve.
+#[derive(Debug, Clone)]
+struct ToneCurve {
+  lut: Vec<f32>,
+}
+
+impl ToneCurve {
+  const SIZE: usize = 4096;
+
+  fn new(points: &[(f32, f32)]) -> Self {
+    let mut segment = 0;
+    let lut = (0..Self::SIZE)
+      .map(|i| {
+        let x = i as f32 / (Self::SIZE - 1) as f32;
+        while segment + 2 < points.len() && points[segment + 1].0 < x {
+          segment += 1;
+        }
+        let (x0, y0) = points[segment];
+        let (x1, y1) = points[segment + 1];
+        if x1 > x0 {
+          y0 + (y1 - y0) * ((x - x0) / (x1 - x0)).clamp(0.0, 1.0)
+        } else {
+          y0
+        }
+      })
+      .collect();
+    Self { lut }
+  }
+
+  fn eval(&self, x: f32) -> f32 {
+    if x >= 1.0 {
+      // Keep highlight headroom above the curve's domain.
+      return self.lut[Self::SIZE - 1] + (x - 1.0);
+    }
+    let pos = x.max(0.0) * (Self::SIZE - 1) as f32;
+    let i = (pos as usize).min(Self::SIZE - 2);
+    let f = pos - i as f32;
+    self.lut[i] * (1.0 - f) + self.lut[i + 1] * f
+  }
+
+  /// Hue preserving application (DNG RGBTone): the largest and smallest
+  /// channels follow the curve and the middle one is interpolated.
+  fn apply(&self, rgb: [f32; 3]) -> [f32; 3] {
+    let mut order = [0, 1, 2];
+    order.sort_by(|a, b| rgb[*b].total_cmp(&rgb[*a]));
+    let (hi, mid, lo) = (rgb[order[0]], rgb[order[1]], rgb[order[2]]);
+    let hi_out = self.eval(hi);
+    let lo_out = self.eval(lo);
+    let mid_out = if hi > lo { lo_out + (hi_out - lo_out) * (mid - lo) / (hi - lo) } else { hi_out };
+    let mut out = [0.0; 3];
+    out[order[0]] = hi_out;
+    out[order[1]] = mid_out;
+    out[order[2]] = lo_out;
+    out
+  }
+}
+
+/// Rendering stages of a profile, prepared for a fixed scene white.
+/// Operates on scene-referred linear sRGB where 1.0 is sensor white.
+#[derive(Debug, Clone)]
+pub struct ProfileRenderer {
+  hue_sat_map: Option<HueSatMap>,
+  /// Linear gain from BaselineExposureOffset, applied before the look.
+  exposure: f32,
+  look_table: Option<HueSatMap>,
+  tone_curve: Option<ToneCurve>,
+  srgb_to_prophoto: Matrix3,
+  prophoto_to_srgb: Matrix3,
+}
+
+impl ProfileRenderer {
+  pub fn apply(&self, srgb: [f32; 3]) -> [f32; 3] {
+    let mut rgb = mul_vec(&self.srgb_to_prophoto, srgb).map(|c| c.max(0.0));
+    if let Some(map) = &self.hue_sat_map {
+      rgb = map.apply(rgb);
+    }
+    rgb = rgb.map(|c| c * self.exposure);
+    if let Some(table) = &self.look_table {
+      rgb = table.apply(rgb);
+    }
+    if let Some(curve) = &self.tone_curve {
+      rgb = curve.apply(rgb);
+    }
+    mul_vec(&self.prophoto_to_srgb, rgb)
+  }
+}
+
+fn mul_vec(m: &Matrix3, v: [f32; 3]) -> [f32; 3] {
+  [
+    m[0][0] * v[0] + m[0][1] * v[1] + m[0][2] * v[2],
+    m[1][0] * v[0] + m[1][1] * v[1] + m[1][2] * v[2],
+    m[2][0] * v[0] + m[2][1] * v[1] + m[2][2] * v[2],
+  ]
+}
+
+fn diagonal(v: [f32; 3]) -> Matrix3 {
+  [[v[0], 0.0, 0.0], [0.0, v[1], 0.0], [0.0, 0.0, v[2]]]
+}
+
+fn xy_to_xyz_unit(xy: (f32, f32)) -> [f32; 3] {
+  [xy.0 / xy.1, 1.0, (1.0 - xy.0 - xy.1) / xy.1]
+}
+
+fn xyz_to_xy(xyz: [f32; 3]) -> Option<(f32, f32)> {
+  let sum = xyz[0] + xyz[1] + xyz[2];
+  if sum > 0.0 && xyz[1] > 0.0 {
+    Some((xyz[0] / sum, xyz[1] / sum))
+  } else {
+    None
+  }
+}
+
+/// Bradford chromatic adaptation matrix mapping XYZ under `from` to XYZ under `to`.
+pub fn bradford_adaptation(from: (f32, f32), to: (f32, f32)) -> Matrix3 {
+  let w1 = mul_vec(&BRADFORD, xy_to_xyz_unit(from));
+  let w2 = mul_vec(&BRADFORD, xy_to_xyz_unit(to));
+  let scale = [0, 1, 2].map(|i| if w1[i] > 0.0 { (w2[i] / w1[i]).clamp(0.1, 10.0) } else { 1.0 });
+  multiply(&pseudo_inverse(BRADFORD), &multiply(&diagonal(scale), &BRADFORD))
+}
+
+/// DNG-style HSV with hue in [0, 6).
+pub fn rgb_to_hsv(rgb: [f32; 3]) -> (f32, f32, f32) {
+  let [r, g, b] = rgb;
+  let max = r.max(g).max(b);
+  let min = r.min(g).min(b);
+  let gap = max - min;
+  if gap <= 0.0 || max <= 0.0 {
+    return (0.0, 0.0, max);
+  }
+  let mut h = if r == max {
+    (g - b) / gap
+  } else if g == max {
+    2.0 + (b - r) / gap
+  } else {
+    4.0 + (r - g) / gap
+  };
+  if h < 0.0 {
+    h += 6.0;
+  }
+  (h, gap / max, max)
+}
+
+pub fn hsv_to_rgb(h: f32, s: f32, v: f32) -> [f32; 3] {
+  if s <= 0.0 {
+    return [v, v, v];
+  }
+  let h = h.rem_euclid(6.0);
+  let i = h.floor();
+  let f = h - i;
+  let p = v * (1.0 - s);
+  let q = v * (1.0 - s * f);
+  let t = v * (1.0 - s * (1.0 - f));
+  match i as u32 {
+    0 => [v, t, p],
+    1 => [q, v, p],
+    2 => [p, v, t],
+    3 => [p, q, v],
+    4 => [t, p, v],
+    _ => [v, p, q],
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn temperature_roundtrip() {
+    for temperature in [2850.0, 4000.0, 5003.0, 6504.0, 9000.0] {
+      for tint in [-20.0, 0.0, 15.0] {
+        let xy = temperature_to_xy(temperature, tint);
+        let (t, n) = xy_to_temperature(xy);
+        assert!((t - temperature).abs() / temperature < 0.005, "{} -> {}", temperature, t);
+        assert!((n - tint).abs() < 0.5, "{} -> {}", tint, n);
+      }
+    }
+  }
+
+  #[test]
+  fn d65_is_about_6500k() {
+    let (t, _) = xy_to_temperature(super::super::xyz::CIE_1931_WHITE_POINT_D65);
+    assert!((t - 6504.0).abs() < 20.0);
+  }
+
+  #[test]
+  fn weight_interpolates_in_mired() {
+    let mut matrices = HashMap::new();
+    matrices.insert(Illuminant::A, vec![1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0]);
+    matrices.insert(Illuminant::D65, vec![1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0]);
+    let profile = DcpProfile::from_color_matrices(&matrices).unwrap();
+    assert_eq!(profile.illuminant1, Some(Illuminant::A));
+    assert_eq!(profile.interpolation_weight(2000.0), 1.0);
+    assert_eq!(profile.interpolation_weight(8000.0), 0.0);
+    let mid = 2.0 / (1.0 / 2850.0 + 1.0 / 6500.0);
+    assert!((profile.interpolation_weight(mid) - 0.5).abs() < 1.0e-4);
+  }
+
+  #[test]
+  fn neutral_roundtrip() {
+    let profile = DcpProfile {
+      illuminant1: Some(Illuminant::A),
+      illuminant2: Some(Illuminant::D65),
+      color_matrix1: Some([[0.9, -0.4, 0.0], [-0.4, 1.2, 0.25], [-0.03, 0.08, 0.76]]),
+      color_matrix2: Some([[0.8, -0.3, -0.07], [-0.48, 1.27, 0.24], [-0.04, 0.12, 0.65]]),
+      ..Default::default()
+    };
+    for temperature in [2850.0, 4500.0, 6500.0, 8000.0] {
+      for tint in [-15.0, 0.0, 20.0] {
+        let white = profile.white_for_neutral(profile.neutral_for_white(temperature, tint));
+        assert!((white.temperature - temperature).abs() / temperature < 0.005, "{} -> {}", temperature, white.temperature);
+        assert!((white.tint - tint).abs() < 0.5, "{} -> {}", tint, white.tint);
+      }
+    }
+  }
+
+  #[test]
+  fn write_and_read_back() {
+    let profile = DcpProfile {
+      name: Some("Test".into()),
+      camera_model: Some("Make Model".into()),
+      illuminant1: Some(Illuminant::A),
+      illuminant2: Some(Illuminant::D65),
+      color_matrix1: Some([[0.9, -0.4, 0.0], [-0.4, 1.2, 0.25], [-0.03, 0.08, 0.76]]),
+      color_matrix2: Some([[0.8, -0.3, -0.07], [-0.48, 1.27, 0.24], [-0.04, 0.12, 0.65]]),
+      forward_matrix1: Some([[0.7, 0.2, 0.05], [0.3, 0.8, -0.1], [0.0, -0.2, 1.0]]),
+      hue_sat_map1: Some(HueSatMap {
+        hue_divisions: 4,
+        sat_divisions: 2,
+        val_divisions: 1,
+        srgb_gamma: false,
+        data: (0..8).map(|i| [i as f32, 1.0, 1.0]).collect(),
+      }),
+      tone_curve: Some(vec![(0.0, 0.0), (0.5, 0.6), (1.0, 1.0)]),
+      ..Default::default()
+    };
+    let parsed = DcpProfile::from_bytes(&profile.to_bytes()).unwrap();
+    assert_eq!(parsed, profile);
+  }
+
+  #[test]
+  fn baseline_exposure_offset_scales_before_the_look() {
+    let profile = DcpProfile {
+      illuminant1: Some(Illuminant::D65),
+      color_matrix1: Some([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]),
+      baseline_exposure_offset: 1.0,
+      ..Default::default()
+    };
+    assert!(profile.has_rendering());
+    let white = profile.white_for_neutral([1.0, 1.0, 1.0]);
+    let out = profile.renderer(&white).apply([0.1, 0.1, 0.1]);
+    for c in out {
+      assert!((c - 0.2).abs() < 1.0e-5, "{:?}", out);
+    }
+  }
+
+  #[test]
+  fn hsv_roundtrip() {
+    let rgb = [0.2, 0.7, 0.4];
+    let (h, s, v) = rgb_to_hsv(rgb);
+    let back = hsv_to_rgb(h, s, v);
+    for c in 0..3 {
+      assert!((back[c] - rgb[c]).abs() < 1.0e-6);
+    }
+  }
+}
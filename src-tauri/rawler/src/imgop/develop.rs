@@ -1,7 +1,7 @@
 // SPDX-License-Identifier: LGPL-2.1
 // Copyright 2021 Daniel Vogelbacher <daniel@chaospixel.com>
 
-use std::io;
+use std::{collections::HashMap, io};
 
 use image::{DynamicImage, ImageBuffer};
 
@@ -15,17 +15,20 @@ use crate::{
 
 use super::{
   convert_from_f32_scaled_u16,
-  raw::{map_3ch_to_rgb, map_4ch_to_rgb},
-  sensor::bayer::{
-    bilinear::Bilinear4Channel, ppg::PPGDemosaic, superpixel::{Superpixel4Channel, SuperpixelQuarterRes3Channel}, Demosaic,
+  dcp::{neutral_from_wb, DcpProfile},
+  raw::{map_3ch_to_rgb, map_3ch_with_matrix, map_4ch_to_rgb},
+  sensor::{
+    bayer::{bilinear::Bilinear4Channel, ppg::PPGDemosaic, superpixel::{Superpixel4Channel, SuperpixelQuarterRes3Channel}, Demosaic},
+    calibration::SensorCalibration,
   },
-  xyz::Illuminant,
+  xyz::{FlatColorMatrix, Illuminant},
   Dim2, Rect,
 };
 
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
 pub enum ProcessingStep {
   Rescale,
+  SensorCalibration,
   Demosaic,
   CropActiveArea,
   WhiteBalance,
@@ -93,6 +96,11 @@ impl Intermediate {
 pub struct RawDevelop {
   pub steps: Vec<ProcessingStep>,
   pub demosaic_algorithm: DemosaicAlgorithm,
+  /// Camera profile used by the calibrate step. If unset, the D65
+  /// calibration matrix is used alone, as it always has been.
+  pub profile: Option<DcpProfile>,
+  /// Dark frame, flat field and hot pixel data for the sensor.
+  pub sensor_calibration: Option<SensorCalibration>,
 }
 
 impl Default for RawDevelop {
@@ -100,6 +108,7 @@ impl Default for RawDevelop {
     Self {
       steps: vec![
         ProcessingStep::Rescale,
+        ProcessingStep::SensorCalibration,
         ProcessingStep::Demosaic,
         ProcessingStep::CropActiveArea,
         ProcessingStep::WhiteBalance,
@@ -108,6 +117,8 @@ impl Default for RawDevelop {
         ProcessingStep::SRgb,
       ],
       demosaic_algorithm: DemosaicAlgorithm::default(),
+      profile: None,
+      sensor_calibration: None,
     }
   }
 }
@@ -127,12 +138,72 @@ impl RawDevelop {
   }
    */
 
+  /// Map camera RGB to linear sRGB, white balanced with `wb_coeffs` if the
+  /// WhiteBalance step is enabled. This is the Calibrate step of
+  /// `develop_intermediate`, exposed so callers can keep the demosaiced
+  /// data and only redo the color conversion when the white balance changes.
+  pub fn calibrate(&self, intermediate: &Intermediate, wb_coeffs: &[f32; 4], color_matrix: &HashMap<Illuminant, FlatColorMatrix>) -> crate::Result<Intermediate> {
+    let profile = match intermediate {
+      Intermediate::ThreeColor(_) => self.profile.as_ref(),
+      _ => None,
+    };
+
+    if let (Some(profile), Intermediate::ThreeColor(pixels)) = (profile, intermediate) {
+      let wb = if self.steps.contains(&ProcessingStep::WhiteBalance) {
+        *wb_coeffs
+      } else {
+        [1.0, 1.0, 1.0, 1.0]
+      };
+      let (cam2rgb, white) = profile.camera_to_srgb(neutral_from_wb(&wb));
+      log::debug!(
+        "profile: {:?}, white: {:.0}K/{:.1}, weight: {:.3}",
+        profile.name,
+        white.temperature,
+        white.tint,
+        white.weight
+      );
+      return Ok(Intermediate::ThreeColor(map_3ch_with_matrix(pixels, cam2rgb)));
+    }
+
+    let mut xyz2cam: [[f32; 3]; 4] = [[0.0; 3]; 4];
+    let color_matrix = color_matrix
+      .iter()
+      .find(|(illuminant, _m)| **illuminant == Illuminant::D65)
+      .ok_or("Illuminant matrix D65 not found")?
+      .1;
+    assert_eq!(color_matrix.len() % 3, 0); // this is not so nice...
+    let components = color_matrix.len() / 3;
+    for i in 0..components {
+      for j in 0..3 {
+        xyz2cam[i][j] = color_matrix[i * 3 + j];
+      }
+    }
+
+    // Some old images may not provide WB coeffs. Assume 1.0 in this case.
+    let mut wb = if wb_coeffs[0].is_nan() { [1.0, 1.0, 1.0, 1.0] } else { *wb_coeffs };
+    if !self.steps.contains(&ProcessingStep::WhiteBalance) {
+      wb = [1.0, 1.0, 1.0, 1.0];
+    }
+
+    log::debug!("wb: {:?}, coeff: {:?}", wb, xyz2cam);
+
+    Ok(match intermediate {
+      Intermediate::Monochrome(_) => intermediate.clone(),
+      Intermediate::ThreeColor(pixels) => Intermediate::ThreeColor(map_3ch_to_rgb(pixels, &wb, xyz2cam)),
+      Intermediate::FourColor(pixels) => Intermediate::ThreeColor(map_4ch_to_rgb(pixels, &wb, xyz2cam)),
+    })
+  }
+
   /// Develop raw image and write result into TIFF.
   /// If demosaic is disabled or camera raw is monochrome, the TIFF
   /// has only one color channel.
   pub fn develop_intermediate(&self, rawimage: &RawImage) -> crate::Result<Intermediate> {
     let mut rawimage = rawimage.clone();
+    let mut adu_scale = [1.0; 4];
     if self.steps.contains(&ProcessingStep::Rescale) {
+      let black = rawimage.blacklevel.as_bayer_array();
+      let white = rawimage.whitelevel.as_bayer_array();
+      adu_scale = [0, 1, 2, 3].map(|i| 1.0 / (white[i] - black[i]).max(1.0));
       rawimage.apply_scaling()?;
     }
 
@@ -151,6 +222,15 @@ impl RawDevelop {
       _ => todo!(),
     };
 
+    if let (true, Some(calibration), Intermediate::Monochrome(pixels), RawPhotometricInterpretation::Cfa(config)) = (
+      self.steps.contains(&ProcessingStep::SensorCalibration),
+      &self.sensor_calibration,
+      &mut intermediate,
+      &rawimage.photometric,
+    ) {
+      calibration.apply(pixels, &config.cfa, &adu_scale)?;
+    }
+
     if self.steps.contains(&ProcessingStep::Demosaic) {
       intermediate = match &rawimage.photometric {
         RawPhotometricInterpretation::Cfa(config) => {
@@ -194,38 +274,7 @@ impl RawDevelop {
     }
 
     if self.steps.contains(&ProcessingStep::Calibrate) {
-      let mut xyz2cam: [[f32; 3]; 4] = [[0.0; 3]; 4];
-      let color_matrix = rawimage
-        .color_matrix
-        .iter()
-        .find(|(illuminant, _m)| **illuminant == Illuminant::D65)
-        .ok_or("Illuminant matrix D65 not found")?
-        .1;
-      assert_eq!(color_matrix.len() % 3, 0); // this is not so nice...
-      let components = color_matrix.len() / 3;
-      for i in 0..components {
-        for j in 0..3 {
-          xyz2cam[i][j] = color_matrix[i * 3 + j];
-        }
-      }
-
-      // Some old images may not provide WB coeffs. Assume 1.0 in this case.
-      let mut wb = if rawimage.wb_coeffs[0].is_nan() {
-        [1.0, 1.0, 1.0, 1.0]
-      } else {
-        rawimage.wb_coeffs
-      };
-      if !self.steps.contains(&ProcessingStep::WhiteBalance) {
-        wb = [1.0, 1.0, 1.0, 1.0];
-      }
-
-      log::debug!("wb: {:?}, coeff: {:?}", wb, xyz2cam);
-
-      intermediate = match intermediate {
-        Intermediate::Monochrome(_) => intermediate,
-        Intermediate::ThreeColor(pixels) => Intermediate::ThreeColor(map_3ch_to_rgb(&pixels, &wb, xyz2cam)),
-        Intermediate::FourColor(pixels) => Intermediate::ThreeColor(map_4ch_to_rgb(&pixels, &wb, xyz2cam)),
-      };
+      intermediate = self.calibrate(&intermediate, &rawimage.wb_coeffs, &rawimage.color_matrix)?;
     }
 
     if self.steps.contains(&ProcessingStep::CropDefault) {
@@ -342,4 +391,34 @@ impl RawDevelop {
 
     Ok(())
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn calibrate_patch(developer: &RawDevelop, matrices: &HashMap<Illuminant, FlatColorMatrix>) -> Vec<[f32; 3]> {
+    let pixels = Color2D::new_with(vec![[0.2, 0.4, 0.3], [0.6, 0.5, 0.1]], 2, 1);
+    let wb = [1.8, 1.0, 1.4, f32::NAN];
+    match developer.calibrate(&Intermediate::ThreeColor(pixels), &wb, matrices).unwrap() {
+      Intermediate::ThreeColor(out) => out.data,
+      _ => unreachable!(),
+    }
+  }
+
+  #[test]
+  fn calibrate_without_profile_uses_the_d65_matrix_alone() {
+    let d65 = vec![0.8, -0.3, -0.07, -0.48, 1.27, 0.24, -0.04, 0.12, 0.65];
+    let only_d65 = HashMap::from([(Illuminant::D65, d65.clone())]);
+    let dual = HashMap::from([(Illuminant::A, vec![0.9, -0.4, 0.0, -0.4, 1.2, 0.25, -0.03, 0.08, 0.76]), (Illuminant::D65, d65)]);
+
+    let developer = RawDevelop::default();
+    assert_eq!(calibrate_patch(&developer, &dual), calibrate_patch(&developer, &only_d65));
+
+    let interpolated = RawDevelop {
+      profile: DcpProfile::from_color_matrices(&dual),
+      ..Default::default()
+    };
+    assert_ne!(calibrate_patch(&interpolated, &dual), calibrate_patch(&developer, &only_d65));
+  }
+}
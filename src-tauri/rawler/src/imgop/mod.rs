@@ -1,6 +1,7 @@
 // SPDX-License-Identifier: LGPL-2.1
 // Copyright 2021 Daniel Vogelbacher <daniel@chaospixel.com>
 
+pub mod dcp;
 pub mod develop;
 pub mod gamma;
 pub mod matrix;
@@ -212,6 +212,27 @@ pub(crate) fn map_3ch_to_rgb(src: &Color2D<f32, 3>, wb_coeff: &[f32; 4], xyz2cam
   RgbF32::new_with(out, src.width, src.height)
 }
 
+/// Map camera RGB to sRGB with a matrix that already includes the white balance.
+#[multiversion(targets("x86_64+avx+avx2", "x86+sse", "aarch64+neon"))]
+pub(crate) fn map_3ch_with_matrix(src: &Color2D<f32, 3>, cam2rgb: [[f32; 3]; 3]) -> RgbF32 {
+  let mut out = Vec::with_capacity(src.data.len());
+
+  src
+    .pixels()
+    .par_iter()
+    .map(|pix| {
+      let srgb = [
+        cam2rgb[0][0] * pix[0] + cam2rgb[0][1] * pix[1] + cam2rgb[0][2] * pix[2],
+        cam2rgb[1][0] * pix[0] + cam2rgb[1][1] * pix[1] + cam2rgb[1][2] * pix[2],
+        cam2rgb[2][0] * pix[0] + cam2rgb[2][1] * pix[1] + cam2rgb[2][2] * pix[2],
+      ];
+      clip_euclidean_norm_avg(&srgb)
+    })
+    .collect_into_vec(&mut out);
+
+  RgbF32::new_with(out, src.width, src.height)
+}
+
 #[multiversion(targets("x86_64+avx+avx2", "x86+sse", "aarch64+neon"))]
 pub(crate) fn map_4ch_to_rgb(src: &Color2D<f32, 4>, wb_coeff: &[f32; 4], xyz2cam: [[f32; 3]; 4]) -> RgbF32 {
   let rgb2cam = normalize(multiply(&xyz2cam, &SRGB_TO_XYZ_D65));
@@ -0,0 +1,102 @@
+// SPDX-License-Identifier: LGPL-2.1
+
+//! Dark frame, flat field and defective pixel correction on CFA data.
+
+use std::sync::Arc;
+
+use rayon::prelude::*;
+
+use crate::{cfa::CFA, pixarray::PixF32};
+
+/// Per-pixel calibration data at full sensor resolution, applied before
+/// any cropping or demosaicing.
+#[derive(Debug, Clone, Default)]
+pub struct SensorCalibration {
+  /// Dark signal above black level, in raw ADU.
+  pub dark_frame: Option<Arc<Vec<f32>>>,
+  /// Relative pixel response, normalized to 1.0 per CFA color.
+  pub flat_field: Option<Arc<Vec<f32>>>,
+  /// Row-major indices of hot or stuck pixels.
+  pub hot_pixels: Arc<Vec<u32>>,
+}
+
+impl SensorCalibration {
+  pub fn is_empty(&self) -> bool {
+    self.dark_frame.is_none() && self.flat_field.is_none() && self.hot_pixels.is_empty()
+  }
+
+  /// Correct rescaled CFA pixels. `adu_scale` holds the factor per 2x2 CFA
+  /// position that converts raw ADU into the rescaled value range.
+  pub fn apply(&self, pixels: &mut PixF32, cfa: &CFA, adu_scale: &[f32; 4]) -> crate::Result<()> {
+    let (width, height) = (pixels.width, pixels.height);
+    for frame in self.dark_frame.iter().chain(self.flat_field.iter()) {
+      if frame.len() != width * height {
+        return Err(format!("Calibration frame has {} pixels, image has {}", frame.len(), width * height).into());
+      }
+    }
+
+    if let Some(dark) = &self.dark_frame {
+      pixels.data.par_chunks_exact_mut(width).zip(dark.par_chunks_exact(width)).enumerate().for_each(|(row, (line, dark))| {
+        for (col, (p, d)) in line.iter_mut().zip(dark).enumerate() {
+          *p = (*p - d * adu_scale[(row % 2) * 2 + col % 2]).max(0.0);
+        }
+      });
+    }
+
+    if let Some(flat) = &self.flat_field {
+      pixels.data.par_iter_mut().zip(flat.par_iter()).for_each(|(p, gain)| {
+        *p /= gain.max(0.05);
+      });
+    }
+
+    if !self.hot_pixels.is_empty() {
+      let is_hot = |row: usize, col: usize| self.hot_pixels.binary_search(&((row * width + col) as u32)).is_ok();
+      let repaired: Vec<(usize, f32)> = self
+        .hot_pixels
+        .par_iter()
+        .map(|&index| index as usize)
+        .filter(|&index| index < width * height)
+        .map(|index| {
+          let (row, col) = (index / width, index % width);
+          let color = cfa.color_at(row, col);
+          let (mut sum, mut count) = (0.0, 0);
+          for r in row.saturating_sub(2)..(row + 3).min(height) {
+            for c in col.saturating_sub(2)..(col + 3).min(width) {
+              if (r, c) != (row, col) && cfa.color_at(r, c) == color && !is_hot(r, c) {
+                sum += pixels.data[r * width + c];
+                count += 1;
+              }
+            }
+          }
+          (index, if count > 0 { sum / count as f32 } else { pixels.data[index] })
+        })
+        .collect();
+      for (index, value) in repaired {
+        pixels.data[index] = value;
+      }
+    }
+
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn corrects_dark_flat_and_hot_pixels() {
+    let cfa = CFA::new("RGGB");
+    let mut pixels = PixF32::new_with(vec![0.5; 36], 6, 6);
+    pixels.data[14] = 1.0;
+    let calibration = SensorCalibration {
+      dark_frame: Some(Arc::new(vec![100.0; 36])),
+      flat_field: Some(Arc::new((0..36).map(|i| if i == 7 { 0.5 } else { 1.0 }).collect())),
+      hot_pixels: Arc::new(vec![14]),
+    };
+    calibration.apply(&mut pixels, &cfa, &[0.001; 4]).unwrap();
+    assert!((pixels.data[0] - 0.4).abs() < 1e-6);
+    assert!((pixels.data[7] - 0.8).abs() < 1e-6);
+    assert!((pixels.data[14] - 0.4).abs() < 1e-6);
+  }
+}
@@ -2,4 +2,5 @@
 // Copyright 2021 Daniel Vogelbacher <daniel@chaospixel.com>
 
 pub mod bayer;
+pub mod calibration;
 pub mod xtrans;
@@ -55,6 +55,11 @@ struct GlobalAdjustments {
     _pad_neg1: f32,
     _pad_neg2: f32,
 
+    tone_mapper: u32,
+    filmic_white_exposure: f32,
+    filmic_black_exposure: f32,
+    filmic_contrast: f32,
+
     color_grading_shadows: ColorGradeSettings,
     color_grading_midtones: ColorGradeSettings,
     color_grading_highlights: ColorGradeSettings,
@@ -117,21 +122,46 @@ struct MaskAdjustments {
     blue_curve_count: u32,
 }
 
+struct LutSettings {
+    enabled: u32,
+    size: u32,
+    shaper_size: u32,
+    interpolation: u32,
+    input_space: u32,
+    intensity: f32,
+    _pad1: f32,
+    _pad2: f32,
+    domain_min: vec4<f32>,
+    domain_max: vec4<f32>,
+    shaper_min: vec4<f32>,
+    shaper_max: vec4<f32>,
+}
+
 struct AllAdjustments {
     global: GlobalAdjustments,
-    mask_adjustments: array<MaskAdjustments, 16>,
     mask_count: u32,
     tile_offset_x: u32,
     tile_offset_y: u32,
+    full_width: u32,
+    full_height: u32,
     _pad1: u32,
+    _pad2: u32,
+    _pad3: u32,
+    lut: LutSettings,
 }
 
 @group(0) @binding(0) var input_texture: texture_2d<f32>;
 @group(0) @binding(1) var output_texture: texture_storage_2d<rgba8unorm, write>;
 @group(0) @binding(2) var<uniform> adjustments: AllAdjustments;
 @group(0) @binding(3) var mask_textures: texture_2d_array<f32>;
+@group(0) @binding(4) var<storage, read> mask_adjustments: array<MaskAdjustments>;
+@group(0) @binding(5) var lut_texture: texture_3d<f32>;
+@group(0) @binding(6) var<storage, read> lut_shaper: array<vec4<f32>>;
 
 const LUMA_COEFF = vec3<f32>(0.2126, 0.7152, 0.0722);
+// Clip the output to the displayable 0..1 range. The gamut analysis variant
+// turns this off to see how far colors fall outside.
+const CLIP_OUTPUT: bool = true;
 
 fn get_luma(c: vec3<f32>) -> f32 {
     return dot(c, LUMA_COEFF);
@@ -146,6 +176,12 @@ fn srgb_to_linear(c: vec3<f32>) -> vec3<f32> {
 }
 
 fn linear_to_srgb(c: vec3<f32>) -> vec3<f32> {
+    if (!CLIP_OUTPUT) {
+        // Mirrored around zero and unbounded above, so out of range values survive.
+        let m = abs(c);
+        let encoded = select((1.055) * pow(m, vec3<f32>(1.0 / 2.4)) - 0.055, m * 12.92, m <= vec3<f32>(0.0031308));
+        return sign(c) * encoded;
+    }
     let c_clamped = clamp(c, vec3<f32>(0.0), vec3<f32>(1.0));
     let cutoff = vec3<f32>(0.0031308);
     let a = vec3<f32>(0.055);
@@ -232,6 +268,15 @@ fn apply_curve(val: f32, points: array<Point, 16>, count: u32) -> f32 {
     if (count < 2u) { return val; }
     var local_points = points;
     let x = val * 255.0;
+    if (!CLIP_OUTPUT) {
+        // Continue the end segments in a straight line instead of clamping.
+        let first = local_points[0];
+        let second = local_points[1];
+        let last = local_points[count - 1u];
+        let before_last = local_points[count - 2u];
+        if (x <= first.x) { return (first.y + (x - first.x) * (second.y - first.y) / max(0.001, second.x - first.x)) / 255.0; }
+        if (x >= last.x) { return (last.y + (x - last.x) * (last.y - before_last.y) / max(0.001, last.x - before_last.x)) / 255.0; }
+    }
     if (x <= local_points[0].x) { return local_points[0].y / 255.0; }
     if (x >= local_points[count - 1u].x) { return local_points[count - 1u].y / 255.0; }
     for (var i = 0u; i < 15u; i = i + 1u) {
@@ -497,8 +542,72 @@ fn apply_noise_reduction(color: vec3<f32>, coords_i: vec2<i32>, luma_amount: f32
     return color;
 }
 
+const TONE_MAPPER_ACES: u32 = 1u;
+const TONE_MAPPER_FILMIC: u32 = 2u;
+const TONE_MAPPER_AGX: u32 = 3u;
+const MIDDLE_GREY: f32 = 0.1845;
+
 fn aces_fitted(c: vec3<f32>) -> vec3<f32> {
-    return c; // FIXME: Maybe with a setting? Most people probably don't like this hardcoded ACES, so removed temporarly.
+    let x = max(c, vec3<f32>(0.0));
+    let a = 2.51;
+    let b = 0.03;
+    let cc = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((x * (a * x + b)) / (x * (cc * x + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+// Log encoding between the black and white exposures (EV relative to middle
+// grey), followed by a power S-curve whose slope at grey is the contrast.
+fn filmic(c: vec3<f32>, white_ev: f32, black_ev: f32, contrast: f32) -> vec3<f32> {
+    let range = max(white_ev - black_ev, 0.1);
+    let grey_log = clamp(-black_ev / range, 0.01, 0.99);
+    let grey_display = 0.4613; // MIDDLE_GREY through the sRGB curve
+    let toe_power = max(contrast * grey_log / grey_display, 1.0);
+    let shoulder_power = max(contrast * (1.0 - grey_log) / (1.0 - grey_display), 1.0);
+
+    let t = clamp((log2(max(c, vec3<f32>(1e-10)) / MIDDLE_GREY) - black_ev) / range, vec3<f32>(0.0), vec3<f32>(1.0));
+    let toe = grey_display * pow(t / grey_log, vec3<f32>(toe_power));
+    let shoulder = 1.0 - (1.0 - grey_display) * pow((1.0 - t) / (1.0 - grey_log), vec3<f32>(shoulder_power));
+    let display = select(shoulder, toe, t < vec3<f32>(grey_log));
+    return srgb_to_linear(display);
+}
+
+fn agx_contrast_approx(x: vec3<f32>) -> vec3<f32> {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+}
+
+fn agx(c: vec3<f32>) -> vec3<f32> {
+    let inset = mat3x3<f32>(
+        vec3<f32>(0.842479062253094, 0.0423282422610123, 0.0423756549057051),
+        vec3<f32>(0.0784335999999992, 0.878468636469772, 0.0784336),
+        vec3<f32>(0.0792237451477643, 0.0791661274605434, 0.879142973793104)
+    );
+    let outset = mat3x3<f32>(
+        vec3<f32>(1.19687900512017, -0.0528968517574562, -0.0529716355144438),
+        vec3<f32>(-0.0980208811401368, 1.15190312990417, -0.0980434501171241),
+        vec3<f32>(-0.0990297440797205, -0.0989611768448433, 1.15107367264116)
+    );
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+    var v = inset * max(c, vec3<f32>(1e-10));
+    v = clamp(log2(v), vec3<f32>(min_ev), vec3<f32>(max_ev));
+    v = agx_contrast_approx((v - min_ev) / (max_ev - min_ev));
+    v = outset * v;
+    return pow(max(v, vec3<f32>(0.0)), vec3<f32>(2.2));
+}
+
+// Maps scene-linear values to display-linear ones. Without a tone mapper
+// values above 1.0 are clipped by the final sRGB encode.
+fn apply_tone_mapping(c: vec3<f32>, adj: GlobalAdjustments) -> vec3<f32> {
+    switch (adj.tone_mapper) {
+        case TONE_MAPPER_ACES: { return aces_fitted(c); }
+        case TONE_MAPPER_FILMIC: { return filmic(c, adj.filmic_white_exposure, adj.filmic_black_exposure, adj.filmic_contrast); }
+        case TONE_MAPPER_AGX: { return agx(c); }
+        default: { return c; }
+    }
 }
 
 fn apply_all_curves(color: vec3<f32>, luma_curve: array<Point, 16>, luma_curve_count: u32, red_curve: array<Point, 16>, red_curve_count: u32, green_curve: array<Point, 16>, green_curve_count: u32, blue_curve: array<Point, 16>, blue_curve_count: u32) -> vec3<f32> {
@@ -511,7 +620,7 @@ fn apply_all_curves(color: vec3<f32>, luma_curve: array<Point, 16>, luma_curve_c
         var final_color: vec3<f32>;
         if (luma_graded > 0.001) { final_color = color_graded * (luma_target / luma_graded); } else { final_color = vec3<f32>(luma_target); }
         let max_comp = max(final_color.r, max(final_color.g, final_color.b));
-        if (max_comp > 1.0) { final_color = final_color / max_comp; }
+        if (CLIP_OUTPUT && max_comp > 1.0) { final_color = final_color / max_comp; }
         return final_color;
     } else {
         return vec3<f32>(apply_curve(color.r, luma_curve, luma_curve_count), apply_curve(color.g, luma_curve, luma_curve_count), apply_curve(color.b, luma_curve, luma_curve_count));
@@ -548,6 +657,76 @@ fn apply_all_mask_adjustments(initial_rgb: vec3<f32>, adj: MaskAdjustments, coor
     return processed_rgb;
 }
 
+const LUT_INTERPOLATION_TETRAHEDRAL: u32 = 1u;
+const LUT_INPUT_LINEAR: u32 = 1u;
+
+fn lut_fetch(p: vec3<i32>) -> vec3<f32> {
+    return textureLoad(lut_texture, p, 0).rgb;
+}
+
+fn apply_lut_shaper(c: vec3<f32>, lut: LutSettings) -> vec3<f32> {
+    let last = f32(lut.shaper_size - 1u);
+    let t = clamp((c - lut.shaper_min.rgb) / (lut.shaper_max.rgb - lut.shaper_min.rgb), vec3<f32>(0.0), vec3<f32>(1.0)) * last;
+    var out: vec3<f32>;
+    for (var ch = 0; ch < 3; ch = ch + 1) {
+        let i0 = u32(floor(t[ch]));
+        let i1 = min(i0 + 1u, lut.shaper_size - 1u);
+        out[ch] = mix(lut_shaper[i0][ch], lut_shaper[i1][ch], t[ch] - f32(i0));
+    }
+    return out;
+}
+
+fn sample_lut(coord: vec3<f32>, lut: LutSettings) -> vec3<f32> {
+    let p = coord * f32(lut.size - 1u);
+    let base = min(vec3<i32>(floor(p)), vec3<i32>(i32(lut.size) - 2));
+    let f = p - vec3<f32>(base);
+    let c000 = lut_fetch(base);
+    let c111 = lut_fetch(base + vec3<i32>(1, 1, 1));
+
+    if (lut.interpolation != LUT_INTERPOLATION_TETRAHEDRAL) {
+        let c100 = lut_fetch(base + vec3<i32>(1, 0, 0));
+        let c010 = lut_fetch(base + vec3<i32>(0, 1, 0));
+        let c110 = lut_fetch(base + vec3<i32>(1, 1, 0));
+        let c001 = lut_fetch(base + vec3<i32>(0, 0, 1));
+        let c101 = lut_fetch(base + vec3<i32>(1, 0, 1));
+        let c011 = lut_fetch(base + vec3<i32>(0, 1, 1));
+        let c0 = mix(mix(c000, c100, f.r), mix(c010, c110, f.r), f.g);
+        let c1 = mix(mix(c001, c101, f.r), mix(c011, c111, f.r), f.g);
+        return mix(c0, c1, f.b);
+    }
+
+    // Split the cell into six tetrahedra along its main diagonal.
+    if (f.r > f.g) {
+        if (f.g > f.b) {
+            return (1.0 - f.r) * c000 + (f.r - f.g) * lut_fetch(base + vec3<i32>(1, 0, 0)) + (f.g - f.b) * lut_fetch(base + vec3<i32>(1, 1, 0)) + f.b * c111;
+        } else if (f.r > f.b) {
+            return (1.0 - f.r) * c000 + (f.r - f.b) * lut_fetch(base + vec3<i32>(1, 0, 0)) + (f.b - f.g) * lut_fetch(base + vec3<i32>(1, 0, 1)) + f.g * c111;
+        } else {
+            return (1.0 - f.b) * c000 + (f.b - f.r) * lut_fetch(base + vec3<i32>(0, 0, 1)) + (f.r - f.g) * lut_fetch(base + vec3<i32>(1, 0, 1)) + f.g * c111;
+        }
+    } else {
+        if (f.b > f.g) {
+            return (1.0 - f.b) * c000 + (f.b - f.g) * lut_fetch(base + vec3<i32>(0, 0, 1)) + (f.g - f.r) * lut_fetch(base + vec3<i32>(0, 1, 1)) + f.r * c111;
+        } else if (f.b > f.r) {
+            return (1.0 - f.g) * c000 + (f.g - f.b) * lut_fetch(base + vec3<i32>(0, 1, 0)) + (f.b - f.r) * lut_fetch(base + vec3<i32>(0, 1, 1)) + f.r * c111;
+        } else {
+            return (1.0 - f.g) * c000 + (f.g - f.r) * lut_fetch(base + vec3<i32>(0, 1, 0)) + (f.r - f.b) * lut_fetch(base + vec3<i32>(1, 1, 0)) + f.b * c111;
+        }
+    }
+}
+
+// Looks up the display-referred color in the LUT and blends by intensity.
+fn apply_lut(color_srgb: vec3<f32>, lut: LutSettings) -> vec3<f32> {
+    let is_linear = lut.input_space == LUT_INPUT_LINEAR;
+    var c = clamp(color_srgb, vec3<f32>(0.0), vec3<f32>(1.0));
+    if (is_linear) { c = srgb_to_linear(c); }
+    if (lut.shaper_size > 1u) { c = apply_lut_shaper(c, lut); }
+    let coord = clamp((c - lut.domain_min.rgb) / (lut.domain_max.rgb - lut.domain_min.rgb), vec3<f32>(0.0), vec3<f32>(1.0));
+    var looked_up = sample_lut(coord, lut);
+    if (is_linear) { looked_up = linear_to_srgb(looked_up); }
+    return mix(color_srgb, looked_up, lut.intensity);
+}
+
 @compute @workgroup_size(8, 8, 1)
 fn main(@builtin(global_invocation_id) id: vec3<u32>) {
     let in_dims = vec2<u32>(textureDimensions(input_texture));
@@ -565,11 +744,14 @@ fn main(@builtin(global_invocation_id) id: vec3<u32>) {
         initial_linear_rgb = max(initial_linear_rgb, vec3<f32>(0.0));
     }
 
-    let absolute_coord_i = vec2<i32>(id.xy) + vec2<i32>(i32(adjustments.tile_offset_x), i32(adjustments.tile_offset_y));
+    // Neighbourhood reads use coordinates within the input texture; anything
+    // tied to the position in the whole image uses the absolute coordinate.
+    let coord_i = vec2<i32>(id.xy);
+    let absolute_coord_i = coord_i + vec2<i32>(i32(adjustments.tile_offset_x), i32(adjustments.tile_offset_y));
 
-    var processed_rgb_linear = apply_all_adjustments(initial_linear_rgb, adjustments.global, absolute_coord_i);
+    var processed_rgb_linear = apply_all_adjustments(initial_linear_rgb, adjustments.global, coord_i);
 
-    let base_srgb = linear_to_srgb(aces_fitted(processed_rgb_linear));
+    let base_srgb = linear_to_srgb(apply_tone_mapping(processed_rgb_linear, adjustments.global));
     
     var final_rgb = apply_all_curves(base_srgb,
         adjustments.global.luma_curve, adjustments.global.luma_curve_count,
@@ -581,18 +763,22 @@ fn main(@builtin(global_invocation_id) id: vec3<u32>) {
     for (var i = 0u; i < adjustments.mask_count; i = i + 1u) {
         let influence = textureLoad(mask_textures, id.xy, i, 0).r;
         if (influence > 0.001) {
-            let mask_adjusted_linear = apply_all_mask_adjustments(processed_rgb_linear, adjustments.mask_adjustments[i], absolute_coord_i);
-            let mask_base_srgb = linear_to_srgb(aces_fitted(mask_adjusted_linear));
+            let mask_adjusted_linear = apply_all_mask_adjustments(processed_rgb_linear, mask_adjustments[i], coord_i);
+            let mask_base_srgb = linear_to_srgb(apply_tone_mapping(mask_adjusted_linear, adjustments.global));
             let mask_final_srgb = apply_all_curves(mask_base_srgb,
-                adjustments.mask_adjustments[i].luma_curve, adjustments.mask_adjustments[i].luma_curve_count,
-                adjustments.mask_adjustments[i].red_curve, adjustments.mask_adjustments[i].red_curve_count,
-                adjustments.mask_adjustments[i].green_curve, adjustments.mask_adjustments[i].green_curve_count,
-                adjustments.mask_adjustments[i].blue_curve, adjustments.mask_adjustments[i].blue_curve_count
+                mask_adjustments[i].luma_curve, mask_adjustments[i].luma_curve_count,
+                mask_adjustments[i].red_curve, mask_adjustments[i].red_curve_count,
+                mask_adjustments[i].green_curve, mask_adjustments[i].green_curve_count,
+                mask_adjustments[i].blue_curve, mask_adjustments[i].blue_curve_count
             );
             final_rgb = mix(final_rgb, mask_final_srgb, influence);
         }
     }
 
+    if (adjustments.lut.enabled == 1u) {
+        final_rgb = apply_lut(final_rgb, adjustments.lut);
+    }
+
     if (adjustments.global.grain_amount > 0.0) {
         let g = adjustments.global;
         let coord = vec2<f32>(absolute_coord_i);
@@ -611,18 +797,22 @@ fn main(@builtin(global_invocation_id) id: vec3<u32>) {
 
     let g = adjustments.global;
     if (g.vignette_amount != 0.0) {
-        let out_coord = vec2<f32>(f32(id.x), f32(id.y));
+        let full_dims = vec2<f32>(f32(adjustments.full_width), f32(adjustments.full_height));
+        let out_coord = vec2<f32>(absolute_coord_i);
         let v_amount = g.vignette_amount;
         let v_mid = g.vignette_midpoint;
         let v_round = 1.0 - g.vignette_roundness;
         let v_feather = g.vignette_feather * 0.5;
-        let aspect = f32(in_dims.y) / f32(in_dims.x);
-        let uv_centered = (out_coord / vec2<f32>(in_dims) - 0.5) * 2.0;
+        let aspect = full_dims.y / full_dims.x;
+        let uv_centered = (out_coord / full_dims - 0.5) * 2.0;
         let uv_round = sign(uv_centered) * pow(abs(uv_centered), vec2<f32>(v_round, v_round));
         let d = length(uv_round * vec2<f32>(1.0, aspect)) * 0.5;
         let vignette_mask = smoothstep(v_mid - v_feather, v_mid + v_feather, d);
         if (v_amount < 0.0) { final_rgb *= (1.0 + v_amount * vignette_mask); } else { final_rgb = mix(final_rgb, vec3<f32>(1.0), v_amount * vignette_mask); }
     }
 
-    textureStore(output_texture, id.xy, vec4<f32>(clamp(final_rgb, vec3<f32>(0.0), vec3<f32>(1.0)), original_color.a));
+    if (CLIP_OUTPUT) {
+        final_rgb = clamp(final_rgb, vec3<f32>(0.0), vec3<f32>(1.0));
+    }
+    textureStore(output_texture, id.xy, vec4<f32>(final_rgb, original_color.a));
 }
\ No newline at end of file
@@ -1,6 +1,4 @@
-use std::fs;
-use std::io::Cursor;
-use std::path::{Path, PathBuf};
+use std::path::Path;
 use std::sync::Arc;
 
 use anyhow::Result;
@@ -9,23 +7,19 @@ use image::imageops::{self, FilterType};
 use ndarray::{Array, IxDyn};
 use ort::{Environment, Session, SessionBuilder, Value};
 use serde::{Deserialize, Serialize};
-use tauri::Manager;
-use tauri::Emitter;
 
-const ENCODER_URL: &str = "https://huggingface.co/CyberTimon/RapidRAW-Models/resolve/main/vit_t_encoder.onnx?download=true";
-const DECODER_URL: &str = "https://huggingface.co/CyberTimon/RapidRAW-Models/resolve/main/vit_t_decoder.onnx?download=true";
-const ENCODER_FILENAME: &str = "vit_t_encoder.onnx";
-const DECODER_FILENAME: &str = "vit_t_decoder.onnx";
-const SAM_INPUT_SIZE: u32 = 1024;
+use crate::model_registry::{ensure_model, ready_model_path, SAM_DECODER, SAM_ENCODER, U2NET};
 
-const U2NETP_URL: &str = "https://huggingface.co/CyberTimon/RapidRAW-Models/resolve/main/u2net.onnx?download=true";
-const U2NETP_FILENAME: &str = "u2net.onnx";
+const SAM_INPUT_SIZE: u32 = 1024;
 const U2NETP_INPUT_SIZE: u32 = 320;
 
+/// Used when the depth model does not declare a fixed input size.
+const DEPTH_DEFAULT_INPUT_SIZE: u32 = 518;
+
 pub struct AiModels {
     pub sam_encoder: Session,
     pub sam_decoder: Session,
-    pub u2netp: Session,
+    pub u2netp: Arc<Session>,
 }
 
 #[derive(Clone)]
@@ -40,55 +34,51 @@ pub struct AiState {
     pub embeddings: Option<ImageEmbeddings>,
 }
 
-fn get_models_dir(app_handle: &tauri::AppHandle) -> Result<PathBuf> {
-    let models_dir = app_handle
-        .path()
-        .app_data_dir()?
-        .join("models");
-    if !models_dir.exists() {
-        fs::create_dir_all(&models_dir)?;
-    }
-    Ok(models_dir)
+/// A user-supplied monocular depth model (MiDaS / Depth Anything style) that
+/// outputs relative inverse depth.
+pub struct DepthModel {
+    pub path: String,
+    pub session: Session,
+}
+
+#[derive(Clone)]
+pub struct DepthMap {
+    pub path_hash: String,
+    /// 0 is nearest and 255 farthest, at the original image size.
+    pub depth: GrayImage,
 }
 
-async fn download_model(url: &str, dest: &Path) -> Result<()> {
-    let response = reqwest::get(url).await?;
-    let mut file = fs::File::create(dest)?;
-    let mut content = Cursor::new(response.bytes().await?);
-    std::io::copy(&mut content, &mut file)?;
-    Ok(())
+/// Kept separate from `AiState` so depth masks work without downloading the
+/// bundled models.
+#[derive(Default)]
+pub struct DepthState {
+    pub model: Option<Arc<DepthModel>>,
+    pub depth_map: Option<DepthMap>,
 }
 
 pub async fn get_or_init_ai_models(app_handle: &tauri::AppHandle) -> Result<Arc<AiModels>> {
-    let models_dir = get_models_dir(app_handle)?;
-    let encoder_path = models_dir.join(ENCODER_FILENAME);
-    let decoder_path = models_dir.join(DECODER_FILENAME);
-    let u2netp_path = models_dir.join(U2NETP_FILENAME);
-
-    if !encoder_path.exists() {
-        let _ = app_handle.emit("ai-model-download-start", "SAM Encoder");
-        download_model(ENCODER_URL, &encoder_path).await?;
-        let _ = app_handle.emit("ai-model-download-finish", "SAM Encoder");
-    }
-    if !decoder_path.exists() {
-        let _ = app_handle.emit("ai-model-download-start", "SAM Decoder");
-        download_model(DECODER_URL, &decoder_path).await?;
-        let _ = app_handle.emit("ai-model-download-finish", "SAM Decoder");
-    }
-    if !u2netp_path.exists() {
-        let _ = app_handle.emit("ai-model-download-start", "Foreground Model");
-        download_model(U2NETP_URL, &u2netp_path).await?;
-        let _ = app_handle.emit("ai-model-download-finish", "Foreground Model");
-    }
+    let encoder_path = ensure_model(app_handle, &SAM_ENCODER).await?;
+    let decoder_path = ensure_model(app_handle, &SAM_DECODER).await?;
+    let u2netp_path = ensure_model(app_handle, &U2NET).await?;
 
     let environment = Arc::new(Environment::builder().with_name("AI").build()?);
     let sam_encoder = SessionBuilder::new(&environment)?.with_model_from_file(encoder_path)?;
     let sam_decoder = SessionBuilder::new(&environment)?.with_model_from_file(decoder_path)?;
-    let u2netp = SessionBuilder::new(&environment)?.with_model_from_file(u2netp_path)?;
+    let u2netp = Arc::new(SessionBuilder::new(&environment)?.with_model_from_file(u2netp_path)?);
 
     Ok(Arc::new(AiModels { sam_encoder, sam_decoder, u2netp }))
 }
 
+/// Loads only the foreground model, and only if it is already on disk. For
+/// features that work without it and should not start a download.
+pub fn load_foreground_model(app_handle: &tauri::AppHandle) -> Result<Option<Session>> {
+    let Some(path) = ready_model_path(app_handle, &U2NET) else {
+        return Ok(None);
+    };
+    let environment = Arc::new(Environment::builder().with_name("Foreground").build()?);
+    Ok(Some(SessionBuilder::new(&environment)?.with_model_from_file(path)?))
+}
+
 pub fn generate_image_embeddings(
     image: &DynamicImage,
     encoder: &Session,
@@ -251,6 +241,75 @@ pub fn run_u2netp_model(
     Ok(final_mask)
 }
 
+pub fn load_depth_model(path: &str) -> Result<DepthModel> {
+    if !Path::new(path).exists() {
+        anyhow::bail!("Depth model not found at {}", path);
+    }
+    let environment = Arc::new(Environment::builder().with_name("Depth").build()?);
+    let session = SessionBuilder::new(&environment)?.with_model_from_file(path)?;
+    Ok(DepthModel { path: path.to_string(), session })
+}
+
+pub fn run_depth_model(image: &DynamicImage, model: &DepthModel) -> Result<GrayImage> {
+    let (orig_width, orig_height) = image.dimensions();
+
+    let declared_size = |index: usize| {
+        model.session.inputs.first()
+            .and_then(|input| input.dimensions.get(index).copied().flatten())
+            .filter(|&size| size > 0)
+    };
+    let input_height = declared_size(2).unwrap_or(DEPTH_DEFAULT_INPUT_SIZE);
+    let input_width = declared_size(3).unwrap_or(DEPTH_DEFAULT_INPUT_SIZE);
+
+    let resized_rgb = image.resize_exact(input_width, input_height, FilterType::Triangle).to_rgb8();
+
+    let mut input_tensor: Array<f32, _> = Array::zeros((1, 3, input_height as usize, input_width as usize));
+    let mean = [0.485, 0.456, 0.406];
+    let std = [0.229, 0.224, 0.225];
+
+    for (x, y, pixel) in resized_rgb.enumerate_pixels() {
+        for c in 0..3 {
+            input_tensor[[0, c, y as usize, x as usize]] = (pixel[c] as f32 / 255.0 - mean[c]) / std[c];
+        }
+    }
+
+    let input_tensor_dyn = input_tensor.into_dyn();
+    let input_values = input_tensor_dyn.as_standard_layout();
+    let inputs = vec![Value::from_array(model.session.allocator(), &input_values)?];
+
+    let outputs = model.session.run(inputs)?;
+    let output_tensor = outputs[0].try_extract::<f32>()?.view().to_owned();
+
+    let shape = output_tensor.shape();
+    if shape.len() < 2 {
+        anyhow::bail!("Unexpected depth model output shape {:?}", shape);
+    }
+    let out_height = shape[shape.len() - 2];
+    let out_width = shape[shape.len() - 1];
+
+    // Normalize robustly so that a few outliers do not flatten the range.
+    let mut sorted: Vec<f32> = output_tensor.iter().copied().filter(|v| v.is_finite()).collect();
+    if sorted.is_empty() {
+        anyhow::bail!("Depth model produced no valid values");
+    }
+    sorted.sort_by(f32::total_cmp);
+    let low = sorted[sorted.len() / 100];
+    let high = sorted[sorted.len() * 99 / 100];
+    let range = (high - low).max(1e-6);
+
+    // The model predicts inverse depth, so near objects have large values.
+    let depth_data: Vec<u8> = output_tensor
+        .iter()
+        .take(out_width * out_height)
+        .map(|&val| ((1.0 - ((val - low) / range).clamp(0.0, 1.0)) * 255.0).round() as u8)
+        .collect();
+
+    let depth = GrayImage::from_raw(out_width as u32, out_height as u32, depth_data)
+        .ok_or_else(|| anyhow::anyhow!("Failed to create depth map from model output"))?;
+
+    Ok(imageops::resize(&depth, orig_width, orig_height, FilterType::Triangle))
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct AiSubjectMaskParameters {
@@ -279,4 +338,23 @@ pub struct AiForegroundMaskParameters {
     pub flip_horizontal: Option<bool>,
     #[serde(default)]
     pub flip_vertical: Option<bool>,
-}
\ No newline at end of file
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct AiDepthMaskParameters {
+    #[serde(default)]
+    pub depth_data_base64: Option<String>,
+    #[serde(default)]
+    pub near: f32,
+    #[serde(default)]
+    pub far: f32,
+    #[serde(default)]
+    pub feather: f32,
+    #[serde(default)]
+    pub rotation: Option<f32>,
+    #[serde(default)]
+    pub flip_horizontal: Option<bool>,
+    #[serde(default)]
+    pub flip_vertical: Option<bool>,
+}
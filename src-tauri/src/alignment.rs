@@ -0,0 +1,428 @@
+use std::collections::HashMap;
+
+use image::{imageops, GrayImage, Rgb32FImage};
+use imageproc::binary_descriptors::brief::{brief, BriefDescriptor, TestPair};
+use imageproc::binary_descriptors::{match_binary_descriptors, BinaryDescriptor};
+use imageproc::corners::oriented_fast;
+use imageproc::point::Point;
+use rayon::prelude::*;
+
+use crate::image_loader::linear_to_srgb;
+use crate::pyramid::WeightImage;
+
+const DESCRIPTOR_BITS: usize = 256;
+const KEYPOINT_MARGIN: u32 = 24;
+const MATCH_MAX_HAMMING: u32 = 64;
+const RANSAC_ITERATIONS: usize = 2000;
+const FEATURE_IMAGE_SIZE: u32 = 1600;
+const MAX_FEATURES: usize = 2500;
+
+pub type Matrix3 = [f64; 9];
+
+pub const IDENTITY: Matrix3 = [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MotionModel {
+    Affine,
+    Homography,
+}
+
+impl MotionModel {
+    fn min_samples(self) -> usize {
+        match self {
+            MotionModel::Affine => 3,
+            MotionModel::Homography => 4,
+        }
+    }
+}
+
+pub struct FeatureSet {
+    pub descriptors: Vec<BriefDescriptor>,
+    pub scale: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Alignment {
+    pub matrix: Matrix3,
+    pub inliers: usize,
+    pub matches: usize,
+}
+
+pub type Correspondence = ((f64, f64), (f64, f64));
+
+struct XorShift(u64);
+
+impl XorShift {
+    fn next_index(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound as u64) as usize
+    }
+}
+
+pub fn mat_mul(a: &Matrix3, b: &Matrix3) -> Matrix3 {
+    let mut out = [0.0; 9];
+    for r in 0..3 {
+        for c in 0..3 {
+            out[r * 3 + c] = (0..3).map(|k| a[r * 3 + k] * b[k * 3 + c]).sum();
+        }
+    }
+    out
+}
+
+pub fn mat_invert(m: &Matrix3) -> Option<Matrix3> {
+    let det = m[0] * (m[4] * m[8] - m[5] * m[7]) - m[1] * (m[3] * m[8] - m[5] * m[6])
+        + m[2] * (m[3] * m[7] - m[4] * m[6]);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+    Some([
+        (m[4] * m[8] - m[5] * m[7]) * inv_det,
+        (m[2] * m[7] - m[1] * m[8]) * inv_det,
+        (m[1] * m[5] - m[2] * m[4]) * inv_det,
+        (m[5] * m[6] - m[3] * m[8]) * inv_det,
+        (m[0] * m[8] - m[2] * m[6]) * inv_det,
+        (m[2] * m[3] - m[0] * m[5]) * inv_det,
+        (m[3] * m[7] - m[4] * m[6]) * inv_det,
+        (m[1] * m[6] - m[0] * m[7]) * inv_det,
+        (m[0] * m[4] - m[1] * m[3]) * inv_det,
+    ])
+}
+
+pub fn translation_matrix(tx: f64, ty: f64) -> Matrix3 {
+    [1.0, 0.0, tx, 0.0, 1.0, ty, 0.0, 0.0, 1.0]
+}
+
+pub fn transform_point(m: &Matrix3, x: f64, y: f64) -> (f64, f64) {
+    let w = m[6] * x + m[7] * y + m[8];
+    let w = if w.abs() < 1e-12 { 1e-12 } else { w };
+    (
+        (m[0] * x + m[1] * y + m[2]) / w,
+        (m[3] * x + m[4] * y + m[5]) / w,
+    )
+}
+
+pub fn sample_bilinear(image: &Rgb32FImage, x: f64, y: f64) -> Option<[f32; 3]> {
+    let (w, h) = image.dimensions();
+    if x < 0.0 || y < 0.0 || x > (w - 1) as f64 || y > (h - 1) as f64 {
+        return None;
+    }
+    let x0 = x.floor() as u32;
+    let y0 = y.floor() as u32;
+    let x1 = (x0 + 1).min(w - 1);
+    let y1 = (y0 + 1).min(h - 1);
+    let fx = (x - x0 as f64) as f32;
+    let fy = (y - y0 as f64) as f32;
+    let (p00, p10) = (image.get_pixel(x0, y0).0, image.get_pixel(x1, y0).0);
+    let (p01, p11) = (image.get_pixel(x0, y1).0, image.get_pixel(x1, y1).0);
+    let mut out = [0.0; 3];
+    for c in 0..3 {
+        let top = p00[c] + (p10[c] - p00[c]) * fx;
+        let bottom = p01[c] + (p11[c] - p01[c]) * fx;
+        out[c] = top + (bottom - top) * fy;
+    }
+    Some(out)
+}
+
+/// Resamples `image` onto a `width` x `height` grid where `dst_to_src` maps output
+/// pixels back into the source. Returns the warped image and its coverage (0 or 1).
+pub fn warp_image(
+    image: &Rgb32FImage,
+    dst_to_src: &Matrix3,
+    width: u32,
+    height: u32,
+) -> (Rgb32FImage, WeightImage) {
+    let rows: Vec<(Vec<f32>, Vec<f32>)> = (0..height)
+        .into_par_iter()
+        .map(|y| {
+            let mut colors = vec![0.0f32; width as usize * 3];
+            let mut coverage = vec![0.0f32; width as usize];
+            for x in 0..width {
+                let (sx, sy) = transform_point(dst_to_src, x as f64, y as f64);
+                if let Some(px) = sample_bilinear(image, sx, sy) {
+                    let idx = x as usize;
+                    colors[idx * 3..idx * 3 + 3].copy_from_slice(&px);
+                    coverage[idx] = 1.0;
+                }
+            }
+            (colors, coverage)
+        })
+        .collect();
+
+    let (colors, coverage): (Vec<Vec<f32>>, Vec<Vec<f32>>) = rows.into_iter().unzip();
+    (
+        Rgb32FImage::from_raw(width, height, colors.concat()).unwrap(),
+        WeightImage::from_raw(width, height, coverage.concat()).unwrap(),
+    )
+}
+
+pub fn brief_test_pairs() -> Vec<TestPair> {
+    let dummy = GrayImage::new(1, 1);
+    brief(&dummy, &[], DESCRIPTOR_BITS, None)
+        .map(|(_, pairs)| pairs)
+        .unwrap_or_default()
+}
+
+/// Downscales `gray` so its long edge is at most `max_dimension`, returning the
+/// scale that maps full-resolution coordinates onto the result.
+fn downscale_for_features(gray: &GrayImage, max_dimension: u32) -> (GrayImage, f64) {
+    let (width, height) = gray.dimensions();
+    let long_edge = width.max(height);
+    if long_edge <= max_dimension {
+        return (gray.clone(), 1.0);
+    }
+    let scale = max_dimension as f64 / long_edge as f64;
+    let new_w = ((width as f64 * scale).round() as u32).max(1);
+    let new_h = ((height as f64 * scale).round() as u32).max(1);
+    (imageops::resize(gray, new_w, new_h, imageops::FilterType::Triangle), scale)
+}
+
+/// Detects oriented FAST corners and describes them with BRIEF. Keypoints whose
+/// neighbourhood touches a zero pixel of `valid` are dropped, which keeps the
+/// empty borders of warped frames from producing features.
+fn detect_features(
+    gray: &GrayImage,
+    valid: Option<&GrayImage>,
+    scale: f64,
+    max_features: usize,
+    test_pairs: &[TestPair],
+) -> FeatureSet {
+    let (width, height) = gray.dimensions();
+    if width <= KEYPOINT_MARGIN * 2 + 1 || height <= KEYPOINT_MARGIN * 2 + 1 {
+        return FeatureSet { descriptors: Vec::new(), scale };
+    }
+
+    let corners = oriented_fast(gray, None, max_features, KEYPOINT_MARGIN, Some(0x5eed));
+
+    let is_valid = |x: u32, y: u32| -> bool {
+        let Some(mask) = valid else { return true };
+        let r = KEYPOINT_MARGIN / 2;
+        [(x - r, y - r), (x + r, y - r), (x - r, y + r), (x + r, y + r), (x, y)]
+            .iter()
+            .all(|&(px, py)| mask.get_pixel(px.min(mask.width() - 1), py.min(mask.height() - 1)).0[0] > 0)
+    };
+
+    let keypoints: Vec<Point<u32>> = corners
+        .iter()
+        .map(|c| c.corner)
+        .filter(|c| {
+            c.x > KEYPOINT_MARGIN
+                && c.y > KEYPOINT_MARGIN
+                && c.x + KEYPOINT_MARGIN < width
+                && c.y + KEYPOINT_MARGIN < height
+                && is_valid(c.x, c.y)
+        })
+        .map(|c| Point::new(c.x, c.y))
+        .collect();
+
+    let descriptors = brief(gray, &keypoints, DESCRIPTOR_BITS, Some(&test_pairs.to_vec()))
+        .map(|(d, _)| d)
+        .unwrap_or_default();
+
+    FeatureSet { descriptors, scale }
+}
+
+/// Features of a linear RGB frame, detected on its sRGB-encoded luma at a
+/// reduced size. Pixels that are zero in `coverage` produce no features.
+pub fn frame_features(image: &Rgb32FImage, coverage: Option<&GrayImage>, test_pairs: &[TestPair]) -> FeatureSet {
+    let (w, h) = image.dimensions();
+    let mut gray = GrayImage::new(w, h);
+    for (g, p) in gray.pixels_mut().zip(image.pixels()) {
+        let luma = 0.2126 * p.0[0] + 0.7152 * p.0[1] + 0.0722 * p.0[2];
+        g.0[0] = (linear_to_srgb(luma).clamp(0.0, 1.0) * 255.0).round() as u8;
+    }
+    let (small, scale) = downscale_for_features(&gray, FEATURE_IMAGE_SIZE);
+    let valid = coverage.map(|c| imageops::resize(c, small.width(), small.height(), imageops::FilterType::Nearest));
+    detect_features(&small, valid.as_ref(), scale, MAX_FEATURES, test_pairs)
+}
+
+/// Mutually consistent descriptor matches, returned in full-resolution pixel
+/// coordinates of the two source images.
+pub fn match_features(a: &FeatureSet, b: &FeatureSet) -> Vec<Correspondence> {
+    let forward = match_binary_descriptors(&a.descriptors, &b.descriptors, MATCH_MAX_HAMMING, Some(1));
+    let backward = match_binary_descriptors(&b.descriptors, &a.descriptors, MATCH_MAX_HAMMING, Some(2));
+
+    let reverse: HashMap<(u32, u32), (u32, u32)> = backward
+        .iter()
+        .map(|(db, da)| {
+            let (pb, pa) = (db.position(), da.position());
+            ((pb.x, pb.y), (pa.x, pa.y))
+        })
+        .collect();
+
+    forward
+        .iter()
+        .filter_map(|(da, db)| {
+            let (pa, pb) = (da.position(), db.position());
+            if reverse.get(&(pb.x, pb.y)) != Some(&(pa.x, pa.y)) {
+                return None;
+            }
+            Some((
+                (pa.x as f64 / a.scale, pa.y as f64 / a.scale),
+                (pb.x as f64 / b.scale, pb.y as f64 / b.scale),
+            ))
+        })
+        .collect()
+}
+
+fn normalization(points: &[(f64, f64)]) -> Matrix3 {
+    let n = points.len() as f64;
+    let (cx, cy) = points.iter().fold((0.0, 0.0), |acc, p| (acc.0 + p.0, acc.1 + p.1));
+    let (cx, cy) = (cx / n, cy / n);
+    let mean_dist = points
+        .iter()
+        .map(|p| ((p.0 - cx).powi(2) + (p.1 - cy).powi(2)).sqrt())
+        .sum::<f64>()
+        / n;
+    let s = if mean_dist > 1e-9 { std::f64::consts::SQRT_2 / mean_dist } else { 1.0 };
+    [s, 0.0, -s * cx, 0.0, s, -s * cy, 0.0, 0.0, 1.0]
+}
+
+fn solve_linear_system(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Option<Vec<f64>> {
+    let n = b.len();
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        let pivot_row = a[col].clone();
+        for row in (col + 1)..n {
+            let factor = a[row][col] / pivot_row[col];
+            for (v, p) in a[row][col..].iter_mut().zip(&pivot_row[col..]) {
+                *v -= factor * p;
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let sum: f64 = ((row + 1)..n).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+    Some(x)
+}
+
+/// Least-squares fit of `model` mapping the first point of each pair onto the
+/// second, using Hartley normalisation for numerical stability.
+pub fn fit_model(model: MotionModel, pairs: &[Correspondence]) -> Option<Matrix3> {
+    if pairs.len() < model.min_samples() {
+        return None;
+    }
+    let src: Vec<(f64, f64)> = pairs.iter().map(|p| p.0).collect();
+    let dst: Vec<(f64, f64)> = pairs.iter().map(|p| p.1).collect();
+    let t_src = normalization(&src);
+    let t_dst = normalization(&dst);
+
+    let params = match model {
+        MotionModel::Affine => 6,
+        MotionModel::Homography => 8,
+    };
+    let mut ata = vec![vec![0.0; params]; params];
+    let mut atb = vec![0.0; params];
+
+    for (s, d) in src.iter().zip(dst.iter()) {
+        let (x, y) = transform_point(&t_src, s.0, s.1);
+        let (u, v) = transform_point(&t_dst, d.0, d.1);
+        let rows: [(Vec<f64>, f64); 2] = match model {
+            MotionModel::Affine => [
+                (vec![x, y, 1.0, 0.0, 0.0, 0.0], u),
+                (vec![0.0, 0.0, 0.0, x, y, 1.0], v),
+            ],
+            MotionModel::Homography => [
+                (vec![x, y, 1.0, 0.0, 0.0, 0.0, -u * x, -u * y], u),
+                (vec![0.0, 0.0, 0.0, x, y, 1.0, -v * x, -v * y], v),
+            ],
+        };
+        for (row, rhs) in rows.iter() {
+            for i in 0..params {
+                atb[i] += row[i] * rhs;
+                for j in 0..params {
+                    ata[i][j] += row[i] * row[j];
+                }
+            }
+        }
+    }
+
+    let h = solve_linear_system(ata, atb)?;
+    let normalized = match model {
+        MotionModel::Affine => [h[0], h[1], h[2], h[3], h[4], h[5], 0.0, 0.0, 1.0],
+        MotionModel::Homography => [h[0], h[1], h[2], h[3], h[4], h[5], h[6], h[7], 1.0],
+    };
+    let result = mat_mul(&mat_invert(&t_dst)?, &mat_mul(&normalized, &t_src));
+    if result[8].abs() < 1e-12 {
+        return None;
+    }
+    let norm = result[8];
+    Some(result.map(|v| v / norm))
+}
+
+/// RANSAC estimate of `model` over the correspondences, refined on the final
+/// inlier set. `threshold` is the reprojection error in pixels.
+pub fn estimate_transform(
+    pairs: &[Correspondence],
+    model: MotionModel,
+    threshold: f64,
+) -> Option<Alignment> {
+    let sample_size = model.min_samples();
+    if pairs.len() < sample_size * 2 {
+        return None;
+    }
+
+    let threshold_sq = threshold * threshold;
+    let inliers_of = |m: &Matrix3| -> Vec<usize> {
+        pairs
+            .iter()
+            .enumerate()
+            .filter(|(_, (s, d))| {
+                let (x, y) = transform_point(m, s.0, s.1);
+                (x - d.0).powi(2) + (y - d.1).powi(2) < threshold_sq
+            })
+            .map(|(i, _)| i)
+            .collect()
+    };
+
+    let mut rng = XorShift(0x9e3779b97f4a7c15);
+    let mut best: Vec<usize> = Vec::new();
+    for _ in 0..RANSAC_ITERATIONS {
+        let mut sample: Vec<usize> = Vec::with_capacity(sample_size);
+        while sample.len() < sample_size {
+            let idx = rng.next_index(pairs.len());
+            if !sample.contains(&idx) {
+                sample.push(idx);
+            }
+        }
+        let subset: Vec<Correspondence> = sample.iter().map(|&i| pairs[i]).collect();
+        let Some(candidate) = fit_model(model, &subset) else { continue };
+        let inliers = inliers_of(&candidate);
+        if inliers.len() > best.len() {
+            best = inliers;
+            if best.len() == pairs.len() {
+                break;
+            }
+        }
+    }
+
+    if best.len() < sample_size * 2 {
+        return None;
+    }
+
+    let mut matrix = fit_model(model, &best.iter().map(|&i| pairs[i]).collect::<Vec<_>>())?;
+    let refined = inliers_of(&matrix);
+    if refined.len() >= best.len() {
+        if let Some(m) = fit_model(model, &refined.iter().map(|&i| pairs[i]).collect::<Vec<_>>()) {
+            matrix = m;
+            best = refined;
+        }
+    }
+
+    Some(Alignment { matrix, inliers: best.len(), matches: pairs.len() })
+}
+
+/// Brown & Lowe's probabilistic check that a pairwise match is not a false positive.
+pub fn is_reliable_alignment(alignment: &Alignment) -> bool {
+    alignment.inliers >= 16 && alignment.inliers as f64 > 8.0 + 0.3 * alignment.matches as f64
+}
@@ -0,0 +1,533 @@
+use std::sync::Arc;
+
+use image::{DynamicImage, GrayImage, RgbImage};
+use ort::Session;
+use serde::Serialize;
+use serde_json::json;
+use tauri::Manager;
+
+use crate::ai_processing::{load_foreground_model, run_u2netp_model};
+use crate::formats::is_raw_file;
+use crate::AppState;
+
+/// Long edge of the thumbnail the scene is measured on.
+const ANALYSIS_SIZE: u32 = 1024;
+/// Linear luma the key of the scene is exposed to, middle gray.
+const TARGET_KEY: f64 = 0.18;
+/// Linear luma the brightest unclipped content should end up below.
+const HIGHLIGHT_CEILING: f64 = 0.95;
+/// Stops the highlights slider pulls back at -100, so exposure may push the
+/// highlights this far past the ceiling.
+const HIGHLIGHT_RECOVERY_EV: f64 = 1.5;
+/// Stops the shadows slider lifts at 100.
+const SHADOW_LIFT_EV: f64 = 0.75;
+/// Darkening never takes the brightest content below this, so high key
+/// scenes such as snow stay bright.
+const HIGH_KEY_FLOOR: f64 = 0.8;
+/// Deep shadows are lifted towards this linear luma.
+const SHADOW_TARGET: f64 = 0.02;
+/// Extra weight of subject pixels over background pixels for the key.
+const SUBJECT_WEIGHT: f32 = 4.0;
+/// Channels at or above this are treated as clipped.
+const CLIP_LEVEL: f32 = 0.98;
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AutoAdjustmentResults {
+    pub exposure: f64,
+    pub contrast: f64,
+    pub highlights: f64,
+    pub shadows: f64,
+    pub vibrancy: f64,
+    pub vignette_amount: f64,
+    pub temperature: f64,
+    pub tint: f64,
+    pub dehaze: f64,
+    /// How much the analysis trusts its result, 0 to 1.
+    pub confidence: f64,
+    pub reasoning: AutoAnalysisReasoning,
+}
+
+/// The measurements behind the results. Luma values are linear.
+#[derive(Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct AutoAnalysisReasoning {
+    /// Share of the frame the foreground model found to be the subject, when
+    /// it was used to weight the exposure.
+    pub subject_coverage: Option<f64>,
+    /// Weighted median luma that exposure is set from.
+    pub scene_key: f64,
+    pub black_point: f64,
+    pub white_point: f64,
+    pub clipped_fraction: f64,
+    /// Stops between the 5th and 95th percentiles.
+    pub dynamic_range_ev: f64,
+    /// Exposure the key alone asked for, before protecting the highlights.
+    pub key_exposure: f64,
+    /// Whether bringing the key to middle gray would have blown more
+    /// highlights than the highlights slider can recover.
+    pub highlight_limited: bool,
+    pub deep_shadow_fraction: f64,
+    /// Illuminant estimates as RGB relative to green.
+    pub gray_world: [f64; 3],
+    pub white_patch: Option<[f64; 3]>,
+    pub illuminant: [f64; 3],
+    /// Share of the usable pixels close enough to neutral to estimate the
+    /// white balance from.
+    pub neutral_fraction: f64,
+    pub exposure_confidence: f64,
+    pub white_balance_confidence: f64,
+}
+
+/// Linear pixels of the analysis thumbnail, with their lumas sorted for
+/// percentile lookups.
+struct Scene {
+    pixels: Vec<[f32; 3]>,
+    sorted_luma: Vec<f32>,
+    /// Running sum of the subject weights over `sorted_luma`.
+    cumulative_weight: Vec<f64>,
+    subject_coverage: Option<f64>,
+}
+
+fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(v: f64) -> f64 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn luma(rgb: [f32; 3]) -> f32 {
+    0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2]
+}
+
+impl Scene {
+    fn measure(preview: &DynamicImage, subject: Option<&GrayImage>) -> Self {
+        let rgb = preview.to_rgb32f();
+        let pixels: Vec<[f32; 3]> = rgb.pixels().map(|p| p.0.map(|c| srgb_to_linear(c.clamp(0.0, 1.0)))).collect();
+
+        // A mask that covers almost nothing or almost everything says nothing
+        // about where the subject is.
+        let subject = subject
+            .filter(|mask| mask.dimensions() == rgb.dimensions())
+            .map(|mask| {
+                let coverage = mask.pixels().map(|p| p[0] as f64).sum::<f64>() / (255.0 * pixels.len().max(1) as f64);
+                (mask, coverage)
+            })
+            .filter(|&(_, coverage)| (0.01..=0.95).contains(&coverage));
+
+        let mut weighted: Vec<(f32, f32)> = pixels
+            .iter()
+            .enumerate()
+            .map(|(i, &p)| {
+                let weight = subject.map_or(1.0, |(mask, _)| 1.0 + SUBJECT_WEIGHT * mask.as_raw()[i] as f32 / 255.0);
+                (luma(p), weight)
+            })
+            .collect();
+        weighted.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut total = 0.0;
+        let cumulative_weight = weighted
+            .iter()
+            .map(|&(_, weight)| {
+                total += weight as f64;
+                total
+            })
+            .collect();
+
+        Self {
+            pixels,
+            sorted_luma: weighted.into_iter().map(|(l, _)| l).collect(),
+            cumulative_weight,
+            subject_coverage: subject.map(|(_, coverage)| coverage),
+        }
+    }
+
+    fn total_weight(&self) -> f64 {
+        self.cumulative_weight.last().copied().unwrap_or(0.0)
+    }
+
+    /// Luma below which `fraction` of the pixels fall, counting subject
+    /// pixels more when `weighted`.
+    fn percentile(&self, fraction: f64, weighted: bool) -> f64 {
+        if self.sorted_luma.is_empty() {
+            return 0.0;
+        }
+        let index = if weighted {
+            let target = self.total_weight() * fraction;
+            self.cumulative_weight.partition_point(|&w| w < target)
+        } else {
+            (fraction * (self.sorted_luma.len() - 1) as f64).round() as usize
+        };
+        self.sorted_luma[index.min(self.sorted_luma.len() - 1)] as f64
+    }
+
+    /// Share of the pixels darker than `level`.
+    fn fraction_below(&self, level: f64, weighted: bool) -> f64 {
+        let count = self.sorted_luma.partition_point(|&l| (l as f64) < level);
+        if count == 0 {
+            return 0.0;
+        }
+        if weighted {
+            self.cumulative_weight[count - 1] / self.total_weight()
+        } else {
+            count as f64 / self.sorted_luma.len() as f64
+        }
+    }
+}
+
+struct Tone {
+    exposure: f64,
+    contrast: f64,
+    highlights: f64,
+    shadows: f64,
+    confidence: f64,
+}
+
+fn estimate_tone(scene: &Scene, reasoning: &mut AutoAnalysisReasoning) -> Tone {
+    let key = scene.percentile(0.5, true).max(1e-4);
+    let black = scene.percentile(0.005, false);
+    let white = scene.percentile(0.995, false).max(1e-4);
+    let clipped_fraction =
+        scene.pixels.iter().filter(|p| p.iter().any(|&c| c >= CLIP_LEVEL)).count() as f64 / scene.pixels.len().max(1) as f64;
+
+    let key_exposure = (TARGET_KEY / key).log2();
+    let headroom = (HIGHLIGHT_CEILING / white).log2();
+    let highlight_limited = key_exposure > headroom + HIGHLIGHT_RECOVERY_EV;
+    let mut exposure = key_exposure.min(headroom + HIGHLIGHT_RECOVERY_EV);
+    if exposure < 0.0 {
+        exposure = exposure.max((HIGH_KEY_FLOOR / white).log2().min(0.0));
+    }
+    let exposure = exposure.clamp(-5.0, 5.0);
+    let gain = exposure.exp2();
+
+    // Pull back whatever exposure pushed past the ceiling, and ease large
+    // bright areas a little even when nothing clips.
+    let overshoot = (exposure - headroom).max(0.0);
+    let bright_fraction = 1.0 - scene.fraction_below(0.75 / gain, false);
+    let highlights = (-(overshoot / HIGHLIGHT_RECOVERY_EV * 100.0)).min(-(bright_fraction * 100.0).min(40.0));
+
+    let shadow = scene.percentile(0.05, true) * gain;
+    let deep_shadow_fraction = scene.fraction_below(SHADOW_TARGET / 2.0 / gain, true);
+    let shadows = if deep_shadow_fraction > 0.05 && shadow < SHADOW_TARGET {
+        let lift = (SHADOW_TARGET / shadow.max(1e-4)).log2().min(SHADOW_LIFT_EV);
+        lift / SHADOW_LIFT_EV * 100.0 * (deep_shadow_fraction * 5.0).min(1.0)
+    } else {
+        0.0
+    };
+
+    // Flat scenes get contrast, unless the recovery above shows they are not
+    // flat at all.
+    let dynamic_range_ev = (scene.percentile(0.95, false).max(1e-4) / scene.percentile(0.05, false).max(1e-4)).log2();
+    let recovery = (highlights.abs() + shadows) / 200.0;
+    let contrast = ((5.0 - dynamic_range_ev).max(0.0) * 12.0 * (1.0 - recovery)).max(0.0);
+
+    let mut confidence = 1.0 - (clipped_fraction * 4.0).min(0.8);
+    if highlight_limited {
+        confidence *= 0.6;
+    }
+    if key < 0.002 {
+        confidence *= 0.5;
+    }
+
+    reasoning.scene_key = key;
+    reasoning.black_point = black;
+    reasoning.white_point = white;
+    reasoning.clipped_fraction = clipped_fraction;
+    reasoning.dynamic_range_ev = dynamic_range_ev;
+    reasoning.key_exposure = key_exposure;
+    reasoning.highlight_limited = highlight_limited;
+    reasoning.deep_shadow_fraction = deep_shadow_fraction;
+    reasoning.exposure_confidence = confidence;
+
+    Tone {
+        exposure,
+        contrast: contrast.clamp(0.0, 100.0),
+        highlights: highlights.clamp(-100.0, 0.0),
+        shadows: shadows.clamp(0.0, 100.0),
+        confidence,
+    }
+}
+
+fn median(values: &mut [f32]) -> f32 {
+    let mid = values.len() / 2;
+    *values.select_nth_unstable_by(mid, |a, b| a.total_cmp(b)).1
+}
+
+fn normalized_to_green(sum: [f64; 3]) -> [f64; 3] {
+    let g = sum[1].max(1e-9);
+    [sum[0] / g, 1.0, sum[2] / g]
+}
+
+struct WhiteBalance {
+    temperature: f64,
+    tint: f64,
+    confidence: f64,
+}
+
+/// Estimates the illuminant from near-neutral pixels, with gray world over
+/// the pixels around the median chromaticity and white patch over the
+/// brightest of them, and corrects by as much as the two agree.
+fn estimate_white_balance(scene: &Scene, reasoning: &mut AutoAnalysisReasoning) -> WhiteBalance {
+    let usable: Vec<([f32; 3], f32, f32)> = scene
+        .pixels
+        .iter()
+        .filter(|p| p.iter().all(|&c| c > 1e-4 && c < CLIP_LEVEL) && (0.01..0.9).contains(&luma(**p)))
+        .map(|&p| (p, (p[0] / p[1]).ln(), (p[2] / p[1]).ln()))
+        .collect();
+    if usable.len() < 100 {
+        reasoning.gray_world = [1.0; 3];
+        reasoning.illuminant = [1.0; 3];
+        return WhiteBalance { temperature: 0.0, tint: 0.0, confidence: 0.0 };
+    }
+
+    let mut rg: Vec<f32> = usable.iter().map(|u| u.1).collect();
+    let mut bg: Vec<f32> = usable.iter().map(|u| u.2).collect();
+    let median_rg = median(&mut rg);
+    let median_bg = median(&mut bg);
+    let spread = |values: &mut Vec<f32>, center: f32| {
+        values.iter_mut().for_each(|v| *v = (*v - center).abs());
+        (median(values) * 3.0 * 1.4826).clamp(0.05, 0.5)
+    };
+    let tolerance_rg = spread(&mut rg, median_rg);
+    let tolerance_bg = spread(&mut bg, median_bg);
+
+    let mut neutrals: Vec<&([f32; 3], f32, f32)> = usable
+        .iter()
+        .filter(|(_, r, b)| {
+            (r - median_rg).abs() <= tolerance_rg && (b - median_bg).abs() <= tolerance_bg && r.abs() < 1.0 && b.abs() < 1.0
+        })
+        .collect();
+    let neutral_fraction = neutrals.len() as f64 / usable.len() as f64;
+
+    let sum_rgb = |pixels: &[&([f32; 3], f32, f32)]| {
+        pixels.iter().fold([0.0f64; 3], |acc, (p, _, _)| [acc[0] + p[0] as f64, acc[1] + p[1] as f64, acc[2] + p[2] as f64])
+    };
+    let gray_world = normalized_to_green(sum_rgb(&neutrals));
+
+    neutrals.sort_unstable_by(|a, b| luma(b.0).total_cmp(&luma(a.0)));
+    let brightest = neutrals.len() / 50;
+    let white_patch = (brightest >= 20).then(|| normalized_to_green(sum_rgb(&neutrals[..brightest])));
+
+    let (illuminant, agreement) = match white_patch {
+        Some(wp) => {
+            let disagreement = (gray_world[0] / wp[0]).ln().hypot((gray_world[2] / wp[2]).ln());
+            let mean = |i: usize| ((gray_world[i].ln() + wp[i].ln()) / 2.0).exp();
+            ([mean(0), 1.0, mean(2)], 1.0 / (1.0 + (disagreement / 0.1).powi(2)))
+        }
+        None => (gray_world, 0.5),
+    };
+    let confidence = (neutral_fraction / 0.5).min(1.0) * agreement;
+
+    // Only part of the cast is removed when the estimate is uncertain, as a
+    // strongly colored scene is more likely than a strongly colored light.
+    let r_cast = illuminant[0].ln() * confidence;
+    let b_cast = illuminant[2].ln() * confidence;
+
+    // Inverts the shader's white balance: temperature scales red by 1 + 0.2t
+    // and blue by 1 - 0.2t, tint scales green by 1 + 0.25n and red and blue
+    // by 1 - 0.25n.
+    let rb_ratio = (b_cast - r_cast).exp();
+    let t = ((rb_ratio - 1.0) / (0.2 * (rb_ratio + 1.0))).clamp(-4.0, 4.0);
+    let green_ratio = ((r_cast + b_cast) / 2.0).exp() * (1.0 - 0.04 * t * t).max(0.01).sqrt() / (1.0 + 0.05 * t);
+    let n = (green_ratio - 1.0) / (0.25 * (green_ratio + 1.0));
+
+    reasoning.gray_world = gray_world;
+    reasoning.white_patch = white_patch;
+    reasoning.illuminant = illuminant;
+    reasoning.neutral_fraction = neutral_fraction;
+    reasoning.white_balance_confidence = confidence;
+
+    WhiteBalance {
+        temperature: (t * 25.0).clamp(-100.0, 100.0),
+        tint: (n * 100.0).clamp(-100.0, 100.0),
+        confidence,
+    }
+}
+
+struct Look {
+    vibrancy: f64,
+    dehaze: f64,
+    vignette_amount: f64,
+}
+
+fn estimate_look(rgb_image: &RgbImage, tonal_range: f64) -> Look {
+    let total_pixels = (rgb_image.width() * rgb_image.height()).max(1) as f64;
+    let mut mean_saturation = 0.0f32;
+    let mut dull_pixel_count = 0;
+    for pixel in rgb_image.pixels() {
+        let max_c = pixel[0].max(pixel[1]).max(pixel[2]) as f32 / 255.0;
+        let min_c = pixel[0].min(pixel[1]).min(pixel[2]) as f32 / 255.0;
+        if max_c > 0.0 {
+            let s = (max_c - min_c) / max_c;
+            mean_saturation += s;
+            if s < 0.1 {
+                dull_pixel_count += 1;
+            }
+        }
+    }
+    mean_saturation /= total_pixels as f32;
+    let dull_pixel_percent = dull_pixel_count as f64 / total_pixels;
+
+    let mut vibrancy = 0.0;
+    let saturation_target = 0.20;
+    if mean_saturation < saturation_target {
+        vibrancy = (saturation_target - mean_saturation) as f64 * 150.0;
+    }
+    if dull_pixel_percent > 0.5 {
+        vibrancy += 10.0;
+    }
+
+    let mut dehaze = 0.0;
+    if tonal_range < 128.0 && mean_saturation < 0.15 {
+        dehaze = (1.0 - (tonal_range / 128.0)) * 40.0;
+    }
+
+    let (width, height) = rgb_image.dimensions();
+    let center_x = (width / 4)..(width * 3 / 4);
+    let center_y = (height / 4)..(height * 3 / 4);
+    let (mut center_sum, mut center_count, mut edge_sum, mut edge_count) = (0.0f32, 0, 0.0f32, 0);
+    for (x, y, pixel) in rgb_image.enumerate_pixels() {
+        let luma = (0.2126 * pixel[0] as f32 + 0.7152 * pixel[1] as f32 + 0.0722 * pixel[2] as f32) / 255.0;
+        if center_x.contains(&x) && center_y.contains(&y) {
+            center_sum += luma;
+            center_count += 1;
+        } else {
+            edge_sum += luma;
+            edge_count += 1;
+        }
+    }
+    let mut vignette_amount = 0.0;
+    if center_count > 0 && edge_count > 0 {
+        let luma_diff = center_sum / center_count as f32 - edge_sum / edge_count as f32;
+        if luma_diff > 0.0 {
+            vignette_amount = -(luma_diff as f64 * 150.0);
+        }
+    }
+
+    Look {
+        vibrancy: vibrancy.clamp(0.0, 80.0),
+        dehaze: dehaze.clamp(0.0, 100.0),
+        vignette_amount: vignette_amount.clamp(-100.0, 0.0),
+    }
+}
+
+fn subject_mask(preview: &DynamicImage, foreground_model: Option<&Session>) -> Option<GrayImage> {
+    let model = foreground_model?;
+    run_u2netp_model(preview, model)
+        .map_err(|e| eprintln!("Failed to find the subject for auto adjustments: {}", e))
+        .ok()
+}
+
+/// Derives the basic sliders from the scene. Exposure is set from the
+/// subject when the foreground model is available, then held back so the
+/// highlights stay recoverable.
+pub fn perform_auto_analysis(image: &DynamicImage, foreground_model: Option<&Session>) -> AutoAdjustmentResults {
+    let preview = image.thumbnail(ANALYSIS_SIZE, ANALYSIS_SIZE);
+    let subject = subject_mask(&preview, foreground_model);
+    let scene = Scene::measure(&preview, subject.as_ref());
+
+    let mut reasoning = AutoAnalysisReasoning { subject_coverage: scene.subject_coverage, ..Default::default() };
+    let tone = estimate_tone(&scene, &mut reasoning);
+    let white_balance = estimate_white_balance(&scene, &mut reasoning);
+
+    let tonal_range =
+        (linear_to_srgb(scene.percentile(0.999, false)) - linear_to_srgb(scene.percentile(0.001, false))) * 255.0;
+    let look = estimate_look(&preview.to_rgb8(), tonal_range);
+
+    AutoAdjustmentResults {
+        exposure: tone.exposure,
+        contrast: tone.contrast,
+        highlights: tone.highlights,
+        shadows: tone.shadows,
+        vibrancy: look.vibrancy,
+        vignette_amount: look.vignette_amount,
+        temperature: white_balance.temperature,
+        tint: white_balance.tint,
+        dehaze: look.dehaze,
+        confidence: tone.confidence * 0.6 + white_balance.confidence * 0.4,
+        reasoning,
+    }
+}
+
+/// The subject-weighted median luma of an image, linear. Used to expose a
+/// set of images alike.
+pub fn measure_scene_key(image: &DynamicImage, foreground_model: Option<&Session>) -> f64 {
+    let preview = image.thumbnail(ANALYSIS_SIZE, ANALYSIS_SIZE);
+    let subject = subject_mask(&preview, foreground_model);
+    Scene::measure(&preview, subject.as_ref()).percentile(0.5, true).max(1e-4)
+}
+
+/// Raw files take their white balance from the raw space estimate instead
+/// of the relative correction, which then stays neutral.
+pub fn auto_results_to_json(results: &AutoAdjustmentResults, is_raw: bool) -> serde_json::Value {
+    let mut adjustments = json!({
+        "exposure": results.exposure,
+        "contrast": results.contrast,
+        "highlights": results.highlights,
+        "shadows": results.shadows,
+        "vibrance": results.vibrancy,
+        "vignetteAmount": results.vignette_amount,
+        "temperature": results.temperature,
+        "tint": results.tint,
+        "dehaze": results.dehaze,
+        "sectionVisibility": {
+            "basic": true,
+            "color": true,
+            "effects": true
+        }
+    });
+    if is_raw {
+        adjustments["temperature"] = json!(0.0);
+        adjustments["tint"] = json!(0.0);
+        adjustments["rawWhiteBalance"] = json!("auto");
+    }
+    adjustments
+}
+
+/// The foreground model for subject weighting: the one loaded for AI masks
+/// if there is one, otherwise the model file on its own if it was
+/// downloaded. Auto adjustments never start a download.
+pub fn foreground_model(app_handle: &tauri::AppHandle) -> Option<Arc<Session>> {
+    let state = app_handle.state::<AppState>();
+    if let Some(ai_state) = state.ai_state.lock().unwrap().as_ref() {
+        return Some(ai_state.models.u2netp.clone());
+    }
+
+    let mut cached = state.foreground_model.lock().unwrap();
+    if cached.is_none() {
+        match load_foreground_model(app_handle) {
+            Ok(model) => *cached = model.map(Arc::new),
+            Err(e) => eprintln!("Failed to load foreground model: {}", e),
+        }
+    }
+    cached.clone()
+}
+
+#[tauri::command]
+pub async fn calculate_auto_adjustments(
+    state: tauri::State<'_, AppState>,
+    app_handle: tauri::AppHandle,
+) -> Result<serde_json::Value, String> {
+    let (original_image, is_raw) = state.original_image.lock().unwrap()
+        .as_ref()
+        .map(|loaded| (loaded.image.clone(), is_raw_file(&loaded.path)))
+        .ok_or("No image loaded for auto adjustments")?;
+
+    let model = foreground_model(&app_handle);
+    let results = perform_auto_analysis(&original_image, model.as_deref());
+
+    Ok(json!({
+        "adjustments": auto_results_to_json(&results, is_raw),
+        "confidence": results.confidence,
+        "reasoning": results.reasoning,
+    }))
+}
@@ -0,0 +1,87 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rawler::imgop::dcp::DcpProfile;
+use serde::Serialize;
+use tauri::{AppHandle, Manager};
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CameraProfileInfo {
+    pub name: String,
+    pub path: String,
+    pub camera_model: Option<String>,
+    pub dual_illuminant: bool,
+    pub has_look: bool,
+    /// The profile tone maps on its own, so the tone mapper is turned off.
+    pub has_tone_curve: bool,
+}
+
+impl CameraProfileInfo {
+    pub fn from_profile(profile: &DcpProfile, path: &Path) -> Self {
+        Self {
+            name: profile.name.clone().unwrap_or_else(|| {
+                path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default()
+            }),
+            path: path.to_string_lossy().into_owned(),
+            camera_model: profile.camera_model.clone(),
+            dual_illuminant: profile.color_matrix2.is_some(),
+            has_look: profile.has_rendering(),
+            has_tone_curve: profile.tone_curve.is_some(),
+        }
+    }
+}
+
+pub fn get_profiles_dir(app_handle: &AppHandle) -> Result<PathBuf, String> {
+    let profiles_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| e.to_string())?
+        .join("profiles");
+
+    if !profiles_dir.exists() {
+        fs::create_dir_all(&profiles_dir).map_err(|e| e.to_string())?;
+    }
+
+    Ok(profiles_dir)
+}
+
+#[tauri::command]
+pub fn list_camera_profiles(app_handle: AppHandle) -> Result<Vec<CameraProfileInfo>, String> {
+    let profiles_dir = get_profiles_dir(&app_handle)?;
+    let mut profiles: Vec<CameraProfileInfo> = fs::read_dir(&profiles_dir)
+        .map_err(|e| e.to_string())?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("dcp"))
+        })
+        .filter_map(|path| match DcpProfile::from_file(&path) {
+            Ok(profile) => Some(CameraProfileInfo::from_profile(&profile, &path)),
+            Err(e) => {
+                eprintln!("Skipping unreadable camera profile {}: {}", path.display(), e);
+                None
+            }
+        })
+        .collect();
+
+    profiles.sort_by(|a, b| a.camera_model.cmp(&b.camera_model).then_with(|| a.name.cmp(&b.name)));
+    Ok(profiles)
+}
+
+#[tauri::command]
+pub fn import_camera_profile(
+    file_path: String,
+    app_handle: AppHandle,
+) -> Result<CameraProfileInfo, String> {
+    let profile = DcpProfile::from_file(&file_path)
+        .map_err(|e| format!("Not a valid DCP profile: {}", e))?;
+
+    let source = Path::new(&file_path);
+    let file_name = source.file_name().ok_or("Invalid profile path")?;
+    let destination = get_profiles_dir(&app_handle)?.join(file_name);
+    fs::copy(source, &destination).map_err(|e| format!("Failed to import profile: {}", e))?;
+
+    Ok(CameraProfileInfo::from_profile(&profile, &destination))
+}
@@ -0,0 +1,786 @@
+use std::collections::HashMap;
+use std::fs;
+
+use anyhow::{anyhow, bail, Context, Result};
+use image::{imageops, GrayImage, Luma, Rgb32FImage};
+use imageproc::region_labelling::{connected_components, Connectivity};
+use rawler::imgop::dcp::{
+    bradford_adaptation, illuminant_temperature, rgb_to_hsv, temperature_to_xy, DcpProfile, HueSatMap,
+};
+use rawler::imgop::xyz::{
+    Illuminant, CIE_1931_WHITE_POINT_A, CIE_1931_WHITE_POINT_B, CIE_1931_WHITE_POINT_C, CIE_1931_WHITE_POINT_D50,
+    CIE_1931_WHITE_POINT_D55, CIE_1931_WHITE_POINT_D65, CIE_1931_WHITE_POINT_D75, XYZ_TO_PROFOTORGB_D50,
+};
+use serde::{Deserialize, Serialize};
+use tauri::AppHandle;
+
+use crate::alignment::{fit_model, mat_invert, mat_mul, transform_point, sample_bilinear, Matrix3, MotionModel};
+use crate::camera_profiles::{get_profiles_dir, CameraProfileInfo};
+use crate::raw_processing::develop_camera_rgb;
+
+const DETECTION_MAX_DIMENSION: u32 = 900;
+const FLAT_GRADIENT_THRESHOLD: f32 = 0.06;
+const CLIP_LEVEL: f32 = 0.98;
+const HSM_HUE_DIVISIONS: usize = 36;
+const HSM_SAT_DIVISIONS: usize = 8;
+
+const D50_XYZ: [f64; 3] = [0.9642, 1.0, 0.8249];
+
+/// X-Rite ColorChecker Classic reference values (D50 Lab, post-2014 formulation).
+const COLORCHECKER_24_LAB: [[f64; 3]; 24] = [
+    [37.54, 14.37, 14.92],
+    [64.66, 19.27, 17.50],
+    [49.32, -3.82, -22.54],
+    [43.46, -12.74, 22.72],
+    [54.94, 9.61, -24.79],
+    [70.48, -32.26, -0.37],
+    [62.73, 35.83, 56.50],
+    [39.43, 10.75, -45.17],
+    [50.57, 48.64, 16.67],
+    [30.10, 22.54, -20.87],
+    [71.77, -24.13, 58.19],
+    [71.51, 18.24, 67.37],
+    [28.37, 15.42, -49.80],
+    [54.38, -39.72, 32.27],
+    [42.43, 51.05, 28.62],
+    [81.80, 2.67, 80.41],
+    [50.63, 51.28, -14.12],
+    [49.57, -29.71, -28.32],
+    [95.19, -1.03, 2.93],
+    [81.29, -0.57, 0.44],
+    [66.89, -0.75, -0.06],
+    [50.76, -0.13, 0.14],
+    [35.63, -0.46, -0.48],
+    [20.64, 0.07, -0.46],
+];
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ChartType {
+    ColorChecker24,
+    ColorCheckerSg,
+}
+
+impl ChartType {
+    /// Patch columns and rows with the chart held in landscape orientation.
+    fn grid(self) -> (usize, usize) {
+        match self {
+            ChartType::ColorChecker24 => (6, 4),
+            ChartType::ColorCheckerSg => (14, 10),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CalibrationOptions {
+    pub chart: ChartType,
+    /// Normalized centers of the first, last-in-first-row, last and
+    /// first-in-last-row patches. Skips automatic detection when set.
+    pub corners: Option<[[f64; 2]; 4]>,
+    /// CGATS file with LAB_L/LAB_A/LAB_B columns in reading order.
+    /// Required for the SG chart, optional for the 24 patch chart.
+    pub reference_file: Option<String>,
+    /// Light source the chart was shot under, e.g. "D65" or "A".
+    pub illuminant: Option<String>,
+    #[serde(default)]
+    pub hue_sat_correction: bool,
+    pub name: Option<String>,
+    pub export_path: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CalibrationResult {
+    pub profile: CameraProfileInfo,
+    pub corners: [[f64; 2]; 4],
+    pub auto_detected: bool,
+    pub patches_used: usize,
+    pub mean_delta_e: f64,
+    pub max_delta_e: f64,
+}
+
+#[tauri::command]
+pub async fn calibrate_camera_profile(
+    path: String,
+    options: CalibrationOptions,
+    app_handle: AppHandle,
+) -> Result<CalibrationResult, String> {
+    run_calibration(&path, &options, &app_handle).map_err(|e| e.to_string())
+}
+
+fn run_calibration(path: &str, options: &CalibrationOptions, app_handle: &AppHandle) -> Result<CalibrationResult> {
+    let (cols, rows) = options.chart.grid();
+    let reference = match (&options.reference_file, options.chart) {
+        (Some(file), _) => load_cgats_lab(file)?,
+        (None, ChartType::ColorChecker24) => COLORCHECKER_24_LAB.to_vec(),
+        (None, ChartType::ColorCheckerSg) => bail!("The ColorChecker SG needs a reference data file"),
+    };
+    if reference.len() != cols * rows {
+        bail!("Reference data has {} patches, expected {}", reference.len(), cols * rows);
+    }
+    let illuminant = match &options.illuminant {
+        Some(name) => Illuminant::new_from_str(name).map_err(|e| anyhow!(e))?,
+        None => Illuminant::D65,
+    };
+    let white_xy = illuminant_xy(illuminant)
+        .ok_or_else(|| anyhow!("Illuminant {:?} has no known white point", illuminant))?;
+
+    let file_bytes = fs::read(path).with_context(|| format!("Failed to read {}", path))?;
+    let camera = develop_camera_rgb(&file_bytes)?;
+
+    let (lattice, auto_detected) = match &options.corners {
+        Some(corners) => (lattice_from_corners(corners, cols, rows)?, false),
+        None => {
+            let lattice = detect_chart(&camera.image, &camera.wb_coeffs, cols, rows, &reference)
+                .ok_or_else(|| anyhow!("Could not find the chart automatically, please place the corners manually"))?;
+            (lattice, true)
+        }
+    };
+
+    let samples = sample_patches(&camera.image, &lattice, cols, rows);
+    let fit = solve_profile(&samples, &reference, white_xy, options.hue_sat_correction)?;
+
+    let name = options.name.clone().unwrap_or_else(|| "Calibrated".to_string());
+    let profile = DcpProfile {
+        name: Some(name.clone()),
+        camera_model: Some(format!("{} {}", camera.make, camera.model)),
+        illuminant1: Some(illuminant),
+        color_matrix1: Some(to_f32_matrix(&fit.color_matrix)),
+        forward_matrix1: Some(to_f32_matrix(&fit.forward_matrix)),
+        hue_sat_map1: fit.hue_sat_map,
+        ..Default::default()
+    };
+    let bytes = profile.to_bytes();
+
+    let file_name = sanitize_file_name(&format!("{} {} - {}.dcp", camera.make, camera.model, name));
+    let destination = get_profiles_dir(app_handle).map_err(|e| anyhow!(e))?.join(file_name);
+    fs::write(&destination, &bytes).context("Failed to save camera profile")?;
+    if let Some(export_path) = &options.export_path {
+        fs::write(export_path, &bytes).context("Failed to export camera profile")?;
+    }
+
+    let corners = [(0, 0), (cols - 1, 0), (cols - 1, rows - 1), (0, rows - 1)].map(|(c, r)| {
+        let (x, y) = transform_point(&lattice, c as f64, r as f64);
+        [x, y]
+    });
+
+    Ok(CalibrationResult {
+        profile: CameraProfileInfo::from_profile(&profile, &destination),
+        corners,
+        auto_detected,
+        patches_used: fit.patches_used,
+        mean_delta_e: fit.mean_delta_e,
+        max_delta_e: fit.max_delta_e,
+    })
+}
+
+fn illuminant_xy(illuminant: Illuminant) -> Option<(f64, f64)> {
+    let xy = match illuminant {
+        Illuminant::A => CIE_1931_WHITE_POINT_A,
+        Illuminant::B => CIE_1931_WHITE_POINT_B,
+        Illuminant::C => CIE_1931_WHITE_POINT_C,
+        Illuminant::D50 => CIE_1931_WHITE_POINT_D50,
+        Illuminant::D55 => CIE_1931_WHITE_POINT_D55,
+        Illuminant::D65 => CIE_1931_WHITE_POINT_D65,
+        Illuminant::D75 => CIE_1931_WHITE_POINT_D75,
+        other => temperature_to_xy(illuminant_temperature(other)?, 0.0),
+    };
+    Some((xy.0 as f64, xy.1 as f64))
+}
+
+fn sanitize_file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') { '_' } else { c })
+        .collect()
+}
+
+fn load_cgats_lab(path: &str) -> Result<Vec<[f64; 3]>> {
+    let content = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?;
+    let mut fields: Vec<String> = Vec::new();
+    let mut in_format = false;
+    let mut in_data = false;
+    let mut columns: Option<[usize; 3]> = None;
+    let mut values = Vec::new();
+
+    for line in content.lines().map(str::trim) {
+        match line {
+            "BEGIN_DATA_FORMAT" => in_format = true,
+            "END_DATA_FORMAT" => {
+                in_format = false;
+                let find = |name: &str| fields.iter().position(|f| f == name);
+                columns = match (find("LAB_L"), find("LAB_A"), find("LAB_B")) {
+                    (Some(l), Some(a), Some(b)) => Some([l, a, b]),
+                    _ => bail!("Reference file has no LAB_L/LAB_A/LAB_B columns"),
+                };
+            }
+            "BEGIN_DATA" => in_data = true,
+            "END_DATA" => in_data = false,
+            _ if in_format => fields.extend(line.split_whitespace().map(str::to_string)),
+            _ if in_data => {
+                let columns = columns.ok_or_else(|| anyhow!("Reference data appears before its format"))?;
+                let parts: Vec<&str> = line.split_whitespace().collect();
+                let mut lab = [0.0; 3];
+                for (value, column) in lab.iter_mut().zip(columns) {
+                    *value = parts
+                        .get(column)
+                        .and_then(|p| p.replace(',', ".").parse().ok())
+                        .ok_or_else(|| anyhow!("Malformed reference line: {}", line))?;
+                }
+                values.push(lab);
+            }
+            _ => {}
+        }
+    }
+    Ok(values)
+}
+
+fn lattice_from_corners(corners: &[[f64; 2]; 4], cols: usize, rows: usize) -> Result<Matrix3> {
+    let grid = [(0.0, 0.0), ((cols - 1) as f64, 0.0), ((cols - 1) as f64, (rows - 1) as f64), (0.0, (rows - 1) as f64)];
+    let pairs: Vec<_> = grid.iter().zip(corners.iter()).map(|(g, c)| (*g, (c[0], c[1]))).collect();
+    fit_model(MotionModel::Homography, &pairs).ok_or_else(|| anyhow!("Chart corners are degenerate"))
+}
+
+/// Average camera RGB over the central part of each patch. Patches that
+/// fall outside the frame or are mostly clipped yield `None`.
+fn sample_patches(image: &Rgb32FImage, lattice: &Matrix3, cols: usize, rows: usize) -> Vec<Option<[f64; 3]>> {
+    const STEPS: usize = 7;
+    let (w, h) = image.dimensions();
+    let mut samples = Vec::with_capacity(cols * rows);
+    for row in 0..rows {
+        for col in 0..cols {
+            let mut sum = [0.0f64; 3];
+            let (mut count, mut clipped, mut total) = (0usize, 0usize, 0usize);
+            for sy in 0..STEPS {
+                for sx in 0..STEPS {
+                    let du = -0.25 + 0.5 * sx as f64 / (STEPS - 1) as f64;
+                    let dv = -0.25 + 0.5 * sy as f64 / (STEPS - 1) as f64;
+                    let (nx, ny) = transform_point(lattice, col as f64 + du, row as f64 + dv);
+                    total += 1;
+                    let Some(p) = sample_bilinear(image, nx * w as f64, ny * h as f64) else {
+                        continue;
+                    };
+                    if p.iter().any(|c| *c >= CLIP_LEVEL) {
+                        clipped += 1;
+                        continue;
+                    }
+                    p.iter().zip(sum.iter_mut()).for_each(|(c, s)| *s += *c as f64);
+                    count += 1;
+                }
+            }
+            samples.push((count * 2 > total && clipped * 2 < total).then(|| sum.map(|s| s / count as f64)));
+        }
+    }
+    samples
+}
+
+/// Finds the patch lattice of the chart. Returns a transform from patch
+/// (column, row) to normalized image coordinates.
+fn detect_chart(
+    image: &Rgb32FImage,
+    wb_coeffs: &[f32; 4],
+    cols: usize,
+    rows: usize,
+    reference: &[[f64; 3]],
+) -> Option<Matrix3> {
+    let (w, h) = image.dimensions();
+    let scale = (DETECTION_MAX_DIMENSION as f64 / w.max(h) as f64).min(1.0);
+    let sw = ((w as f64 * scale).round() as u32).max(1);
+    let sh = ((h as f64 * scale).round() as u32).max(1);
+    let small = imageops::resize(image, sw, sh, imageops::FilterType::Triangle);
+
+    let blobs = find_patch_blobs(&perceptual_preview(&small, wb_coeffs));
+    if blobs.len() < 4 {
+        return None;
+    }
+    let (u, v) = lattice_axes(&blobs)?;
+
+    // Integer lattice coordinates relative to the blob closest to the centroid.
+    let n = blobs.len() as f64;
+    let centroid = blobs.iter().fold((0.0, 0.0), |acc, b| (acc.0 + b.0 / n, acc.1 + b.1 / n));
+    let origin = blobs
+        .iter()
+        .min_by(|a, b| distance(**a, centroid).total_cmp(&distance(**b, centroid)))?;
+    let det = u.0 * v.1 - u.1 * v.0;
+    if det.abs() < 1e-9 {
+        return None;
+    }
+    let mut cells: HashMap<(i64, i64), (f64, f64)> = HashMap::new();
+    for blob in &blobs {
+        let (dx, dy) = (blob.0 - origin.0, blob.1 - origin.1);
+        let a = (dx * v.1 - dy * v.0) / det;
+        let b = (u.0 * dy - u.1 * dx) / det;
+        if (a - a.round()).abs() < 0.3 && (b - b.round()).abs() < 0.3 {
+            cells.entry((a.round() as i64, b.round() as i64)).or_insert((blob.0, blob.1));
+        }
+    }
+
+    // Densest window with the chart's dimensions, in either orientation.
+    let (amin, amax) = cells.keys().fold((i64::MAX, i64::MIN), |acc, k| (acc.0.min(k.0), acc.1.max(k.0)));
+    let (bmin, bmax) = cells.keys().fold((i64::MAX, i64::MIN), |acc, k| (acc.0.min(k.1), acc.1.max(k.1)));
+    let mut best: Option<(usize, i64, i64, bool)> = None;
+    for transposed in [false, true] {
+        let (wc, wr) = if transposed { (rows as i64, cols as i64) } else { (cols as i64, rows as i64) };
+        for a0 in (amin - wc + 1)..=amax {
+            for b0 in (bmin - wr + 1)..=bmax {
+                let count = cells
+                    .keys()
+                    .filter(|(a, b)| *a >= a0 && *a < a0 + wc && *b >= b0 && *b < b0 + wr)
+                    .count();
+                if best.is_none_or(|b| count > b.0) {
+                    best = Some((count, a0, b0, transposed));
+                }
+            }
+        }
+    }
+    let (count, a0, b0, transposed) = best?;
+    if count < (cols * rows / 2).max(4) {
+        return None;
+    }
+
+    let (wc, wr) = if transposed { (rows, cols) } else { (cols, rows) };
+    let window: Vec<((usize, usize), (f64, f64))> = cells
+        .iter()
+        .filter_map(|(&(a, b), &p)| {
+            let (x, y) = (a - a0, b - b0);
+            (x >= 0 && y >= 0 && (x as usize) < wc && (y as usize) < wr)
+                .then(|| ((x as usize, y as usize), (p.0 / sw as f64, p.1 / sh as f64)))
+        })
+        .collect();
+
+    // Pick the chart orientation whose samples are best explained by a
+    // linear fit to the reference colors.
+    let model = if window.len() >= 8 { MotionModel::Homography } else { MotionModel::Affine };
+    let mut best_fit: Option<(f64, Matrix3)> = None;
+    for flip_x in [false, true] {
+        for flip_y in [false, true] {
+            let pairs: Vec<_> = window
+                .iter()
+                .map(|&((x, y), p)| {
+                    let x = if flip_x { wc - 1 - x } else { x };
+                    let y = if flip_y { wr - 1 - y } else { y };
+                    let (col, row) = if transposed { (y, x) } else { (x, y) };
+                    ((col as f64, row as f64), p)
+                })
+                .collect();
+            let Some(lattice) = fit_model(model, &pairs) else {
+                continue;
+            };
+            let samples = sample_patches(&small, &lattice, cols, rows);
+            let Some(error) = orientation_error(&samples, reference) else {
+                continue;
+            };
+            if best_fit.is_none_or(|b| error < b.0) {
+                best_fit = Some((error, lattice));
+            }
+        }
+    }
+    best_fit.map(|b| b.1)
+}
+
+fn distance(a: (f64, f64, f64), b: (f64, f64)) -> f64 {
+    ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt()
+}
+
+/// White balanced, square-root encoded preview for segmentation.
+fn perceptual_preview(image: &Rgb32FImage, wb_coeffs: &[f32; 4]) -> Rgb32FImage {
+    let wb = if wb_coeffs[..3].iter().all(|c| c.is_normal() && *c > 0.0) {
+        [wb_coeffs[0], wb_coeffs[1], wb_coeffs[2]]
+    } else {
+        [1.0, 1.0, 1.0]
+    };
+    let mut preview = image.clone();
+    preview.pixels_mut().for_each(|p| {
+        p.0.iter_mut().zip(wb).for_each(|(c, w)| *c = (*c * w).max(0.0));
+    });
+
+    let mut peaks: Vec<f32> = preview.pixels().map(|p| p.0[0].max(p.0[1]).max(p.0[2])).collect();
+    let index = ((peaks.len() as f32 * 0.99) as usize).min(peaks.len() - 1);
+    let peak = peaks.select_nth_unstable_by(index, |a, b| a.total_cmp(b)).1.max(1e-6);
+    preview.pixels_mut().for_each(|p| p.0.iter_mut().for_each(|c| *c = (*c / peak).min(1.0).sqrt()));
+    preview
+}
+
+struct BlobStats {
+    area: f64,
+    sum_x: f64,
+    sum_y: f64,
+    min: (u32, u32),
+    max: (u32, u32),
+}
+
+/// Centers and sizes of uniform, roughly square regions that could be patches.
+fn find_patch_blobs(preview: &Rgb32FImage) -> Vec<(f64, f64, f64)> {
+    let (w, h) = preview.dimensions();
+    let mut flat = GrayImage::new(w, h);
+    for y in 1..h.saturating_sub(1) {
+        for x in 1..w.saturating_sub(1) {
+            let gradient = (0..3)
+                .map(|c| {
+                    let dx = preview.get_pixel(x + 1, y).0[c] - preview.get_pixel(x - 1, y).0[c];
+                    let dy = preview.get_pixel(x, y + 1).0[c] - preview.get_pixel(x, y - 1).0[c];
+                    dx.abs() + dy.abs()
+                })
+                .fold(0.0f32, f32::max);
+            if gradient < FLAT_GRADIENT_THRESHOLD {
+                flat.put_pixel(x, y, Luma([255]));
+            }
+        }
+    }
+
+    let labels = connected_components(&flat, Connectivity::Four, Luma([0u8]));
+    let mut stats: HashMap<u32, BlobStats> = HashMap::new();
+    for (x, y, label) in labels.enumerate_pixels() {
+        if label.0[0] == 0 {
+            continue;
+        }
+        let s = stats.entry(label.0[0]).or_insert(BlobStats {
+            area: 0.0,
+            sum_x: 0.0,
+            sum_y: 0.0,
+            min: (x, y),
+            max: (x, y),
+        });
+        s.area += 1.0;
+        s.sum_x += x as f64;
+        s.sum_y += y as f64;
+        s.min = (s.min.0.min(x), s.min.1.min(y));
+        s.max = (s.max.0.max(x), s.max.1.max(y));
+    }
+
+    let max_area = (w * h) as f64 / 30.0;
+    let candidates: Vec<(f64, f64, f64)> = stats
+        .values()
+        .filter_map(|s| {
+            let bw = (s.max.0 - s.min.0 + 1) as f64;
+            let bh = (s.max.1 - s.min.1 + 1) as f64;
+            let square = bw / bh > 0.5 && bw / bh < 2.0 && s.area / (bw * bh) > 0.55;
+            (s.area >= 20.0 && s.area <= max_area && square)
+                .then(|| (s.sum_x / s.area, s.sum_y / s.area, s.area.sqrt()))
+        })
+        .collect();
+
+    // Chart patches share a size; keep the most common one.
+    let support = |size: f64| candidates.iter().filter(|c| c.2 / size > 0.75 && c.2 / size < 1.33).count();
+    let Some(mode) = candidates.iter().map(|c| c.2).max_by_key(|size| support(*size)) else {
+        return Vec::new();
+    };
+    candidates
+        .into_iter()
+        .filter(|c| c.2 / mode > 0.7 && c.2 / mode < 1.45)
+        .collect()
+}
+
+/// Two dominant neighbour vectors of the blob grid.
+fn lattice_axes(blobs: &[(f64, f64, f64)]) -> Option<((f64, f64), (f64, f64))> {
+    let mut vectors = Vec::new();
+    for (i, a) in blobs.iter().enumerate() {
+        let mut neighbours: Vec<(f64, f64, f64)> = blobs
+            .iter()
+            .enumerate()
+            .filter(|(j, _)| *j != i)
+            .map(|(_, b)| {
+                let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+                (dx, dy, (dx * dx + dy * dy).sqrt())
+            })
+            .filter(|n| n.2 > 0.9 * a.2 && n.2 < 2.5 * a.2)
+            .collect();
+        neighbours.sort_by(|x, y| x.2.total_cmp(&y.2));
+        if let Some(nearest) = neighbours.first().map(|n| n.2) {
+            vectors.extend(neighbours.into_iter().take(4).filter(|n| n.2 < 1.2 * nearest));
+        }
+    }
+    if vectors.len() < 4 {
+        return None;
+    }
+
+    const BINS: usize = 36;
+    let bin_of = |dx: f64, dy: f64| {
+        let angle = dy.atan2(dx).rem_euclid(std::f64::consts::PI);
+        ((angle / std::f64::consts::PI * BINS as f64) as usize).min(BINS - 1)
+    };
+    let mut histogram = [0usize; BINS];
+    vectors.iter().for_each(|v| histogram[bin_of(v.0, v.1)] += 1);
+    let smoothed: Vec<usize> = (0..BINS)
+        .map(|i| histogram[(i + BINS - 1) % BINS] + 2 * histogram[i] + histogram[(i + 1) % BINS])
+        .collect();
+    let first = (0..BINS).max_by_key(|&i| smoothed[i])?;
+    let bin_distance = |a: usize, b: usize| {
+        let d = a.abs_diff(b);
+        d.min(BINS - d)
+    };
+    let second = (0..BINS).filter(|&i| bin_distance(i, first) >= 6).max_by_key(|&i| smoothed[i])?;
+    if smoothed[second] == 0 {
+        return None;
+    }
+
+    let mean_vector = |bin: usize| {
+        let center = (bin as f64 + 0.5) / BINS as f64 * std::f64::consts::PI;
+        let axis = (center.cos(), center.sin());
+        let (mut sx, mut sy, mut n) = (0.0, 0.0, 0.0);
+        for v in vectors.iter().filter(|v| bin_distance(bin_of(v.0, v.1), bin) <= 2) {
+            let sign = if v.0 * axis.0 + v.1 * axis.1 < 0.0 { -1.0 } else { 1.0 };
+            sx += sign * v.0;
+            sy += sign * v.1;
+            n += 1.0;
+        }
+        (sx / n, sy / n)
+    };
+    Some((mean_vector(first), mean_vector(second)))
+}
+
+/// Relative residual of a linear camera-to-XYZ fit; low for the correct orientation.
+fn orientation_error(samples: &[Option<[f64; 3]>], reference: &[[f64; 3]]) -> Option<f64> {
+    let (cam, xyz): (Vec<[f64; 3]>, Vec<[f64; 3]>) = samples
+        .iter()
+        .zip(reference)
+        .filter_map(|(s, lab)| s.map(|s| (s, lab_to_xyz(lab))))
+        .unzip();
+    if cam.len() < 6 {
+        return None;
+    }
+    let m = fit_matrix(&cam, &xyz, None)?;
+    let (mut residual, mut energy) = (0.0, 0.0);
+    for (c, x) in cam.iter().zip(&xyz) {
+        let p = mul_vec(&m, *c);
+        residual += (0..3).map(|i| (p[i] - x[i]).powi(2)).sum::<f64>();
+        energy += x.iter().map(|v| v * v).sum::<f64>();
+    }
+    Some(residual / energy.max(1e-12) + (samples.len() - cam.len()) as f64 * 0.01)
+}
+
+/// Least-squares 3x3 matrix mapping `src` onto `dst`, optionally with an
+/// extra weighted equation pinning one source vector to a target.
+fn fit_matrix(src: &[[f64; 3]], dst: &[[f64; 3]], pin: Option<([f64; 3], [f64; 3], f64)>) -> Option<Matrix3> {
+    let mut ata = [0.0; 9];
+    let mut atb = [[0.0; 3]; 3];
+    let mut accumulate = |s: &[f64; 3], d: &[f64; 3], weight: f64| {
+        for i in 0..3 {
+            for j in 0..3 {
+                ata[i * 3 + j] += weight * s[i] * s[j];
+            }
+            for (row, value) in d.iter().enumerate() {
+                atb[row][i] += weight * s[i] * value;
+            }
+        }
+    };
+    src.iter().zip(dst).for_each(|(s, d)| accumulate(s, d, 1.0));
+    if let Some((s, d, weight)) = pin {
+        accumulate(&s, &d, weight);
+    }
+    let inverse = mat_invert(&ata)?;
+    let mut m = [0.0; 9];
+    for row in 0..3 {
+        let solution = mul_vec(&inverse, atb[row]);
+        m[row * 3..row * 3 + 3].copy_from_slice(&solution);
+    }
+    Some(m)
+}
+
+struct ProfileFit {
+    color_matrix: Matrix3,
+    forward_matrix: Matrix3,
+    hue_sat_map: Option<HueSatMap>,
+    patches_used: usize,
+    mean_delta_e: f64,
+    max_delta_e: f64,
+}
+
+fn solve_profile(
+    samples: &[Option<[f64; 3]>],
+    reference: &[[f64; 3]],
+    white_xy: (f64, f64),
+    hue_sat_correction: bool,
+) -> Result<ProfileFit> {
+    let used: Vec<([f64; 3], [f64; 3])> = samples
+        .iter()
+        .zip(reference)
+        .filter_map(|(s, lab)| s.map(|s| (s, *lab)))
+        .collect();
+    if used.len() < 6 {
+        bail!("Only {} usable patches found, the chart may be clipped or out of frame", used.len());
+    }
+
+    let neutrals: Vec<&([f64; 3], [f64; 3])> = used
+        .iter()
+        .filter(|(_, lab)| lab[1].hypot(lab[2]) < 3.0 && lab[0] > 30.0 && lab[0] < 92.0)
+        .collect();
+    if neutrals.is_empty() {
+        bail!("No usable neutral patches found");
+    }
+    let mut neutral = [0.0; 3];
+    neutrals.iter().for_each(|(s, _)| (0..3).for_each(|c| neutral[c] += s[c]));
+    if neutral[1] <= 0.0 {
+        bail!("Neutral patches are black");
+    }
+    let neutral = neutral.map(|c| c / neutral[1]);
+
+    let exposure = neutrals.iter().map(|(_, lab)| lab_to_xyz(lab)[1]).sum::<f64>()
+        / neutrals.iter().map(|(s, _)| s[1] / neutral[1]).sum::<f64>();
+    let balanced: Vec<[f64; 3]> = used
+        .iter()
+        .map(|(s, _)| [0, 1, 2].map(|c| s[c] / neutral[c] * exposure))
+        .collect();
+    let targets: Vec<[f64; 3]> = used.iter().map(|(_, lab)| lab_to_xyz(lab)).collect();
+
+    let forward_matrix = fit_matrix(&balanced, &targets, Some(([1.0; 3], D50_XYZ, used.len() as f64)))
+        .ok_or_else(|| anyhow!("Patch colors are degenerate"))?;
+
+    // XYZ under the shooting illuminant to camera: invert FM * diag(1 / neutral)
+    // after adapting D50 back to the illuminant white.
+    let camera_to_d50 = mat_mul(&forward_matrix, &diagonal(neutral.map(|n| 1.0 / n)));
+    let adapt = bradford_adaptation(CIE_1931_WHITE_POINT_D50, (white_xy.0 as f32, white_xy.1 as f32));
+    let adapt: Matrix3 = std::array::from_fn(|i| adapt[i / 3][i % 3] as f64);
+    let mut color_matrix = mat_invert(&mat_mul(&adapt, &camera_to_d50))
+        .ok_or_else(|| anyhow!("Color matrix is not invertible"))?;
+    let white = mul_vec(&color_matrix, xy_to_xyz(white_xy));
+    let white_max = white.iter().copied().fold(f64::MIN, f64::max);
+    if white_max > 0.0 {
+        color_matrix.iter_mut().for_each(|v| *v /= white_max);
+    }
+
+    let xyz_to_prophoto: Matrix3 = std::array::from_fn(|i| XYZ_TO_PROFOTORGB_D50[i / 3][i % 3] as f64);
+    let predicted: Vec<[f32; 3]> = balanced
+        .iter()
+        .map(|b| mul_vec(&xyz_to_prophoto, mul_vec(&forward_matrix, *b)).map(|c| c as f32))
+        .collect();
+    let wanted: Vec<[f32; 3]> = targets.iter().map(|t| mul_vec(&xyz_to_prophoto, *t).map(|c| c as f32)).collect();
+
+    let hue_sat_map = hue_sat_correction.then(|| build_hue_sat_map(&predicted, &wanted, reference_chroma(&used)));
+
+    let prophoto_to_xyz = mat_invert(&xyz_to_prophoto).expect("ProPhoto matrix is invertible");
+    let delta_e: Vec<f64> = predicted
+        .iter()
+        .zip(&used)
+        .map(|(p, (_, lab))| {
+            let p = hue_sat_map.as_ref().map_or(*p, |map| map.apply(*p));
+            let xyz = mul_vec(&prophoto_to_xyz, p.map(|c| c as f64));
+            let fitted = xyz_to_lab(&xyz);
+            ((fitted[0] - lab[0]).powi(2) + (fitted[1] - lab[1]).powi(2) + (fitted[2] - lab[2]).powi(2)).sqrt()
+        })
+        .collect();
+
+    Ok(ProfileFit {
+        color_matrix,
+        forward_matrix,
+        hue_sat_map,
+        patches_used: used.len(),
+        mean_delta_e: delta_e.iter().sum::<f64>() / delta_e.len() as f64,
+        max_delta_e: delta_e.iter().copied().fold(0.0, f64::max),
+    })
+}
+
+fn reference_chroma(used: &[([f64; 3], [f64; 3])]) -> Vec<f64> {
+    used.iter().map(|(_, lab)| lab[1].hypot(lab[2])).collect()
+}
+
+/// Smoothly spreads the per-patch hue/saturation/value residuals of the
+/// matrix fit over a 2D HueSatMap. Neutral patches and empty regions of the
+/// table stay at identity.
+fn build_hue_sat_map(predicted: &[[f32; 3]], wanted: &[[f32; 3]], chroma: Vec<f64>) -> HueSatMap {
+    const HUE_SIGMA: f32 = 25.0;
+    const SAT_SIGMA: f32 = 0.25;
+    const PRIOR_WEIGHT: f32 = 0.3;
+
+    let corrections: Vec<(f32, f32, [f32; 3])> = predicted
+        .iter()
+        .zip(wanted)
+        .zip(chroma)
+        .filter(|(_, c)| *c >= 8.0)
+        .filter_map(|((p, w), _)| {
+            let (ph, ps, pv) = rgb_to_hsv(*p);
+            let (wh, ws, wv) = rgb_to_hsv(*w);
+            if ps <= 0.01 || pv <= 0.0 {
+                return None;
+            }
+            let hue_shift = ((wh - ph + 3.0).rem_euclid(6.0) - 3.0) * 60.0;
+            Some((
+                ph * 60.0,
+                ps,
+                [
+                    hue_shift.clamp(-20.0, 20.0),
+                    (ws / ps).clamp(0.7, 1.4),
+                    (wv / pv).clamp(0.85, 1.15),
+                ],
+            ))
+        })
+        .collect();
+
+    let mut data = Vec::with_capacity(HSM_HUE_DIVISIONS * HSM_SAT_DIVISIONS);
+    for hue_index in 0..HSM_HUE_DIVISIONS {
+        let hue = hue_index as f32 * 360.0 / HSM_HUE_DIVISIONS as f32;
+        for sat_index in 0..HSM_SAT_DIVISIONS {
+            let sat = sat_index as f32 / (HSM_SAT_DIVISIONS - 1) as f32;
+            if sat_index == 0 {
+                data.push([0.0, 1.0, 1.0]);
+                continue;
+            }
+            let mut sum = [0.0, PRIOR_WEIGHT, PRIOR_WEIGHT];
+            let mut total = PRIOR_WEIGHT;
+            for (patch_hue, patch_sat, delta) in &corrections {
+                let dh = ((hue - patch_hue + 180.0).rem_euclid(360.0) - 180.0) / HUE_SIGMA;
+                let ds = (sat - patch_sat) / SAT_SIGMA;
+                let weight = (-0.5 * (dh * dh + ds * ds)).exp() * sat.min(1.0);
+                for c in 0..3 {
+                    sum[c] += weight * delta[c];
+                }
+                total += weight;
+            }
+            data.push(sum.map(|s| s / total));
+        }
+    }
+
+    HueSatMap {
+        hue_divisions: HSM_HUE_DIVISIONS,
+        sat_divisions: HSM_SAT_DIVISIONS,
+        val_divisions: 1,
+        srgb_gamma: false,
+        data,
+    }
+}
+
+fn diagonal(v: [f64; 3]) -> Matrix3 {
+    [v[0], 0.0, 0.0, 0.0, v[1], 0.0, 0.0, 0.0, v[2]]
+}
+
+fn mul_vec(m: &Matrix3, v: [f64; 3]) -> [f64; 3] {
+    [
+        m[0] * v[0] + m[1] * v[1] + m[2] * v[2],
+        m[3] * v[0] + m[4] * v[1] + m[5] * v[2],
+        m[6] * v[0] + m[7] * v[1] + m[8] * v[2],
+    ]
+}
+
+fn to_f32_matrix(m: &Matrix3) -> [[f32; 3]; 3] {
+    std::array::from_fn(|i| std::array::from_fn(|j| m[i * 3 + j] as f32))
+}
+
+fn xy_to_xyz(xy: (f64, f64)) -> [f64; 3] {
+    [xy.0 / xy.1, 1.0, (1.0 - xy.0 - xy.1) / xy.1]
+}
+
+fn lab_to_xyz(lab: &[f64; 3]) -> [f64; 3] {
+    let fy = (lab[0] + 16.0) / 116.0;
+    let fx = fy + lab[1] / 500.0;
+    let fz = fy - lab[2] / 200.0;
+    let inverse = |t: f64| {
+        if t > 6.0 / 29.0 {
+            t * t * t
+        } else {
+            3.0 * (6.0f64 / 29.0).powi(2) * (t - 4.0 / 29.0)
+        }
+    };
+    [inverse(fx) * D50_XYZ[0], inverse(fy) * D50_XYZ[1], inverse(fz) * D50_XYZ[2]]
+}
+
+fn xyz_to_lab(xyz: &[f64; 3]) -> [f64; 3] {
+    let f = |t: f64| {
+        if t > (6.0f64 / 29.0).powi(3) {
+            t.cbrt()
+        } else {
+            t / (3.0 * (6.0f64 / 29.0).powi(2)) + 4.0 / 29.0
+        }
+    };
+    let (fx, fy, fz) = (f(xyz[0] / D50_XYZ[0]), f(xyz[1] / D50_XYZ[1]), f(xyz[2] / D50_XYZ[2]));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
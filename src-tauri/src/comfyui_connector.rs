@@ -1,27 +1,270 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 use futures_util::StreamExt;
 use image::{DynamicImage, ImageFormat};
-use reqwest::multipart;
+use reqwest::{multipart, Client};
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use std::collections::HashMap;
 use std::io::Cursor;
 use std::path::Path;
 use std::fs;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::Instant;
 use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
 use uuid::Uuid;
 
 const WORKFLOWS_DIR: &str = "./workflows";
+const GENERATIVE_REPLACE_ID: &str = "generative_replace";
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_EXECUTION_TIMEOUT: Duration = Duration::from_secs(600);
 
-pub struct WorkflowInputs {
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ParameterKind {
+    /// Integer seed; -1 picks a random one for every run.
+    Seed,
+    Int,
+    Float,
+    String,
+    /// Checkpoint file name, as listed by `get_checkpoints`.
+    Checkpoint,
+}
+
+/// A user-adjustable workflow input, written to `inputs[input]` of a node.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkflowParameter {
+    pub id: String,
+    pub label: String,
+    pub kind: ParameterKind,
+    pub node_id: String,
+    pub input: String,
+    #[serde(default)]
+    pub default: Option<Value>,
+    #[serde(default)]
+    pub min: Option<f64>,
+    #[serde(default)]
+    pub max: Option<f64>,
+}
+
+/// A ComfyUI API-format workflow together with the nodes RapidRAW fills in.
+/// User templates are JSON files with these fields and the workflow under
+/// `workflow`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkflowTemplate {
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
     pub source_image_node_id: String,
+    #[serde(default)]
     pub mask_image_node_id: Option<String>,
+    #[serde(default)]
     pub text_prompt_node_id: Option<String>,
-    pub final_output_node_id: String,
+    /// Nodes whose images are returned; all image outputs when empty.
+    #[serde(default)]
+    pub output_node_ids: Vec<String>,
+    #[serde(default)]
+    pub parameters: Vec<WorkflowParameter>,
+    #[serde(skip_serializing)]
+    pub workflow: Value,
+}
+
+pub struct WorkflowRequest {
+    pub source_image: DynamicImage,
+    pub mask_image: Option<DynamicImage>,
+    pub text_prompt: Option<String>,
+    pub parameters: HashMap<String, Value>,
+}
+
+/// Cancels a running workflow from another task.
+#[derive(Clone)]
+pub struct CancelToken(Arc<watch::Sender<bool>>);
+
+impl Default for CancelToken {
+    fn default() -> Self {
+        Self(Arc::new(watch::channel(false).0))
+    }
+}
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.send_replace(true);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    async fn cancelled(&self) {
+        let mut receiver = self.0.subscribe();
+        let _ = receiver.wait_for(|&cancelled| cancelled).await;
+    }
+}
+
+pub struct ExecutionOptions {
+    /// Limit for each HTTP request and the websocket handshake.
+    pub request_timeout: Duration,
+    /// Limit for the whole run, from queueing until the last output.
+    pub execution_timeout: Duration,
+    pub cancel: CancelToken,
 }
 
-async fn upload_image(address: &str, image: DynamicImage, form_name: &str) -> Result<String> {
+impl Default for ExecutionOptions {
+    fn default() -> Self {
+        Self {
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            execution_timeout: DEFAULT_EXECUTION_TIMEOUT,
+            cancel: CancelToken::default(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ComfyProgress {
+    /// "queued", "executing", "sampling" or "downloading".
+    pub stage: String,
+    pub node: Option<String>,
+    pub value: u64,
+    pub max: u64,
+}
+
+impl ComfyProgress {
+    fn new(stage: &str, node: Option<&str>, value: u64, max: u64) -> Self {
+        Self { stage: stage.to_string(), node: node.map(String::from), value, max }
+    }
+}
+
+fn builtin_templates() -> Vec<WorkflowTemplate> {
+    let path = Path::new(WORKFLOWS_DIR).join(format!("{}.json", GENERATIVE_REPLACE_ID));
+    let Some(workflow) = fs::read_to_string(&path)
+        .ok()
+        .and_then(|content| serde_json::from_str::<Value>(&content).ok())
+    else {
+        return Vec::new();
+    };
+
+    let parameter = |id: &str, label: &str, kind, node_id: &str, input: &str, default: Option<Value>, range: Option<(f64, f64)>| {
+        WorkflowParameter {
+            id: id.to_string(),
+            label: label.to_string(),
+            kind,
+            node_id: node_id.to_string(),
+            input: input.to_string(),
+            default,
+            min: range.map(|r| r.0),
+            max: range.map(|r| r.1),
+        }
+    };
+
+    vec![WorkflowTemplate {
+        id: GENERATIVE_REPLACE_ID.to_string(),
+        name: "Generative Replace".to_string(),
+        description: "Inpaints the masked area guided by the prompt.".to_string(),
+        source_image_node_id: "11".to_string(),
+        mask_image_node_id: Some("148".to_string()),
+        text_prompt_node_id: Some("6".to_string()),
+        output_node_ids: vec!["252".to_string()],
+        parameters: vec![
+            parameter("seed", "Seed", ParameterKind::Seed, "239", "seed", Some(json!(-1)), None),
+            parameter("steps", "Steps", ParameterKind::Int, "3", "steps", None, Some((1.0, 150.0))),
+            parameter("denoise", "Denoise", ParameterKind::Float, "3", "denoise", None, Some((0.0, 1.0))),
+            parameter("checkpoint", "Checkpoint", ParameterKind::Checkpoint, "4", "ckpt_name", None, None),
+        ],
+        workflow,
+    }]
+}
+
+/// Built-in workflows plus the templates found in `user_dir`, which replace
+/// built-ins with the same id (file stem).
+pub fn load_workflow_templates(user_dir: Option<&Path>) -> Vec<WorkflowTemplate> {
+    let mut templates = builtin_templates();
+
+    let Some(entries) = user_dir.and_then(|dir| fs::read_dir(dir).ok()) else {
+        return templates;
+    };
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().and_then(|e| e.to_str()) == Some("json"))
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        let template = fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|content| Ok(serde_json::from_str::<WorkflowTemplate>(&content)?));
+        match template {
+            Ok(mut template) => {
+                template.id = path.file_stem().and_then(|s| s.to_str()).unwrap_or_default().to_string();
+                templates.retain(|t| t.id != template.id);
+                templates.push(template);
+            }
+            Err(e) => eprintln!("Skipping workflow template {:?}: {}", path, e),
+        }
+    }
+    templates
+}
+
+fn random_seed() -> u64 {
+    // ComfyUI seeds are limited to 2^53 by its JSON handling.
+    (Uuid::new_v4().as_u128() as u64) & ((1 << 53) - 1)
+}
+
+fn set_node_input(workflow: &mut Value, node_id: &str, input: &str, value: Value) -> Result<()> {
+    let node = workflow
+        .get_mut(node_id)
+        .ok_or_else(|| anyhow!("Node ID '{}' not found in workflow.", node_id))?;
+    node["inputs"][input] = value;
+    Ok(())
+}
+
+/// Writes parameter values (or their defaults) into the workflow, clamped to
+/// the declared range.
+pub fn apply_parameters(workflow: &mut Value, template: &WorkflowTemplate, values: &HashMap<String, Value>) -> Result<()> {
+    for parameter in &template.parameters {
+        let Some(value) = values.get(&parameter.id).or(parameter.default.as_ref()) else {
+            continue;
+        };
+        let clamp = |v: f64| {
+            let v = parameter.min.map_or(v, |min| v.max(min));
+            parameter.max.map_or(v, |max| v.min(max))
+        };
+        let value = match parameter.kind {
+            ParameterKind::Seed => match value.as_i64() {
+                Some(seed) if seed >= 0 => json!(seed),
+                _ => json!(random_seed()),
+            },
+            ParameterKind::Int => {
+                let v = value.as_f64().ok_or_else(|| anyhow!("Parameter '{}' must be a number", parameter.id))?;
+                json!(clamp(v).round() as i64)
+            }
+            ParameterKind::Float => {
+                let v = value.as_f64().ok_or_else(|| anyhow!("Parameter '{}' must be a number", parameter.id))?;
+                json!(clamp(v))
+            }
+            ParameterKind::String | ParameterKind::Checkpoint => {
+                let v = value.as_str().ok_or_else(|| anyhow!("Parameter '{}' must be a string", parameter.id))?;
+                json!(v)
+            }
+        };
+        set_node_input(workflow, &parameter.node_id, &parameter.input, value)?;
+    }
+    Ok(())
+}
+
+fn build_client(options: &ExecutionOptions) -> Result<Client> {
+    Ok(Client::builder().timeout(options.request_timeout).build()?)
+}
+
+async fn upload_image(client: &Client, address: &str, image: DynamicImage, form_name: &str) -> Result<String> {
     let mut image_bytes = Cursor::new(Vec::new());
     image.write_to(&mut image_bytes, ImageFormat::Png)?;
-    
+
     let part = multipart::Part::bytes(image_bytes.into_inner())
         .file_name(format!("{}.png", Uuid::new_v4()))
         .mime_str("image/png")?;
@@ -30,7 +273,6 @@ async fn upload_image(address: &str, image: DynamicImage, form_name: &str) -> Re
         .part(form_name.to_string(), part)
         .text("overwrite", "true");
 
-    let client = reqwest::Client::new();
     let response = client
         .post(format!("http://{}/upload/image", address))
         .multipart(form)
@@ -52,13 +294,12 @@ async fn upload_image(address: &str, image: DynamicImage, form_name: &str) -> Re
         .ok_or_else(|| anyhow!("Failed to get filename from ComfyUI upload response. Full response: {}", response_json))
 }
 
-async fn queue_prompt(address: &str, prompt: Value, client_id: &str) -> Result<String> {
+async fn queue_prompt(client: &Client, address: &str, prompt: Value, client_id: &str) -> Result<String> {
     let payload = json!({
         "prompt": prompt,
         "client_id": client_id,
     });
 
-    let client = reqwest::Client::new();
     let response = client
         .post(format!("http://{}/prompt", address))
         .json(&payload)
@@ -80,9 +321,23 @@ async fn queue_prompt(address: &str, prompt: Value, client_id: &str) -> Result<S
         .ok_or_else(|| anyhow!("Failed to get prompt_id from ComfyUI. Full response: {}", response_json))
 }
 
-async fn get_history(address: &str, prompt_id: &str) -> Result<Value> {
+/// Stops the prompt if it is running and removes it from the queue if not.
+async fn interrupt(client: &Client, address: &str, prompt_id: &str) {
+    let _ = client
+        .post(format!("http://{}/interrupt", address))
+        .json(&json!({ "prompt_id": prompt_id }))
+        .send()
+        .await;
+    let _ = client
+        .post(format!("http://{}/queue", address))
+        .json(&json!({ "delete": [prompt_id] }))
+        .send()
+        .await;
+}
+
+async fn get_history(client: &Client, address: &str, prompt_id: &str) -> Result<Value> {
     let url = format!("http://{}/history/{}", address, prompt_id);
-    let response = reqwest::get(&url).await?;
+    let response = client.get(&url).send().await?;
 
     if !response.status().is_success() {
         let status = response.status();
@@ -93,8 +348,7 @@ async fn get_history(address: &str, prompt_id: &str) -> Result<Value> {
     Ok(response.json::<Value>().await?)
 }
 
-async fn get_image(address: &str, filename: &str, subfolder: &str, folder_type: &str) -> Result<Vec<u8>> {
-    let client = reqwest::Client::new();
+async fn get_image(client: &Client, address: &str, filename: &str, subfolder: &str, folder_type: &str) -> Result<Vec<u8>> {
     let response = client.get(format!("http://{}/view", address))
         .query(&[
             ("filename", filename),
@@ -118,86 +372,472 @@ pub async fn ping_server(address: &str) -> Result<()> {
     Ok(())
 }
 
+/// Checkpoint names the server can load, for `Checkpoint` parameters.
+pub async fn get_checkpoints(address: &str, options: &ExecutionOptions) -> Result<Vec<String>> {
+    let client = build_client(options)?;
+    let info = client
+        .get(format!("http://{}/object_info/CheckpointLoaderSimple", address))
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<Value>()
+        .await?;
+    let names = info["CheckpointLoaderSimple"]["input"]["required"]["ckpt_name"][0]
+        .as_array()
+        .ok_or_else(|| anyhow!("Unexpected object_info response from ComfyUI"))?;
+    Ok(names.iter().filter_map(|n| n.as_str().map(String::from)).collect())
+}
+
+/// Waits until the prompt has finished, forwarding progress messages.
+async fn wait_for_completion(
+    client: &Client,
+    address: &str,
+    prompt_id: &str,
+    read: &mut (impl StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin),
+    options: &ExecutionOptions,
+    on_progress: &impl Fn(ComfyProgress),
+) -> Result<()> {
+    let deadline = Instant::now() + options.execution_timeout;
+    loop {
+        let message = tokio::select! {
+            message = read.next() => message,
+            _ = options.cancel.cancelled() => {
+                interrupt(client, address, prompt_id).await;
+                bail!("ComfyUI workflow was cancelled");
+            }
+            _ = tokio::time::sleep_until(deadline) => {
+                interrupt(client, address, prompt_id).await;
+                bail!("ComfyUI workflow timed out after {} seconds", options.execution_timeout.as_secs());
+            }
+        };
+
+        let text = match message {
+            Some(Ok(Message::Text(text))) => text,
+            Some(Ok(Message::Close(_))) | None => bail!("WebSocket stream ended unexpectedly"),
+            Some(Ok(_)) => continue,
+            Some(Err(e)) => bail!("WebSocket error: {}", e),
+        };
+        let Ok(v) = serde_json::from_str::<Value>(&text) else {
+            continue;
+        };
+
+        let data = &v["data"];
+        if data["prompt_id"].as_str().is_some_and(|id| id != prompt_id) {
+            continue;
+        }
+        let node = data["node"].as_str();
+        match v["type"].as_str() {
+            Some("status") => {
+                if let Some(remaining) = data["status"]["exec_info"]["queue_remaining"].as_u64() {
+                    on_progress(ComfyProgress::new("queued", None, remaining, remaining));
+                }
+            }
+            Some("executing") if data["node"].is_null() => return Ok(()),
+            Some("executing") => on_progress(ComfyProgress::new("executing", node, 0, 0)),
+            Some("progress") => {
+                let value = data["value"].as_u64().unwrap_or(0);
+                let max = data["max"].as_u64().unwrap_or(0);
+                on_progress(ComfyProgress::new("sampling", node, value, max));
+            }
+            Some("execution_success") => return Ok(()),
+            Some("execution_error") => bail!(
+                "ComfyUI execution failed in node {} ({}): {}",
+                data["node_id"].as_str().unwrap_or("?"),
+                data["node_type"].as_str().unwrap_or("unknown"),
+                data["exception_message"].as_str().unwrap_or("unknown error")
+            ),
+            Some("execution_interrupted") => bail!("ComfyUI workflow was interrupted"),
+            _ => {}
+        }
+    }
+}
+
+/// Runs a workflow template and returns every output image as PNG bytes.
 pub async fn execute_workflow(
     address: &str,
-    workflow_name: &str,
-    inputs: WorkflowInputs,
-    source_image: DynamicImage,
-    mask_image: Option<DynamicImage>,
-    text_prompt: Option<String>,
-) -> Result<Vec<u8>> {
-    let workflow_path = Path::new(WORKFLOWS_DIR).join(format!("{}.json", workflow_name));
-    let workflow_str = fs::read_to_string(&workflow_path)
-        .map_err(|e| anyhow!("Failed to read workflow file at {:?}: {}", workflow_path, e))?;
-    let mut workflow: Value = serde_json::from_str(&workflow_str)?;
-
-    let source_filename = upload_image(address, source_image, "image").await?;
-    if let Some(node) = workflow.get_mut(&inputs.source_image_node_id) {
-        node["inputs"]["image"] = json!(source_filename);
-    } else {
-        return Err(anyhow!("Source image node ID '{}' not found in workflow.", inputs.source_image_node_id));
+    template: &WorkflowTemplate,
+    request: WorkflowRequest,
+    options: &ExecutionOptions,
+    on_progress: impl Fn(ComfyProgress),
+) -> Result<Vec<Vec<u8>>> {
+    let client = build_client(options)?;
+    let mut workflow = template.workflow.clone();
+    apply_parameters(&mut workflow, template, &request.parameters)?;
+
+    let source_filename = upload_image(&client, address, request.source_image, "image").await?;
+    if workflow.get(&template.source_image_node_id).is_none() {
+        return Err(anyhow!("Source image node ID '{}' not found in workflow.", template.source_image_node_id));
     }
+    set_node_input(&mut workflow, &template.source_image_node_id, "image", json!(source_filename))?;
 
-    if let (Some(mask), Some(mask_node_id)) = (mask_image, &inputs.mask_image_node_id) {
-        let mask_filename = upload_image(address, mask, "image").await?;
-        if let Some(node) = workflow.get_mut(mask_node_id) {
-            node["inputs"]["image"] = json!(mask_filename);
-        } else {
+    if let (Some(mask), Some(mask_node_id)) = (request.mask_image, &template.mask_image_node_id) {
+        if workflow.get(mask_node_id).is_none() {
             return Err(anyhow!("Mask image node ID '{}' not found in workflow.", mask_node_id));
         }
+        let mask_filename = upload_image(&client, address, mask, "image").await?;
+        set_node_input(&mut workflow, mask_node_id, "image", json!(mask_filename))?;
     }
 
-    if let (Some(prompt_text), Some(prompt_node_id)) = (text_prompt, &inputs.text_prompt_node_id) {
-        if let Some(node) = workflow.get_mut(prompt_node_id) {
-            if let Some(node_inputs) = node.get_mut("inputs") {
-                node_inputs["text"] = json!(prompt_text);
-            }
-        } else {
+    if let (Some(prompt_text), Some(prompt_node_id)) = (request.text_prompt, &template.text_prompt_node_id) {
+        if workflow.get(prompt_node_id).is_none() {
             return Err(anyhow!("Text prompt node ID '{}' not found in workflow.", prompt_node_id));
         }
+        set_node_input(&mut workflow, prompt_node_id, "text", json!(prompt_text))?;
+    }
+
+    if options.cancel.is_cancelled() {
+        bail!("ComfyUI workflow was cancelled");
     }
 
     let client_id = Uuid::new_v4().to_string();
     let ws_url = format!("ws://{}/ws?clientId={}", address, client_id);
-    let (ws_stream, _) = connect_async(&ws_url).await.map_err(|e| anyhow!("Failed to connect to WebSocket at {}: {}", ws_url, e))?;
+    let (ws_stream, _) = tokio::time::timeout(options.request_timeout, connect_async(&ws_url))
+        .await
+        .map_err(|_| anyhow!("Timed out connecting to WebSocket at {}", ws_url))?
+        .map_err(|e| anyhow!("Failed to connect to WebSocket at {}: {}", ws_url, e))?;
     let (_write, mut read) = ws_stream.split();
 
-    let prompt_id = queue_prompt(address, workflow, &client_id).await?;
+    let prompt_id = queue_prompt(&client, address, workflow, &client_id).await?;
+    on_progress(ComfyProgress::new("queued", None, 0, 0));
+    wait_for_completion(&client, address, &prompt_id, &mut read, options, &on_progress).await?;
 
-    loop {
-        match read.next().await {
-            Some(Ok(msg)) => {
-                if let Message::Text(text) = msg {
-                    if let Ok(v) = serde_json::from_str::<Value>(&text) {
-                        if v["type"] == "executing" && v["data"]["node"].is_null() && v["data"]["prompt_id"] == prompt_id {
-                            break;
-                        }
-                    }
+    let history = get_history(&client, address, &prompt_id).await?;
+    let outputs = history.get(&prompt_id)
+        .and_then(|h| h.get("outputs"))
+        .and_then(|o| o.as_object())
+        .ok_or_else(|| anyhow!("Could not find outputs for prompt_id {} in history", prompt_id))?;
+
+    let mut node_ids: Vec<&String> = if template.output_node_ids.is_empty() {
+        outputs.keys().collect()
+    } else {
+        template.output_node_ids.iter().collect()
+    };
+    node_ids.sort_by_key(|id| (id.parse::<u64>().unwrap_or(u64::MAX), id.to_string()));
+
+    let image_infos: Vec<&Value> = node_ids
+        .iter()
+        .filter_map(|id| outputs.get(id.as_str()))
+        .filter_map(|node| node.get("images").and_then(|i| i.as_array()))
+        .flatten()
+        .collect();
+    if image_infos.is_empty() {
+        return Err(anyhow!("Workflow produced no output images"));
+    }
+
+    let mut images = Vec::with_capacity(image_infos.len());
+    for (index, info) in image_infos.iter().enumerate() {
+        on_progress(ComfyProgress::new("downloading", None, index as u64, image_infos.len() as u64));
+        let filename = info.get("filename").and_then(|f| f.as_str()).ok_or_else(|| anyhow!("Could not get filename from output"))?;
+        let subfolder = info.get("subfolder").and_then(|s| s.as_str()).unwrap_or("");
+        let folder_type = info.get("type").and_then(|t| t.as_str()).ok_or_else(|| anyhow!("Could not get type from output"))?;
+        images.push(get_image(&client, address, filename, subfolder, folder_type).await?);
+    }
+    Ok(images)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::SinkExt;
+    use std::sync::Mutex;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::broadcast;
+
+    #[derive(Clone, Copy)]
+    enum Script {
+        Complete,
+        Hang,
+        Fail,
+    }
+
+    #[derive(Default)]
+    struct Recorded {
+        prompts: Vec<Value>,
+        uploads: usize,
+        interrupted: bool,
+    }
+
+    /// Minimal ComfyUI stand-in serving the HTTP routes and websocket used
+    /// by the connector.
+    struct MockComfy {
+        address: String,
+        recorded: Arc<Mutex<Recorded>>,
+    }
+
+    impl MockComfy {
+        async fn start(script: Script) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap().to_string();
+            let recorded = Arc::new(Mutex::new(Recorded::default()));
+            let (events, _) = broadcast::channel::<String>(64);
+
+            let state = recorded.clone();
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(handle_connection(stream, script, state.clone(), events.clone()));
+                }
+            });
+            Self { address, recorded }
+        }
+    }
+
+    async fn handle_connection(stream: TcpStream, script: Script, recorded: Arc<Mutex<Recorded>>, events: broadcast::Sender<String>) {
+        let mut peek = [0u8; 16];
+        let n = stream.peek(&mut peek).await.unwrap_or(0);
+        if peek[..n].starts_with(b"GET /ws") {
+            let mut receiver = events.subscribe();
+            let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+            while let Ok(text) = receiver.recv().await {
+                if ws.send(Message::Text(text)).await.is_err() {
+                    break;
                 }
             }
-            Some(Err(e)) => return Err(anyhow!("WebSocket error: {}", e)),
-            None => return Err(anyhow!("WebSocket stream ended unexpectedly")),
+            return;
         }
+
+        let mut stream = stream;
+        let mut buffer = Vec::new();
+        let mut chunk = [0u8; 4096];
+        let header_end = loop {
+            let n = stream.read(&mut chunk).await.unwrap();
+            if n == 0 {
+                return;
+            }
+            buffer.extend_from_slice(&chunk[..n]);
+            if let Some(pos) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+                break pos + 4;
+            }
+        };
+        let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+        let content_length = head
+            .lines()
+            .find_map(|l| l.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap()))
+            .unwrap_or(0);
+        while buffer.len() < header_end + content_length {
+            let n = stream.read(&mut chunk).await.unwrap();
+            buffer.extend_from_slice(&chunk[..n]);
+        }
+        let body = &buffer[header_end..header_end + content_length];
+        let request_line = head.lines().next().unwrap_or_default().to_string();
+        let path = request_line.split_whitespace().nth(1).unwrap_or("/").to_string();
+
+        let response: Vec<u8> = if path.starts_with("/upload/image") {
+            let mut state = recorded.lock().unwrap();
+            state.uploads += 1;
+            json!({ "name": format!("upload_{}.png", state.uploads) }).to_string().into_bytes()
+        } else if path == "/prompt" {
+            let payload: Value = serde_json::from_slice(body).unwrap();
+            recorded.lock().unwrap().prompts.push(payload["prompt"].clone());
+            let events = events.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                let messages = match script {
+                    Script::Complete => vec![
+                        json!({ "type": "status", "data": { "status": { "exec_info": { "queue_remaining": 1 } } } }),
+                        json!({ "type": "executing", "data": { "node": "3", "prompt_id": "other" } }),
+                        json!({ "type": "executing", "data": { "node": "3", "prompt_id": "p1" } }),
+                        json!({ "type": "progress", "data": { "value": 1, "max": 2, "node": "3", "prompt_id": "p1" } }),
+                        json!({ "type": "progress", "data": { "value": 2, "max": 2, "node": "3", "prompt_id": "p1" } }),
+                        json!({ "type": "executing", "data": { "node": null, "prompt_id": "p1" } }),
+                    ],
+                    Script::Hang => vec![
+                        json!({ "type": "progress", "data": { "value": 1, "max": 20, "node": "3", "prompt_id": "p1" } }),
+                    ],
+                    Script::Fail => vec![json!({
+                        "type": "execution_error",
+                        "data": { "prompt_id": "p1", "node_id": "3", "node_type": "KSampler", "exception_message": "out of memory" }
+                    })],
+                };
+                for message in messages {
+                    let _ = events.send(message.to_string());
+                }
+            });
+            json!({ "prompt_id": "p1" }).to_string().into_bytes()
+        } else if path.starts_with("/history/p1") {
+            json!({ "p1": { "outputs": {
+                "55": { "images": [{ "filename": "preview.png", "subfolder": "", "type": "temp" }] },
+                "252": { "images": [
+                    { "filename": "variant_a.png", "subfolder": "", "type": "output" },
+                    { "filename": "variant_b.png", "subfolder": "", "type": "output" }
+                ] }
+            } } })
+            .to_string()
+            .into_bytes()
+        } else if let Some(query) = path.strip_prefix("/view?") {
+            let filename = query
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("filename="))
+                .unwrap_or_default();
+            filename.as_bytes().to_vec()
+        } else if path == "/interrupt" {
+            recorded.lock().unwrap().interrupted = true;
+            b"{}".to_vec()
+        } else if path.starts_with("/object_info/CheckpointLoaderSimple") {
+            json!({ "CheckpointLoaderSimple": { "input": { "required": {
+                "ckpt_name": [["sdxl.safetensors", "flux.safetensors"]]
+            } } } })
+            .to_string()
+            .into_bytes()
+        } else {
+            b"{}".to_vec()
+        };
+
+        let head = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            response.len()
+        );
+        let _ = stream.write_all(head.as_bytes()).await;
+        let _ = stream.write_all(&response).await;
+        let _ = stream.shutdown().await;
     }
 
-    let history = get_history(address, &prompt_id).await?;
-    let outputs = history.get(&prompt_id)
-        .and_then(|h| h.get("outputs"))
-        .ok_or_else(|| anyhow!("Could not find outputs for prompt_id {} in history", prompt_id))?;
-    
-    let images = outputs.get(&inputs.final_output_node_id)
-        .and_then(|n| n.get("images"))
-        .and_then(|i| i.as_array())
-        .ok_or_else(|| anyhow!("No 'images' array found in specified output node '{}'", inputs.final_output_node_id))?;
-    
-    if images.is_empty() {
-        return Err(anyhow!("Output node '{}' produced no images", inputs.final_output_node_id));
-    }
-
-    let first_image_info = &images[0];
-    let final_filename = first_image_info.get("filename").and_then(|f| f.as_str()).ok_or_else(|| anyhow!("Could not get filename from output"))?;
-    let subfolder = first_image_info.get("subfolder").and_then(|s| s.as_str()).unwrap_or("");
-    let folder_type = first_image_info.get("type").and_then(|t| t.as_str()).ok_or_else(|| anyhow!("Could not get type from output"))?;
-
-    get_image(address, final_filename, subfolder, folder_type).await
-}
\ No newline at end of file
+    fn template(output_node_ids: Vec<String>) -> WorkflowTemplate {
+        WorkflowTemplate {
+            id: "test".to_string(),
+            name: "Test".to_string(),
+            description: String::new(),
+            source_image_node_id: "11".to_string(),
+            mask_image_node_id: Some("148".to_string()),
+            text_prompt_node_id: Some("6".to_string()),
+            output_node_ids,
+            parameters: vec![
+                WorkflowParameter {
+                    id: "seed".to_string(),
+                    label: "Seed".to_string(),
+                    kind: ParameterKind::Seed,
+                    node_id: "3".to_string(),
+                    input: "seed".to_string(),
+                    default: Some(json!(-1)),
+                    min: None,
+                    max: None,
+                },
+                WorkflowParameter {
+                    id: "steps".to_string(),
+                    label: "Steps".to_string(),
+                    kind: ParameterKind::Int,
+                    node_id: "3".to_string(),
+                    input: "steps".to_string(),
+                    default: None,
+                    min: Some(1.0),
+                    max: Some(50.0),
+                },
+            ],
+            workflow: json!({
+                "3": { "class_type": "KSampler", "inputs": { "seed": 0, "steps": 20 } },
+                "6": { "class_type": "CLIPTextEncode", "inputs": { "text": "" } },
+                "11": { "class_type": "LoadImage", "inputs": { "image": "" } },
+                "148": { "class_type": "LoadImageMask", "inputs": { "image": "" } }
+            }),
+        }
+    }
+
+    fn request(parameters: HashMap<String, Value>) -> WorkflowRequest {
+        WorkflowRequest {
+            source_image: DynamicImage::new_rgb8(8, 8),
+            mask_image: Some(DynamicImage::new_luma8(8, 8)),
+            text_prompt: Some("a red barn".to_string()),
+            parameters,
+        }
+    }
+
+    #[tokio::test]
+    async fn returns_all_output_variants_and_reports_progress() {
+        let mock = MockComfy::start(Script::Complete).await;
+        let progress = Mutex::new(Vec::new());
+        let parameters = HashMap::from([("steps".to_string(), json!(80))]);
+
+        let images = execute_workflow(
+            &mock.address,
+            &template(vec!["252".to_string()]),
+            request(parameters),
+            &ExecutionOptions::default(),
+            |p| progress.lock().unwrap().push(p),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(images, vec![b"variant_a.png".to_vec(), b"variant_b.png".to_vec()]);
+        let progress = progress.into_inner().unwrap();
+        assert!(progress.contains(&ComfyProgress::new("sampling", Some("3"), 2, 2)));
+        assert_eq!(progress.iter().filter(|p| p.stage == "executing").count(), 1);
+
+        let recorded = mock.recorded.lock().unwrap();
+        let prompt = &recorded.prompts[0];
+        assert_eq!(recorded.uploads, 2);
+        assert_eq!(prompt["3"]["inputs"]["steps"], json!(50));
+        assert_ne!(prompt["3"]["inputs"]["seed"], json!(0));
+        assert_eq!(prompt["6"]["inputs"]["text"], json!("a red barn"));
+        assert_eq!(prompt["11"]["inputs"]["image"], json!("upload_1.png"));
+        assert_eq!(prompt["148"]["inputs"]["image"], json!("upload_2.png"));
+    }
+
+    #[tokio::test]
+    async fn returns_every_output_node_when_none_are_declared() {
+        let mock = MockComfy::start(Script::Complete).await;
+        let images = execute_workflow(&mock.address, &template(Vec::new()), request(HashMap::new()), &ExecutionOptions::default(), |_| {})
+            .await
+            .unwrap();
+        assert_eq!(images.len(), 3);
+        assert_eq!(images[0], b"preview.png".to_vec());
+    }
+
+    #[tokio::test]
+    async fn cancellation_interrupts_the_server() {
+        let mock = MockComfy::start(Script::Hang).await;
+        let options = ExecutionOptions::default();
+        let cancel = options.cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            cancel.cancel();
+        });
+
+        let result = execute_workflow(&mock.address, &template(Vec::new()), request(HashMap::new()), &options, |_| {}).await;
+        assert!(result.unwrap_err().to_string().contains("cancelled"));
+        assert!(mock.recorded.lock().unwrap().interrupted);
+    }
+
+    #[tokio::test]
+    async fn execution_timeout_interrupts_the_server() {
+        let mock = MockComfy::start(Script::Hang).await;
+        let options = ExecutionOptions { execution_timeout: Duration::from_millis(300), ..Default::default() };
+
+        let result = execute_workflow(&mock.address, &template(Vec::new()), request(HashMap::new()), &options, |_| {}).await;
+        assert!(result.unwrap_err().to_string().contains("timed out"));
+        assert!(mock.recorded.lock().unwrap().interrupted);
+    }
+
+    #[tokio::test]
+    async fn execution_errors_are_reported() {
+        let mock = MockComfy::start(Script::Fail).await;
+        let result = execute_workflow(&mock.address, &template(Vec::new()), request(HashMap::new()), &ExecutionOptions::default(), |_| {}).await;
+        let message = result.unwrap_err().to_string();
+        assert!(message.contains("KSampler") && message.contains("out of memory"));
+    }
+
+    #[tokio::test]
+    async fn lists_checkpoints() {
+        let mock = MockComfy::start(Script::Complete).await;
+        let checkpoints = get_checkpoints(&mock.address, &ExecutionOptions::default()).await.unwrap();
+        assert_eq!(checkpoints, vec!["sdxl.safetensors", "flux.safetensors"]);
+    }
+
+    #[test]
+    fn loads_user_templates_over_builtins() {
+        let dir = std::env::temp_dir().join(format!("rapidraw-workflows-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut user_template = serde_json::to_value(template(vec!["9".to_string()])).unwrap();
+        user_template["workflow"] = json!({ "11": { "inputs": { "image": "" } } });
+        fs::write(dir.join("generative_replace.json"), user_template.to_string()).unwrap();
+        fs::write(dir.join("broken.json"), "{ not json").unwrap();
+
+        let templates = load_workflow_templates(Some(&dir));
+        fs::remove_dir_all(&dir).unwrap();
+
+        let replaced: Vec<_> = templates.iter().filter(|t| t.id == GENERATIVE_REPLACE_ID).collect();
+        assert_eq!(replaced.len(), 1);
+        assert_eq!(replaced[0].output_node_ids, vec!["9".to_string()]);
+        assert_eq!(replaced[0].parameters.len(), 2);
+        assert!(templates.iter().all(|t| t.id != "broken"));
+    }
+}
@@ -0,0 +1,151 @@
+use rayon::prelude::*;
+
+/// Standard deviation of unit white noise in each à trous detail level of
+/// the B3-spline wavelet, used to scale thresholds per level.
+const LEVEL_NOISE: [f32; 5] = [0.8908, 0.2007, 0.0856, 0.0413, 0.0205];
+/// Threshold in noise standard deviations at full strength.
+const MAX_THRESHOLD: f32 = 3.0;
+/// Chroma noise is more objectionable than luminance noise and carries
+/// little detail, so it is suppressed harder.
+const CHROMA_THRESHOLD_SCALE: f32 = 1.5;
+/// Pixels sampled to estimate the noise level.
+const NOISE_SAMPLES: usize = 200_000;
+
+/// One pass of the B3-spline kernel with holes of `step` pixels, mirrored at
+/// the borders.
+fn atrous_smooth(src: &[f32], width: usize, height: usize, step: usize) -> Vec<f32> {
+    const KERNEL: [f32; 5] = [1.0 / 16.0, 4.0 / 16.0, 6.0 / 16.0, 4.0 / 16.0, 1.0 / 16.0];
+    let mirror = |i: isize, n: usize| -> usize {
+        let n = n as isize;
+        let mut i = i;
+        if i < 0 {
+            i = -i;
+        }
+        if i >= n {
+            i = 2 * (n - 1) - i;
+        }
+        i.clamp(0, n - 1) as usize
+    };
+
+    let mut horizontal = vec![0.0f32; src.len()];
+    horizontal
+        .par_chunks_exact_mut(width)
+        .zip(src.par_chunks_exact(width))
+        .for_each(|(out, row)| {
+            for (x, o) in out.iter_mut().enumerate() {
+                *o = KERNEL
+                    .iter()
+                    .enumerate()
+                    .map(|(k, w)| w * row[mirror(x as isize + (k as isize - 2) * step as isize, width)])
+                    .sum();
+            }
+        });
+
+    let mut out = vec![0.0f32; src.len()];
+    out.par_chunks_exact_mut(width).enumerate().for_each(|(y, row)| {
+        let rows: Vec<&[f32]> = (0..5)
+            .map(|k| {
+                let sy = mirror(y as isize + (k as isize - 2) * step as isize, height);
+                &horizontal[sy * width..(sy + 1) * width]
+            })
+            .collect();
+        for (x, o) in row.iter_mut().enumerate() {
+            *o = KERNEL.iter().zip(&rows).map(|(w, r)| w * r[x]).sum();
+        }
+    });
+    out
+}
+
+/// Robust noise estimate from the finest detail level (median absolute
+/// deviation), normalized to the noise of the undecomposed plane.
+fn estimate_noise(detail: &[f32]) -> f32 {
+    let stride = (detail.len() / NOISE_SAMPLES).max(1);
+    let mut samples: Vec<f32> = detail.iter().step_by(stride).map(|v| v.abs()).collect();
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let mid = samples.len() / 2;
+    let (_, median, _) = samples.select_nth_unstable_by(mid, f32::total_cmp);
+    *median / 0.6745 / LEVEL_NOISE[0]
+}
+
+/// Shrinks the wavelet details of a plane below `threshold` noise deviations.
+fn denoise_plane(plane: &mut [f32], width: usize, height: usize, threshold: f32) {
+    let mut current = plane.to_vec();
+    let mut result = vec![0.0f32; plane.len()];
+    let mut sigma = None;
+
+    for (level, level_noise) in LEVEL_NOISE.iter().enumerate() {
+        let step = 1 << level;
+        if step * 2 >= width.min(height) {
+            break;
+        }
+        let smooth = atrous_smooth(&current, width, height, step);
+        let mut detail: Vec<f32> = current.par_iter().zip(&smooth).map(|(c, s)| c - s).collect();
+        let sigma = *sigma.get_or_insert_with(|| estimate_noise(&detail));
+
+        let t = threshold * sigma * level_noise;
+        // Non-negative garrote: removes small coefficients like soft
+        // thresholding but leaves strong edges nearly untouched.
+        detail.par_iter_mut().for_each(|d| *d *= (1.0 - (t * t) / (*d * *d).max(1e-20)).max(0.0));
+        result.par_iter_mut().zip(&detail).for_each(|(r, d)| *r += d);
+        current = smooth;
+    }
+
+    plane
+        .par_iter_mut()
+        .zip(result.par_iter().zip(&current))
+        .for_each(|(p, (r, c))| *p = r + c);
+}
+
+fn threshold_for(strength: f32) -> f32 {
+    (strength / 100.0).clamp(0.0, 1.0) * MAX_THRESHOLD
+}
+
+/// Denoises linear RGB in place. The square root roughly equalizes the
+/// signal-dependent shot noise, and the image is split into luminance and two
+/// chroma planes before thresholding. `strength` is 0..100.
+pub fn denoise_linear_rgb(data: &mut [[f32; 3]], width: usize, height: usize, strength: f32) {
+    let threshold = threshold_for(strength);
+    if threshold <= 0.0 || width < 2 || height < 2 {
+        return;
+    }
+
+    let n = width * height;
+    let mut luma = vec![0.0f32; n];
+    let mut cb = vec![0.0f32; n];
+    let mut cr = vec![0.0f32; n];
+    data.par_iter()
+        .zip(luma.par_iter_mut().zip(cb.par_iter_mut().zip(cr.par_iter_mut())))
+        .for_each(|(p, (l, (b, r)))| {
+            let [sr, sg, sb] = p.map(|c| c.max(0.0).sqrt());
+            *l = (sr + sg + sb) / 3.0;
+            *b = sb - *l;
+            *r = sr - *l;
+        });
+
+    denoise_plane(&mut luma, width, height, threshold);
+    denoise_plane(&mut cb, width, height, threshold * CHROMA_THRESHOLD_SCALE);
+    denoise_plane(&mut cr, width, height, threshold * CHROMA_THRESHOLD_SCALE);
+
+    data.par_iter_mut()
+        .zip(luma.par_iter().zip(cb.par_iter().zip(cr.par_iter())))
+        .for_each(|(p, (l, (b, r)))| {
+            let sr = l + r;
+            let sb = l + b;
+            let sg = 3.0 * l - sr - sb;
+            *p = [sr, sg, sb].map(|c| c.max(0.0).powi(2));
+        });
+}
+
+/// Monochrome variant of `denoise_linear_rgb`.
+pub fn denoise_linear_mono(data: &mut [f32], width: usize, height: usize, strength: f32) {
+    let threshold = threshold_for(strength);
+    if threshold <= 0.0 || width < 2 || height < 2 {
+        return;
+    }
+
+    data.par_iter_mut().for_each(|v| *v = v.max(0.0).sqrt());
+    denoise_plane(data, width, height, threshold);
+    data.par_iter_mut().for_each(|v| *v = v.max(0.0).powi(2));
+}
@@ -0,0 +1,343 @@
+use std::collections::HashSet;
+use std::fs;
+use std::thread;
+
+use anyhow::{bail, Context, Result};
+use rawler::{decoders::RawDecodeParams, rawsource::RawSource, Orientation};
+use rayon::prelude::*;
+use serde::Serialize;
+use tauri::{AppHandle, Emitter};
+
+use crate::formats::is_raw_file;
+use crate::image_loader::{load_base_image_from_bytes, read_orientation};
+use crate::image_processing::apply_orientation;
+use crate::mask_refinement::box_filter;
+use crate::raw_processing::RawDevelopSettings;
+use crate::retouch::{RetouchMode, RetouchParameters, RetouchPoint, RetouchSpace, RetouchStroke};
+
+/// Long side of the frames during analysis.
+const ANALYSIS_SIZE: u32 = 1536;
+/// Radius of the local background estimate, in analysis pixels.
+const BACKGROUND_RADIUS: usize = 16;
+/// Dust darkens the background by at least this fraction...
+const MIN_CONTRAST: f32 = 0.012;
+/// ...and by at most this one; darker blobs are scene content.
+const MAX_CONTRAST: f32 = 0.35;
+const MAX_SPOT_RADIUS: f32 = 14.0;
+const MIN_SPOT_AREA: usize = 4;
+/// Mean relative gradient below which an area counts as flat (sky, walls).
+const FLAT_TEXTURE: f32 = 0.01;
+/// Detections closer than this (fraction of the long side) are the same spot.
+const MATCH_DISTANCE: f32 = 0.004;
+const MIN_SCORE: f32 = 0.3;
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DustSpot {
+    /// Normalized sensor coordinates, before orientation.
+    pub x: f32,
+    pub y: f32,
+    /// Fraction of the long side.
+    pub radius: f32,
+    /// Mean darkening relative to the surroundings.
+    pub contrast: f32,
+    pub detected_frames: usize,
+    /// Frames that were flat enough at this position to show the spot.
+    pub checked_frames: usize,
+    pub score: f32,
+    /// Proposed heal patch in sensor space.
+    pub retouch: RetouchParameters,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DustDetectionResult {
+    pub spots: Vec<DustSpot>,
+    pub frame_count: usize,
+}
+
+struct Candidate {
+    x: f32,
+    y: f32,
+    radius: f32,
+    contrast: f32,
+}
+
+struct FrameAnalysis {
+    width: usize,
+    height: usize,
+    flat: Vec<bool>,
+    candidates: Vec<Candidate>,
+}
+
+impl FrameAnalysis {
+    fn is_flat_at(&self, x: f32, y: f32) -> bool {
+        let px = ((x * self.width as f32) as usize).min(self.width - 1);
+        let py = ((y * self.height as f32) as usize).min(self.height - 1);
+        self.flat[py * self.width + px]
+    }
+}
+
+struct Cluster {
+    x: f32,
+    y: f32,
+    radius: f32,
+    contrast: f32,
+    frames: HashSet<usize>,
+}
+
+fn camera_id(bytes: &[u8], path: &str) -> Option<String> {
+    if !is_raw_file(path) {
+        return None;
+    }
+    let source = RawSource::new_from_slice(bytes);
+    let decoder = rawler::get_decoder(&source).ok()?;
+    let metadata = decoder.raw_metadata(&source, &RawDecodeParams::default()).ok()?;
+    Some(format!(
+        "{} {} {}",
+        metadata.make,
+        metadata.model,
+        metadata.exif.serial_number.unwrap_or_default()
+    ))
+}
+
+fn inverse_orientation(orientation: Orientation) -> Orientation {
+    match orientation {
+        Orientation::Rotate90 => Orientation::Rotate270,
+        Orientation::Rotate270 => Orientation::Rotate90,
+        other => other,
+    }
+}
+
+/// Luminance of the frame in sensor orientation, downscaled for analysis.
+fn load_sensor_luminance(path: &str, bytes: &[u8]) -> Result<(Vec<f32>, usize, usize)> {
+    let image = load_base_image_from_bytes(bytes, path, true, &RawDevelopSettings::default())
+        .with_context(|| format!("Failed to load {}", path))?;
+    let image = apply_orientation(image, inverse_orientation(read_orientation(bytes, path)));
+    let image = if image.width().max(image.height()) > ANALYSIS_SIZE {
+        image.thumbnail(ANALYSIS_SIZE, ANALYSIS_SIZE)
+    } else {
+        image
+    };
+    let rgb = image.to_rgb32f();
+    let luminance = rgb
+        .pixels()
+        .map(|p| 0.2126 * p[0] + 0.7152 * p[1] + 0.0722 * p[2])
+        .collect();
+    Ok((luminance, rgb.width() as usize, rgb.height() as usize))
+}
+
+/// Finds small, soft darkenings of flat areas in one frame.
+fn analyze_frame(luminance: &[f32], width: usize, height: usize) -> FrameAnalysis {
+    let smooth = box_filter(&box_filter(luminance, width, height, 1), width, height, 1);
+    let background = box_filter(
+        &box_filter(luminance, width, height, BACKGROUND_RADIUS),
+        width,
+        height,
+        BACKGROUND_RADIUS,
+    );
+
+    let relative: Vec<f32> = smooth
+        .par_iter()
+        .zip(background.par_iter())
+        .map(|(s, b)| s / b.max(1e-3) - 1.0)
+        .collect();
+    let gradient: Vec<f32> = (0..width * height)
+        .into_par_iter()
+        .map(|i| {
+            let (x, y) = (i % width, i / width);
+            let gx = smooth[y * width + (x + 1).min(width - 1)] - smooth[y * width + x.saturating_sub(1)];
+            let gy = smooth[(y + 1).min(height - 1) * width + x] - smooth[y.saturating_sub(1) * width + x];
+            0.5 * (gx * gx + gy * gy).sqrt() / background[i].max(1e-3)
+        })
+        .collect();
+    let texture = box_filter(&gradient, width, height, BACKGROUND_RADIUS);
+
+    // Dust is invisible in shadows and clipped highlights.
+    let flat: Vec<bool> = (0..width * height)
+        .map(|i| texture[i] < FLAT_TEXTURE && background[i] > 0.08 && background[i] < 0.97)
+        .collect();
+
+    let mut visited = vec![false; width * height];
+    let mut candidates = Vec::new();
+    for start in 0..width * height {
+        if visited[start] || !flat[start] || relative[start] > -MIN_CONTRAST {
+            continue;
+        }
+
+        let mut stack = vec![start];
+        visited[start] = true;
+        let (mut area, mut depth_sum, mut sx, mut sy, mut peak) = (0usize, 0.0f32, 0.0f32, 0.0f32, 0.0f32);
+        while let Some(i) = stack.pop() {
+            let (x, y) = (i % width, i / width);
+            let depth = -relative[i];
+            area += 1;
+            depth_sum += depth;
+            sx += depth * x as f32;
+            sy += depth * y as f32;
+            peak = peak.max(depth);
+
+            let neighbours = [
+                (x > 0).then(|| i - 1),
+                (x + 1 < width).then(|| i + 1),
+                (y > 0).then(|| i - width),
+                (y + 1 < height).then(|| i + width),
+            ];
+            for n in neighbours.into_iter().flatten() {
+                if !visited[n] && flat[n] && relative[n] <= -MIN_CONTRAST {
+                    visited[n] = true;
+                    stack.push(n);
+                }
+            }
+        }
+
+        let radius = (area as f32 / std::f32::consts::PI).sqrt();
+        if area < MIN_SPOT_AREA || radius > MAX_SPOT_RADIUS || peak > MAX_CONTRAST {
+            continue;
+        }
+        candidates.push(Candidate {
+            x: (sx / depth_sum + 0.5) / width as f32,
+            y: (sy / depth_sum + 0.5) / height as f32,
+            radius: radius / width.max(height) as f32,
+            contrast: depth_sum / area as f32,
+        });
+    }
+
+    FrameAnalysis { width, height, flat, candidates }
+}
+
+/// Groups detections at the same sensor position and scores each group by
+/// how consistently it appears in the frames where it could be seen.
+fn score_clusters(frames: &[FrameAnalysis]) -> Vec<DustSpot> {
+    let mut detections: Vec<(usize, &Candidate)> = frames
+        .iter()
+        .enumerate()
+        .flat_map(|(index, frame)| frame.candidates.iter().map(move |c| (index, c)))
+        .collect();
+    detections.sort_by(|a, b| b.1.contrast.total_cmp(&a.1.contrast));
+
+    let aspect = frames
+        .first()
+        .map(|f| f.width as f32 / f.height as f32)
+        .unwrap_or(1.0);
+    let (scale_x, scale_y) = if aspect >= 1.0 { (1.0, 1.0 / aspect) } else { (aspect, 1.0) };
+
+    let mut clusters: Vec<Cluster> = Vec::new();
+    for (frame, candidate) in detections {
+        let tolerance = MATCH_DISTANCE.max(candidate.radius);
+        let existing = clusters.iter_mut().find(|cluster| {
+            let dx = (cluster.x - candidate.x) * scale_x;
+            let dy = (cluster.y - candidate.y) * scale_y;
+            (dx * dx + dy * dy).sqrt() <= tolerance.max(cluster.radius)
+        });
+        match existing {
+            Some(cluster) if cluster.frames.contains(&frame) => {}
+            Some(cluster) => {
+                let n = cluster.frames.len() as f32;
+                cluster.x = (cluster.x * n + candidate.x) / (n + 1.0);
+                cluster.y = (cluster.y * n + candidate.y) / (n + 1.0);
+                cluster.radius = (cluster.radius * n + candidate.radius) / (n + 1.0);
+                cluster.contrast = (cluster.contrast * n + candidate.contrast) / (n + 1.0);
+                cluster.frames.insert(frame);
+            }
+            None => clusters.push(Cluster {
+                x: candidate.x,
+                y: candidate.y,
+                radius: candidate.radius,
+                contrast: candidate.contrast,
+                frames: HashSet::from([frame]),
+            }),
+        }
+    }
+
+    let mut spots: Vec<DustSpot> = clusters
+        .into_iter()
+        .filter_map(|cluster| {
+            let detected = cluster.frames.len();
+            let checked = frames
+                .iter()
+                .enumerate()
+                .filter(|(index, frame)| cluster.frames.contains(index) || frame.is_flat_at(cluster.x, cluster.y))
+                .count();
+            // A single detection carries little evidence, repeated ones more.
+            let consistency = detected as f32 / checked.max(1) as f32;
+            let score = consistency * detected as f32 / (detected as f32 + 1.0);
+            if score < MIN_SCORE {
+                return None;
+            }
+
+            let retouch = RetouchParameters {
+                mode: RetouchMode::Heal,
+                strokes: vec![RetouchStroke {
+                    points: vec![RetouchPoint { x: cluster.x, y: cluster.y }],
+                    radius: cluster.radius * 2.0 + 0.002,
+                    feather: 0.6,
+                }],
+                source_offset: None,
+                opacity: 1.0,
+                space: RetouchSpace::Sensor,
+            };
+            Some(DustSpot {
+                x: cluster.x,
+                y: cluster.y,
+                radius: cluster.radius,
+                contrast: cluster.contrast,
+                detected_frames: detected,
+                checked_frames: checked,
+                score,
+                retouch,
+            })
+        })
+        .collect();
+    spots.sort_by(|a, b| b.score.total_cmp(&a.score));
+    spots
+}
+
+fn detect(paths: &[String], app_handle: &AppHandle) -> Result<DustDetectionResult> {
+    let total = paths.len();
+    let emit_progress = |stage: &str, current: usize| {
+        let _ = app_handle.emit(
+            "dust-detection-progress",
+            serde_json::json!({ "stage": stage, "current": current, "total": total }),
+        );
+    };
+
+    let mut camera: Option<String> = None;
+    let mut frames = Vec::with_capacity(total);
+    for (index, path) in paths.iter().enumerate() {
+        emit_progress("analyzing", index);
+        let bytes = fs::read(path).with_context(|| format!("Failed to read {}", path))?;
+        if let Some(id) = camera_id(&bytes, path) {
+            match &camera {
+                Some(first) if *first != id => bail!("All frames must come from the same camera"),
+                _ => camera = Some(id),
+            }
+        }
+        let (luminance, width, height) = load_sensor_luminance(path, &bytes)?;
+        if frames.first().is_some_and(|first: &FrameAnalysis| (first.width, first.height) != (width, height)) {
+            bail!("{} does not match the sensor size of the other frames", path);
+        }
+        frames.push(analyze_frame(&luminance, width, height));
+    }
+    emit_progress("scoring", total);
+
+    Ok(DustDetectionResult { spots: score_clusters(&frames), frame_count: total })
+}
+
+#[tauri::command]
+pub fn detect_dust_spots(paths: Vec<String>, app_handle: AppHandle) -> Result<(), String> {
+    if paths.is_empty() {
+        return Err("At least one image is needed.".to_string());
+    }
+
+    thread::spawn(move || match detect(&paths, &app_handle) {
+        Ok(result) => {
+            let _ = app_handle.emit("dust-detection-complete", result);
+        }
+        Err(e) => {
+            let _ = app_handle.emit("dust-detection-error", e.to_string());
+        }
+    });
+
+    Ok(())
+}
@@ -1,6 +1,6 @@
 use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::io::Cursor;
+use std::io::{BufWriter, Cursor};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::sync::atomic::{AtomicUsize, Ordering};
@@ -10,7 +10,12 @@ use std::thread;
 use anyhow::Result;
 use base64::{engine::general_purpose, Engine as _};
 use image::codecs::jpeg::JpegEncoder;
-use image::{DynamicImage, GenericImageView, ImageBuffer, Luma};
+use image::{DynamicImage, GenericImageView, ImageBuffer, Luma, Rgb32FImage};
+use rawler::dng::{writer::DngWriter, DngCompression, DNG_VERSION_V1_4};
+use rawler::formats::tiff::SRational;
+use rawler::imgop::xyz::{Illuminant, XYZ_TO_SRGB_D65};
+use rawler::tags::{DngTag, TiffCommonTag};
+use rawler::Orientation;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -19,14 +24,16 @@ use uuid::Uuid;
 use walkdir::WalkDir;
 
 use crate::gpu_processing;
-use crate::formats::is_supported_image_file;
+use crate::formats::{is_raw_file, is_supported_image_file};
 use crate::image_processing::GpuContext;
 use crate::image_loader;
+use crate::raw_processing::RawDevelopSettings;
+use crate::auto_adjust::{auto_results_to_json, foreground_model, measure_scene_key, perform_auto_analysis};
 use crate::image_processing::{
-    apply_crop, apply_flip, apply_rotation, auto_results_to_json, get_all_adjustments_from_json,
-    perform_auto_analysis, Crop, ImageMetadata,
+    apply_crop, apply_flip, apply_rotation, get_all_adjustments_from_json, Crop, ImageMetadata,
 };
 use crate::mask_generation::{generate_mask_bitmap, MaskDefinition};
+use crate::retouch::{find_source_offset, RetouchMode, RetouchParameters};
 use crate::AppState;
 
 const THUMBNAIL_WIDTH: u32 = 640;
@@ -99,9 +106,17 @@ pub struct AppSettings {
     pub transparent: Option<bool>,
     pub decorations: Option<bool>,
     pub comfyui_address: Option<String>,
+    /// Longest a ComfyUI workflow may run before it is interrupted.
+    pub comfyui_timeout_seconds: Option<u64>,
     pub last_folder_state: Option<LastFolderState>,
     pub adaptive_editor_theme: Option<bool>,
     pub ui_visibility: Option<Value>,
+    /// Local ONNX depth-estimation model used by depth masks.
+    pub depth_model_path: Option<String>,
+    /// Base URL serving the bundled AI models, e.g. a local mirror.
+    pub ai_model_mirror_url: Option<String>,
+    /// Size of the vectorscope grid, parade levels and raw histogram bins.
+    pub scope_resolution: Option<u32>,
 }
 
 impl Default for AppSettings {
@@ -118,9 +133,13 @@ impl Default for AppSettings {
             #[cfg(any(target_os = "windows", target_os = "macos"))]
             decorations: Some(false),
             comfyui_address: None,
+            comfyui_timeout_seconds: None,
             last_folder_state: None,
             adaptive_editor_theme: Some(false),
             ui_visibility: None,
+            depth_model_path: None,
+            ai_model_mirror_url: None,
+            scope_resolution: Some(256),
         }
     }
 }
@@ -366,6 +385,57 @@ pub fn get_sidecar_path(image_path: &str) -> PathBuf {
     path.with_file_name(new_filename)
 }
 
+pub fn unique_sibling_path(source_path: &str, suffix: &str, extension: &str) -> PathBuf {
+    let source = Path::new(source_path);
+    let parent = source.parent().unwrap_or_else(|| Path::new("."));
+    let stem = source.file_stem().unwrap_or_default().to_string_lossy();
+    let mut candidate = parent.join(format!("{}-{}.{}", stem, suffix, extension));
+    let mut counter = 2;
+    while candidate.exists() {
+        candidate = parent.join(format!("{}-{}-{}.{}", stem, suffix, counter, extension));
+        counter += 1;
+    }
+    candidate
+}
+
+/// Highest multiple of the working white a linear DNG keeps. Lowering the
+/// white level to make room for it still leaves 4096 codes below white.
+const LINEAR_DNG_HEADROOM: f32 = 16.0;
+
+/// Writes scene-linear sRGB data as a 16-bit linear DNG. The white level sits
+/// below the top code, so highlights up to `LINEAR_DNG_HEADROOM` times the
+/// working white survive, and the color matrix describes the sRGB primaries,
+/// so raw converters (this one included) develop it like any linear DNG.
+pub fn save_linear_dng(image: &Rgb32FImage, path: &Path) -> Result<()> {
+    let (width, height) = image.dimensions();
+    let peak = image.as_raw().par_iter().copied().reduce(|| 0.0, f32::max);
+    let white_level = (u16::MAX as f32 / peak.clamp(1.0, LINEAR_DNG_HEADROOM)).floor();
+    let data: Vec<u16> = image
+        .as_raw()
+        .par_iter()
+        .map(|&c| (c * white_level).round().clamp(0.0, u16::MAX as f32) as u16)
+        .collect();
+
+    let mut dng = DngWriter::new(BufWriter::new(fs::File::create(path)?), DNG_VERSION_V1_4)?;
+    dng.root_ifd_mut().add_tag(TiffCommonTag::Make, "RapidRAW");
+    dng.root_ifd_mut().add_tag(TiffCommonTag::Model, "Linear sRGB");
+    dng.root_ifd_mut().add_tag(DngTag::UniqueCameraModel, "RapidRAW Linear sRGB");
+    let xyz_to_srgb: Vec<SRational> = XYZ_TO_SRGB_D65
+        .iter()
+        .flatten()
+        .map(|&v| SRational::new((v * 10_000.0).round() as i32, 10_000))
+        .collect();
+    dng.color_matrix(1, Illuminant::D65, xyz_to_srgb);
+
+    {
+        let mut raw = dng.subframe_on_root(0);
+        raw.rgb_image_u16(&data, width as usize, height as usize, DngCompression::Lossless, 1)?;
+        raw.ifd_mut().add_tag(DngTag::WhiteLevel, [white_level as u16; 3]);
+    }
+    dng.close()?;
+    Ok(())
+}
+
 pub fn generate_thumbnail_data(
     path_str: &str,
     gpu_context: Option<&GpuContext>,
@@ -381,8 +451,10 @@ pub fn generate_thumbnail_data(
     let base_image = image_loader::load_and_composite(path_str, &adjustments, true)?;
     let original_dims = base_image.dimensions();
 
-    if let (Some(context), Some(meta)) = (gpu_context, metadata) {
-        if !meta.adjustments.is_null() {
+    // Raw files are scene-referred and need the tone mapper even when unedited.
+    let is_raw = is_raw_file(path_str);
+    if let Some(context) = gpu_context {
+        if !adjustments.is_null() || is_raw {
             const THUMBNAIL_PROCESSING_DIM: u32 = 1280;
             let (full_w, full_h) = original_dims;
 
@@ -400,17 +472,17 @@ pub fn generate_thumbnail_data(
                     (base_image.clone(), 1.0)
                 };
 
-            let rotation_degrees = meta.adjustments["rotation"].as_f64().unwrap_or(0.0) as f32;
-            let flip_horizontal = meta.adjustments["flipHorizontal"]
+            let rotation_degrees = adjustments["rotation"].as_f64().unwrap_or(0.0) as f32;
+            let flip_horizontal = adjustments["flipHorizontal"]
                 .as_bool()
                 .unwrap_or(false);
-            let flip_vertical = meta.adjustments["flipVertical"].as_bool().unwrap_or(false);
+            let flip_vertical = adjustments["flipVertical"].as_bool().unwrap_or(false);
 
             let flipped_image = apply_flip(processing_base, flip_horizontal, flip_vertical);
             let rotated_image = apply_rotation(&flipped_image, rotation_degrees);
 
             let crop_data: Option<Crop> =
-                serde_json::from_value(meta.adjustments["crop"].clone()).ok();
+                serde_json::from_value(adjustments["crop"].clone()).ok();
             let scaled_crop_json = if let Some(c) = &crop_data {
                 serde_json::to_value(Crop {
                     x: c.x * scale_for_gpu as f64,
@@ -428,8 +500,7 @@ pub fn generate_thumbnail_data(
 
             let unscaled_crop_offset = crop_data.map_or((0.0, 0.0), |c| (c.x as f32, c.y as f32));
 
-            let mask_definitions: Vec<MaskDefinition> = meta
-                .adjustments
+            let mask_definitions: Vec<MaskDefinition> = adjustments
                 .get("masks")
                 .and_then(|m| serde_json::from_value(m.clone()).ok())
                 .unwrap_or_else(Vec::new);
@@ -439,6 +510,7 @@ pub fn generate_thumbnail_data(
                 .filter_map(|def| {
                     generate_mask_bitmap(
                         def,
+                        &cropped_preview,
                         preview_w,
                         preview_h,
                         scale_for_gpu,
@@ -450,7 +522,7 @@ pub fn generate_thumbnail_data(
                 })
                 .collect();
 
-            let gpu_adjustments = get_all_adjustments_from_json(&meta.adjustments);
+            let gpu_adjustments = get_all_adjustments_from_json(&adjustments, is_raw);
 
             if let Ok(processed_image) = gpu_processing::process_and_get_dynamic_image(
                 context,
@@ -852,6 +924,69 @@ pub fn save_metadata_and_update_thumbnail(
     Ok(())
 }
 
+fn is_sensor_patch(patch: &Value) -> bool {
+    patch
+        .get("retouch")
+        .and_then(|retouch| retouch.get("space"))
+        .and_then(|space| space.as_str())
+        == Some("sensor")
+}
+
+/// Converts sensor-space retouch patches (such as dust spots) to the
+/// orientation of the file and picks their heal source on this file. When
+/// only such patches are pasted, they are added to the file's existing
+/// patches instead of replacing them.
+fn resolve_sensor_patches(path: &str, existing: Option<&Value>, pasted: &[Value]) -> Value {
+    let orientation = fs::read(path)
+        .map(|bytes| image_loader::read_orientation(&bytes, path))
+        .unwrap_or(Orientation::Normal);
+    // The source search runs on a downscaled copy, so the fast develop is enough.
+    let source_image = std::cell::OnceCell::new();
+
+    let resolved: Vec<Value> = pasted
+        .iter()
+        .map(|patch| {
+            let mut patch = patch.clone();
+            if is_sensor_patch(&patch) {
+                if let Ok(mut params) = serde_json::from_value::<RetouchParameters>(patch["retouch"].clone()) {
+                    params.resolve_sensor_space(orientation);
+                    if params.source_offset.is_none() && params.mode != RetouchMode::Fill {
+                        let image = source_image.get_or_init(|| {
+                            image_loader::load_and_composite(path, &Value::Null, true).ok().map(|i| i.to_rgba32f())
+                        });
+                        params.source_offset = image.as_ref().and_then(|image| find_source_offset(image, &params));
+                    }
+                    patch["retouch"] = serde_json::to_value(params).unwrap_or(Value::Null);
+                }
+            }
+            patch
+        })
+        .collect();
+
+    if !pasted.iter().all(is_sensor_patch) {
+        return Value::Array(resolved);
+    }
+
+    let pasted_ids: HashSet<&str> = resolved
+        .iter()
+        .filter_map(|patch| patch.get("id").and_then(|id| id.as_str()))
+        .collect();
+    let mut merged: Vec<Value> = existing
+        .and_then(|v| v.as_array())
+        .map(|patches| {
+            patches
+                .iter()
+                .filter(|patch| {
+                    !matches!(patch.get("id").and_then(|id| id.as_str()), Some(id) if pasted_ids.contains(id))
+                })
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_default();
+    merged.extend(resolved.iter().cloned());
+    Value::Array(merged)
+}
+
 #[tauri::command]
 pub fn apply_adjustments_to_paths(
     paths: Vec<String>,
@@ -879,7 +1014,13 @@ pub fn apply_adjustments_to_paths(
             (new_adjustments.as_object_mut(), adjustments.as_object())
         {
             for (k, v) in pasted_map {
-                new_map.insert(k.clone(), v.clone());
+                let value = match v.as_array() {
+                    Some(patches) if k == "aiPatches" && patches.iter().any(is_sensor_patch) => {
+                        resolve_sensor_patches(path, new_map.get(k), patches)
+                    }
+                    _ => v.clone(),
+                };
+                new_map.insert(k.clone(), value);
             }
         }
 
@@ -940,29 +1081,57 @@ pub fn reset_adjustments_for_paths(
     Ok(())
 }
 
+fn load_metadata_and_image(path: &str) -> Result<(ImageMetadata, DynamicImage), String> {
+    let sidecar_path = get_sidecar_path(path);
+    let metadata: ImageMetadata = if sidecar_path.exists() {
+        fs::read_to_string(&sidecar_path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    } else {
+        ImageMetadata::default()
+    };
+
+    let raw_settings = RawDevelopSettings::from_adjustments(&metadata.adjustments);
+    let file_bytes = fs::read(path).map_err(|e| e.to_string())?;
+    let image = image_loader::load_base_image_from_bytes(&file_bytes, path, false, &raw_settings)
+        .map_err(|e| e.to_string())?;
+    Ok((metadata, image))
+}
+
+/// Applies auto adjustments to each image, or with `reference_path` only
+/// sets their exposure so their subjects come out as bright as the
+/// reference's does with its current adjustments.
 #[tauri::command]
 pub fn apply_auto_adjustments_to_paths(
     paths: Vec<String>,
+    reference_path: Option<String>,
     app_handle: AppHandle,
 ) -> Result<(), String> {
-    paths.par_iter().for_each(|path| {
-        let result: Result<(), String> = (|| {
-            let file_bytes = fs::read(path).map_err(|e| e.to_string())?;
-            let image =
-                image_loader::load_base_image_from_bytes(&file_bytes, path, false)
-                    .map_err(|e| e.to_string())?;
+    let model = foreground_model(&app_handle);
 
-            let auto_results = perform_auto_analysis(&image);
-            let auto_adjustments_json = auto_results_to_json(&auto_results);
+    let reference_key = match &reference_path {
+        Some(reference) => {
+            let (metadata, image) = load_metadata_and_image(reference)?;
+            let exposure = metadata.adjustments["exposure"].as_f64().unwrap_or(0.0);
+            Some(measure_scene_key(&image, model.as_deref()) * exposure.exp2())
+        }
+        None => None,
+    };
 
-            let sidecar_path = get_sidecar_path(path);
-            let mut existing_metadata: ImageMetadata = if sidecar_path.exists() {
-                fs::read_to_string(&sidecar_path)
-                    .ok()
-                    .and_then(|content| serde_json::from_str(&content).ok())
-                    .unwrap_or_default()
-            } else {
-                ImageMetadata::default()
+    paths.par_iter().filter(|path| reference_path.as_ref() != Some(*path)).for_each(|path| {
+        let result: Result<(), String> = (|| {
+            let (mut existing_metadata, image) = load_metadata_and_image(path)?;
+
+            let auto_adjustments_json = match reference_key {
+                Some(reference_key) => {
+                    let key = measure_scene_key(&image, model.as_deref());
+                    serde_json::json!({
+                        "exposure": (reference_key / key).log2().clamp(-5.0, 5.0),
+                        "sectionVisibility": { "basic": true }
+                    })
+                }
+                None => auto_results_to_json(&perform_auto_analysis(&image, model.as_deref()), is_raw_file(path)),
             };
 
             if existing_metadata.adjustments.is_null() {
@@ -998,7 +1167,7 @@ pub fn apply_auto_adjustments_to_paths(
                 adjustments: existing_metadata.adjustments,
             };
             if let Ok(json_string) = serde_json::to_string_pretty(&metadata) {
-                let _ = std::fs::write(sidecar_path, json_string);
+                let _ = std::fs::write(get_sidecar_path(path), json_string);
             }
             Ok(())
         })();
@@ -0,0 +1,374 @@
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::thread;
+
+use anyhow::{anyhow, Context, Result};
+use image::{imageops, GrayImage, Luma, Rgb32FImage};
+use imageproc::filter::median_filter;
+use serde::{Deserialize, Serialize};
+use tauri::Emitter;
+use uuid::Uuid;
+
+use crate::alignment::{
+    brief_test_pairs, estimate_transform, frame_features, is_reliable_alignment, mat_invert, mat_mul, match_features, warp_image, FeatureSet, Matrix3,
+    MotionModel, IDENTITY,
+};
+use crate::file_management::{save_linear_dng, unique_sibling_path};
+use crate::image_loader::{linear_to_srgb, load_linear_image};
+use crate::pyramid::{
+    fill_empty_regions, gaussian_pyramid, laplacian_pyramid, level_count, collapse, WeightImage,
+};
+
+const RANSAC_THRESHOLD_PX: f64 = 2.0;
+const MAX_PYRAMID_LEVELS: usize = 8;
+const DETAIL_SIGMA: f32 = 1.0;
+const ENERGY_SIGMA: f32 = 3.0;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum FocusStackMethod {
+    Pyramid,
+    DepthMap,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FocusStackOptions {
+    pub method: FocusStackMethod,
+    pub align: bool,
+    pub generate_depth_map: bool,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FocusStackResult {
+    pub path: String,
+    pub depth_map_path: Option<String>,
+    pub width: u32,
+    pub height: u32,
+    pub skipped: Vec<String>,
+}
+
+fn luma_f32(image: &Rgb32FImage) -> WeightImage {
+    let (w, h) = image.dimensions();
+    let mut luma = WeightImage::new(w, h);
+    for (l, p) in luma.pixels_mut().zip(image.pixels()) {
+        l.0[0] = linear_to_srgb(0.2126 * p.0[0] + 0.7152 * p.0[1] + 0.0722 * p.0[2]);
+    }
+    luma
+}
+
+/// Local focus measure: smoothed magnitude of the high-pass luma detail. Pixels the
+/// warped frame doesn't cover score zero so they never win the merge.
+fn sharpness(image: &Rgb32FImage, coverage: &WeightImage) -> WeightImage {
+    let luma = luma_f32(image);
+    let smooth = imageops::blur(&luma, DETAIL_SIGMA);
+    let mut detail = luma;
+    detail
+        .iter_mut()
+        .zip(smooth.iter())
+        .for_each(|(d, s)| *d = (*d - s).abs());
+    let mut energy = imageops::blur(&detail, ENERGY_SIGMA);
+    energy
+        .iter_mut()
+        .zip(coverage.iter())
+        .for_each(|(e, c)| *e *= if *c >= 1.0 { 1.0 } else { 0.0 });
+    energy
+}
+
+fn band_energy(band: &Rgb32FImage, coverage: &WeightImage) -> WeightImage {
+    let (w, h) = band.dimensions();
+    let mut energy = WeightImage::new(w, h);
+    for ((e, p), c) in energy.pixels_mut().zip(band.pixels()).zip(coverage.pixels()) {
+        e.0[0] = (p.0[0].abs() + p.0[1].abs() + p.0[2].abs()) * c.0[0];
+    }
+    imageops::blur(&energy, 1.0)
+}
+
+/// Chains neighbour-to-neighbour alignments outwards from the reference frame.
+/// Focus breathing changes the magnification a little from frame to frame, so an
+/// affine model is used and adjacent frames (which look the most alike) are matched.
+fn align_frames(
+    features: &[FeatureSet],
+    reference: usize,
+) -> Vec<Option<Matrix3>> {
+    let count = features.len();
+    let mut transforms: Vec<Option<Matrix3>> = vec![None; count];
+    transforms[reference] = Some(IDENTITY);
+
+    let align_pair = |from: usize, to: usize| -> Option<Matrix3> {
+        let matches = match_features(&features[from], &features[to]);
+        let threshold = RANSAC_THRESHOLD_PX / features[from].scale.min(features[to].scale);
+        let alignment = estimate_transform(&matches, MotionModel::Affine, threshold)?;
+        is_reliable_alignment(&alignment).then_some(alignment.matrix)
+    };
+
+    for direction in [-1i64, 1] {
+        let mut anchor = reference;
+        let mut i = reference as i64 + direction;
+        while i >= 0 && (i as usize) < count {
+            let idx = i as usize;
+            let to_ref = align_pair(idx, anchor)
+                .and_then(|m| transforms[anchor].map(|a| mat_mul(&a, &m)))
+                .or_else(|| align_pair(idx, reference));
+            if let Some(t) = to_ref {
+                transforms[idx] = Some(t);
+                anchor = idx;
+            }
+            i += direction;
+        }
+    }
+
+    transforms
+}
+
+/// Developed frames kept on disk between the analysis and the merge pass,
+/// so every frame is developed once. Removed when dropped.
+struct FrameCache {
+    dir: PathBuf,
+}
+
+impl FrameCache {
+    fn new() -> Result<Self> {
+        let dir = std::env::temp_dir().join(format!("rapidraw-focus-stack-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    fn frame_path(&self, index: usize) -> PathBuf {
+        self.dir.join(format!("{}.rgbf", index))
+    }
+
+    fn store(&self, index: usize, image: &Rgb32FImage) -> Result<()> {
+        let mut file = BufWriter::new(fs::File::create(self.frame_path(index))?);
+        file.write_all(&image.width().to_ne_bytes())?;
+        file.write_all(&image.height().to_ne_bytes())?;
+        file.write_all(bytemuck::cast_slice(image.as_raw()))?;
+        file.flush()?;
+        Ok(())
+    }
+
+    fn load(&self, index: usize) -> Result<Rgb32FImage> {
+        let bytes = fs::read(self.frame_path(index))?;
+        let dimension = |offset: usize| bytes.get(offset..offset + 4).map(|b| u32::from_ne_bytes(b.try_into().unwrap()));
+        let (width, height) = dimension(0).zip(dimension(4)).context("Corrupt cached frame")?;
+        let data: Vec<f32> = bytes[8..].chunks_exact(4).map(|b| f32::from_ne_bytes(b.try_into().unwrap())).collect();
+        Rgb32FImage::from_raw(width, height, data).context("Corrupt cached frame")
+    }
+}
+
+impl Drop for FrameCache {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn stack(paths: &[String], options: &FocusStackOptions, app_handle: &tauri::AppHandle) -> Result<FocusStackResult> {
+    let total = paths.len();
+    let emit_progress = |stage: &str, current: usize| {
+        let _ = app_handle.emit(
+            "focus-stack-progress",
+            serde_json::json!({ "stage": stage, "current": current, "total": total }),
+        );
+    };
+
+    let reference = total / 2;
+    let frame_cache = if options.align { Some(FrameCache::new()?) } else { None };
+    let transforms: Vec<Option<Matrix3>> = if options.align {
+        let test_pairs = brief_test_pairs();
+        let mut features = Vec::with_capacity(total);
+        for (i, path) in paths.iter().enumerate() {
+            emit_progress("analyzing", i);
+            let image = load_linear_image(path)?;
+            features.push(frame_features(&image, None, &test_pairs));
+            if let Some(cache) = &frame_cache {
+                cache.store(i, &image)?;
+            }
+        }
+        emit_progress("aligning", 0);
+        align_frames(&features, reference)
+    } else {
+        vec![Some(IDENTITY); total]
+    };
+
+    let skipped: Vec<String> = transforms
+        .iter()
+        .zip(paths.iter())
+        .filter(|(t, _)| t.is_none())
+        .map(|(_, p)| p.clone())
+        .collect();
+    let included: Vec<(usize, Matrix3)> = transforms
+        .iter()
+        .enumerate()
+        .filter_map(|(i, t)| t.map(|m| (i, m)))
+        .collect();
+    if included.len() < 2 {
+        return Err(anyhow!("Could not align enough frames to build a focus stack."));
+    }
+
+    // The reference frame defines the output geometry, so it is merged first. The
+    // depth map still uses each frame's position in the input order.
+    let mut merge_order: Vec<(usize, usize, Matrix3)> = included
+        .iter()
+        .enumerate()
+        .map(|(rank, (i, m))| (rank, *i, *m))
+        .collect();
+    merge_order.sort_by_key(|(_, i, _)| *i != reference);
+
+    let mut width = 0;
+    let mut height = 0;
+    let mut levels = 1;
+    let mut fused_bands: Vec<Rgb32FImage> = Vec::new();
+    let mut best_band_energy: Vec<WeightImage> = Vec::new();
+    let mut residual_sum: Option<Rgb32FImage> = None;
+    let mut residual_weight: Option<WeightImage> = None;
+    let mut merged = Rgb32FImage::new(0, 0);
+    let mut best_sharpness = WeightImage::new(0, 0);
+    let mut depth_index: Vec<u16> = Vec::new();
+
+    for (n, (rank, i, frame_to_ref)) in merge_order.iter().enumerate() {
+        emit_progress("merging", n);
+        let source = match &frame_cache {
+            Some(cache) => cache.load(*i)?,
+            None => load_linear_image(&paths[*i])?,
+        };
+        if n == 0 {
+            (width, height) = source.dimensions();
+            levels = level_count(width, height, MAX_PYRAMID_LEVELS);
+            merged = Rgb32FImage::new(width, height);
+            best_sharpness = WeightImage::from_pixel(width, height, Luma([-1.0]));
+            depth_index = vec![0; (width * height) as usize];
+        }
+
+        let ref_to_frame = mat_invert(frame_to_ref).context("Degenerate frame alignment")?;
+        let (mut image, coverage) = warp_image(&source, &ref_to_frame, width, height);
+        drop(source);
+
+        let focus = sharpness(&image, &coverage);
+        for (idx, ((s, best), px)) in focus
+            .pixels()
+            .zip(best_sharpness.pixels_mut())
+            .zip(image.pixels())
+            .enumerate()
+        {
+            let x = idx as u32 % width;
+            let y = idx as u32 / width;
+            if s.0[0] > best.0[0] {
+                best.0[0] = s.0[0];
+                depth_index[idx] = *rank as u16;
+                if options.method == FocusStackMethod::DepthMap {
+                    merged.put_pixel(x, y, *px);
+                }
+            }
+        }
+
+        if options.method == FocusStackMethod::Pyramid {
+            fill_empty_regions(&mut image, &coverage);
+            let bands = laplacian_pyramid(&image, levels);
+            let coverages = gaussian_pyramid(&coverage, levels);
+            let top = levels - 1;
+
+            if fused_bands.is_empty() {
+                fused_bands = bands[..top].to_vec();
+                best_band_energy = bands[..top]
+                    .iter()
+                    .zip(coverages.iter())
+                    .map(|(b, c)| band_energy(b, c))
+                    .collect();
+            } else {
+                for k in 0..top {
+                    let energy = band_energy(&bands[k], &coverages[k]);
+                    for ((e, best), (src, dst)) in energy
+                        .pixels()
+                        .zip(best_band_energy[k].pixels_mut())
+                        .zip(bands[k].pixels().zip(fused_bands[k].pixels_mut()))
+                    {
+                        if e.0[0] > best.0[0] {
+                            best.0[0] = e.0[0];
+                            *dst = *src;
+                        }
+                    }
+                }
+            }
+
+            let mut weighted = bands[top].clone();
+            for (p, c) in weighted.pixels_mut().zip(coverages[top].pixels()) {
+                p.0.iter_mut().for_each(|v| *v *= c.0[0]);
+            }
+            match (&mut residual_sum, &mut residual_weight) {
+                (Some(sum), Some(weight)) => {
+                    sum.iter_mut().zip(weighted.iter()).for_each(|(s, v)| *s += v);
+                    weight.iter_mut().zip(coverages[top].iter()).for_each(|(s, v)| *s += v);
+                }
+                _ => {
+                    residual_sum = Some(weighted);
+                    residual_weight = Some(coverages[top].clone());
+                }
+            }
+        }
+    }
+
+    if options.method == FocusStackMethod::Pyramid {
+        let (mut residual, weight) = (residual_sum.unwrap(), residual_weight.unwrap());
+        for (p, w) in residual.pixels_mut().zip(weight.pixels()) {
+            if w.0[0] > 1e-6 {
+                p.0.iter_mut().for_each(|v| *v /= w.0[0]);
+            }
+        }
+        fused_bands.push(residual);
+        merged = collapse(fused_bands);
+    }
+
+    emit_progress("saving", 0);
+    let first_path = &paths[included[0].0];
+    let output_path = unique_sibling_path(first_path, "Stacked", "dng");
+    save_linear_dng(&merged, &output_path).context("Failed to write focus stack")?;
+
+    let depth_map_path = if options.generate_depth_map {
+        let steps = (included.len() - 1).max(1) as f32;
+        let depth = GrayImage::from_fn(width, height, |x, y| {
+            let n = depth_index[(y * width + x) as usize] as f32;
+            Luma([(n / steps * 255.0).round() as u8])
+        });
+        let depth = median_filter(&depth, 2, 2);
+        let path = unique_sibling_path(first_path, "Stacked-Depth", "png");
+        depth.save(&path).context("Failed to write depth map")?;
+        Some(path.to_string_lossy().into_owned())
+    } else {
+        None
+    };
+
+    Ok(FocusStackResult {
+        path: output_path.to_string_lossy().into_owned(),
+        depth_map_path,
+        width,
+        height,
+        skipped,
+    })
+}
+
+#[tauri::command]
+pub fn focus_stack(
+    paths: Vec<String>,
+    options: FocusStackOptions,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    if paths.len() < 2 {
+        return Err("At least two images are needed for a focus stack.".to_string());
+    }
+    if paths.len() > u16::MAX as usize {
+        return Err("Too many frames for a single focus stack.".to_string());
+    }
+
+    thread::spawn(move || match stack(&paths, &options, &app_handle) {
+        Ok(result) => {
+            let _ = app_handle.emit("focus-stack-complete", result);
+        }
+        Err(e) => {
+            let _ = app_handle.emit("focus-stack-error", e.to_string());
+        }
+    });
+
+    Ok(())
+}
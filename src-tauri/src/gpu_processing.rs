@@ -1,11 +1,16 @@
-use std::sync::Arc;
+use std::borrow::Cow;
 
 use bytemuck;
-use image::{DynamicImage, GenericImageView, ImageBuffer, Rgba, Luma};
+use image::{DynamicImage, GenericImageView, ImageBuffer, Rgba, Rgba32FImage, Luma};
 use wgpu::util::{DeviceExt, TextureDataOrder};
 
 use crate::AppState;
-use crate::image_processing::{AllAdjustments, GpuContext};
+use crate::image_processing::{AllAdjustments, GpuContext, MaskAdjustments};
+use crate::lut_processes::lut::{Lut, LutAdjustment};
+
+/// Pixels of context around each tile. Must cover the widest neighbourhood
+/// the shader reads, which is the radius 20 local contrast used for structure.
+pub const TILE_APRON: u32 = 20;
 
 pub fn get_or_init_gpu_context(state: &tauri::State<AppState>) -> Result<GpuContext, String> {
     let mut context_lock = state.gpu_context.lock().unwrap();
@@ -32,22 +37,306 @@ pub fn get_or_init_gpu_context(state: &tauri::State<AppState>) -> Result<GpuCont
         None,
     )).map_err(|e| e.to_string())?;
 
-    let new_context = GpuContext {
-        device: Arc::new(device),
-        queue: Arc::new(queue),
-        limits,
-    };
+    let new_context = GpuContext::from_device(device, queue, limits);
     *context_lock = Some(new_context.clone());
     Ok(new_context)
 }
 
+/// A LUT uploaded as a 3D texture with its shaper curve.
+struct LutResources {
+    hash: blake3::Hash,
+    texture_view: wgpu::TextureView,
+    shaper_buffer: wgpu::Buffer,
+}
+
+impl LutResources {
+    fn new(device: &wgpu::Device, queue: &wgpu::Queue, lut: &Lut) -> Self {
+        let texture = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some("LUT Texture"),
+                size: wgpu::Extent3d { width: lut.size, height: lut.size, depth_or_array_layers: lut.size },
+                mip_level_count: 1, sample_count: 1,
+                dimension: wgpu::TextureDimension::D3,
+                format: wgpu::TextureFormat::Rgba32Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            TextureDataOrder::LayerMajor, bytemuck::cast_slice(&lut.data),
+        );
+        // Storage buffers cannot be empty, so a LUT without a shaper gets one unused entry.
+        let shaper_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("LUT Shaper Buffer"),
+            contents: bytemuck::cast_slice(lut.shaper.as_ref().map_or(&[0.0f32; 4][..], |shaper| &shaper.data)),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        Self { hash: lut.hash, texture_view: texture.create_view(&Default::default()), shaper_buffer }
+    }
+}
+
+/// What a pipeline writes to its output texture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipelineOutput {
+    /// 8-bit display colors, as previewed and exported.
+    Display,
+    /// The display colors as float, for readbacks that must not be quantized.
+    DisplayFloat,
+    /// Float colors without the final clip to 0..1, for gamut analysis.
+    /// Values are sRGB encoded, mirrored around zero for negative components.
+    Unclipped,
+}
+
+/// The compiled shader and bind group layout, plus the resources bound when
+/// an image has no masks or no LUT. Built once per device and output.
+pub struct GpuPipeline {
+    bind_group_layout: wgpu::BindGroupLayout,
+    compute_pipeline: wgpu::ComputePipeline,
+    empty_mask_view: wgpu::TextureView,
+    identity_lut: LutResources,
+    output_format: wgpu::TextureFormat,
+}
+
+impl GpuPipeline {
+    /// The pipeline behind previews and exports, writing 8-bit output.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        Self::with_output(device, queue, PipelineOutput::Display)
+    }
+
+    pub fn with_output(device: &wgpu::Device, queue: &wgpu::Queue, output: PipelineOutput) -> Self {
+        let shader_source = include_str!("../shaders/shader.wgsl");
+        let float_source = || shader_source.replace("texture_storage_2d<rgba8unorm, write>", "texture_storage_2d<rgba32float, write>");
+        let (output_format, shader_source) = match output {
+            PipelineOutput::Display => (wgpu::TextureFormat::Rgba8Unorm, Cow::Borrowed(shader_source)),
+            PipelineOutput::DisplayFloat => (wgpu::TextureFormat::Rgba32Float, Cow::Owned(float_source())),
+            PipelineOutput::Unclipped => (
+                wgpu::TextureFormat::Rgba32Float,
+                Cow::Owned(float_source().replace("const CLIP_OUTPUT: bool = true;", "const CLIP_OUTPUT: bool = false;")),
+            ),
+        };
+        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Image Processing Shader"),
+            source: wgpu::ShaderSource::Wgsl(shader_source),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Bind Group Layout"),
+            entries: &[
+                // Input Image
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0, visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2, multisampled: false,
+                    }, count: None,
+                },
+                // Output Image
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1, visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: output_format,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    }, count: None,
+                },
+                // Adjustments Uniform
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2, visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false, min_binding_size: None,
+                    }, count: None,
+                },
+                // Mask Texture Array
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3, visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                // Mask Adjustments Storage
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4, visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false, min_binding_size: None,
+                    }, count: None,
+                },
+                // 3D LUT
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5, visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                // LUT Shaper Storage
+                wgpu::BindGroupLayoutEntry {
+                    binding: 6, visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false, min_binding_size: None,
+                    }, count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Compute Pipeline"), layout: Some(&pipeline_layout),
+            module: &shader_module, entry_point: "main",
+        });
+
+        let empty_mask_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Empty Mask Texture"),
+            size: wgpu::Extent3d { width: 1, height: 1, depth_or_array_layers: 1 },
+            mip_level_count: 1, sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        Self {
+            bind_group_layout,
+            compute_pipeline,
+            empty_mask_view: mask_array_view(&empty_mask_texture),
+            identity_lut: LutResources::new(device, queue, &Lut::identity()),
+            output_format,
+        }
+    }
+
+    fn output_bytes_per_pixel(&self) -> u32 {
+        match self.output_format {
+            wgpu::TextureFormat::Rgba32Float => 16,
+            _ => 4,
+        }
+    }
+}
+
+/// Identifies the base image of a preview: the loaded image and the geometry
+/// (`calculate_transform_hash`) applied to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GpuImageKey {
+    pub image_id: u64,
+    pub transform_hash: u64,
+}
+
+/// GPU resources of the last rendered preview. While the base image stays the
+/// same, changing an adjustment only rewrites the uniform; mask layers and the
+/// LUT are re-uploaded only when they change.
+pub struct GpuImageCache {
+    key: GpuImageKey,
+    width: u32,
+    height: u32,
+    input_view: wgpu::TextureView,
+    output_texture: wgpu::Texture,
+    output_view: wgpu::TextureView,
+    adjustments_buffer: wgpu::Buffer,
+    mask_texture: Option<wgpu::Texture>,
+    mask_view: Option<wgpu::TextureView>,
+    mask_hashes: Vec<blake3::Hash>,
+    mask_adjustments_buffer: wgpu::Buffer,
+    mask_adjustments_len: usize,
+    lut: Option<LutResources>,
+    bind_group: Option<wgpu::BindGroup>,
+}
+
+impl GpuImageCache {
+    fn new(device: &wgpu::Device, queue: &wgpu::Queue, key: GpuImageKey, image: &DynamicImage) -> Self {
+        let (width, height) = image.dimensions();
+        let (input_format, _, input_pixels) = input_texture_data(image);
+        let input_texture = create_input_texture(device, queue, width, height, input_format, &input_pixels);
+        let output_texture = create_output_texture(device, width, height, wgpu::TextureFormat::Rgba8Unorm);
+        let adjustments_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Adjustments Buffer"),
+            size: std::mem::size_of::<crate::image_processing::AdjustmentsUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            key,
+            width,
+            height,
+            input_view: input_texture.create_view(&Default::default()),
+            output_view: output_texture.create_view(&Default::default()),
+            output_texture,
+            adjustments_buffer,
+            mask_texture: None,
+            mask_view: None,
+            mask_hashes: Vec::new(),
+            mask_adjustments_buffer: create_mask_adjustments_buffer(device, &[]),
+            mask_adjustments_len: 0,
+            lut: None,
+            bind_group: None,
+        }
+    }
+
+    /// Uploads the mask layers whose contents changed. Returns true when the
+    /// mask texture was recreated and the bind group must be rebuilt.
+    fn update_masks(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, mask_bitmaps: &[ImageBuffer<Luma<u8>, Vec<u8>>]) -> bool {
+        let hashes: Vec<blake3::Hash> = mask_bitmaps.iter().map(|mask| blake3::hash(mask.as_raw())).collect();
+        let recreated = hashes.len() != self.mask_hashes.len();
+        if recreated {
+            self.mask_texture = (!hashes.is_empty())
+                .then(|| create_mask_texture(device, self.width, self.height, hashes.len() as u32));
+            self.mask_view = self.mask_texture.as_ref().map(mask_array_view);
+            self.mask_hashes.clear();
+        }
+
+        if let Some(mask_texture) = &self.mask_texture {
+            for (i, (mask_bitmap, hash)) in mask_bitmaps.iter().zip(&hashes).enumerate() {
+                if self.mask_hashes.get(i) != Some(hash) {
+                    write_mask_layer(queue, mask_texture, i as u32, mask_bitmap, self.width, self.height);
+                }
+            }
+        }
+        self.mask_hashes = hashes;
+        recreated
+    }
+
+    /// Returns true when the buffer was recreated.
+    fn update_mask_adjustments(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, mask_adjustments: &[MaskAdjustments]) -> bool {
+        if mask_adjustments.len() != self.mask_adjustments_len {
+            self.mask_adjustments_buffer = create_mask_adjustments_buffer(device, mask_adjustments);
+            self.mask_adjustments_len = mask_adjustments.len();
+            return true;
+        }
+        if !mask_adjustments.is_empty() {
+            queue.write_buffer(&self.mask_adjustments_buffer, 0, bytemuck::cast_slice(mask_adjustments));
+        }
+        false
+    }
+
+    /// Returns true when a different LUT was bound.
+    fn update_lut(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, lut: Option<&LutAdjustment>) -> bool {
+        let wanted = lut.map(|l| l.lut.hash);
+        if wanted == self.lut.as_ref().map(|l| l.hash) {
+            return false;
+        }
+        self.lut = lut.map(|l| LutResources::new(device, queue, &l.lut));
+        true
+    }
+}
+
 fn read_texture_data(
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     texture: &wgpu::Texture,
     size: wgpu::Extent3d,
+    bytes_per_pixel: u32,
 ) -> Result<Vec<u8>, String> {
-    let unpadded_bytes_per_row = 4 * size.width;
+    let unpadded_bytes_per_row = bytes_per_pixel * size.width;
     let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
     let padded_bytes_per_row = (unpadded_bytes_per_row + align - 1) & !(align - 1);
     let output_buffer_size = (padded_bytes_per_row * size.height) as u64;
@@ -90,298 +379,354 @@ fn read_texture_data(
     }
 }
 
+/// Float images carry scene-referred values above 1.0 and are uploaded
+/// without quantizing. Everything else stays 8-bit to keep uploads small.
+fn input_texture_data(image: &DynamicImage) -> (wgpu::TextureFormat, u32, Vec<u8>) {
+    match image {
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => (
+            wgpu::TextureFormat::Rgba32Float,
+            16,
+            bytemuck::cast_slice(image.to_rgba32f().as_raw()).to_vec(),
+        ),
+        _ => (wgpu::TextureFormat::Rgba8Unorm, 4, image.to_rgba8().into_raw()),
+    }
+}
+
+fn create_input_texture(device: &wgpu::Device, queue: &wgpu::Queue, width: u32, height: u32, format: wgpu::TextureFormat, pixels: &[u8]) -> wgpu::Texture {
+    device.create_texture_with_data(
+        queue,
+        &wgpu::TextureDescriptor {
+            label: Some("Input Texture"),
+            size: wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+            mip_level_count: 1, sample_count: 1,
+            dimension: wgpu::TextureDimension::D2, format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST, view_formats: &[],
+        },
+        TextureDataOrder::MipMajor, pixels,
+    )
+}
+
+fn create_output_texture(device: &wgpu::Device, width: u32, height: u32, format: wgpu::TextureFormat) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Output Texture"),
+        size: wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+        mip_level_count: 1, sample_count: 1,
+        dimension: wgpu::TextureDimension::D2, format,
+        usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC, view_formats: &[],
+    })
+}
+
+fn create_mask_texture(device: &wgpu::Device, width: u32, height: u32, layers: u32) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Mask Texture Array"),
+        size: wgpu::Extent3d { width, height, depth_or_array_layers: layers },
+        mip_level_count: 1, sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::R8Unorm,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    })
+}
+
+fn write_mask_layer(queue: &wgpu::Queue, texture: &wgpu::Texture, layer: u32, data: &[u8], width: u32, height: u32) {
+    queue.write_texture(
+        wgpu::ImageCopyTexture {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d { x: 0, y: 0, z: layer },
+            aspect: wgpu::TextureAspect::All,
+        },
+        data,
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(width),
+            rows_per_image: Some(height),
+        },
+        wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+    );
+}
+
+fn mask_array_view(texture: &wgpu::Texture) -> wgpu::TextureView {
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::D2Array),
+        ..Default::default()
+    })
+}
+
+fn create_mask_adjustments_buffer(device: &wgpu::Device, mask_adjustments: &[MaskAdjustments]) -> wgpu::Buffer {
+    // Storage buffers cannot be empty, so bind a single unused entry when there are no masks.
+    let unused = [MaskAdjustments::default()];
+    let contents = if mask_adjustments.is_empty() { &unused[..] } else { mask_adjustments };
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Mask Adjustments Buffer"),
+        contents: bytemuck::cast_slice(contents),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+fn create_bind_group(
+    device: &wgpu::Device,
+    pipeline: &GpuPipeline,
+    input_view: &wgpu::TextureView,
+    output_view: &wgpu::TextureView,
+    adjustments_buffer: &wgpu::Buffer,
+    mask_view: &wgpu::TextureView,
+    mask_adjustments_buffer: &wgpu::Buffer,
+    lut: &LutResources,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Processing Bind Group"), layout: &pipeline.bind_group_layout,
+        entries: &[
+            wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::TextureView(input_view) },
+            wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::TextureView(output_view) },
+            wgpu::BindGroupEntry { binding: 2, resource: adjustments_buffer.as_entire_binding() },
+            wgpu::BindGroupEntry { binding: 3, resource: wgpu::BindingResource::TextureView(mask_view) },
+            wgpu::BindGroupEntry { binding: 4, resource: mask_adjustments_buffer.as_entire_binding() },
+            wgpu::BindGroupEntry { binding: 5, resource: wgpu::BindingResource::TextureView(&lut.texture_view) },
+            wgpu::BindGroupEntry { binding: 6, resource: lut.shaper_buffer.as_entire_binding() },
+        ],
+    })
+}
+
+fn dispatch(device: &wgpu::Device, queue: &wgpu::Queue, pipeline: &GpuPipeline, bind_group: &wgpu::BindGroup, width: u32, height: u32) {
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Processing Encoder") });
+    {
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None, timestamp_writes: None });
+        compute_pass.set_pipeline(&pipeline.compute_pipeline);
+        compute_pass.set_bind_group(0, bind_group, &[]);
+        compute_pass.dispatch_workgroups(width.div_ceil(8), height.div_ceil(8), 1);
+    }
+    queue.submit(Some(encoder.finish()));
+}
+
+fn check_masks(context: &GpuContext, adjustments: &AllAdjustments, mask_bitmaps: &[ImageBuffer<Luma<u8>, Vec<u8>>]) -> Result<(), String> {
+    let num_masks = mask_bitmaps.len();
+    if num_masks != adjustments.mask_adjustments.len() {
+        return Err(format!(
+            "Mask count mismatch: {} bitmaps for {} mask adjustments.",
+            num_masks, adjustments.mask_adjustments.len()
+        ));
+    }
+    let max_layers = context.device.limits().max_texture_array_layers as usize;
+    if num_masks > max_layers {
+        return Err(format!("Too many masks: this GPU supports at most {} masks per image.", max_layers));
+    }
+    Ok(())
+}
+
 pub fn run_gpu_processing(
     context: &GpuContext,
     image: &DynamicImage,
     adjustments: AllAdjustments,
     mask_bitmaps: &[ImageBuffer<Luma<u8>, Vec<u8>>],
 ) -> Result<Vec<u8>, String> {
-    let device = &context.device;
-    let queue = &context.queue;
     let (width, height) = image.dimensions();
-    let max_dim = context.limits.max_texture_dimension_2d;
-
-    let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-        label: Some("Image Processing Shader"),
-        source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/shader.wgsl").into()),
-    });
-
-    let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-        label: Some("Bind Group Layout"),
-        entries: &[
-            // Input Image
-            wgpu::BindGroupLayoutEntry {
-                binding: 0, visibility: wgpu::ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::Texture {
-                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
-                    view_dimension: wgpu::TextureViewDimension::D2, multisampled: false,
-                }, count: None,
-            },
-            // Output Image
-            wgpu::BindGroupLayoutEntry {
-                binding: 1, visibility: wgpu::ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::StorageTexture {
-                    access: wgpu::StorageTextureAccess::WriteOnly,
-                    format: wgpu::TextureFormat::Rgba8Unorm,
-                    view_dimension: wgpu::TextureViewDimension::D2,
-                }, count: None,
-            },
-            // Adjustments Uniform
-            wgpu::BindGroupLayoutEntry {
-                binding: 2, visibility: wgpu::ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false, min_binding_size: None,
-                }, count: None,
-            },
-            // Mask Texture Array
-            wgpu::BindGroupLayoutEntry {
-                binding: 3, visibility: wgpu::ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::Texture {
-                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
-                    view_dimension: wgpu::TextureViewDimension::D2Array,
-                    multisampled: false,
-                },
-                count: None,
-            },
-        ],
-    });
+    let tile_size = tile_size_for(context, width, height);
+    run_gpu_processing_tiled(context, &context.pipeline, image, adjustments, mask_bitmaps, tile_size, (0, 0), (width, height))
+}
 
-    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-        label: Some("Pipeline Layout"),
-        bind_group_layouts: &[&bind_group_layout],
-        push_constant_ranges: &[],
-    });
+/// Renders images within the texture limit in one piece. Larger ones are
+/// processed in tiles small enough that a tile plus its aprons still fits.
+fn tile_size_for(context: &GpuContext, width: u32, height: u32) -> u32 {
+    let max_dim = context.limits.max_texture_dimension_2d;
+    if width <= max_dim && height <= max_dim {
+        width.max(height)
+    } else {
+        (max_dim / 2).min(2048)
+    }
+}
 
-    let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-        label: Some("Compute Pipeline"), layout: Some(&pipeline_layout),
-        module: &shader_module, entry_point: "main",
-    });
+/// Processes a region cut from a larger image whose top-left pixel sits at
+/// `origin` in an image of `full_size`, so vignette and grain line up with
+/// the whole image. Neighbourhood reads clamp at the region border, so the
+/// caller includes a `TILE_APRON` margin wherever the region is not at the
+/// image border.
+pub fn run_gpu_processing_region(
+    context: &GpuContext,
+    image: &DynamicImage,
+    adjustments: AllAdjustments,
+    mask_bitmaps: &[ImageBuffer<Luma<u8>, Vec<u8>>],
+    origin: (u32, u32),
+    full_size: (u32, u32),
+) -> Result<Vec<u8>, String> {
+    let (width, height) = image.dimensions();
+    let tile_size = tile_size_for(context, width, height);
+    run_gpu_processing_tiled(context, &context.pipeline, image, adjustments, mask_bitmaps, tile_size, origin, full_size)
+}
 
-    let num_masks = mask_bitmaps.len();
-    // Create the texture once. It's cheap and can be reused to create views.
-    let empty_mask_texture = device.create_texture(&wgpu::TextureDescriptor {
-        label: Some("Empty Mask Texture"),
-        size: wgpu::Extent3d { width: 1, height: 1, depth_or_array_layers: 1 },
-        mip_level_count: 1, sample_count: 1,
-        dimension: wgpu::TextureDimension::D2,
-        format: wgpu::TextureFormat::R8Unorm,
-        usage: wgpu::TextureUsages::TEXTURE_BINDING,
-        view_formats: &[],
-    });
+/// Renders a preview, reusing the textures of the previous call when `key`
+/// and the image size match. Images that need tiling are not cached.
+pub fn run_gpu_processing_cached(
+    context: &GpuContext,
+    key: GpuImageKey,
+    image: &DynamicImage,
+    adjustments: AllAdjustments,
+    mask_bitmaps: &[ImageBuffer<Luma<u8>, Vec<u8>>],
+) -> Result<Vec<u8>, String> {
+    let (width, height) = image.dimensions();
+    let max_dim = context.limits.max_texture_dimension_2d;
+    if width > max_dim || height > max_dim {
+        return run_gpu_processing(context, image, adjustments, mask_bitmaps);
+    }
+    check_masks(context, &adjustments, mask_bitmaps)?;
 
-    if width <= max_dim && height <= max_dim {
-        let img_rgba = image.to_rgba8();
-        let texture_size = wgpu::Extent3d { width, height, depth_or_array_layers: 1 };
+    let device = &context.device;
+    let queue = &context.queue;
+    let pipeline = &context.pipeline;
 
-        let adjustments_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Adjustments Buffer"),
-            contents: bytemuck::bytes_of(&adjustments),
-            usage: wgpu::BufferUsages::UNIFORM,
-        });
+    // Held for the whole render, so concurrent previews take turns on the cached textures.
+    let mut cache_lock = context.image_cache.lock().unwrap();
+    let mut cache = match cache_lock.take() {
+        Some(cache) if cache.key == key && cache.width == width && cache.height == height => cache,
+        _ => GpuImageCache::new(device, queue, key, image),
+    };
 
-        let input_texture = device.create_texture_with_data(
-            queue,
-            &wgpu::TextureDescriptor {
-                label: Some("Input Texture"), size: texture_size, mip_level_count: 1, sample_count: 1,
-                dimension: wgpu::TextureDimension::D2, format: wgpu::TextureFormat::Rgba8Unorm,
-                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST, view_formats: &[],
-            },
-            TextureDataOrder::MipMajor, &img_rgba,
-        );
+    queue.write_buffer(&cache.adjustments_buffer, 0, bytemuck::bytes_of(&adjustments.uniform(0, 0, width, height)));
+    let mut rebind = cache.bind_group.is_none();
+    rebind |= cache.update_masks(device, queue, mask_bitmaps);
+    rebind |= cache.update_mask_adjustments(device, queue, &adjustments.mask_adjustments);
+    rebind |= cache.update_lut(device, queue, adjustments.lut.as_ref());
+
+    if rebind {
+        cache.bind_group = Some(create_bind_group(
+            device, pipeline,
+            &cache.input_view, &cache.output_view, &cache.adjustments_buffer,
+            cache.mask_view.as_ref().unwrap_or(&pipeline.empty_mask_view),
+            &cache.mask_adjustments_buffer,
+            cache.lut.as_ref().unwrap_or(&pipeline.identity_lut),
+        ));
+    }
+    if let Some(bind_group) = &cache.bind_group {
+        dispatch(device, queue, pipeline, bind_group, width, height);
+    }
 
-        let output_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Output Texture"), size: texture_size, mip_level_count: 1, sample_count: 1,
-            dimension: wgpu::TextureDimension::D2, format: wgpu::TextureFormat::Rgba8Unorm,
-            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC, view_formats: &[],
-        });
+    let texture_size = wgpu::Extent3d { width, height, depth_or_array_layers: 1 };
+    let result = read_texture_data(device, queue, &cache.output_texture, texture_size, 4);
+    *cache_lock = Some(cache);
+    result
+}
 
-        let mask_texture_array_view = if num_masks > 0 {
-            let mask_texture_array = device.create_texture(&wgpu::TextureDescriptor {
-                label: Some("Mask Texture Array"),
-                size: wgpu::Extent3d { width, height, depth_or_array_layers: num_masks as u32 },
-                mip_level_count: 1, sample_count: 1,
-                dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::R8Unorm,
-                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-                view_formats: &[],
-            });
+/// Processes the image in square tiles of `tile_size`. Each tile is rendered
+/// with an apron of neighbouring pixels and the uniform carries the tile
+/// offset and the full image size, so the result does not depend on the tile
+/// size. `origin` and `full_size` place the image within a larger frame.
+/// Returns pixels in the output format of `pipeline`.
+#[allow(clippy::too_many_arguments)]
+fn run_gpu_processing_tiled(
+    context: &GpuContext,
+    pipeline: &GpuPipeline,
+    image: &DynamicImage,
+    adjustments: AllAdjustments,
+    mask_bitmaps: &[ImageBuffer<Luma<u8>, Vec<u8>>],
+    tile_size: u32,
+    origin: (u32, u32),
+    full_size: (u32, u32),
+) -> Result<Vec<u8>, String> {
+    check_masks(context, &adjustments, mask_bitmaps)?;
 
-            for (i, mask_bitmap) in mask_bitmaps.iter().enumerate() {
-                queue.write_texture(
-                    wgpu::ImageCopyTexture {
-                        texture: &mask_texture_array,
-                        mip_level: 0,
-                        origin: wgpu::Origin3d { x: 0, y: 0, z: i as u32 },
-                        aspect: wgpu::TextureAspect::All,
-                    },
-                    mask_bitmap,
-                    wgpu::ImageDataLayout {
-                        offset: 0,
-                        bytes_per_row: Some(width),
-                        rows_per_image: Some(height),
-                    },
-                    texture_size,
-                );
-            }
-            mask_texture_array.create_view(&wgpu::TextureViewDescriptor {
-                dimension: Some(wgpu::TextureViewDimension::D2Array),
-                ..Default::default()
-            })
-        } else {
-            // Create a new view from the empty texture.
-            empty_mask_texture.create_view(&wgpu::TextureViewDescriptor {
-                dimension: Some(wgpu::TextureViewDimension::D2Array),
-                ..Default::default()
-            })
-        };
+    let device = &context.device;
+    let queue = &context.queue;
+    let (width, height) = image.dimensions();
+    let num_masks = mask_bitmaps.len();
+    let output_bytes_per_pixel = pipeline.output_bytes_per_pixel();
 
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Single Texture Bind Group"), layout: &bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::TextureView(&input_texture.create_view(&Default::default())) },
-                wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::TextureView(&output_texture.create_view(&Default::default())) },
-                wgpu::BindGroupEntry { binding: 2, resource: adjustments_buffer.as_entire_binding() },
-                wgpu::BindGroupEntry { binding: 3, resource: wgpu::BindingResource::TextureView(&mask_texture_array_view) },
-            ],
-        });
+    let mask_adjustments_buffer = create_mask_adjustments_buffer(device, &adjustments.mask_adjustments);
 
-        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
-        {
-            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None, timestamp_writes: None });
-            compute_pass.set_pipeline(&compute_pipeline);
-            compute_pass.set_bind_group(0, &bind_group, &[]);
-            compute_pass.dispatch_workgroups((width + 7) / 8, (height + 7) / 8, 1);
+    let custom_lut;
+    let lut = match &adjustments.lut {
+        Some(lut_adjustment) => {
+            custom_lut = LutResources::new(device, queue, &lut_adjustment.lut);
+            &custom_lut
         }
+        None => &pipeline.identity_lut,
+    };
 
-        queue.submit(Some(encoder.finish()));
-        return read_texture_data(device, queue, &output_texture, texture_size);
-    }
-
-    // Tiling logic for very large images
-    let tile_size = (max_dim / 2).min(2048);
-    let img_rgba = image.to_rgba8();
-    let mut final_pixels = vec![0u8; (width * height * 4) as usize];
+    let (input_format, bytes_per_pixel, input_pixels) = input_texture_data(image);
 
     let tiles_x = (width + tile_size - 1) / tile_size;
     let tiles_y = (height + tile_size - 1) / tile_size;
-
-    let raw_buffer = img_rgba.as_raw();
+    let single_tile = tiles_x == 1 && tiles_y == 1;
+    let mut final_pixels = if single_tile { Vec::new() } else { vec![0u8; (width * height * output_bytes_per_pixel) as usize] };
 
     for tile_y in 0..tiles_y {
         for tile_x in 0..tiles_x {
+            // The part of the image this tile produces.
             let x_start = tile_x * tile_size;
             let y_start = tile_y * tile_size;
             let x_end = (x_start + tile_size).min(width);
             let y_end = (y_start + tile_size).min(height);
 
-            let tile_width = x_end - x_start;
-            let tile_height = y_end - y_start;
-
-            let mut tile_pixels = Vec::with_capacity((tile_width * tile_height * 4) as usize);
+            // The region uploaded for it, grown by the apron. At the image
+            // border the apron is cut off, so neighbourhood reads clamp at the
+            // same pixels as they would on the whole image.
+            let region_x = x_start.saturating_sub(TILE_APRON);
+            let region_y = y_start.saturating_sub(TILE_APRON);
+            let region_width = (x_end + TILE_APRON).min(width) - region_x;
+            let region_height = (y_end + TILE_APRON).min(height) - region_y;
 
-            for y in y_start..y_end {
-                let pixel_row_start = (y * width + x_start) as usize * 4;
-                let pixel_row_end = pixel_row_start + (tile_width as usize * 4);
-                tile_pixels.extend_from_slice(&raw_buffer[pixel_row_start..pixel_row_end]);
-            }
-
-            let texture_size = wgpu::Extent3d { width: tile_width, height: tile_height, depth_or_array_layers: 1 };
-
-            let mut tile_adjustments = adjustments;
-            tile_adjustments.tile_offset_x = x_start;
-            tile_adjustments.tile_offset_y = y_start;
+            let tile_pixels: Cow<[u8]> = if single_tile {
+                Cow::Borrowed(&input_pixels)
+            } else {
+                let row_bytes = (region_width * bytes_per_pixel) as usize;
+                let mut tile_pixels = Vec::with_capacity(row_bytes * region_height as usize);
+                for y in region_y..region_y + region_height {
+                    let pixel_row_start = (y * width + region_x) as usize * bytes_per_pixel as usize;
+                    tile_pixels.extend_from_slice(&input_pixels[pixel_row_start..pixel_row_start + row_bytes]);
+                }
+                Cow::Owned(tile_pixels)
+            };
 
             let adjustments_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some("Tile Adjustments Buffer"),
-                contents: bytemuck::bytes_of(&tile_adjustments),
+                contents: bytemuck::bytes_of(&adjustments.uniform(origin.0 + region_x, origin.1 + region_y, full_size.0, full_size.1)),
                 usage: wgpu::BufferUsages::UNIFORM,
             });
 
-            let input_texture = device.create_texture_with_data(
-                queue,
-                &wgpu::TextureDescriptor {
-                    label: Some("Input Tile Texture"), size: texture_size, mip_level_count: 1, sample_count: 1,
-                    dimension: wgpu::TextureDimension::D2, format: wgpu::TextureFormat::Rgba8Unorm,
-                    usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST, view_formats: &[],
-                },
-                TextureDataOrder::MipMajor, &tile_pixels,
-            );
+            let input_texture = create_input_texture(device, queue, region_width, region_height, input_format, &tile_pixels);
+            let output_texture = create_output_texture(device, region_width, region_height, pipeline.output_format);
 
-            let output_texture = device.create_texture(&wgpu::TextureDescriptor {
-                label: Some("Output Tile Texture"), size: texture_size, mip_level_count: 1, sample_count: 1,
-                dimension: wgpu::TextureDimension::D2, format: wgpu::TextureFormat::Rgba8Unorm,
-                usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC, view_formats: &[],
-            });
-
-            // Create a texture array with cropped versions of the masks for this tile
+            // Create a texture array with the masks cropped to this tile's region
             let mask_texture_array_view = if num_masks > 0 {
-                let mask_texture_array = device.create_texture(&wgpu::TextureDescriptor {
-                    label: Some("Tile Mask Texture Array"),
-                    size: wgpu::Extent3d { width: tile_width, height: tile_height, depth_or_array_layers: num_masks as u32 },
-                    mip_level_count: 1, sample_count: 1,
-                    dimension: wgpu::TextureDimension::D2,
-                    format: wgpu::TextureFormat::R8Unorm,
-                    usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-                    view_formats: &[],
-                });
-
+                let mask_texture_array = create_mask_texture(device, region_width, region_height, num_masks as u32);
                 for (i, full_mask_bitmap) in mask_bitmaps.iter().enumerate() {
-                    let cropped_mask = image::imageops::crop_imm(full_mask_bitmap, x_start, y_start, tile_width, tile_height).to_image();
-                    queue.write_texture(
-                        wgpu::ImageCopyTexture {
-                            texture: &mask_texture_array,
-                            mip_level: 0,
-                            origin: wgpu::Origin3d { x: 0, y: 0, z: i as u32 },
-                            aspect: wgpu::TextureAspect::All,
-                        },
-                        &cropped_mask,
-                        wgpu::ImageDataLayout {
-                            offset: 0,
-                            bytes_per_row: Some(tile_width),
-                            rows_per_image: Some(tile_height),
-                        },
-                        texture_size,
-                    );
+                    let cropped_mask;
+                    let mask_data: &[u8] = if single_tile {
+                        full_mask_bitmap
+                    } else {
+                        cropped_mask = image::imageops::crop_imm(full_mask_bitmap, region_x, region_y, region_width, region_height).to_image();
+                        &cropped_mask
+                    };
+                    write_mask_layer(queue, &mask_texture_array, i as u32, mask_data, region_width, region_height);
                 }
-                mask_texture_array.create_view(&wgpu::TextureViewDescriptor {
-                    dimension: Some(wgpu::TextureViewDimension::D2Array),
-                    ..Default::default()
-                })
+                Some(mask_array_view(&mask_texture_array))
             } else {
-                // Create a new view from the empty texture for this tile.
-                empty_mask_texture.create_view(&wgpu::TextureViewDescriptor {
-                    dimension: Some(wgpu::TextureViewDimension::D2Array),
-                    ..Default::default()
-                })
+                None
             };
 
-            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-                label: Some("Tile Bind Group"), layout: &bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::TextureView(&input_texture.create_view(&Default::default())) },
-                    wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::TextureView(&output_texture.create_view(&Default::default())) },
-                    wgpu::BindGroupEntry { binding: 2, resource: adjustments_buffer.as_entire_binding() },
-                    wgpu::BindGroupEntry { binding: 3, resource: wgpu::BindingResource::TextureView(&mask_texture_array_view) },
-                ],
-            });
+            let bind_group = create_bind_group(
+                device, pipeline,
+                &input_texture.create_view(&Default::default()),
+                &output_texture.create_view(&Default::default()),
+                &adjustments_buffer,
+                mask_texture_array_view.as_ref().unwrap_or(&pipeline.empty_mask_view),
+                &mask_adjustments_buffer,
+                lut,
+            );
+            dispatch(device, queue, pipeline, &bind_group, region_width, region_height);
 
-            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Tile Encoder") });
-            {
-                let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None, timestamp_writes: None });
-                compute_pass.set_pipeline(&compute_pipeline);
-                compute_pass.set_bind_group(0, &bind_group, &[]);
-                compute_pass.dispatch_workgroups((tile_width + 7) / 8, (tile_height + 7) / 8, 1);
+            let texture_size = wgpu::Extent3d { width: region_width, height: region_height, depth_or_array_layers: 1 };
+            let processed_tile_data = read_texture_data(device, queue, &output_texture, texture_size, output_bytes_per_pixel)?;
+            if single_tile {
+                return Ok(processed_tile_data);
             }
-            queue.submit(Some(encoder.finish()));
-
-            let processed_tile_data = read_texture_data(device, queue, &output_texture, texture_size)?;
 
-            for row in 0..tile_height {
-                let final_y = y_start + row;
-                let final_row_offset = (final_y * width + x_start) as usize * 4;
-                let tile_row_offset = (row * tile_width) as usize * 4;
-                let copy_bytes = (tile_width * 4) as usize;
+            // Keep only the tile itself; the apron is produced by its neighbours.
+            let bpp = output_bytes_per_pixel as usize;
+            let copy_bytes = (x_end - x_start) as usize * bpp;
+            for y in y_start..y_end {
+                let final_row_offset = (y * width + x_start) as usize * bpp;
+                let tile_row_offset = ((y - region_y) * region_width + (x_start - region_x)) as usize * bpp;
 
                 final_pixels[final_row_offset..final_row_offset + copy_bytes]
                     .copy_from_slice(&processed_tile_data[tile_row_offset..tile_row_offset + copy_bytes]);
@@ -392,6 +737,12 @@ pub fn run_gpu_processing(
     Ok(final_pixels)
 }
 
+fn pixels_to_dynamic_image(width: u32, height: u32, processed_pixels: Vec<u8>) -> Result<DynamicImage, String> {
+    let img_buf = ImageBuffer::<Rgba<u8>, Vec<u8>>::from_raw(width, height, processed_pixels)
+        .ok_or("Failed to create image buffer from GPU data")?;
+    Ok(DynamicImage::ImageRgba8(img_buf))
+}
+
 pub fn process_and_get_dynamic_image(
     context: &GpuContext,
     base_image: &DynamicImage,
@@ -400,7 +751,190 @@ pub fn process_and_get_dynamic_image(
 ) -> Result<DynamicImage, String> {
     let processed_pixels = run_gpu_processing(context, base_image, all_adjustments, mask_bitmaps)?;
     let (width, height) = base_image.dimensions();
-    let img_buf = ImageBuffer::<Rgba<u8>, Vec<u8>>::from_raw(width, height, processed_pixels)
-        .ok_or("Failed to create image buffer from GPU data")?;
-    Ok(DynamicImage::ImageRgba8(img_buf))
-}
\ No newline at end of file
+    pixels_to_dynamic_image(width, height, processed_pixels)
+}
+
+/// Like `process_and_get_dynamic_image`, but renders with a float `pipeline`
+/// (see `GpuContext::float_pipeline`) and reads the result back as 32-bit
+/// float instead of quantizing it to 8 bits.
+pub fn process_and_get_rgba32f(
+    context: &GpuContext,
+    pipeline: &GpuPipeline,
+    base_image: &DynamicImage,
+    all_adjustments: AllAdjustments,
+    mask_bitmaps: &[ImageBuffer<Luma<u8>, Vec<u8>>],
+) -> Result<Rgba32FImage, String> {
+    let (width, height) = base_image.dimensions();
+    let tile_size = tile_size_for(context, width, height);
+    let processed_pixels = run_gpu_processing_tiled(
+        context, pipeline, base_image, all_adjustments, mask_bitmaps, tile_size, (0, 0), (width, height),
+    )?;
+    ImageBuffer::from_raw(width, height, bytemuck::pod_collect_to_vec::<u8, f32>(&processed_pixels))
+        .ok_or_else(|| "Failed to create image buffer from GPU data".to_string())
+}
+
+/// Like `process_and_get_dynamic_image`, for a region of a larger image. See
+/// `run_gpu_processing_region`.
+pub fn process_region_and_get_dynamic_image(
+    context: &GpuContext,
+    region_image: &DynamicImage,
+    all_adjustments: AllAdjustments,
+    mask_bitmaps: &[ImageBuffer<Luma<u8>, Vec<u8>>],
+    origin: (u32, u32),
+    full_size: (u32, u32),
+) -> Result<DynamicImage, String> {
+    let processed_pixels = run_gpu_processing_region(context, region_image, all_adjustments, mask_bitmaps, origin, full_size)?;
+    let (width, height) = region_image.dimensions();
+    pixels_to_dynamic_image(width, height, processed_pixels)
+}
+
+/// Like `process_and_get_dynamic_image`, for the interactive preview whose
+/// base image is keyed by `key`.
+pub fn process_cached_and_get_dynamic_image(
+    context: &GpuContext,
+    key: GpuImageKey,
+    base_image: &DynamicImage,
+    all_adjustments: AllAdjustments,
+    mask_bitmaps: &[ImageBuffer<Luma<u8>, Vec<u8>>],
+) -> Result<DynamicImage, String> {
+    let processed_pixels = run_gpu_processing_cached(context, key, base_image, all_adjustments, mask_bitmaps)?;
+    let (width, height) = base_image.dimensions();
+    pixels_to_dynamic_image(width, height, processed_pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image_processing::get_all_adjustments_from_json;
+    use image::{Rgb, RgbImage};
+    use serde_json::json;
+
+    const WIDTH: u32 = 301;
+    const HEIGHT: u32 = 203;
+
+    /// A busy pattern so that neighbourhood operations have detail to act on.
+    fn test_image() -> RgbImage {
+        ImageBuffer::from_fn(WIDTH, HEIGHT, |x, y| {
+            Rgb([
+                ((x * 7 + y * 13) % 256) as u8,
+                ((x * x + y * 3) % 256) as u8,
+                (((x ^ y) * 5) % 256) as u8,
+            ])
+        })
+    }
+
+    fn test_adjustments() -> AllAdjustments {
+        let mut adjustments = get_all_adjustments_from_json(&json!({
+            "exposure": 0.4,
+            "contrast": 20,
+            "sharpness": 60,
+            "clarity": 40,
+            "structure": 35,
+            "lumaNoiseReduction": 30,
+            "colorNoiseReduction": 40,
+            "vignetteAmount": -50,
+            "grainAmount": 40,
+        }), false);
+        let mut mask = MaskAdjustments::default();
+        mask.exposure = 0.5;
+        mask.clarity = 0.6;
+        adjustments.mask_adjustments.push(mask);
+        adjustments
+    }
+
+    fn gradient_mask() -> ImageBuffer<Luma<u8>, Vec<u8>> {
+        ImageBuffer::from_fn(WIDTH, HEIGHT, |x, _| Luma([(x * 255 / (WIDTH - 1)) as u8]))
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter, run with `cargo test -- --ignored`"]
+    fn tiled_output_matches_untiled() {
+        let context = pollster::block_on(GpuContext::new()).expect("no GPU adapter available");
+
+        let rgb = test_image();
+        let images = [
+            DynamicImage::ImageRgb8(rgb.clone()),
+            DynamicImage::ImageRgb32F(DynamicImage::ImageRgb8(rgb).to_rgb32f()),
+        ];
+        let masks = [gradient_mask()];
+
+        for image in &images {
+            let untiled = run_gpu_processing(&context, image, test_adjustments(), &masks).unwrap();
+            // Tile sizes smaller than, equal to and not dividing the apron.
+            for tile_size in [16, 20, 64, 97] {
+                let tiled = run_gpu_processing_tiled(&context, &context.pipeline, image, test_adjustments(), &masks, tile_size, (0, 0), (WIDTH, HEIGHT)).unwrap();
+                let first_difference = tiled.iter().zip(&untiled).position(|(a, b)| a != b);
+                assert_eq!(
+                    first_difference.map(|i| ((i / 4) as u32 % WIDTH, (i / 4) as u32 / WIDTH)),
+                    None,
+                    "tile size {} differs from the untiled result at pixel (x, y)",
+                    tile_size
+                );
+            }
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter, run with `cargo test -- --ignored`"]
+    fn cached_preview_matches_uncached() {
+        let context = pollster::block_on(GpuContext::new()).expect("no GPU adapter available");
+
+        let image = DynamicImage::ImageRgb8(test_image());
+        let key = GpuImageKey { image_id: 1, transform_hash: 0 };
+        let mut inverted_mask = gradient_mask();
+        image::imageops::invert(&mut inverted_mask);
+
+        // A slider change, a changed mask layer and a removed mask, rendered
+        // in sequence so each step reuses what the previous one uploaded.
+        let mut steps = vec![(test_adjustments(), vec![gradient_mask()])];
+        let mut brighter = test_adjustments();
+        brighter.global.exposure += 0.5;
+        steps.push((brighter.clone(), vec![gradient_mask()]));
+        steps.push((brighter.clone(), vec![inverted_mask]));
+        brighter.mask_adjustments.clear();
+        steps.push((brighter, Vec::new()));
+
+        for (adjustments, masks) in steps {
+            let cached = run_gpu_processing_cached(&context, key, &image, adjustments.clone(), &masks).unwrap();
+            let uncached = run_gpu_processing(&context, &image, adjustments, &masks).unwrap();
+            assert!(cached == uncached, "cached preview differs from a fresh render");
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter, run with `cargo test -- --ignored`"]
+    fn region_matches_full_render() {
+        let context = pollster::block_on(GpuContext::new()).expect("no GPU adapter available");
+
+        let image = DynamicImage::ImageRgb8(test_image());
+        let masks = vec![gradient_mask()];
+        let full = run_gpu_processing(&context, &image, test_adjustments(), &masks).unwrap();
+
+        let (visible_x, visible_y, visible_w, visible_h) = (70, 45, 120, 90);
+        let (region_x, region_y) = (visible_x - TILE_APRON, visible_y - TILE_APRON);
+        let (region_w, region_h) = (visible_w + 2 * TILE_APRON, visible_h + 2 * TILE_APRON);
+        let region_image = image.crop_imm(region_x, region_y, region_w, region_h);
+        let region_masks: Vec<_> = masks
+            .iter()
+            .map(|m| image::imageops::crop_imm(m, region_x, region_y, region_w, region_h).to_image())
+            .collect();
+
+        let region = run_gpu_processing_region(
+            &context,
+            &region_image,
+            test_adjustments(),
+            &region_masks,
+            (region_x, region_y),
+            (WIDTH, HEIGHT),
+        )
+        .unwrap();
+
+        for y in 0..visible_h {
+            for x in 0..visible_w {
+                let r = (((y + TILE_APRON) * region_w + x + TILE_APRON) * 4) as usize;
+                let f = (((y + visible_y) * WIDTH + x + visible_x) * 4) as usize;
+                assert_eq!(region[r..r + 4], full[f..f + 4], "region differs at ({}, {})", x, y);
+            }
+        }
+    }
+}
@@ -1,17 +1,19 @@
 use anyhow::{Result, Context};
 use base64::{engine::general_purpose, Engine as _};
-use image::{imageops, DynamicImage, ImageReader, RgbaImage};
-use rawler::Orientation;
+use image::{imageops, DynamicImage, ImageReader, Rgb32FImage, RgbaImage};
+use rawler::{decoders::RawDecodeParams, rawsource::RawSource, Orientation};
 use std::io::Cursor;
 use rayon::prelude::*;
 use serde_json::Value;
 use std::fs;
 
 use exif::{Reader as ExifReader, Tag};
-use crate::image_processing::apply_orientation;
+use crate::file_management::get_sidecar_path;
+use crate::image_processing::{apply_orientation, ImageMetadata};
 
 use crate::formats::is_raw_file;
-use crate::raw_processing::develop_raw_image;
+use crate::retouch::{apply_retouch, RetouchParameters};
+use crate::raw_processing::{develop_raw_image, RawDevelopSettings};
 
 pub fn load_and_composite(
     path: &str,
@@ -19,17 +21,53 @@ pub fn load_and_composite(
     use_fast_raw_dev: bool,
 ) -> Result<DynamicImage> {
     let file_bytes = fs::read(path)?;
-    let base_image = load_base_image_from_bytes(&file_bytes, path, use_fast_raw_dev)?;
+    let raw_settings = RawDevelopSettings::from_adjustments(adjustments);
+    let base_image = load_base_image_from_bytes(&file_bytes, path, use_fast_raw_dev, &raw_settings)?;
     composite_patches_on_image(&base_image, adjustments)
 }
 
+/// Loads an image through the regular develop path (including AI patches) and
+/// converts it to linear-light RGB for merge operations like stitching and stacking.
+/// Values are left unbounded so highlights recovered from raw files survive.
+pub fn load_linear_image(path: &str) -> Result<Rgb32FImage> {
+    let metadata: ImageMetadata = fs::read_to_string(get_sidecar_path(path))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default();
+
+    let image = load_and_composite(path, &metadata.adjustments, false)
+        .with_context(|| format!("Failed to load {}", path))?;
+    let mut linear = image.to_rgb32f();
+    linear.par_chunks_mut(3).for_each(|p| {
+        p.iter_mut().for_each(|c| *c = srgb_to_linear(*c));
+    });
+    Ok(linear)
+}
+
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
 pub fn load_base_image_from_bytes(
     bytes: &[u8],
     path_for_ext_check: &str,
     use_fast_raw_dev: bool,
+    raw_settings: &RawDevelopSettings,
 ) -> Result<DynamicImage> {
     if is_raw_file(path_for_ext_check) {
-        develop_raw_image(bytes, use_fast_raw_dev)
+        develop_raw_image(bytes, use_fast_raw_dev, raw_settings)
     } else {
         load_image_with_orientation(bytes)
     }
@@ -55,6 +93,29 @@ pub fn load_image_with_orientation(bytes: &[u8]) -> Result<DynamicImage> {
     Ok(image)
 }
 
+/// EXIF orientation of a file without decoding its pixels.
+pub fn read_orientation(bytes: &[u8], path_for_ext_check: &str) -> Orientation {
+    if is_raw_file(path_for_ext_check) {
+        let source = RawSource::new_from_slice(bytes);
+        return rawler::get_decoder(&source)
+            .and_then(|decoder| decoder.raw_metadata(&source, &RawDecodeParams::default()))
+            .ok()
+            .and_then(|metadata| metadata.exif.orientation)
+            .map(Orientation::from_u16)
+            .unwrap_or(Orientation::Normal);
+    }
+
+    ExifReader::new()
+        .read_from_container(&mut Cursor::new(bytes))
+        .ok()
+        .and_then(|exif| {
+            exif.get_field(Tag::Orientation, exif::In::PRIMARY)
+                .and_then(|f| f.value.get_uint(0))
+        })
+        .map(|orientation| Orientation::from_u16(orientation as u16))
+        .unwrap_or(Orientation::Normal)
+}
+
 pub fn composite_patches_on_image(
     base_image: &DynamicImage,
     current_adjustments: &Value,
@@ -69,39 +130,70 @@ pub fn composite_patches_on_image(
         _ => return Ok(base_image.clone()),
     };
 
-    let visible_patches_b64: Vec<&str> = patches_arr
-        .par_iter()
-        .filter_map(|patch_obj| {
-            let is_visible = patch_obj
+    let visible_patches: Vec<&Value> = patches_arr
+        .iter()
+        .filter(|patch_obj| {
+            patch_obj
                 .get("visible")
                 .and_then(|v| v.as_bool())
-                .unwrap_or(true);
-            if is_visible {
-                patch_obj.get("patchDataBase64").and_then(|v| v.as_str())
-            } else {
-                None
-            }
+                .unwrap_or(true)
         })
         .collect();
 
-    if visible_patches_b64.is_empty() {
+    if visible_patches.is_empty() {
         return Ok(base_image.clone());
     }
 
-    let patch_layers: Result<Vec<RgbaImage>> = visible_patches_b64
+    // Retouch patches are rendered at the current resolution, bitmap patches
+    // are decoded from their PNG layer.
+    let patch_layers: Result<Vec<Option<RgbaImage>>> = visible_patches
         .par_iter()
-        .map(|&b64_data| {
-            let png_bytes = general_purpose::STANDARD.decode(b64_data)?;
-            let patch_layer = image::load_from_memory(&png_bytes)?;
-            Ok(patch_layer.to_rgba8())
+        .map(|patch_obj| {
+            if patch_obj.get("retouch").is_some() {
+                return Ok(None);
+            }
+            // Generated patches may carry several variants, one of them selected.
+            let selected_variant = patch_obj
+                .get("selectedVariant")
+                .and_then(|v| v.as_u64())
+                .and_then(|index| patch_obj.get("variants")?.get(index as usize));
+            match selected_variant.or_else(|| patch_obj.get("patchDataBase64")).and_then(|v| v.as_str()) {
+                Some(b64_data) => {
+                    let png_bytes = general_purpose::STANDARD.decode(b64_data)?;
+                    let patch_layer = image::load_from_memory(&png_bytes)?;
+                    Ok(Some(patch_layer.to_rgba8()))
+                }
+                None => Ok(None),
+            }
         })
         .collect();
-
     let patch_layers = patch_layers?;
-    let mut composited_rgba = base_image.to_rgba8();
-    for patch_layer in &patch_layers {
-        imageops::overlay(&mut composited_rgba, patch_layer, 0, 0);
+
+    let has_retouch = visible_patches.iter().any(|patch_obj| patch_obj.get("retouch").is_some());
+    if !has_retouch && patch_layers.iter().all(Option::is_none) {
+        return Ok(base_image.clone());
+    }
+    // Developed raw images keep highlights above 1.0, which 8 bits would clip.
+    let is_float = matches!(base_image, DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_));
+    if !has_retouch && !is_float {
+        let mut composited_rgba = base_image.to_rgba8();
+        for patch_layer in patch_layers.iter().flatten() {
+            imageops::overlay(&mut composited_rgba, patch_layer, 0, 0);
+        }
+        return Ok(DynamicImage::ImageRgba8(composited_rgba));
     }
 
-    Ok(DynamicImage::ImageRgba8(composited_rgba))
-}
\ No newline at end of file
+    let mut composited = base_image.to_rgba32f();
+    for (patch_obj, patch_layer) in visible_patches.iter().zip(&patch_layers) {
+        if let Some(retouch_val) = patch_obj.get("retouch") {
+            let params: RetouchParameters = serde_json::from_value(retouch_val.clone())
+                .context("Invalid retouch patch")?;
+            apply_retouch(&mut composited, &params);
+        } else if let Some(patch_layer) = patch_layer {
+            let layer = DynamicImage::ImageRgba8(patch_layer.clone()).to_rgba32f();
+            imageops::overlay(&mut composited, &layer, 0, 0);
+        }
+    }
+
+    Ok(DynamicImage::ImageRgba32F(composited))
+}
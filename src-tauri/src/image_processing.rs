@@ -1,4 +1,4 @@
-use std::{io::Read, sync::Arc};
+use std::{io::Read, sync::{Arc, Mutex, OnceLock}};
 use base64::{engine::general_purpose, Engine};
 use bytemuck::{Pod, Zeroable};
 use image::{buffer, DynamicImage, GenericImageView, Rgba};
@@ -7,10 +7,12 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::f32::consts::PI;
 use rawler::decoders::Orientation;
-use serde_json::json;
 
-pub use crate::gpu_processing::{get_or_init_gpu_context, process_and_get_dynamic_image};
+pub use crate::gpu_processing::{get_or_init_gpu_context, process_and_get_dynamic_image, process_and_get_rgba32f};
+use crate::gpu_processing::{GpuImageCache, GpuPipeline, PipelineOutput};
+use crate::raw_processing::camera_profile_has_tone_curve;
 use crate::{AppState, mask_generation::MaskDefinition, load_settings};
+use crate::lut_processes::lut::{LutAdjustment, LutUniform};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ImageMetadata {
@@ -55,6 +57,16 @@ pub fn apply_rotation(image: &DynamicImage, rotation_degrees: f32) -> DynamicIma
         return image.clone();
     }
 
+    if let DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) = image {
+        let rotated = rotate_about_center(
+            &image.to_rgba32f(),
+            rotation_degrees * PI / 180.0,
+            Interpolation::Bilinear,
+            Rgba([0.0f32, 0.0, 0.0, 0.0]),
+        );
+        return DynamicImage::ImageRgba32F(rotated);
+    }
+
     let rgba_image = image.to_rgba8();
     
     let rotated = rotate_about_center(
@@ -102,19 +114,6 @@ pub fn apply_flip(image: DynamicImage, horizontal: bool, vertical: bool) -> Dyna
     img
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct AutoAdjustmentResults {
-    pub exposure: f64,
-    pub contrast: f64,
-    pub highlights: f64,
-    pub shadows: f64,
-    pub vibrancy: f64,
-    pub vignette_amount: f64,
-    pub temperature: f64,
-    pub tint: f64,
-    pub dehaze: f64,
-}
-
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, Pod, Zeroable, Default)]
 #[repr(C)]
 pub struct Point {
@@ -180,6 +179,11 @@ pub struct GlobalAdjustments {
     _pad_neg1: f32,
     _pad_neg2: f32,
 
+    pub tone_mapper: u32,
+    pub filmic_white_exposure: f32,
+    pub filmic_black_exposure: f32,
+    pub filmic_contrast: f32,
+
     pub color_grading_shadows: ColorGradeSettings,
     pub color_grading_midtones: ColorGradeSettings,
     pub color_grading_highlights: ColorGradeSettings,
@@ -244,15 +248,46 @@ pub struct MaskAdjustments {
     pub blue_curve_count: u32,
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct AllAdjustments {
+    pub global: GlobalAdjustments,
+    /// One entry per mask bitmap, uploaded to the GPU as a storage buffer.
+    pub mask_adjustments: Vec<MaskAdjustments>,
+    pub lut: Option<LutAdjustment>,
+}
+
+impl AllAdjustments {
+    /// Builds the uniform for a tile whose top-left pixel sits at the given
+    /// offset in an image of `full_width` x `full_height`.
+    pub fn uniform(&self, tile_offset_x: u32, tile_offset_y: u32, full_width: u32, full_height: u32) -> AdjustmentsUniform {
+        AdjustmentsUniform {
+            global: self.global,
+            mask_count: self.mask_adjustments.len() as u32,
+            tile_offset_x,
+            tile_offset_y,
+            full_width,
+            full_height,
+            _pad1: 0,
+            _pad2: 0,
+            _pad3: 0,
+            lut: self.lut.as_ref().map(LutAdjustment::uniform).unwrap_or_default(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Pod, Zeroable, Default)]
 #[repr(C)]
-pub struct AllAdjustments {
+pub struct AdjustmentsUniform {
     pub global: GlobalAdjustments,
-    pub mask_adjustments: [MaskAdjustments; 16],
     pub mask_count: u32,
     pub tile_offset_x: u32,
     pub tile_offset_y: u32,
+    pub full_width: u32,
+    pub full_height: u32,
     _pad1: u32,
+    _pad2: u32,
+    _pad3: u32,
+    pub lut: LutUniform,
 }
 
 struct AdjustmentScales {
@@ -372,9 +407,42 @@ fn convert_points_to_aligned(frontend_points: Vec<serde_json::Value>) -> [Point;
     aligned_points
 }
 
-fn get_global_adjustments_from_json(js_adjustments: &serde_json::Value) -> GlobalAdjustments {
+const TONE_MAPPER_NONE: u32 = 0;
+const TONE_MAPPER_ACES: u32 = 1;
+const TONE_MAPPER_FILMIC: u32 = 2;
+const TONE_MAPPER_AGX: u32 = 3;
+
+const DEFAULT_FILMIC_WHITE_EXPOSURE: f64 = 4.0;
+const DEFAULT_FILMIC_BLACK_EXPOSURE: f64 = -8.0;
+const DEFAULT_FILMIC_CONTRAST: f64 = 1.6;
+
+/// Raw files are developed scene-referred, so without an explicit choice they
+/// get the ACES curve that used to be baked into the raw develop. Other
+/// images are already display-referred and are only clipped.
+fn parse_tone_mapper(js_adjustments: &serde_json::Value, is_raw: bool) -> u32 {
+    // A DCP tone curve has already tone mapped the raw data during develop.
+    if is_raw && js_adjustments["cameraProfile"].as_str().is_some_and(camera_profile_has_tone_curve) {
+        return TONE_MAPPER_NONE;
+    }
+    match js_adjustments["toneMapper"].as_str() {
+        Some("none") => TONE_MAPPER_NONE,
+        Some("aces") => TONE_MAPPER_ACES,
+        Some("filmic") => TONE_MAPPER_FILMIC,
+        Some("agx") => TONE_MAPPER_AGX,
+        _ if is_raw => TONE_MAPPER_ACES,
+        _ => TONE_MAPPER_NONE,
+    }
+}
+
+fn get_global_adjustments_from_json(js_adjustments: &serde_json::Value, is_raw: bool) -> GlobalAdjustments {
     if js_adjustments.is_null() {
-        return GlobalAdjustments::default();
+        return GlobalAdjustments {
+            tone_mapper: parse_tone_mapper(js_adjustments, is_raw),
+            filmic_white_exposure: DEFAULT_FILMIC_WHITE_EXPOSURE as f32,
+            filmic_black_exposure: DEFAULT_FILMIC_BLACK_EXPOSURE as f32,
+            filmic_contrast: DEFAULT_FILMIC_CONTRAST as f32,
+            ..GlobalAdjustments::default()
+        };
     }
 
     let visibility = js_adjustments.get("sectionVisibility");
@@ -450,6 +518,11 @@ fn get_global_adjustments_from_json(js_adjustments: &serde_json::Value) -> Globa
         _pad_neg1: 0.0,
         _pad_neg2: 0.0,
 
+        tone_mapper: parse_tone_mapper(js_adjustments, is_raw),
+        filmic_white_exposure: js_adjustments["filmicWhiteExposure"].as_f64().unwrap_or(DEFAULT_FILMIC_WHITE_EXPOSURE) as f32,
+        filmic_black_exposure: js_adjustments["filmicBlackExposure"].as_f64().unwrap_or(DEFAULT_FILMIC_BLACK_EXPOSURE) as f32,
+        filmic_contrast: js_adjustments["filmicContrast"].as_f64().unwrap_or(DEFAULT_FILMIC_CONTRAST) as f32,
+
         color_grading_shadows: if is_visible("color") { parse_color_grade_settings(&cg_obj["shadows"]) } else { ColorGradeSettings::default() },
         color_grading_midtones: if is_visible("color") { parse_color_grade_settings(&cg_obj["midtones"]) } else { ColorGradeSettings::default() },
         color_grading_highlights: if is_visible("color") { parse_color_grade_settings(&cg_obj["highlights"]) } else { ColorGradeSettings::default() },
@@ -541,27 +614,23 @@ fn get_mask_adjustments_from_json(adj: &serde_json::Value) -> MaskAdjustments {
     }
 }
 
-pub fn get_all_adjustments_from_json(js_adjustments: &serde_json::Value) -> AllAdjustments {
-    let global = get_global_adjustments_from_json(js_adjustments);
-    let mut mask_adjustments = [MaskAdjustments::default(); 16];
-    let mut mask_count = 0;
+pub fn get_all_adjustments_from_json(js_adjustments: &serde_json::Value, is_raw: bool) -> AllAdjustments {
+    let global = get_global_adjustments_from_json(js_adjustments, is_raw);
 
     let mask_definitions: Vec<MaskDefinition> = js_adjustments.get("masks")
         .and_then(|m| serde_json::from_value(m.clone()).ok())
         .unwrap_or_else(Vec::new);
 
-    for (i, mask_def) in mask_definitions.iter().filter(|m| m.visible).enumerate().take(16) {
-        mask_adjustments[i] = get_mask_adjustments_from_json(&mask_def.adjustments);
-        mask_count += 1;
-    }
+    // Must match the masks for which `generate_mask_bitmap` produces a bitmap.
+    let mask_adjustments = mask_definitions.iter()
+        .filter(|m| m.visible && !m.sub_masks.is_empty())
+        .map(|m| get_mask_adjustments_from_json(&m.adjustments))
+        .collect();
 
     AllAdjustments {
         global,
         mask_adjustments,
-        mask_count,
-        tile_offset_x: 0,
-        tile_offset_y: 0,
-        _pad1: 0,
+        lut: js_adjustments.get("lut").and_then(LutAdjustment::from_json),
     }
 }
 
@@ -570,9 +639,38 @@ pub struct GpuContext {
     pub device: Arc<wgpu::Device>,
     pub queue: Arc<wgpu::Queue>,
     pub limits: wgpu::Limits,
+    pub pipeline: Arc<GpuPipeline>,
+    /// Textures of the last interactive preview, see `run_gpu_processing_cached`.
+    pub image_cache: Arc<Mutex<Option<GpuImageCache>>>,
+    /// Float output variants of `pipeline`, compiled on first use.
+    float_pipeline: Arc<OnceLock<GpuPipeline>>,
+    unclipped_pipeline: Arc<OnceLock<GpuPipeline>>,
 }
 
 impl GpuContext {
+    pub fn from_device(device: wgpu::Device, queue: wgpu::Queue, limits: wgpu::Limits) -> Self {
+        let pipeline = GpuPipeline::new(&device, &queue);
+        GpuContext {
+            device: Arc::new(device),
+            queue: Arc::new(queue),
+            limits,
+            pipeline: Arc::new(pipeline),
+            image_cache: Arc::new(Mutex::new(None)),
+            float_pipeline: Arc::new(OnceLock::new()),
+            unclipped_pipeline: Arc::new(OnceLock::new()),
+        }
+    }
+
+    pub fn float_pipeline(&self) -> &GpuPipeline {
+        self.float_pipeline
+            .get_or_init(|| GpuPipeline::with_output(&self.device, &self.queue, PipelineOutput::DisplayFloat))
+    }
+
+    pub fn unclipped_pipeline(&self) -> &GpuPipeline {
+        self.unclipped_pipeline
+            .get_or_init(|| GpuPipeline::with_output(&self.device, &self.queue, PipelineOutput::Unclipped))
+    }
+
     pub async fn new() -> Result<Self, String> {
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
         let adapter = instance.request_adapter(&wgpu::RequestAdapterOptions {
@@ -590,11 +688,7 @@ impl GpuContext {
             None,
         ).await.map_err(|e| format!("Failed to create device: {}", e))?;
 
-        Ok(GpuContext {
-            device: Arc::new(device),
-            queue: Arc::new(queue),
-            limits: adapter.limits(),
-        })
+        Ok(GpuContext::from_device(device, queue, adapter.limits()))
     }
 }
 
@@ -753,38 +847,44 @@ pub fn generate_waveform(state: tauri::State<AppState>, app_handle: tauri::AppHa
 pub fn calculate_waveform_from_image(image: &DynamicImage) -> Result<WaveformData, String> {
     const WAVEFORM_WIDTH: u32 = 256;
     const WAVEFORM_HEIGHT: u32 = 256;
+    calculate_waveform_with_size(image, WAVEFORM_WIDTH, WAVEFORM_HEIGHT)
+}
 
+/// Waveform with `width` columns across the image and `height` levels from
+/// black at the bottom row to white at the top row.
+pub fn calculate_waveform_with_size(image: &DynamicImage, width: u32, height: u32) -> Result<WaveformData, String> {
     if image.width() == 0 || image.height() == 0 {
         return Err("Image has zero dimensions.".to_string());
     }
-    let preview_height = (image.height() as f32 * (WAVEFORM_WIDTH as f32 / image.width() as f32)).round() as u32;
+    if width == 0 || height < 2 {
+        return Err("Waveform size is too small.".to_string());
+    }
+    let preview_height = (image.height() as f32 * (width as f32 / image.width() as f32)).round() as u32;
     if preview_height == 0 {
         return Err("Image has zero height after scaling for waveform.".to_string());
     }
-    let preview = image.resize(WAVEFORM_WIDTH, preview_height, image::imageops::FilterType::Triangle);
+    let preview = image.resize_exact(width, preview_height, image::imageops::FilterType::Triangle);
     let rgb_image = preview.to_rgb8();
 
-    let mut red = vec![0; (WAVEFORM_WIDTH * WAVEFORM_HEIGHT) as usize];
-    let mut green = vec![0; (WAVEFORM_WIDTH * WAVEFORM_HEIGHT) as usize];
-    let mut blue = vec![0; (WAVEFORM_WIDTH * WAVEFORM_HEIGHT) as usize];
-    let mut luma = vec![0; (WAVEFORM_WIDTH * WAVEFORM_HEIGHT) as usize];
+    let mut red = vec![0; (width * height) as usize];
+    let mut green = vec![0; (width * height) as usize];
+    let mut blue = vec![0; (width * height) as usize];
+    let mut luma = vec![0; (width * height) as usize];
 
-    for (x, _, pixel) in rgb_image.enumerate_pixels() {
-        let r = pixel[0] as usize;
-        let g = pixel[1] as usize;
-        let b = pixel[2] as usize;
+    let top = (height - 1) as usize;
+    let level = |value: f32| ((value / 255.0 * top as f32).round() as usize).min(top);
 
-        let r_idx = (255 - r) * WAVEFORM_WIDTH as usize + x as usize;
-        let g_idx = (255 - g) * WAVEFORM_WIDTH as usize + x as usize;
-        let b_idx = (255 - b) * WAVEFORM_WIDTH as usize + x as usize;
+    for (x, _, pixel) in rgb_image.enumerate_pixels() {
+        let r = pixel[0] as f32;
+        let g = pixel[1] as f32;
+        let b = pixel[2] as f32;
 
-        red[r_idx] += 1;
-        green[g_idx] += 1;
-        blue[b_idx] += 1;
+        red[(top - level(r)) * width as usize + x as usize] += 1;
+        green[(top - level(g)) * width as usize + x as usize] += 1;
+        blue[(top - level(b)) * width as usize + x as usize] += 1;
 
-        let luma_val = (0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32).round() as usize;
-        let luma_idx = (255 - luma_val.min(255)) * WAVEFORM_WIDTH as usize + x as usize;
-        luma[luma_idx] += 1;
+        let luma_val = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        luma[(top - level(luma_val)) * width as usize + x as usize] += 1;
     }
 
     Ok(WaveformData {
@@ -792,8 +892,8 @@ pub fn calculate_waveform_from_image(image: &DynamicImage) -> Result<WaveformDat
         green,
         blue,
         luma,
-        width: WAVEFORM_WIDTH,
-        height: WAVEFORM_HEIGHT,
+        width,
+        height,
     })
 }
 
@@ -816,208 +916,4 @@ pub fn read_file_data(path: &str) -> Result<String, String> {
     let mut contents = String::new();
     file.read_to_string(&mut contents).map_err(|e| format!("Failed to read file: {}", e))?;
     Ok(contents)
-}
-
-pub fn perform_auto_analysis(image: &DynamicImage) -> AutoAdjustmentResults {
-    let analysis_preview = image.thumbnail(1024, 1024);
-    let rgb_image = analysis_preview.to_rgb8();
-    let total_pixels = (rgb_image.width() * rgb_image.height()) as f64;
-
-    let mut luma_hist = vec![0u32; 256];
-    let mut mean_saturation = 0.0f32;
-    let mut dull_pixel_count = 0;
-    let mut brightest_pixels = Vec::with_capacity((total_pixels * 0.01) as usize);
-
-    for pixel in rgb_image.pixels() {
-        let r_f = pixel[0] as f32;
-        let g_f = pixel[1] as f32;
-        let b_f = pixel[2] as f32;
-
-        let luma_val = (0.2126 * r_f + 0.7152 * g_f + 0.0722 * b_f).round() as usize;
-        luma_hist[luma_val.min(255)] += 1;
-
-        let r_norm = r_f / 255.0;
-        let g_norm = g_f / 255.0;
-        let b_norm = b_f / 255.0;
-        let max_c = r_norm.max(g_norm.max(b_norm));
-        let min_c = r_norm.min(g_norm.min(b_norm));
-        if max_c > 0.0 {
-            let s = (max_c - min_c) / max_c;
-            mean_saturation += s;
-            if s < 0.1 {
-                dull_pixel_count += 1;
-            }
-        }
-        brightest_pixels.push((luma_val, (r_f, g_f, b_f)));
-    }
-
-    if total_pixels > 0.0 {
-        mean_saturation /= total_pixels as f32;
-    }
-    let dull_pixel_percent = dull_pixel_count as f64 / total_pixels;
-
-    let mut black_point = 0;
-    let mut white_point = 255;
-    let clip_threshold = (total_pixels * 0.001) as u32;
-    let mut cumulative_sum = 0u32;
-    for i in 0..256 {
-        cumulative_sum += luma_hist[i];
-        if cumulative_sum > clip_threshold { black_point = i; break; }
-    }
-    cumulative_sum = 0;
-    for i in (0..256).rev() {
-        cumulative_sum += luma_hist[i];
-        if cumulative_sum > clip_threshold { white_point = i; break; }
-    }
-
-    let mid_point = (black_point + white_point) / 2;
-    let range = (white_point as f64 - black_point as f64).max(1.0);
-    let mut exposure = 0.0;
-    let mut contrast = 0.0;
-    if range > 20.0 {
-        exposure = (128.0 - mid_point as f64) * 0.35;
-        let target_range = 250.0;
-        if range < target_range {
-            contrast = (target_range / range - 1.0) * 50.0;
-        }
-    }
-
-    let shadow_percent = luma_hist[0..32].iter().sum::<u32>() as f64 / total_pixels;
-    let highlight_percent = luma_hist[224..256].iter().sum::<u32>() as f64 / total_pixels;
-    let mut shadows = 0.0;
-    if shadow_percent > 0.05 && black_point < 10 {
-        shadows = (shadow_percent * 150.0).min(80.0);
-    }
-    let mut highlights = 0.0;
-    if highlight_percent > 0.05 && white_point > 245 {
-        highlights = -(highlight_percent * 150.0).min(80.0);
-    }
-
-    brightest_pixels.sort_by(|a, b| b.0.cmp(&a.0));
-    let num_brightest = (total_pixels * 0.01).ceil() as usize;
-    let top_pixels = &brightest_pixels[..num_brightest.min(brightest_pixels.len())];
-    let mut bright_r = 0.0;
-    let mut bright_g = 0.0;
-    let mut bright_b = 0.0;
-    if !top_pixels.is_empty() {
-        for &(_, (r, g, b)) in top_pixels {
-            bright_r += r as f64;
-            bright_g += g as f64;
-            bright_b += b as f64;
-        }
-        bright_r /= top_pixels.len() as f64;
-        bright_g /= top_pixels.len() as f64;
-        bright_b /= top_pixels.len() as f64;
-    }
-
-    let mut temperature = 0.0;
-    let mut tint = 0.0;
-    if (bright_r - bright_b).abs() > 3.0 || (bright_g - (bright_r + bright_b) / 2.0).abs() > 3.0 {
-        temperature = (bright_b - bright_r) * 0.4;
-        tint = (bright_g - (bright_r + bright_b) / 2.0) * 0.5;
-    }
-
-    let mut vibrancy = 0.0;
-    let saturation_target = 0.20;
-    if mean_saturation < saturation_target {
-        vibrancy = (saturation_target - mean_saturation) as f64 * 150.0;
-    }
-    if dull_pixel_percent > 0.5 {
-        vibrancy += 10.0;
-    }
-
-    let mut dehaze = 0.0;
-    if range < 128.0 && mean_saturation < 0.15 {
-        dehaze = (1.0 - (range / 128.0)) * 40.0;
-    }
-
-    let (width, height) = rgb_image.dimensions();
-    let center_x_start = (width as f32 * 0.25) as u32;
-    let center_x_end = (width as f32 * 0.75) as u32;
-    let center_y_start = (height as f32 * 0.25) as u32;
-    let center_y_end = (height as f32 * 0.75) as u32;
-    let mut center_luma_sum = 0.0;
-    let mut center_pixel_count = 0;
-    let mut edge_luma_sum = 0.0;
-    let mut edge_pixel_count = 0;
-    for (x, y, pixel) in rgb_image.enumerate_pixels() {
-        let luma = (0.2126 * pixel[0] as f32 + 0.7152 * pixel[1] as f32 + 0.0722 * pixel[2] as f32) / 255.0;
-        if x >= center_x_start && x < center_x_end && y >= center_y_start && y < center_y_end {
-            center_luma_sum += luma;
-            center_pixel_count += 1;
-        } else {
-            edge_luma_sum += luma;
-            edge_pixel_count += 1;
-        }
-    }
-    let mut vignette_amount = 0.0;
-    let mut avg_center_luma = 0.0;
-    let mut avg_edge_luma = 0.0;
-    if center_pixel_count > 0 && edge_pixel_count > 0 {
-        avg_center_luma = center_luma_sum / center_pixel_count as f32;
-        avg_edge_luma = edge_luma_sum / edge_pixel_count as f32;
-        if avg_edge_luma < avg_center_luma {
-            let luma_diff = (avg_center_luma - avg_edge_luma).max(0.0);
-            vignette_amount = -(luma_diff as f64 * 150.0);
-        }
-    }
-
-    println!("\n--- Auto Adjustments Analysis ---");
-    println!("Tonal Range: black_point={:.1}, white_point={:.1}, mid_point={:.1}, range={:.1}", black_point, white_point, mid_point, range);
-    println!("Distribution: shadow_percent={:.2}%, highlight_percent={:.2}%", shadow_percent * 100.0, highlight_percent * 100.0);
-    println!("White Balance Trigger: bright_r={:.1}, bright_g={:.1}, bright_b={:.1}", bright_r, bright_g, bright_b);
-    println!("Saturation: mean_saturation={:.3}, dull_pixel_percent={:.2}%", mean_saturation, dull_pixel_percent * 100.0);
-    println!("Dehaze Trigger: range < 128.0 ({}), mean_saturation < 0.15 ({})", range < 128.0, mean_saturation < 0.15);
-    println!("Vignette: center_luma={:.3}, edge_luma={:.3}", avg_center_luma, avg_edge_luma);
-    println!("---------------------------------");
-    println!("Calculated Values (pre-clamp):");
-    println!("  Exposure: {:.2}, Contrast: {:.2}", exposure / 20.0, contrast);
-    println!("  Highlights: {:.2}, Shadows: {:.2}", highlights, shadows);
-    println!("  Temperature: {:.2}, Tint: {:.2}", temperature, tint);
-    println!("  Vibrance: {:.2}, Dehaze: {:.2}", vibrancy, dehaze);
-    println!("  Vignette: {:.2}", vignette_amount);
-    println!("---------------------------------\n");
-
-    AutoAdjustmentResults {
-        exposure: (exposure / 20.0).clamp(-5.0, 5.0),
-        contrast: contrast.clamp(0.0, 100.0),
-        highlights: highlights.clamp(-100.0, 0.0),
-        shadows: shadows.clamp(0.0, 100.0),
-        vibrancy: vibrancy.clamp(0.0, 80.0),
-        vignette_amount: vignette_amount.clamp(-100.0, 0.0),
-        temperature: temperature.clamp(-100.0, 100.0),
-        tint: tint.clamp(-100.0, 100.0),
-        dehaze: dehaze.clamp(0.0, 100.0),
-    }
-}
-
-pub fn auto_results_to_json(results: &AutoAdjustmentResults) -> serde_json::Value {
-    json!({
-        "exposure": results.exposure,
-        "contrast": results.contrast,
-        "highlights": results.highlights,
-        "shadows": results.shadows,
-        "vibrance": results.vibrancy,
-        "vignetteAmount": results.vignette_amount,
-        "temperature": results.temperature,
-        "tint": results.tint,
-        "dehaze": results.dehaze,
-        "sectionVisibility": {
-            "basic": true,
-            "color": true,
-            "effects": true
-        }
-    })
-}
-
-#[tauri::command]
-pub fn calculate_auto_adjustments(state: tauri::State<AppState>) -> Result<serde_json::Value, String> {
-    let original_image = state.original_image.lock().unwrap()
-        .as_ref()
-        .ok_or("No image loaded for auto adjustments")?
-        .image.clone();
-
-    let results = perform_auto_analysis(&original_image);
-
-    Ok(auto_results_to_json(&results))
 }
\ No newline at end of file
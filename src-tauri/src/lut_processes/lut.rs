@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::SystemTime;
+
+use anyhow::{bail, Context, Result};
+use bytemuck::{Pod, Zeroable};
+use serde_json::Value;
+
+use super::lut_3dl::parse_3dl_lut;
+use super::lut_cube::parse_cube_lut;
+use super::lut_hald::parse_hald_clut;
+
+/// Parsed LUTs keyed by the hash of their file contents, so the same LUT under
+/// several paths is parsed and uploaded once.
+static LUTS: OnceLock<Mutex<LutCache>> = OnceLock::new();
+/// Content hash per path, valid while size and modification time match. Saves
+/// re-reading the file on every slider change.
+static FILE_HASHES: OnceLock<Mutex<HashMap<PathBuf, FileStamp>>> = OnceLock::new();
+
+/// A 65³ LUT with a shaper takes a few megabytes, so only the most recently
+/// used ones are kept.
+const MAX_CACHED_LUTS: usize = 16;
+/// Stamps are tiny; the cap only guards against browsing huge LUT folders.
+const MAX_FILE_HASHES: usize = 4096;
+
+/// Least recently used cache of parsed LUTs.
+#[derive(Default)]
+struct LutCache {
+    entries: HashMap<blake3::Hash, (Arc<Lut>, u64)>,
+    clock: u64,
+}
+
+impl LutCache {
+    fn get(&mut self, hash: &blake3::Hash) -> Option<Arc<Lut>> {
+        self.clock += 1;
+        let clock = self.clock;
+        self.entries.get_mut(hash).map(|(lut, last_used)| {
+            *last_used = clock;
+            lut.clone()
+        })
+    }
+
+    fn insert(&mut self, hash: blake3::Hash, lut: Arc<Lut>) {
+        self.clock += 1;
+        self.entries.insert(hash, (lut, self.clock));
+        while self.entries.len() > MAX_CACHED_LUTS {
+            let oldest = self.entries.iter().min_by_key(|(_, (_, last_used))| *last_used).map(|(hash, _)| *hash);
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct FileStamp {
+    len: u64,
+    modified: Option<SystemTime>,
+    hash: blake3::Hash,
+}
+
+/// Per-channel 1D curve applied before the 3D lookup, as written by Resolve
+/// into combined 1D/3D .cube files.
+#[derive(Debug, Clone)]
+pub struct Shaper {
+    /// RGBA entries, alpha unused.
+    pub data: Vec<f32>,
+    pub domain_min: [f32; 3],
+    pub domain_max: [f32; 3],
+}
+
+impl Shaper {
+    pub fn size(&self) -> u32 {
+        (self.data.len() / 4) as u32
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Lut {
+    /// Lattice points per axis.
+    pub size: u32,
+    /// RGBA entries with red varying fastest, then green, then blue.
+    pub data: Vec<f32>,
+    pub domain_min: [f32; 3],
+    pub domain_max: [f32; 3],
+    pub shaper: Option<Shaper>,
+    pub hash: blake3::Hash,
+}
+
+impl Lut {
+    /// Builds a LUT from RGB triplets in lattice order (red fastest).
+    pub fn from_rgb(size: u32, rgb: &[f32]) -> Result<Self> {
+        if size < 2 {
+            bail!("LUT size must be at least 2, got {}", size);
+        }
+        let expected = (size * size * size * 3) as usize;
+        if rgb.len() != expected {
+            bail!("LUT has {} values, expected {} for size {}", rgb.len(), expected, size);
+        }
+        Ok(Self {
+            size,
+            data: to_rgba(rgb),
+            domain_min: [0.0; 3],
+            domain_max: [1.0; 3],
+            shaper: None,
+            hash: blake3::hash(&[]),
+        })
+    }
+
+    /// A 2-point lattice that maps every input to itself.
+    pub fn identity() -> Self {
+        let rgb: Vec<f32> = (0..8)
+            .flat_map(|i| [(i & 1) as f32, ((i >> 1) & 1) as f32, ((i >> 2) & 1) as f32])
+            .collect();
+        Self::from_rgb(2, &rgb).expect("identity LUT is well formed")
+    }
+}
+
+pub fn to_rgba(rgb: &[f32]) -> Vec<f32> {
+    rgb.chunks_exact(3).flat_map(|c| [c[0], c[1], c[2], 1.0]).collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LutInterpolation {
+    Trilinear = 0,
+    Tetrahedral = 1,
+}
+
+/// Encoding the LUT expects its input in. Either way the result replaces the
+/// display-referred output of the edit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LutInputSpace {
+    Srgb = 0,
+    Linear = 1,
+}
+
+/// The `lut` block of the adjustments, resolved to a loaded LUT.
+#[derive(Debug, Clone)]
+pub struct LutAdjustment {
+    pub lut: Arc<Lut>,
+    /// Blend between the unmodified and the looked-up color, 0..1.
+    pub intensity: f32,
+    pub interpolation: LutInterpolation,
+    pub input_space: LutInputSpace,
+}
+
+#[derive(Debug, Clone, Copy, Pod, Zeroable, Default)]
+#[repr(C)]
+pub struct LutUniform {
+    pub enabled: u32,
+    pub size: u32,
+    pub shaper_size: u32,
+    pub interpolation: u32,
+    pub input_space: u32,
+    pub intensity: f32,
+    _pad1: f32,
+    _pad2: f32,
+    pub domain_min: [f32; 4],
+    pub domain_max: [f32; 4],
+    pub shaper_min: [f32; 4],
+    pub shaper_max: [f32; 4],
+}
+
+impl LutAdjustment {
+    /// Reads `{ path, intensity, interpolation, inputSpace }`. Returns `None`
+    /// when there is no LUT or it cannot be loaded, so a moved LUT file does
+    /// not break rendering.
+    pub fn from_json(js_lut: &Value) -> Option<Self> {
+        let path = js_lut.get("path")?.as_str()?;
+        if js_lut["enabled"].as_bool() == Some(false) {
+            return None;
+        }
+        let lut = match load_lut(Path::new(path)) {
+            Ok(lut) => lut,
+            Err(e) => {
+                eprintln!("Failed to load LUT {}: {}", path, e);
+                return None;
+            }
+        };
+        let intensity = js_lut["intensity"].as_f64().unwrap_or(100.0) as f32 / 100.0;
+        let interpolation = match js_lut["interpolation"].as_str() {
+            Some("trilinear") => LutInterpolation::Trilinear,
+            _ => LutInterpolation::Tetrahedral,
+        };
+        let input_space = match js_lut["inputSpace"].as_str() {
+            Some("linear") => LutInputSpace::Linear,
+            _ => LutInputSpace::Srgb,
+        };
+        Some(Self { lut, intensity: intensity.clamp(0.0, 1.0), interpolation, input_space })
+    }
+
+    pub fn uniform(&self) -> LutUniform {
+        let extend = |v: [f32; 3]| [v[0], v[1], v[2], 0.0];
+        let (shaper_size, shaper_min, shaper_max) = match &self.lut.shaper {
+            Some(shaper) => (shaper.size(), extend(shaper.domain_min), extend(shaper.domain_max)),
+            None => (0, [0.0; 4], [1.0, 1.0, 1.0, 0.0]),
+        };
+        LutUniform {
+            enabled: 1,
+            size: self.lut.size,
+            shaper_size,
+            interpolation: self.interpolation as u32,
+            input_space: self.input_space as u32,
+            intensity: self.intensity,
+            _pad1: 0.0,
+            _pad2: 0.0,
+            domain_min: extend(self.lut.domain_min),
+            domain_max: extend(self.lut.domain_max),
+            shaper_min,
+            shaper_max,
+        }
+    }
+}
+
+fn parse_lut_file(path: &Path, bytes: &[u8]) -> Result<Lut> {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_lowercase)
+        .unwrap_or_default();
+    let text = || std::str::from_utf8(bytes).context("LUT file is not valid text");
+    match extension.as_str() {
+        "cube" => parse_cube_lut(text()?),
+        "3dl" => parse_3dl_lut(text()?),
+        "png" | "tif" | "tiff" => parse_hald_clut(bytes),
+        _ => bail!("Unsupported LUT format: .{}", extension),
+    }
+}
+
+/// Loads a .cube, .3dl or HaldCLUT image, reusing the parsed LUT if a file
+/// with the same contents was loaded before.
+pub fn load_lut(path: &Path) -> Result<Arc<Lut>> {
+    let metadata = fs::metadata(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let (len, modified) = (metadata.len(), metadata.modified().ok());
+
+    let file_hashes = FILE_HASHES.get_or_init(|| Mutex::new(HashMap::new()));
+    let luts = LUTS.get_or_init(|| Mutex::new(LutCache::default()));
+
+    let known = file_hashes.lock().unwrap().get(path).copied();
+    if let Some(stamp) = known.filter(|s| s.len == len && s.modified == modified) {
+        if let Some(lut) = luts.lock().unwrap().get(&stamp.hash) {
+            return Ok(lut);
+        }
+    }
+
+    let bytes = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let hash = blake3::hash(&bytes);
+    {
+        let mut file_hashes = file_hashes.lock().unwrap();
+        if file_hashes.len() >= MAX_FILE_HASHES {
+            file_hashes.clear();
+        }
+        file_hashes.insert(path.to_path_buf(), FileStamp { len, modified, hash });
+    }
+
+    if let Some(lut) = luts.lock().unwrap().get(&hash) {
+        return Ok(lut);
+    }
+
+    let mut lut = parse_lut_file(path, &bytes)?;
+    lut.hash = hash;
+    let lut = Arc::new(lut);
+    luts.lock().unwrap().insert(hash, lut.clone());
+    Ok(lut)
+}
@@ -0,0 +1,58 @@
+use anyhow::{bail, Result};
+
+use super::lut::Lut;
+
+/// Parses an Autodesk/Lustre .3dl file. The optional first numeric row lists
+/// the input grid positions and gives the lattice size. Entries are integers
+/// with blue varying fastest; the output bit depth is inferred from the
+/// largest value.
+pub fn parse_3dl_lut(lut_text: &str) -> Result<Lut> {
+    let mut grid_size: Option<u32> = None;
+    let mut entries: Vec<[f32; 3]> = Vec::new();
+
+    for line in lut_text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        // Headers such as "3DMESH", "Mesh 4 12" or "LUT8" are not needed.
+        let Ok(values) = line.split_whitespace().map(str::parse::<f32>).collect::<Result<Vec<f32>, _>>() else {
+            continue;
+        };
+        match values.len() {
+            3 => entries.push([values[0], values[1], values[2]]),
+            n if n >= 2 && entries.is_empty() && grid_size.is_none() => grid_size = Some(n as u32),
+            _ => bail!("Invalid .3dl line: {}", line),
+        }
+    }
+
+    let size = match grid_size {
+        Some(size) => size,
+        None => (entries.len() as f64).cbrt().round() as u32,
+    };
+    if (size as usize).pow(3) != entries.len() {
+        bail!("LUT data size does not match a {}^3 lattice", size);
+    }
+
+    let max_value = entries.iter().flatten().fold(0.0f32, |acc, &v| acc.max(v));
+    let scale = if max_value <= 1.0 {
+        1.0
+    } else {
+        let bits = (max_value + 1.0).log2().ceil();
+        2f32.powf(bits) - 1.0
+    };
+
+    // Reorder from blue-fastest to the red-fastest lattice order.
+    let n = size as usize;
+    let mut rgb = vec![0.0f32; n * n * n * 3];
+    for (index, entry) in entries.iter().enumerate() {
+        let (r, g, b) = (index / (n * n), (index / n) % n, index % n);
+        let target = (r + g * n + b * n * n) * 3;
+        for c in 0..3 {
+            rgb[target + c] = entry[c] / scale;
+        }
+    }
+
+    Lut::from_rgb(size, &rgb)
+}
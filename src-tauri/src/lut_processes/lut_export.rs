@@ -0,0 +1,142 @@
+use std::fmt::Write as _;
+use std::fs;
+
+use image::{DynamicImage, GenericImageView, ImageBuffer, Rgb};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::image_processing::{
+    get_all_adjustments_from_json, get_or_init_gpu_context, process_and_get_rgba32f, GpuContext,
+};
+use crate::AppState;
+
+pub const SUPPORTED_LUT_EXPORT_SIZES: [u32; 2] = [33, 65];
+
+/// Adjustments that read neighbouring pixels or depend on the pixel position,
+/// as (section, key, label). They cannot be expressed as a color transform.
+const SPATIAL_ADJUSTMENTS: [(&str, &str, &str); 8] = [
+    ("details", "sharpness", "Sharpness"),
+    ("details", "lumaNoiseReduction", "Luminance Noise Reduction"),
+    ("details", "colorNoiseReduction", "Color Noise Reduction"),
+    ("effects", "clarity", "Clarity"),
+    ("effects", "dehaze", "Dehaze"),
+    ("effects", "structure", "Structure"),
+    ("effects", "vignetteAmount", "Vignette"),
+    ("effects", "grainAmount", "Grain"),
+];
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct LutExportResult {
+    pub path: String,
+    pub size: u32,
+    /// Labels of the active adjustments that are not part of the LUT.
+    pub omitted: Vec<String>,
+}
+
+/// Returns a copy of the adjustments with every spatial effect and all masks
+/// switched off, along with the labels of those that were active.
+pub fn strip_spatial_adjustments(js_adjustments: &Value) -> (Value, Vec<String>) {
+    let mut stripped = js_adjustments.clone();
+    let mut omitted = Vec::new();
+    let Some(object) = stripped.as_object_mut() else {
+        return (stripped, omitted);
+    };
+
+    let is_visible = |section: &str| {
+        js_adjustments
+            .get("sectionVisibility")
+            .and_then(|v| v.get(section))
+            .and_then(|s| s.as_bool())
+            .unwrap_or(true)
+    };
+
+    for (section, key, label) in SPATIAL_ADJUSTMENTS {
+        let active = js_adjustments[key].as_f64().is_some_and(|v| v != 0.0);
+        if active && is_visible(section) {
+            omitted.push(label.to_string());
+        }
+        object.insert(key.to_string(), Value::from(0));
+    }
+
+    let has_masks = js_adjustments["masks"]
+        .as_array()
+        .is_some_and(|masks| masks.iter().any(|m| m["visible"].as_bool().unwrap_or(true)));
+    if has_masks {
+        omitted.push("Masks".to_string());
+    }
+    object.insert("masks".to_string(), Value::Array(Vec::new()));
+
+    (stripped, omitted)
+}
+
+/// An image holding every lattice point once, sRGB encoded. Pixel `(x, y)`
+/// is lattice point `r = x % size, g = x / size, b = y`, so reading the image
+/// row by row visits the lattice with red varying fastest, as .cube expects.
+fn identity_lattice_image(size: u32) -> DynamicImage {
+    let max = (size - 1) as f32;
+    let lattice = ImageBuffer::from_fn(size * size, size, |x, y| {
+        Rgb([(x % size) as f32 / max, (x / size) as f32 / max, y as f32 / max])
+    });
+    DynamicImage::ImageRgb32F(lattice)
+}
+
+/// Evaluates the adjustments on an identity lattice with the regular GPU
+/// shader and returns the output colors in lattice order. Input and output
+/// are both float, so the entries are not quantized to 8 bits.
+pub fn bake_lut(
+    context: &GpuContext,
+    js_adjustments: &Value,
+    size: u32,
+    is_raw: bool,
+) -> Result<Vec<[f32; 3]>, String> {
+    let lattice = identity_lattice_image(size);
+    let all_adjustments = get_all_adjustments_from_json(js_adjustments, is_raw);
+    let processed = process_and_get_rgba32f(context, context.float_pipeline(), &lattice, all_adjustments, &[])?;
+    debug_assert_eq!(processed.dimensions(), lattice.dimensions());
+
+    Ok(processed.pixels().map(|p| [p[0], p[1], p[2]]).collect())
+}
+
+pub fn format_cube_lut(title: &str, size: u32, entries: &[[f32; 3]]) -> String {
+    let mut out = String::with_capacity(entries.len() * 28 + 128);
+    let _ = writeln!(out, "TITLE \"{}\"", title.replace('"', "'"));
+    let _ = writeln!(out, "LUT_3D_SIZE {}", size);
+    let _ = writeln!(out, "DOMAIN_MIN 0.0 0.0 0.0");
+    let _ = writeln!(out, "DOMAIN_MAX 1.0 1.0 1.0");
+    for [r, g, b] in entries {
+        let _ = writeln!(out, "{:.6} {:.6} {:.6}", r, g, b);
+    }
+    out
+}
+
+/// Bakes the global color part of the edit into a .cube file. The LUT expects
+/// sRGB-encoded input in [0, 1] and reproduces the adjustments the way they
+/// are applied to the currently loaded image, including its default tone
+/// mapper. Spatial effects and masks are left out and reported back.
+#[tauri::command]
+pub fn export_lut_cube(
+    js_adjustments: Value,
+    path: String,
+    size: u32,
+    state: tauri::State<AppState>,
+) -> Result<LutExportResult, String> {
+    if !SUPPORTED_LUT_EXPORT_SIZES.contains(&size) {
+        return Err(format!("Unsupported LUT size {}, expected 33 or 65", size));
+    }
+
+    let context = get_or_init_gpu_context(&state)?;
+    let is_raw = crate::is_loaded_image_raw(&state);
+    let (lut_adjustments, omitted) = strip_spatial_adjustments(&js_adjustments);
+
+    let entries = bake_lut(&context, &lut_adjustments, size, is_raw)?;
+
+    let title = std::path::Path::new(&path)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("RapidRAW")
+        .to_string();
+    fs::write(&path, format_cube_lut(&title, size, &entries)).map_err(|e| e.to_string())?;
+
+    Ok(LutExportResult { path, size, omitted })
+}
@@ -0,0 +1,17 @@
+use anyhow::{bail, Context, Result};
+
+use super::lut::Lut;
+
+/// Parses a HaldCLUT image. A level `L` Hald is `L^3` pixels square and holds
+/// an `L^2` lattice whose entries run through the image in reading order with
+/// red varying fastest, so the pixels are already in lattice order.
+pub fn parse_hald_clut(bytes: &[u8]) -> Result<Lut> {
+    let image = image::load_from_memory(bytes).context("Failed to decode HaldCLUT image")?;
+    let (width, height) = (image.width(), image.height());
+    let level = (width as f64).cbrt().round() as u32;
+    if width != height || level.pow(3) != width {
+        bail!("{}x{} is not a valid HaldCLUT size", width, height);
+    }
+
+    Lut::from_rgb(level * level, image.to_rgb32f().as_raw())
+}
@@ -1 +1,5 @@
-pub mod lut_cube;
\ No newline at end of file
+pub mod lut;
+pub mod lut_3dl;
+pub mod lut_cube;
+pub mod lut_export;
+pub mod lut_hald;
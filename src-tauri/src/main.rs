@@ -6,13 +6,32 @@ mod file_management;
 mod gpu_processing;
 mod raw_processing;
 mod mask_generation;
+mod mask_refinement;
+mod denoise;
 mod ai_processing;
 mod formats;
 mod image_loader;
 mod lut_processes;
+mod alignment;
+mod pyramid;
+mod panorama;
+mod focus_stacking;
+mod camera_profiles;
+mod color_calibration;
+mod sensor_calibration;
+mod model_registry;
+mod retouch;
+mod dust_detection;
+mod super_resolution;
+mod preview_pyramid;
+mod scopes;
+mod overlays;
+mod auto_adjust;
+mod white_balance;
 
 use std::io::Cursor;
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::thread;
 use std::fs;
 use std::collections::{HashMap, hash_map::DefaultHasher};
@@ -36,27 +55,45 @@ use crate::image_processing::{
     get_all_adjustments_from_json, get_or_init_gpu_context, GpuContext,
     ImageMetadata, process_and_get_dynamic_image, Crop, apply_crop, apply_rotation, apply_flip,
 };
+use crate::gpu_processing::{
+    process_cached_and_get_dynamic_image, process_region_and_get_dynamic_image, GpuImageKey, TILE_APRON,
+};
+use crate::preview_pyramid::{PreviewPyramid, ViewportRect};
 use crate::file_management::{get_sidecar_path, load_settings, AppSettings};
 use crate::mask_generation::{MaskDefinition, generate_mask_bitmap};
 use crate::ai_processing::{
     AiState, get_or_init_ai_models, generate_image_embeddings, run_sam_decoder,
-    AiSubjectMaskParameters, run_u2netp_model, AiForegroundMaskParameters
+    AiSubjectMaskParameters, run_u2netp_model, AiForegroundMaskParameters,
+    AiDepthMaskParameters, DepthMap, DepthState, load_depth_model, run_depth_model
 };
 use crate::formats::{is_raw_file};
 use crate::image_loader::{load_base_image_from_bytes, composite_patches_on_image, load_and_composite};
+use crate::raw_processing::{develop_raw_file_cached, RawDevelopSettings};
+use crate::retouch::{RetouchParameters, RetouchPoint};
 
-use crate::lut_processes::*;
 
 #[derive(Clone)]
 pub struct LoadedImage {
-    image: DynamicImage,
+    /// Unique per load or re-develop, identifies the pixels for GPU caching.
+    id: u64,
+    path: String,
+    /// Shared with the preview cache and the pyramid while they need no
+    /// transformed copy of it.
+    image: Arc<DynamicImage>,
     full_width: u32,
     full_height: u32,
+    raw_settings: RawDevelopSettings,
+}
+
+static NEXT_IMAGE_ID: AtomicU64 = AtomicU64::new(1);
+
+fn next_image_id() -> u64 {
+    NEXT_IMAGE_ID.fetch_add(1, Ordering::Relaxed)
 }
 
 #[derive(Clone)]
 pub struct CachedPreview {
-    image: DynamicImage,
+    image: Arc<DynamicImage>,
     transform_hash: u64,
     scale: f32,
     unscaled_crop_offset: (f32, f32),
@@ -65,9 +102,19 @@ pub struct CachedPreview {
 pub struct AppState {
     original_image: Mutex<Option<LoadedImage>>,
     cached_preview: Mutex<Option<CachedPreview>>,
+    preview_pyramid: Mutex<Option<Arc<PreviewPyramid>>>,
+    /// Last adjusted editor preview, as sent to the frontend.
+    processed_preview: Mutex<Option<Arc<DynamicImage>>>,
+    scopes: Mutex<scopes::ScopeState>,
+    overlay_cache: Mutex<overlays::OverlayCache>,
     pub gpu_context: Mutex<Option<GpuContext>>,
     ai_state: Mutex<Option<AiState>>,
+    depth_state: Mutex<DepthState>,
+    /// Foreground model loaded for auto adjustments while the AI masks are
+    /// not in use.
+    foreground_model: Mutex<Option<Arc<ort::Session>>>,
     export_task_handle: Mutex<Option<JoinHandle<()>>>,
+    comfyui_cancel: Mutex<Option<comfyui_connector::CancelToken>>,
 }
 
 #[derive(serde::Serialize)]
@@ -138,6 +185,22 @@ fn apply_all_transformations(
     (cropped_image, unscaled_crop_offset)
 }
 
+/// True when neither patches nor `apply_all_transformations` would change
+/// `image`, so it can be used as is.
+fn is_untransformed(image: &DynamicImage, adjustments: &serde_json::Value) -> bool {
+    let has_visible_patches = adjustments["aiPatches"]
+        .as_array()
+        .is_some_and(|patches| patches.iter().any(|p| p["visible"].as_bool().unwrap_or(true)));
+    let rotation = adjustments["rotation"].as_f64().unwrap_or(0.0);
+    let flipped = adjustments["flipHorizontal"].as_bool().unwrap_or(false)
+        || adjustments["flipVertical"].as_bool().unwrap_or(false);
+    let (width, height) = image.dimensions();
+    let crops = serde_json::from_value::<Crop>(adjustments["crop"].clone()).is_ok_and(|c| {
+        c.x.round() > 0.0 || c.y.round() > 0.0 || c.width.round() < width as f64 || c.height.round() < height as f64
+    });
+    !has_visible_patches && rotation == 0.0 && !flipped && !crops
+}
+
 fn calculate_transform_hash(adjustments: &serde_json::Value) -> u64 {
     let mut hasher = DefaultHasher::new();
     
@@ -167,6 +230,14 @@ fn calculate_transform_hash(adjustments: &serde_json::Value) -> u64 {
 
                 let data_exists = patch.get("patchDataBase64").is_some();
                 data_exists.hash(&mut hasher);
+
+                if let Some(retouch) = patch.get("retouch") {
+                    retouch.to_string().hash(&mut hasher);
+                }
+
+                if let Some(variant) = patch.get("selectedVariant").and_then(|v| v.as_u64()) {
+                    variant.hash(&mut hasher);
+                }
             }
         }
     }
@@ -174,19 +245,26 @@ fn calculate_transform_hash(adjustments: &serde_json::Value) -> u64 {
     hasher.finish()
 }
 
+/// Preview base, its scale relative to the loaded image and the unscaled crop offset.
+type TransformedPreview = (Arc<DynamicImage>, f32, (f32, f32));
+
 fn generate_transformed_preview(
     loaded_image: &LoadedImage,
     adjustments: &serde_json::Value,
     app_handle: &tauri::AppHandle,
-) -> Result<(DynamicImage, f32, (f32, f32)), String> {
-    let patched_original_image = composite_patches_on_image(&loaded_image.image, adjustments)
-        .map_err(|e| format!("Failed to composite AI patches: {}", e))?;
-    
+) -> Result<TransformedPreview, String> {
     let (full_w, full_h) = (loaded_image.full_width, loaded_image.full_height);
 
     let settings = load_settings(app_handle.clone()).unwrap_or_default();
     let final_preview_dim = settings.editor_preview_resolution.unwrap_or(1920);
 
+    if full_w <= final_preview_dim && full_h <= final_preview_dim && is_untransformed(&loaded_image.image, adjustments) {
+        return Ok((loaded_image.image.clone(), 1.0, (0.0, 0.0)));
+    }
+
+    let patched_original_image = composite_patches_on_image(&loaded_image.image, adjustments)
+        .map_err(|e| format!("Failed to composite AI patches: {}", e))?;
+
     let (processing_base, scale_for_gpu) = 
         if full_w > final_preview_dim || full_h > final_preview_dim {
             let base = patched_original_image.thumbnail(final_preview_dim, final_preview_dim);
@@ -199,7 +277,7 @@ fn generate_transformed_preview(
     let (final_preview_base, unscaled_crop_offset) = 
         apply_all_transformations(&processing_base, adjustments, scale_for_gpu);
     
-    Ok((final_preview_base, scale_for_gpu, unscaled_crop_offset))
+    Ok((Arc::new(final_preview_base), scale_for_gpu, unscaled_crop_offset))
 }
 
 fn encode_to_base64(image: &DynamicImage, quality: u8) -> Result<String, String> {
@@ -244,12 +322,17 @@ async fn load_image(path: String, state: tauri::State<'_, AppState>, app_handle:
         ImageMetadata::default()
     };
 
+    let raw_settings = RawDevelopSettings::from_adjustments(&metadata.adjustments);
     let file_bytes = fs::read(&path).map_err(|e| e.to_string())?;
-    let pristine_img = load_base_image_from_bytes(&file_bytes, &path, false)
-        .map_err(|e| e.to_string())?;
+    let is_raw = is_raw_file(&path);
+    let pristine_img = if is_raw {
+        develop_raw_file_cached(&path, &raw_settings)
+    } else {
+        load_base_image_from_bytes(&file_bytes, &path, false, &raw_settings)
+    }
+    .map_err(|e| e.to_string())?;
 
     let (orig_width, orig_height) = pristine_img.dimensions();
-    let is_raw = is_raw_file(&path);
 
     let exif_data = read_exif_data(&file_bytes);
 
@@ -259,10 +342,15 @@ async fn load_image(path: String, state: tauri::State<'_, AppState>, app_handle:
     let original_base64 = encode_to_base64(&display_preview, 85)?;
 
     *state.cached_preview.lock().unwrap() = None;
+    *state.preview_pyramid.lock().unwrap() = None;
+    *state.processed_preview.lock().unwrap() = None;
     *state.original_image.lock().unwrap() = Some(LoadedImage {
-        image: pristine_img,
+        id: next_image_id(),
+        path,
+        image: Arc::new(pristine_img),
         full_width: orig_width,
         full_height: orig_height,
+        raw_settings,
     });
     
     Ok(LoadImageResult {
@@ -275,6 +363,36 @@ async fn load_image(path: String, state: tauri::State<'_, AppState>, app_handle:
     })
 }
 
+/// Returns the loaded image, re-developing the raw file first if the
+/// adjustments changed settings that apply at develop time. White balance,
+/// camera profile and denoise changes reuse the cached demosaiced data.
+fn get_loaded_image_for_adjustments(
+    state: &tauri::State<AppState>,
+    js_adjustments: &serde_json::Value,
+) -> Result<LoadedImage, String> {
+    let loaded_image = state.original_image.lock().unwrap().clone().ok_or("No original image loaded")?;
+    let raw_settings = RawDevelopSettings::from_adjustments(js_adjustments);
+    if !is_raw_file(&loaded_image.path) || raw_settings == loaded_image.raw_settings {
+        return Ok(loaded_image);
+    }
+
+    let image = develop_raw_file_cached(&loaded_image.path, &raw_settings).map_err(|e| e.to_string())?;
+    let (full_width, full_height) = image.dimensions();
+    let redeveloped = LoadedImage {
+        id: next_image_id(),
+        path: loaded_image.path,
+        image: Arc::new(image),
+        full_width,
+        full_height,
+        raw_settings,
+    };
+
+    *state.cached_preview.lock().unwrap() = None;
+    *state.preview_pyramid.lock().unwrap() = None;
+    *state.original_image.lock().unwrap() = Some(redeveloped.clone());
+    Ok(redeveloped)
+}
+
 #[tauri::command]
 fn apply_adjustments(
     js_adjustments: serde_json::Value,
@@ -284,8 +402,10 @@ fn apply_adjustments(
     let context = get_or_init_gpu_context(&state)?;
     let adjustments_clone = js_adjustments.clone();
     
-    let loaded_image = state.original_image.lock().unwrap().clone().ok_or("No original image loaded")?;
+    let loaded_image = get_loaded_image_for_adjustments(&state, &adjustments_clone)?;
+    let is_raw = is_raw_file(&loaded_image.path);
     let new_transform_hash = calculate_transform_hash(&adjustments_clone);
+    let gpu_image_key = GpuImageKey { image_id: loaded_image.id, transform_hash: new_transform_hash };
 
     let mut cached_preview_lock = state.cached_preview.lock().unwrap();
     
@@ -326,12 +446,18 @@ fn apply_adjustments(
         let scaled_crop_offset = (unscaled_crop_offset.0 * scale_for_gpu, unscaled_crop_offset.1 * scale_for_gpu);
 
         let mask_bitmaps: Vec<ImageBuffer<Luma<u8>, Vec<u8>>> = mask_definitions.iter()
-            .filter_map(|def| generate_mask_bitmap(def, preview_width, preview_height, scale_for_gpu, scaled_crop_offset))
+            .filter_map(|def| generate_mask_bitmap(def, &final_preview_base, preview_width, preview_height, scale_for_gpu, scaled_crop_offset))
             .collect();
 
-        let final_adjustments = get_all_adjustments_from_json(&adjustments_clone);
+        let final_adjustments = get_all_adjustments_from_json(&adjustments_clone, is_raw);
+
+        if let Ok(final_processed_image) = process_cached_and_get_dynamic_image(&context, gpu_image_key, &final_preview_base, final_adjustments, &mask_bitmaps) {
+            // Stored before the preview is announced, so requests that follow
+            // the event see this render.
+            let final_processed_image = Arc::new(final_processed_image);
+            let state = app_handle.state::<AppState>();
+            *state.processed_preview.lock().unwrap() = Some(final_processed_image.clone());
 
-        if let Ok(final_processed_image) = process_and_get_dynamic_image(&context, &final_preview_base, final_adjustments, &mask_bitmaps) {
             if let Ok(histogram_data) = image_processing::calculate_histogram_from_image(&final_processed_image) {
                 let _ = app_handle.emit("histogram-update", histogram_data);
             }
@@ -343,6 +469,11 @@ fn apply_adjustments(
             if let Ok(base64_str) = encode_to_base64(&final_processed_image, 88) {
                 let _ = app_handle.emit("preview-update-final", base64_str);
             }
+
+            if scopes::has_enabled_scopes(&state) {
+                let scopes_data = scopes::calculate_scopes(&state, &app_handle, Some(&final_processed_image));
+                let _ = app_handle.emit("scopes-update", scopes_data);
+            }
         }
     });
 
@@ -357,14 +488,15 @@ fn generate_uncropped_preview(
 ) -> Result<(), String> {
     let context = get_or_init_gpu_context(&state)?;
     let adjustments_clone = js_adjustments.clone();
-    let loaded_image = state.original_image.lock().unwrap().clone().ok_or("No original image loaded")?;
+    let loaded_image = get_loaded_image_for_adjustments(&state, &adjustments_clone)?;
+    let is_raw = is_raw_file(&loaded_image.path);
 
     thread::spawn(move || {
         let patched_image = match composite_patches_on_image(&loaded_image.image, &adjustments_clone) {
             Ok(img) => img,
             Err(e) => {
                 eprintln!("Failed to composite patches for uncropped preview: {}", e);
-                loaded_image.image
+                (*loaded_image.image).clone()
             },
         };
         
@@ -389,10 +521,10 @@ fn generate_uncropped_preview(
             .unwrap_or_else(Vec::new);
 
         let mask_bitmaps: Vec<ImageBuffer<Luma<u8>, Vec<u8>>> = mask_definitions.iter()
-            .filter_map(|def| generate_mask_bitmap(def, preview_width, preview_height, scale_for_gpu, (0.0, 0.0)))
+            .filter_map(|def| generate_mask_bitmap(def, &processing_base, preview_width, preview_height, scale_for_gpu, (0.0, 0.0)))
             .collect();
 
-        let uncropped_adjustments = get_all_adjustments_from_json(&adjustments_clone);
+        let uncropped_adjustments = get_all_adjustments_from_json(&adjustments_clone, is_raw);
 
         if let Ok(processed_image) = process_and_get_dynamic_image(&context, &processing_base, uncropped_adjustments, &mask_bitmaps) {
             if let Ok(base64_str) = encode_to_base64(&processed_image, 85) {
@@ -407,7 +539,11 @@ fn generate_uncropped_preview(
 fn get_full_image_for_processing(state: &tauri::State<AppState>) -> Result<DynamicImage, String> {
     let original_image_lock = state.original_image.lock().unwrap();
     let loaded_image = original_image_lock.as_ref().ok_or("No original image loaded")?;
-    Ok(loaded_image.image.clone())
+    Ok((*loaded_image.image).clone())
+}
+
+fn is_loaded_image_raw(state: &tauri::State<AppState>) -> bool {
+    state.original_image.lock().unwrap().as_ref().is_some_and(|loaded| is_raw_file(&loaded.path))
 }
 
 #[tauri::command]
@@ -417,6 +553,7 @@ fn generate_fullscreen_preview(
 ) -> Result<String, String> {
     let context = get_or_init_gpu_context(&state)?;
     let original_image = get_full_image_for_processing(&state)?;
+    let is_raw = is_loaded_image_raw(&state);
     let base_image = composite_patches_on_image(&original_image, &js_adjustments)
         .map_err(|e| format!("Failed to composite AI patches for fullscreen: {}", e))?;
     
@@ -429,15 +566,118 @@ fn generate_fullscreen_preview(
         .unwrap_or_else(Vec::new);
 
     let mask_bitmaps: Vec<ImageBuffer<Luma<u8>, Vec<u8>>> = mask_definitions.iter()
-        .filter_map(|def| generate_mask_bitmap(def, img_w, img_h, 1.0, unscaled_crop_offset))
+        .filter_map(|def| generate_mask_bitmap(def, &transformed_image, img_w, img_h, 1.0, unscaled_crop_offset))
         .collect();
 
-    let all_adjustments = get_all_adjustments_from_json(&js_adjustments);
+    let all_adjustments = get_all_adjustments_from_json(&js_adjustments, is_raw);
     let final_image = process_and_get_dynamic_image(&context, &transformed_image, all_adjustments, &mask_bitmaps)?;
     
     encode_to_base64(&final_image, 95)
 }
 
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ViewportRenderResult {
+    image_base64: String,
+    /// The rendered rectangle in normalized image coordinates. It can be
+    /// slightly larger than the requested one, snapped to whole pixels.
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+    scale: f32,
+}
+
+/// Returns the transformed full-resolution pyramid for the current geometry,
+/// building it when the image or the geometry changed.
+fn get_preview_pyramid(
+    state: &tauri::State<AppState>,
+    loaded_image: &LoadedImage,
+    js_adjustments: &serde_json::Value,
+) -> Result<Arc<PreviewPyramid>, String> {
+    let transform_hash = calculate_transform_hash(js_adjustments);
+    let mut pyramid_lock = state.preview_pyramid.lock().unwrap();
+    if let Some(pyramid) = &*pyramid_lock {
+        if pyramid.image_id == loaded_image.id && pyramid.transform_hash == transform_hash {
+            return Ok(pyramid.clone());
+        }
+    }
+
+    // Without patches or geometry changes the pyramid starts from the loaded
+    // pixels instead of a full-resolution copy of them.
+    let (base, unscaled_crop_offset) = if is_untransformed(&loaded_image.image, js_adjustments) {
+        (loaded_image.image.clone(), (0.0, 0.0))
+    } else {
+        let patched_image = composite_patches_on_image(&loaded_image.image, js_adjustments)
+            .map_err(|e| format!("Failed to composite AI patches for viewport: {}", e))?;
+        let (transformed_image, unscaled_crop_offset) = apply_all_transformations(&patched_image, js_adjustments, 1.0);
+        (Arc::new(transformed_image), unscaled_crop_offset)
+    };
+    let pyramid = Arc::new(PreviewPyramid::new(loaded_image.id, transform_hash, base, unscaled_crop_offset));
+    *pyramid_lock = Some(pyramid.clone());
+    Ok(pyramid)
+}
+
+/// Renders only the part of the image inside `viewport`, at the scale that
+/// makes the whole image `display_width` pixels wide (never above 100%).
+/// The region is rendered with a margin of real pixels so that sharpening,
+/// clarity and the other neighbourhood effects are correct up to its border.
+#[tauri::command]
+fn render_viewport(
+    js_adjustments: serde_json::Value,
+    viewport: ViewportRect,
+    display_width: u32,
+    state: tauri::State<AppState>,
+) -> Result<ViewportRenderResult, String> {
+    let context = get_or_init_gpu_context(&state)?;
+    let loaded_image = get_loaded_image_for_adjustments(&state, &js_adjustments)?;
+    let is_raw = is_raw_file(&loaded_image.path);
+    let pyramid = get_preview_pyramid(&state, &loaded_image, &js_adjustments)?;
+
+    let (full_w, _) = pyramid.full_dimensions();
+    let scale = display_width.max(1) as f32 / full_w.max(1) as f32;
+    let region = pyramid.extract(viewport, scale, TILE_APRON).ok_or("Viewport does not intersect the image")?;
+    let (region_w, region_h) = region.image.dimensions();
+
+    let mask_definitions: Vec<MaskDefinition> = js_adjustments.get("masks")
+        .and_then(|m| serde_json::from_value(m.clone()).ok())
+        .unwrap_or_default();
+
+    let crop_offset = (
+        pyramid.unscaled_crop_offset.0 * region.scale + region.region_x as f32,
+        pyramid.unscaled_crop_offset.1 * region.scale + region.region_y as f32,
+    );
+    let mask_bitmaps: Vec<ImageBuffer<Luma<u8>, Vec<u8>>> = mask_definitions.iter()
+        .filter_map(|def| generate_mask_bitmap(def, &region.image, region_w, region_h, region.scale, crop_offset))
+        .collect();
+
+    let all_adjustments = get_all_adjustments_from_json(&js_adjustments, is_raw);
+    let processed = process_region_and_get_dynamic_image(
+        &context,
+        &region.image,
+        all_adjustments,
+        &mask_bitmaps,
+        (region.region_x, region.region_y),
+        (region.frame_width, region.frame_height),
+    )?;
+
+    let visible = processed.crop_imm(
+        region.visible_x - region.region_x,
+        region.visible_y - region.region_y,
+        region.visible_width,
+        region.visible_height,
+    );
+
+    Ok(ViewportRenderResult {
+        image_base64: encode_to_base64(&visible, 90)?,
+        x: region.visible_x as f32 / region.frame_width as f32,
+        y: region.visible_y as f32 / region.frame_height as f32,
+        width: region.visible_width as f32 / region.frame_width as f32,
+        height: region.visible_height as f32 / region.frame_height as f32,
+        scale: region.scale,
+    })
+}
+
 #[tauri::command]
 async fn export_image(
     original_path: String,
@@ -453,6 +693,7 @@ async fn export_image(
 
     let context = get_or_init_gpu_context(&state)?;
     let original_image_data = get_full_image_for_processing(&state)?;
+    let is_raw = is_loaded_image_raw(&state);
     let context = Arc::new(context);
 
     let task = tokio::spawn(async move {
@@ -469,10 +710,10 @@ async fn export_image(
                 .unwrap_or_else(Vec::new);
 
             let mask_bitmaps: Vec<ImageBuffer<Luma<u8>, Vec<u8>>> = mask_definitions.iter()
-                .filter_map(|def| generate_mask_bitmap(def, img_w, img_h, 1.0, unscaled_crop_offset))
+                .filter_map(|def| generate_mask_bitmap(def, &transformed_image, img_w, img_h, 1.0, unscaled_crop_offset))
                 .collect();
 
-            let all_adjustments = get_all_adjustments_from_json(&js_adjustments);
+            let all_adjustments = get_all_adjustments_from_json(&js_adjustments, is_raw);
             let mut final_image = process_and_get_dynamic_image(&context, &transformed_image, all_adjustments, &mask_bitmaps)?;
 
             if let Some(resize_opts) = export_settings.resize {
@@ -586,6 +827,7 @@ async fn batch_export_images(
                     ImageMetadata::default()
                 };
                 let js_adjustments = metadata.adjustments;
+                let is_raw = is_raw_file(image_path_str);
 
                 let base_image = load_and_composite(image_path_str, &js_adjustments, false)
                     .map_err(|e| e.to_string())?;
@@ -599,10 +841,10 @@ async fn batch_export_images(
                     .unwrap_or_else(Vec::new);
 
                 let mask_bitmaps: Vec<ImageBuffer<Luma<u8>, Vec<u8>>> = mask_definitions.iter()
-                    .filter_map(|def| generate_mask_bitmap(def, img_w, img_h, 1.0, unscaled_crop_offset))
+                    .filter_map(|def| generate_mask_bitmap(def, &transformed_image, img_w, img_h, 1.0, unscaled_crop_offset))
                     .collect();
 
-                let all_adjustments = get_all_adjustments_from_json(&js_adjustments);
+                let all_adjustments = get_all_adjustments_from_json(&js_adjustments, is_raw);
                 let mut final_image = process_and_get_dynamic_image(&context, &transformed_image, all_adjustments, &mask_bitmaps)?;
 
                 if let Some(resize_opts) = &export_settings.resize {
@@ -801,11 +1043,17 @@ fn generate_mask_overlay(
     height: u32,
     scale: f32,
     crop_offset: (f32, f32),
+    state: tauri::State<AppState>,
 ) -> Result<String, String> {
 
     let scaled_crop_offset = (crop_offset.0 * scale, crop_offset.1 * scale);
 
-    if let Some(gray_mask) = generate_mask_bitmap(&mask_def, width, height, scale, scaled_crop_offset) {
+    let preview_image = match state.cached_preview.lock().unwrap().as_ref() {
+        Some(cached) => cached.image.clone(),
+        None => Arc::new(get_full_image_for_processing(&state)?),
+    };
+
+    if let Some(gray_mask) = generate_mask_bitmap(&mask_def, &preview_image, width, height, scale, scaled_crop_offset) {
         let mut rgba_mask = RgbaImage::new(width, height);
         for (x, y, pixel) in gray_mask.enumerate_pixels() {
             let intensity = pixel[0];
@@ -984,6 +1232,64 @@ async fn generate_ai_subject_mask(
     })
 }
 
+#[tauri::command]
+async fn generate_ai_depth_mask(
+    path: String,
+    rotation: f32,
+    flip_horizontal: bool,
+    flip_vertical: bool,
+    state: tauri::State<'_, AppState>,
+    app_handle: tauri::AppHandle,
+) -> Result<AiDepthMaskParameters, String> {
+    let settings = load_settings(app_handle.clone()).unwrap_or_default();
+    let model_path = settings.depth_model_path
+        .filter(|p| !p.is_empty())
+        .ok_or("No depth model configured. Select a local depth estimation ONNX model in the settings.")?;
+
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(path.as_bytes());
+    hasher.update(model_path.as_bytes());
+    let path_hash = hasher.finalize().to_hex().to_string();
+
+    let cached_depth = state.depth_state.lock().unwrap().depth_map.as_ref()
+        .filter(|depth_map| depth_map.path_hash == path_hash)
+        .map(|depth_map| depth_map.depth.clone());
+
+    let depth = if let Some(depth) = cached_depth {
+        depth
+    } else {
+        let cached_model = state.depth_state.lock().unwrap().model.as_ref()
+            .filter(|model| model.path == model_path)
+            .cloned();
+        let model = match cached_model {
+            Some(model) => model,
+            None => {
+                let model = Arc::new(load_depth_model(&model_path).map_err(|e| e.to_string())?);
+                state.depth_state.lock().unwrap().model = Some(model.clone());
+                model
+            }
+        };
+
+        let full_image = get_full_image_for_processing(&state)?;
+        let depth = run_depth_model(&full_image, &model).map_err(|e| e.to_string())?;
+        state.depth_state.lock().unwrap().depth_map = Some(DepthMap {
+            path_hash,
+            depth: depth.clone(),
+        });
+        depth
+    };
+
+    Ok(AiDepthMaskParameters {
+        depth_data_base64: Some(encode_to_base64_png(&depth)?),
+        near: 0.0,
+        far: 0.5,
+        feather: 0.1,
+        rotation: Some(rotation),
+        flip_horizontal: Some(flip_horizontal),
+        flip_vertical: Some(flip_vertical),
+    })
+}
+
 #[tauri::command]
 fn generate_preset_preview(
     js_adjustments: serde_json::Value,
@@ -993,6 +1299,7 @@ fn generate_preset_preview(
 
     let loaded_image = state.original_image.lock().unwrap().clone()
         .ok_or("No original image loaded for preset preview")?;
+    let is_raw = is_raw_file(&loaded_image.path);
     let original_image = loaded_image.image;
     
     const PRESET_PREVIEW_DIM: u32 = 200;
@@ -1007,10 +1314,10 @@ fn generate_preset_preview(
         .unwrap_or_else(Vec::new);
 
     let mask_bitmaps: Vec<ImageBuffer<Luma<u8>, Vec<u8>>> = mask_definitions.iter()
-        .filter_map(|def| generate_mask_bitmap(def, img_w, img_h, 1.0, unscaled_crop_offset))
+        .filter_map(|def| generate_mask_bitmap(def, &transformed_image, img_w, img_h, 1.0, unscaled_crop_offset))
         .collect();
 
-    let all_adjustments = get_all_adjustments_from_json(&js_adjustments);
+    let all_adjustments = get_all_adjustments_from_json(&js_adjustments, is_raw);
     
     let processed_image = process_and_get_dynamic_image(&context, &transformed_image, all_adjustments, &mask_bitmaps)?;
     
@@ -1080,19 +1387,57 @@ async fn test_comfyui_connection(address: String) -> Result<(), String> {
         .map_err(|e| e.to_string())
 }
 
+fn comfyui_workflows_dir(app_handle: &tauri::AppHandle) -> Option<std::path::PathBuf> {
+    app_handle.path().app_data_dir().ok().map(|dir| dir.join("workflows"))
+}
+
+fn comfyui_options(settings: &AppSettings) -> comfyui_connector::ExecutionOptions {
+    comfyui_connector::ExecutionOptions {
+        execution_timeout: settings
+            .comfyui_timeout_seconds
+            .map(std::time::Duration::from_secs)
+            .unwrap_or(comfyui_connector::DEFAULT_EXECUTION_TIMEOUT),
+        ..Default::default()
+    }
+}
+
 #[tauri::command]
+fn list_comfyui_workflows(app_handle: tauri::AppHandle) -> Vec<comfyui_connector::WorkflowTemplate> {
+    comfyui_connector::load_workflow_templates(comfyui_workflows_dir(&app_handle).as_deref())
+}
+
+#[tauri::command]
+async fn get_comfyui_checkpoints(app_handle: tauri::AppHandle) -> Result<Vec<String>, String> {
+    let settings = load_settings(app_handle).unwrap_or_default();
+    let address = settings.comfyui_address.clone()
+        .ok_or_else(|| "ComfyUI address is not configured in settings.".to_string())?;
+    comfyui_connector::get_checkpoints(&address, &comfyui_options(&settings))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
 async fn invoke_generative_replace(
     _path: String,
     mask_data_base64: String,
     prompt: String,
     current_adjustments: Value,
+    workflow_id: Option<String>,
+    parameters: Option<HashMap<String, Value>>,
     app_handle: tauri::AppHandle,
     state: tauri::State<'_, AppState>,
-) -> Result<String, String> {
-    let settings = load_settings(app_handle).unwrap_or_default();
-    let address = settings.comfyui_address
+) -> Result<Vec<String>, String> {
+    let settings = load_settings(app_handle.clone()).unwrap_or_default();
+    let address = settings.comfyui_address.clone()
         .ok_or_else(|| "ComfyUI address is not configured in settings.".to_string())?;
 
+    let workflow_id = workflow_id.unwrap_or_else(|| "generative_replace".to_string());
+    let template = comfyui_connector::load_workflow_templates(comfyui_workflows_dir(&app_handle).as_deref())
+        .into_iter()
+        .find(|t| t.id == workflow_id)
+        .ok_or_else(|| format!("Workflow '{}' not found.", workflow_id))?;
+
     let base_image = get_full_image_for_processing(&state)?;
     let source_image = composite_patches_on_image(&base_image, &current_adjustments)
         .map_err(|e| format!("Failed to prepare source image: {}", e))?;
@@ -1107,23 +1452,49 @@ async fn invoke_generative_replace(
     let mask_image = image::load_from_memory(&mask_bytes)
         .map_err(|e| format!("Failed to load mask image: {}", e))?;
 
-    let workflow_inputs = comfyui_connector::WorkflowInputs {
-        source_image_node_id: "11".to_string(),
-        mask_image_node_id: Some("148".to_string()),
-        text_prompt_node_id: Some("6".to_string()),
-        final_output_node_id: "252".to_string(),
+    let request = comfyui_connector::WorkflowRequest {
+        source_image,
+        mask_image: Some(mask_image),
+        text_prompt: Some(prompt),
+        parameters: parameters.unwrap_or_default(),
     };
 
-    let result_png_bytes = comfyui_connector::execute_workflow(
-        &address,
-        "generative_replace",
-        workflow_inputs,
-        source_image,
-        Some(mask_image),
-        Some(prompt)
-    ).await.map_err(|e| e.to_string())?;
+    let options = comfyui_options(&settings);
+    *state.comfyui_cancel.lock().unwrap() = Some(options.cancel.clone());
+
+    let result = comfyui_connector::execute_workflow(&address, &template, request, &options, |progress| {
+        let _ = app_handle.emit("comfyui-progress", &progress);
+    })
+    .await;
+
+    *state.comfyui_cancel.lock().unwrap() = None;
 
-    Ok(general_purpose::STANDARD.encode(&result_png_bytes))
+    let variants = result.map_err(|e| e.to_string())?;
+    Ok(variants.iter().map(|png| general_purpose::STANDARD.encode(png)).collect())
+}
+
+#[tauri::command]
+fn cancel_generative_replace(state: tauri::State<AppState>) {
+    if let Some(cancel) = state.comfyui_cancel.lock().unwrap().as_ref() {
+        cancel.cancel();
+    }
+}
+
+#[tauri::command]
+fn find_retouch_source(
+    retouch: Value,
+    current_adjustments: Value,
+    state: tauri::State<AppState>,
+) -> Result<RetouchPoint, String> {
+    let params: RetouchParameters = serde_json::from_value(retouch)
+        .map_err(|e| format!("Invalid retouch parameters: {}", e))?;
+
+    let base_image = get_full_image_for_processing(&state)?;
+    let source_image = composite_patches_on_image(&base_image, &current_adjustments)
+        .map_err(|e| format!("Failed to prepare source image: {}", e))?;
+
+    retouch::find_source_offset(&source_image.to_rgba32f(), &params)
+        .ok_or_else(|| "No suitable source area found.".to_string())
 }
 
 #[tauri::command]
@@ -1153,9 +1524,16 @@ fn main() {
                     app.manage(AppState {
                         original_image: Mutex::new(None),
                         cached_preview: Mutex::new(None),
+                        preview_pyramid: Mutex::new(None),
+                        processed_preview: Mutex::new(None),
+                        scopes: Mutex::new(scopes::ScopeState::default()),
+                        overlay_cache: Mutex::new(overlays::OverlayCache::default()),
                         gpu_context: Mutex::new(Some(gpu_context)),
                         ai_state: Mutex::new(None),
+                        depth_state: Mutex::new(DepthState::default()),
+                        foreground_model: Mutex::new(None),
                         export_task_handle: Mutex::new(None),
+                        comfyui_cancel: Mutex::new(None),
                     });
                 },
                 Err(e) => {
@@ -1187,6 +1565,10 @@ fn main() {
             std::env::set_var("ORT_DYLIB_PATH", &ort_library_path);
             println!("Set ORT_DYLIB_PATH to: {}", ort_library_path.display());
 
+            if let Err(e) = sensor_calibration::init_calibration_dir(&app_handle) {
+                eprintln!("Failed to initialize calibration directory: {}", e);
+            }
+
             let settings: AppSettings = load_settings(app_handle.clone()).unwrap_or_default();
             let window_cfg = app.config().app.windows.get(0).unwrap().clone();
             let transparent = settings.transparent.unwrap_or(window_cfg.transparent);
@@ -1213,22 +1595,45 @@ fn main() {
             batch_export_images,
             cancel_export,
             generate_fullscreen_preview,
+            render_viewport,
             generate_preset_preview,
             generate_uncropped_preview,
             generate_mask_overlay,
+            overlays::generate_analysis_overlay,
             generate_ai_subject_mask,
             generate_ai_foreground_mask,
+            generate_ai_depth_mask,
             update_window_effect,
             check_comfyui_status,
             test_comfyui_connection,
             invoke_generative_replace,
+            cancel_generative_replace,
+            list_comfyui_workflows,
+            get_comfyui_checkpoints,
+            find_retouch_source,
             get_supported_file_types,
             image_processing::generate_histogram,
             image_processing::generate_waveform,
+            scopes::set_active_scopes,
             image_processing::load_file_data,
             image_processing::read_file_data,
-            lut_cube::apply_lut_type_gpu,
-            image_processing::calculate_auto_adjustments,
+            auto_adjust::calculate_auto_adjustments,
+            white_balance::get_raw_white_balance,
+            white_balance::pick_raw_white_balance,
+            panorama::stitch_panorama,
+            focus_stacking::focus_stack,
+            camera_profiles::list_camera_profiles,
+            camera_profiles::import_camera_profile,
+            color_calibration::calibrate_camera_profile,
+            sensor_calibration::create_calibration_frame,
+            sensor_calibration::list_calibration_frames,
+            sensor_calibration::delete_calibration_frame,
+            model_registry::get_ai_model_status,
+            model_registry::download_ai_model,
+            model_registry::import_ai_model,
+            dust_detection::detect_dust_spots,
+            super_resolution::enhance_resolution,
+            lut_processes::lut_export::export_lut_cube,
             file_management::list_images_in_dir,
             file_management::get_folder_tree,
             file_management::get_file_tree,
@@ -1,15 +1,24 @@
-use image::{GrayImage, Luma};
+use image::{imageops::FilterType, DynamicImage, GrayImage, Luma, Rgb32FImage};
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::cell::OnceCell;
+use std::collections::HashMap;
 use std::f32::consts::PI;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::SystemTime;
 use base64::{Engine as _, engine::general_purpose};
-use crate::ai_processing::{AiSubjectMaskParameters, AiForegroundMaskParameters};
+use crate::ai_processing::{AiSubjectMaskParameters, AiForegroundMaskParameters, AiDepthMaskParameters};
+use crate::mask_refinement::refine_mask;
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub enum SubMaskMode {
     Additive,
     Subtractive,
+    Intersect,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -20,9 +29,16 @@ pub struct SubMask {
     pub mask_type: String,
     pub visible: bool,
     pub mode: SubMaskMode,
+    /// Strength of this sub-mask in percent.
+    #[serde(default = "default_opacity")]
+    pub opacity: f32,
     pub parameters: Value,
 }
 
+fn default_opacity() -> f32 {
+    100.0
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct MaskDefinition {
@@ -72,6 +88,77 @@ impl Default for LinearMaskParameters {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct DepthMapMaskParameters {
+    #[serde(default)]
+    depth_map_path: Option<String>,
+    #[serde(default)]
+    near: f32,
+    #[serde(default = "default_far")]
+    far: f32,
+    #[serde(default)]
+    feather: f32,
+    #[serde(default)]
+    rotation: Option<f32>,
+    #[serde(default)]
+    flip_horizontal: Option<bool>,
+    #[serde(default)]
+    flip_vertical: Option<bool>,
+}
+
+fn default_far() -> f32 {
+    1.0
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+enum ColorRangeSpace {
+    #[default]
+    Lab,
+    Hsl,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct ColorRangeMaskParameters {
+    /// Points sampled from the image, in unscaled image coordinates.
+    #[serde(default)]
+    samples: Vec<Point>,
+    /// Additional target colors as sRGB in 0..1.
+    #[serde(default)]
+    target_colors: Vec<[f32; 3]>,
+    #[serde(default)]
+    color_space: ColorRangeSpace,
+    #[serde(default = "default_color_tolerance")]
+    tolerance: f32,
+    #[serde(default = "default_color_smoothness")]
+    smoothness: f32,
+}
+
+fn default_color_tolerance() -> f32 {
+    20.0
+}
+
+fn default_color_smoothness() -> f32 {
+    20.0
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct LuminanceRangeMaskParameters {
+    #[serde(default)]
+    low: f32,
+    #[serde(default = "default_far")]
+    high: f32,
+    #[serde(default = "default_luminance_feather")]
+    feather: f32,
+}
+
+fn default_luminance_feather() -> f32 {
+    0.1
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct Point {
     x: f64,
@@ -86,12 +173,20 @@ struct BrushLine {
     points: Vec<Point>,
     #[serde(default = "default_brush_feather")]
     feather: f32,
+    /// Only paint over colors similar to the one under the brush center.
+    #[serde(default)]
+    smart: bool,
 }
 
 fn default_brush_feather() -> f32 {
     0.5
 }
 
+/// Delta E up to which the smart brush paints at full strength, and the
+/// width of its falloff beyond that.
+const SMART_BRUSH_TOLERANCE: f32 = 12.0;
+const SMART_BRUSH_SOFTNESS: f32 = 10.0;
+
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 #[serde(rename_all = "camelCase")]
 struct BrushMaskParameters {
@@ -106,6 +201,7 @@ fn draw_feathered_ellipse_mut(
     feather: f32,
     color_value: u8,
     is_eraser: bool,
+    weight: impl Fn(u32, u32) -> f32,
 ) {
     if radius <= 0.0 {
         return;
@@ -137,7 +233,7 @@ fn draw_feathered_ellipse_mut(
                     1.0 - (dist - inner_radius) / (radius - inner_radius).max(0.01)
                 };
                 
-                let final_value = (intensity * color_value as f32) as u8;
+                let final_value = (intensity * weight(x as u32, y as u32) * color_value as f32) as u8;
 
                 let current_pixel = mask.get_pixel_mut(x as u32, y as u32);
                 
@@ -249,8 +345,25 @@ fn generate_linear_bitmap(
     mask
 }
 
+/// Weight of a smart brush dab at each pixel, from the color difference to
+/// the pixel under the dab center.
+fn smart_brush_weight<'a>(image: &'a MaskImage, center: (i32, i32)) -> impl Fn(u32, u32) -> f32 + 'a {
+    let lab = image.lab();
+    let width = image.width as usize;
+    let cx = center.0.clamp(0, image.width as i32 - 1) as usize;
+    let cy = center.1.clamp(0, image.height as i32 - 1) as usize;
+    let target = lab[cy * width + cx];
+    move |x, y| {
+        let pixel = lab[y as usize * width + x as usize];
+        let distance = ((pixel[0] - target[0]).powi(2) + (pixel[1] - target[1]).powi(2) + (pixel[2] - target[2]).powi(2)).sqrt();
+        let t = ((distance - SMART_BRUSH_TOLERANCE) / SMART_BRUSH_SOFTNESS).clamp(0.0, 1.0);
+        1.0 - t * t * (3.0 - 2.0 * t)
+    }
+}
+
 fn generate_brush_bitmap(
     params_value: &Value,
+    image: &MaskImage,
     width: u32,
     height: u32,
     scale: f32,
@@ -266,6 +379,13 @@ fn generate_brush_bitmap(
         let color_value = 255u8;
         let radius = (line.brush_size * scale / 2.0).max(0.0);
         let feather = line.feather.clamp(0.0, 1.0);
+        let draw_dab = |mask: &mut GrayImage, center: (i32, i32)| {
+            if line.smart && width > 0 && height > 0 {
+                draw_feathered_ellipse_mut(mask, center, radius, feather, color_value, is_eraser, smart_brush_weight(image, center));
+            } else {
+                draw_feathered_ellipse_mut(mask, center, radius, feather, color_value, is_eraser, |_, _| 1.0);
+            }
+        };
 
         if line.points.len() > 1 {
             for points_pair in line.points.windows(2) {
@@ -286,18 +406,18 @@ fn generate_brush_bitmap(
                         let t = i as f32 / steps as f32;
                         let interp_x = (x1_f + t * (x2_f - x1_f)) as i32;
                         let interp_y = (y1_f + t * (y2_f - y1_f)) as i32;
-                        draw_feathered_ellipse_mut(&mut mask, (interp_x, interp_y), radius, feather, color_value, is_eraser);
+                        draw_dab(&mut mask, (interp_x, interp_y));
                     }
                 } else {
-                    draw_feathered_ellipse_mut(&mut mask, (x1_f as i32, y1_f as i32), radius, feather, color_value, is_eraser);
-                    draw_feathered_ellipse_mut(&mut mask, (x2_f as i32, y2_f as i32), radius, feather, color_value, is_eraser);
+                    draw_dab(&mut mask, (x1_f as i32, y1_f as i32));
+                    draw_dab(&mut mask, (x2_f as i32, y2_f as i32));
                 }
             }
         } else {
             let p1 = &line.points[0];
             let x1 = (p1.x as f32 * scale - crop_offset.0) as i32;
             let y1 = (p1.y as f32 * scale - crop_offset.1) as i32;
-            draw_feathered_ellipse_mut(&mut mask, (x1, y1), radius, feather, color_value, is_eraser);
+            draw_dab(&mut mask, (x1, y1));
         }
     }
     mask
@@ -353,6 +473,17 @@ fn generate_ai_bitmap_from_full_mask(
     final_mask
 }
 
+fn decode_base64_gray(data_url: &str) -> Option<GrayImage> {
+    let b64_data = if let Some(idx) = data_url.find(',') {
+        &data_url[idx + 1..]
+    } else {
+        data_url
+    };
+    
+    let decoded_bytes = general_purpose::STANDARD.decode(b64_data).ok()?;
+    Some(image::load_from_memory(&decoded_bytes).ok()?.to_luma8())
+}
+
 fn generate_ai_bitmap_from_base64(
     data_url: &str,
     rotation: f32,
@@ -363,14 +494,7 @@ fn generate_ai_bitmap_from_base64(
     scale: f32,
     crop_offset: (f32, f32),
 ) -> Option<GrayImage> {
-    let b64_data = if let Some(idx) = data_url.find(',') {
-        &data_url[idx + 1..]
-    } else {
-        data_url
-    };
-    
-    let decoded_bytes = general_purpose::STANDARD.decode(b64_data).ok()?;
-    let full_mask_image = image::load_from_memory(&decoded_bytes).ok()?.to_luma8();
+    let full_mask_image = decode_base64_gray(data_url)?;
 
     Some(generate_ai_bitmap_from_full_mask(
         &full_mask_image,
@@ -422,8 +546,293 @@ fn generate_ai_subject_bitmap(
     )
 }
 
+/// Intensity of `value` for a selection between `lo` and `hi` (in 0..1) that
+/// fades out linearly over `feather` on either side.
+fn range_intensity(value: f32, lo: f32, hi: f32, feather: f32) -> f32 {
+    let distance = if value < lo { lo - value } else if value > hi { value - hi } else { 0.0 };
+    if distance <= 0.0 {
+        1.0
+    } else if feather > 0.0 {
+        (1.0 - distance / feather).clamp(0.0, 1.0)
+    } else {
+        0.0
+    }
+}
+
+/// Selects the pixels of a depth map (0 = first/near, 255 = last/far) that fall
+/// between `near` and `far`, fading out over `feather` on either side.
+fn depth_range_mask(depth: &GrayImage, near: f32, far: f32, feather: f32) -> GrayImage {
+    let (lo, hi) = (near.min(far).clamp(0.0, 1.0), near.max(far).clamp(0.0, 1.0));
+    let feather = feather.max(0.0);
+    let mut mask = GrayImage::new(depth.width(), depth.height());
+    for (out, d) in mask.pixels_mut().zip(depth.pixels()) {
+        out[0] = (range_intensity(d[0] as f32 / 255.0, lo, hi, feather) * 255.0) as u8;
+    }
+    mask
+}
+
+/// Decoded depth maps by path, valid while size and modification time match,
+/// so masks do not decode the file on every render.
+type DepthMapEntry = (u64, Option<SystemTime>, Arc<GrayImage>);
+static DEPTH_MAPS: OnceLock<Mutex<HashMap<PathBuf, DepthMapEntry>>> = OnceLock::new();
+const MAX_CACHED_DEPTH_MAPS: usize = 4;
+
+fn load_depth_map(path: &str) -> Option<Arc<GrayImage>> {
+    let path = PathBuf::from(path);
+    let metadata = fs::metadata(&path).ok()?;
+    let stamp = (metadata.len(), metadata.modified().ok());
+    let cache = DEPTH_MAPS.get_or_init(|| Mutex::new(HashMap::new()));
+
+    if let Some((len, modified, depth)) = cache.lock().unwrap().get(&path) {
+        if (*len, *modified) == stamp {
+            return Some(depth.clone());
+        }
+    }
+
+    let depth = Arc::new(image::open(&path).ok()?.to_luma8());
+    let mut cache = cache.lock().unwrap();
+    if cache.len() >= MAX_CACHED_DEPTH_MAPS && !cache.contains_key(&path) {
+        cache.clear();
+    }
+    cache.insert(path, (stamp.0, stamp.1, depth.clone()));
+    Some(depth)
+}
+
+fn generate_depth_map_bitmap(
+    params_value: &Value,
+    width: u32,
+    height: u32,
+    scale: f32,
+    crop_offset: (f32, f32),
+) -> Option<GrayImage> {
+    let params: DepthMapMaskParameters = serde_json::from_value(params_value.clone()).ok()?;
+    let depth = load_depth_map(&params.depth_map_path?)?;
+    let range_mask = depth_range_mask(&depth, params.near, params.far, params.feather);
+
+    Some(generate_ai_bitmap_from_full_mask(
+        &range_mask,
+        params.rotation.unwrap_or(0.0),
+        params.flip_horizontal.unwrap_or(false),
+        params.flip_vertical.unwrap_or(false),
+        width, height, scale, crop_offset
+    ))
+}
+
+/// The image a mask is evaluated against, resampled to the mask size and
+/// converted on first use so that all parametric sub-masks share the work.
+struct MaskImage<'a> {
+    image: &'a DynamicImage,
+    width: u32,
+    height: u32,
+    rgb: OnceCell<Rgb32FImage>,
+    lab: OnceCell<Vec<[f32; 3]>>,
+    luminance: OnceCell<Vec<f32>>,
+}
+
+impl<'a> MaskImage<'a> {
+    fn new(image: &'a DynamicImage, width: u32, height: u32) -> Self {
+        Self { image, width, height, rgb: OnceCell::new(), lab: OnceCell::new(), luminance: OnceCell::new() }
+    }
+
+    fn rgb(&self) -> &Rgb32FImage {
+        self.rgb.get_or_init(|| {
+            if self.image.width() == self.width && self.image.height() == self.height {
+                self.image.to_rgb32f()
+            } else {
+                self.image.resize_exact(self.width, self.height, FilterType::Triangle).to_rgb32f()
+            }
+        })
+    }
+
+    fn lab(&self) -> &[[f32; 3]] {
+        self.lab.get_or_init(|| {
+            self.rgb()
+                .as_raw()
+                .par_chunks_exact(3)
+                .map(|p| srgb_to_lab([p[0], p[1], p[2]]))
+                .collect()
+        })
+    }
+
+    /// Lightness in 0..1, used as the guide for edge refinement.
+    fn luminance(&self) -> &[f32] {
+        self.luminance.get_or_init(|| self.lab().par_iter().map(|lab| lab[0] / 100.0).collect())
+    }
+
+    /// Average color around a point given in unscaled image coordinates.
+    fn sample(&self, point: &Point, scale: f32, crop_offset: (f32, f32)) -> Option<[f32; 3]> {
+        let rgb = self.rgb();
+        let cx = (point.x as f32 * scale - crop_offset.0).round() as i64;
+        let cy = (point.y as f32 * scale - crop_offset.1).round() as i64;
+        let (mut sum, mut count) = ([0.0f32; 3], 0);
+        for y in (cy - 2)..=(cy + 2) {
+            for x in (cx - 2)..=(cx + 2) {
+                if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+                    continue;
+                }
+                let p = rgb.get_pixel(x as u32, y as u32);
+                for c in 0..3 {
+                    sum[c] += p[c];
+                }
+                count += 1;
+            }
+        }
+        (count > 0).then(|| sum.map(|v| v / count as f32))
+    }
+}
+
+fn srgb_to_linear(c: f32) -> f32 {
+    let c = c.clamp(0.0, 1.0);
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+fn srgb_to_lab(rgb: [f32; 3]) -> [f32; 3] {
+    let [r, g, b] = rgb.map(srgb_to_linear);
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+    let f = |t: f32| if t > 0.008856 { t.cbrt() } else { 7.787 * t + 16.0 / 116.0 };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+/// Hue in degrees, saturation and lightness in 0..1.
+fn srgb_to_hsl(rgb: [f32; 3]) -> [f32; 3] {
+    let [r, g, b] = rgb.map(|c| c.clamp(0.0, 1.0));
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let l = (max + min) / 2.0;
+    let delta = max - min;
+    if delta < 1e-6 {
+        return [0.0, 0.0, l];
+    }
+    let s = delta / (1.0 - (2.0 * l - 1.0).abs()).max(1e-6);
+    let h = if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    [h, s.min(1.0), l]
+}
+
+/// Distance between two HSL colors on a scale comparable to Lab delta E.
+/// Hue differences count less as either color approaches gray.
+fn hsl_distance(a: [f32; 3], b: [f32; 3]) -> f32 {
+    let hue_diff = (a[0] - b[0]).abs();
+    let hue_diff = hue_diff.min(360.0 - hue_diff) / 1.8 * a[1].min(b[1]);
+    let sat_diff = (a[1] - b[1]) * 50.0;
+    let light_diff = (a[2] - b[2]) * 50.0;
+    (hue_diff * hue_diff + sat_diff * sat_diff + light_diff * light_diff).sqrt()
+}
+
+/// Lab delta E with lightness weighted down, so that a sampled color also
+/// selects its lighter and darker shades.
+fn lab_distance(a: [f32; 3], b: [f32; 3]) -> f32 {
+    let dl = (a[0] - b[0]) * 0.5;
+    let da = a[1] - b[1];
+    let db = a[2] - b[2];
+    (dl * dl + da * da + db * db).sqrt()
+}
+
+fn generate_color_range_bitmap(
+    params_value: &Value,
+    image: &MaskImage,
+    scale: f32,
+    crop_offset: (f32, f32),
+) -> Option<GrayImage> {
+    let params: ColorRangeMaskParameters = serde_json::from_value(params_value.clone()).ok()?;
+    let targets: Vec<[f32; 3]> = params
+        .samples
+        .iter()
+        .filter_map(|point| image.sample(point, scale, crop_offset))
+        .chain(params.target_colors.iter().copied())
+        .collect();
+    if targets.is_empty() {
+        return None;
+    }
+
+    let tolerance = params.tolerance.max(0.0);
+    let softness = params.smoothness.max(0.0);
+    let falloff = |distance: f32| {
+        if distance <= tolerance {
+            1.0
+        } else if softness > 0.0 {
+            let t = ((distance - tolerance) / softness).min(1.0);
+            1.0 - t * t * (3.0 - 2.0 * t)
+        } else {
+            0.0
+        }
+    };
+
+    let data: Vec<u8> = match params.color_space {
+        ColorRangeSpace::Lab => {
+            let targets: Vec<[f32; 3]> = targets.into_iter().map(srgb_to_lab).collect();
+            image
+                .lab()
+                .par_iter()
+                .map(|&lab| {
+                    let distance = targets.iter().map(|&t| lab_distance(lab, t)).fold(f32::MAX, f32::min);
+                    (falloff(distance) * 255.0) as u8
+                })
+                .collect()
+        }
+        ColorRangeSpace::Hsl => {
+            let targets: Vec<[f32; 3]> = targets.into_iter().map(srgb_to_hsl).collect();
+            image
+                .rgb()
+                .as_raw()
+                .par_chunks_exact(3)
+                .map(|p| {
+                    let hsl = srgb_to_hsl([p[0], p[1], p[2]]);
+                    let distance = targets.iter().map(|&t| hsl_distance(hsl, t)).fold(f32::MAX, f32::min);
+                    (falloff(distance) * 255.0) as u8
+                })
+                .collect()
+        }
+    };
+
+    GrayImage::from_raw(image.width, image.height, data)
+}
+
+fn generate_luminance_range_bitmap(params_value: &Value, image: &MaskImage) -> Option<GrayImage> {
+    let params: LuminanceRangeMaskParameters = serde_json::from_value(params_value.clone()).ok()?;
+    let (lo, hi) = (params.low.min(params.high).clamp(0.0, 1.0), params.low.max(params.high).clamp(0.0, 1.0));
+    let feather = params.feather.max(0.0);
+
+    let data: Vec<u8> = image
+        .lab()
+        .par_iter()
+        .map(|lab| (range_intensity(lab[0] / 100.0, lo, hi, feather) * 255.0) as u8)
+        .collect();
+
+    GrayImage::from_raw(image.width, image.height, data)
+}
+
+fn generate_ai_depth_bitmap(
+    params_value: &Value,
+    width: u32,
+    height: u32,
+    scale: f32,
+    crop_offset: (f32, f32),
+) -> Option<GrayImage> {
+    let params: AiDepthMaskParameters = serde_json::from_value(params_value.clone()).ok()?;
+    let depth = decode_base64_gray(&params.depth_data_base64?)?;
+    let range_mask = depth_range_mask(&depth, params.near, params.far, params.feather);
+
+    Some(generate_ai_bitmap_from_full_mask(
+        &range_mask,
+        params.rotation.unwrap_or(0.0),
+        params.flip_horizontal.unwrap_or(false),
+        params.flip_vertical.unwrap_or(false),
+        width, height, scale, crop_offset
+    ))
+}
+
 fn generate_sub_mask_bitmap(
     sub_mask: &SubMask,
+    image: &MaskImage,
     width: u32,
     height: u32,
     scale: f32,
@@ -433,18 +842,36 @@ fn generate_sub_mask_bitmap(
         return None;
     }
 
-    match sub_mask.mask_type.as_str() {
+    let bitmap = match sub_mask.mask_type.as_str() {
         "radial" => Some(generate_radial_bitmap(&sub_mask.parameters, width, height, scale, crop_offset)),
         "linear" => Some(generate_linear_bitmap(&sub_mask.parameters, width, height, scale, crop_offset)),
-        "brush" => Some(generate_brush_bitmap(&sub_mask.parameters, width, height, scale, crop_offset)),
+        "brush" => Some(generate_brush_bitmap(&sub_mask.parameters, image, width, height, scale, crop_offset)),
         "ai-subject" => generate_ai_subject_bitmap(&sub_mask.parameters, width, height, scale, crop_offset),
         "ai-foreground" => generate_ai_foreground_bitmap(&sub_mask.parameters, width, height, scale, crop_offset),
+        "depth-map" => generate_depth_map_bitmap(&sub_mask.parameters, width, height, scale, crop_offset),
+        "depth" => generate_ai_depth_bitmap(&sub_mask.parameters, width, height, scale, crop_offset),
+        "color-range" => generate_color_range_bitmap(&sub_mask.parameters, image, scale, crop_offset),
+        "luminance-range" => generate_luminance_range_bitmap(&sub_mask.parameters, image),
         _ => None,
+    }?;
+
+    let refine_radius = sub_mask.parameters["refineRadius"].as_f64().unwrap_or(0.0) as f32 * scale;
+    if refine_radius <= 0.0 {
+        return Some(bitmap);
     }
+    let refine_contrast = sub_mask.parameters["refineContrast"].as_f64().unwrap_or(0.0) as f32;
+    Some(refine_mask(&bitmap, image.luminance(), refine_radius, refine_contrast))
+}
+
+fn multiply(a: u8, b: u8) -> u8 {
+    ((a as u32 * b as u32 + 127) / 255) as u8
 }
 
+/// Renders a mask at `width` x `height`. `image` is the image being edited at
+/// the same working resolution, used by the color and luminance sub-masks.
 pub fn generate_mask_bitmap(
     mask_def: &MaskDefinition,
+    image: &DynamicImage,
     width: u32,
     height: u32,
     scale: f32,
@@ -454,31 +881,59 @@ pub fn generate_mask_bitmap(
         return None;
     }
 
-    let mut additive_canvas = GrayImage::new(width, height);
+    // Additive sub-masks are combined as a union, which intersect sub-masks
+    // multiply down and subtractive sub-masks are removed from. Without any
+    // additive bitmap, the intersect sub-masks only restrict each other. An
+    // intersect sub-mask that fails to generate selects nothing, so it still
+    // restricts the result instead of being dropped.
+    let mask_image = MaskImage::new(image, width, height);
+    let sub_bitmaps: Vec<(&SubMask, Option<GrayImage>)> = mask_def
+        .sub_masks
+        .iter()
+        .filter(|sub_mask| sub_mask.visible)
+        .map(|sub_mask| (sub_mask, generate_sub_mask_bitmap(sub_mask, &mask_image, width, height, scale, crop_offset)))
+        .collect();
+
+    let has_additive = sub_bitmaps.iter().any(|(m, b)| m.mode == SubMaskMode::Additive && b.is_some());
+    let has_intersect = sub_bitmaps.iter().any(|(m, _)| m.mode == SubMaskMode::Intersect);
+    let additive_base = if !has_additive && has_intersect { 255 } else { 0 };
+    let mut additive_canvas = GrayImage::from_pixel(width, height, Luma([additive_base]));
+    let mut intersect_canvas = GrayImage::from_pixel(width, height, Luma([255]));
     let mut subtractive_canvas = GrayImage::new(width, height);
 
-    for sub_mask in &mask_def.sub_masks {
-        if let Some(sub_bitmap) = generate_sub_mask_bitmap(sub_mask, width, height, scale, crop_offset) {
-            match sub_mask.mode {
-                SubMaskMode::Additive => {
-                    for (x, y, pixel) in additive_canvas.enumerate_pixels_mut() {
-                        let sub_pixel = sub_bitmap.get_pixel(x, y);
-                        pixel[0] = pixel[0].max(sub_pixel[0]);
-                    }
+    for (sub_mask, sub_bitmap) in sub_bitmaps {
+        let sub_bitmap = match (sub_bitmap, sub_mask.mode) {
+            (Some(bitmap), _) => bitmap,
+            (None, SubMaskMode::Intersect) => GrayImage::new(width, height),
+            (None, _) => continue,
+        };
+        let opacity = (sub_mask.opacity / 100.0).clamp(0.0, 1.0);
+        let weighted = |value: u8| (value as f32 * opacity).round() as u8;
+        match sub_mask.mode {
+            SubMaskMode::Additive => {
+                for (pixel, sub_pixel) in additive_canvas.pixels_mut().zip(sub_bitmap.pixels()) {
+                    pixel[0] = pixel[0].max(weighted(sub_pixel[0]));
                 }
-                SubMaskMode::Subtractive => {
-                    for (x, y, pixel) in subtractive_canvas.enumerate_pixels_mut() {
-                        let sub_pixel = sub_bitmap.get_pixel(x, y);
-                        pixel[0] = pixel[0].max(sub_pixel[0]);
-                    }
+            }
+            SubMaskMode::Subtractive => {
+                for (pixel, sub_pixel) in subtractive_canvas.pixels_mut().zip(sub_bitmap.pixels()) {
+                    pixel[0] = pixel[0].max(weighted(sub_pixel[0]));
+                }
+            }
+            SubMaskMode::Intersect => {
+                for (pixel, sub_pixel) in intersect_canvas.pixels_mut().zip(sub_bitmap.pixels()) {
+                    pixel[0] = multiply(pixel[0], 255 - weighted(255 - sub_pixel[0]));
                 }
             }
         }
     }
 
-    for (x, y, final_pixel) in additive_canvas.enumerate_pixels_mut() {
-        let subtractive_pixel = subtractive_canvas.get_pixel(x, y);
-        final_pixel[0] = final_pixel[0].saturating_sub(subtractive_pixel[0]);
+    for ((final_pixel, intersect_pixel), subtractive_pixel) in additive_canvas
+        .pixels_mut()
+        .zip(intersect_canvas.pixels())
+        .zip(subtractive_canvas.pixels())
+    {
+        final_pixel[0] = multiply(final_pixel[0], intersect_pixel[0]).saturating_sub(subtractive_pixel[0]);
     }
 
     if mask_def.invert {
@@ -488,4 +943,174 @@ pub fn generate_mask_bitmap(
     }
 
     Some(additive_canvas)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+    use serde_json::json;
+
+    fn assert_close(actual: [f32; 3], expected: [f32; 3], tolerance: f32) {
+        for c in 0..3 {
+            assert!((actual[c] - expected[c]).abs() <= tolerance, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    fn image_of(colors: &[[u8; 3]]) -> DynamicImage {
+        let pixels = RgbImage::from_fn(colors.len() as u32, 1, |x, _| Rgb(colors[x as usize]));
+        DynamicImage::ImageRgb8(pixels)
+    }
+
+    #[test]
+    fn range_intensity_feathers_linearly_outside_the_range() {
+        assert_eq!(range_intensity(0.5, 0.4, 0.6, 0.1), 1.0);
+        assert!((range_intensity(0.35, 0.4, 0.6, 0.1) - 0.5).abs() < 1e-6);
+        assert!((range_intensity(0.65, 0.4, 0.6, 0.1) - 0.5).abs() < 1e-6);
+        assert_eq!(range_intensity(0.8, 0.4, 0.6, 0.1), 0.0);
+        assert_eq!(range_intensity(0.61, 0.4, 0.6, 0.0), 0.0);
+    }
+
+    #[test]
+    fn srgb_to_lab_matches_reference_values() {
+        assert_close(srgb_to_lab([1.0, 1.0, 1.0]), [100.0, 0.0, 0.0], 0.1);
+        assert_close(srgb_to_lab([0.0, 0.0, 0.0]), [0.0, 0.0, 0.0], 0.1);
+        assert_close(srgb_to_lab([1.0, 0.0, 0.0]), [53.24, 80.09, 67.20], 0.5);
+    }
+
+    #[test]
+    fn srgb_to_hsl_matches_reference_values() {
+        assert_close(srgb_to_hsl([1.0, 0.0, 0.0]), [0.0, 1.0, 0.5], 1e-4);
+        assert_close(srgb_to_hsl([0.0, 0.0, 1.0]), [240.0, 1.0, 0.5], 1e-4);
+        assert_close(srgb_to_hsl([0.5, 0.5, 0.5]), [0.0, 0.0, 0.5], 1e-4);
+    }
+
+    #[test]
+    fn hsl_distance_wraps_around_the_hue_circle() {
+        let (a, b) = ([350.0, 1.0, 0.5], [10.0, 1.0, 0.5]);
+        assert!((hsl_distance(a, b) - hsl_distance([0.0, 1.0, 0.5], [20.0, 1.0, 0.5])).abs() < 1e-4);
+    }
+
+    #[test]
+    fn color_range_selects_the_target_color() {
+        let image = image_of(&[[255, 0, 0], [0, 0, 255], [250, 10, 10]]);
+        let mask_image = MaskImage::new(&image, 3, 1);
+        for color_space in ["lab", "hsl"] {
+            let params = json!({ "targetColors": [[1.0, 0.0, 0.0]], "colorSpace": color_space, "tolerance": 10.0, "smoothness": 0.0 });
+            let bitmap = generate_color_range_bitmap(&params, &mask_image, 1.0, (0.0, 0.0)).unwrap();
+            assert_eq!(bitmap.into_raw(), vec![255, 0, 255], "{}", color_space);
+        }
+        assert!(generate_color_range_bitmap(&json!({}), &mask_image, 1.0, (0.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn luminance_range_selects_by_lightness() {
+        let image = image_of(&[[0, 0, 0], [119, 119, 119], [255, 255, 255]]);
+        let mask_image = MaskImage::new(&image, 3, 1);
+        let bitmap = generate_luminance_range_bitmap(&json!({ "low": 0.4, "high": 0.6, "feather": 0.0 }), &mask_image).unwrap();
+        assert_eq!(bitmap.into_raw(), vec![0, 255, 0]);
+    }
+
+    fn luminance(mode: SubMaskMode, opacity: f32, low: f32, high: f32) -> SubMask {
+        SubMask {
+            id: String::new(),
+            mask_type: "luminance-range".to_string(),
+            visible: true,
+            mode,
+            opacity,
+            parameters: json!({ "low": low, "high": high, "feather": 0.0 }),
+        }
+    }
+
+    // Grey ramp with lightness 0, ~36, ~70 and 100.
+    fn render(sub_masks: Vec<SubMask>, invert: bool) -> Option<Vec<u8>> {
+        let image = image_of(&[[0, 0, 0], [85, 85, 85], [170, 170, 170], [255, 255, 255]]);
+        let mask_def = MaskDefinition {
+            id: String::new(),
+            name: String::new(),
+            visible: true,
+            invert,
+            adjustments: Value::Null,
+            sub_masks,
+        };
+        generate_mask_bitmap(&mask_def, &image, 4, 1, 1.0, (0.0, 0.0)).map(GrayImage::into_raw)
+    }
+
+    #[test]
+    fn additive_sub_masks_form_a_union() {
+        let sub_masks = vec![
+            luminance(SubMaskMode::Additive, 100.0, 0.0, 0.2),
+            luminance(SubMaskMode::Additive, 100.0, 0.5, 1.0),
+        ];
+        assert_eq!(render(sub_masks, false), Some(vec![255, 0, 255, 255]));
+    }
+
+    #[test]
+    fn subtractive_sub_masks_remove_from_the_union() {
+        let sub_masks = vec![
+            luminance(SubMaskMode::Additive, 100.0, 0.0, 1.0),
+            luminance(SubMaskMode::Subtractive, 100.0, 0.5, 1.0),
+        ];
+        assert_eq!(render(sub_masks, false), Some(vec![255, 255, 0, 0]));
+    }
+
+    #[test]
+    fn intersect_sub_masks_restrict_the_union() {
+        let sub_masks = vec![
+            luminance(SubMaskMode::Additive, 100.0, 0.2, 1.0),
+            luminance(SubMaskMode::Intersect, 100.0, 0.0, 0.8),
+        ];
+        assert_eq!(render(sub_masks, false), Some(vec![0, 255, 255, 0]));
+    }
+
+    #[test]
+    fn intersect_sub_masks_alone_restrict_each_other() {
+        let sub_masks = vec![
+            luminance(SubMaskMode::Intersect, 100.0, 0.2, 1.0),
+            luminance(SubMaskMode::Intersect, 100.0, 0.0, 0.8),
+        ];
+        assert_eq!(render(sub_masks, false), Some(vec![0, 255, 255, 0]));
+    }
+
+    #[test]
+    fn failed_intersect_sub_mask_selects_nothing() {
+        let mut failed = luminance(SubMaskMode::Intersect, 100.0, 0.0, 1.0);
+        failed.mask_type = "color-range".to_string();
+        failed.parameters = json!({});
+        let sub_masks = vec![luminance(SubMaskMode::Additive, 100.0, 0.0, 1.0), failed];
+        assert_eq!(render(sub_masks, false), Some(vec![0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn failed_additive_sub_mask_is_skipped() {
+        let mut failed = luminance(SubMaskMode::Additive, 100.0, 0.0, 1.0);
+        failed.mask_type = "depth".to_string();
+        let sub_masks = vec![failed, luminance(SubMaskMode::Additive, 100.0, 0.5, 1.0)];
+        assert_eq!(render(sub_masks, false), Some(vec![0, 0, 255, 255]));
+    }
+
+    #[test]
+    fn opacity_weakens_each_mode() {
+        let additive = vec![luminance(SubMaskMode::Additive, 50.0, 0.0, 1.0)];
+        assert_eq!(render(additive, false), Some(vec![128, 128, 128, 128]));
+
+        let intersect = vec![
+            luminance(SubMaskMode::Additive, 100.0, 0.0, 1.0),
+            luminance(SubMaskMode::Intersect, 50.0, 0.5, 1.0),
+        ];
+        assert_eq!(render(intersect, false), Some(vec![127, 127, 255, 255]));
+
+        let subtractive = vec![
+            luminance(SubMaskMode::Additive, 100.0, 0.0, 1.0),
+            luminance(SubMaskMode::Subtractive, 50.0, 0.5, 1.0),
+        ];
+        assert_eq!(render(subtractive, false), Some(vec![255, 255, 127, 127]));
+    }
+
+    #[test]
+    fn invert_flips_the_combined_mask() {
+        let sub_masks = vec![luminance(SubMaskMode::Additive, 100.0, 0.5, 1.0)];
+        assert_eq!(render(sub_masks, true), Some(vec![255, 255, 0, 0]));
+        assert_eq!(render(Vec::new(), false), None);
+    }
+}
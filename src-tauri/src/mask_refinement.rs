@@ -0,0 +1,144 @@
+use image::GrayImage;
+use rayon::prelude::*;
+
+/// Regularization of the guided filter. Smaller values follow weaker edges.
+const GUIDED_FILTER_EPS: f32 = 1e-3;
+
+/// Mean over a (2r+1)x(2r+1) window, clamped at the image borders.
+pub fn box_filter(src: &[f32], width: usize, height: usize, radius: usize) -> Vec<f32> {
+    let mut horizontal = vec![0.0f32; src.len()];
+    horizontal
+        .par_chunks_exact_mut(width)
+        .zip(src.par_chunks_exact(width))
+        .for_each(|(out, row)| {
+            let mut prefix = Vec::with_capacity(width + 1);
+            prefix.push(0.0f64);
+            for &v in row {
+                prefix.push(prefix.last().unwrap() + v as f64);
+            }
+            for (x, o) in out.iter_mut().enumerate() {
+                let lo = x.saturating_sub(radius);
+                let hi = (x + radius + 1).min(width);
+                *o = ((prefix[hi] - prefix[lo]) / (hi - lo) as f64) as f32;
+            }
+        });
+
+    let mut out = vec![0.0f32; src.len()];
+    let columns: Vec<Vec<f32>> = (0..width)
+        .into_par_iter()
+        .map(|x| {
+            let mut prefix = Vec::with_capacity(height + 1);
+            prefix.push(0.0f64);
+            for y in 0..height {
+                prefix.push(prefix.last().unwrap() + horizontal[y * width + x] as f64);
+            }
+            (0..height)
+                .map(|y| {
+                    let lo = y.saturating_sub(radius);
+                    let hi = (y + radius + 1).min(height);
+                    ((prefix[hi] - prefix[lo]) / (hi - lo) as f64) as f32
+                })
+                .collect()
+        })
+        .collect();
+    for (x, column) in columns.iter().enumerate() {
+        for (y, v) in column.iter().enumerate() {
+            out[y * width + x] = *v;
+        }
+    }
+    out
+}
+
+/// Edge-preserving smoothing of `src` that follows the structure of `guide`
+/// (He et al., "Guided Image Filtering"). Both are in 0..1.
+pub fn guided_filter(guide: &[f32], src: &[f32], width: usize, height: usize, radius: usize, eps: f32) -> Vec<f32> {
+    let mean_i = box_filter(guide, width, height, radius);
+    let mean_p = box_filter(src, width, height, radius);
+    let ii: Vec<f32> = guide.par_iter().map(|i| i * i).collect();
+    let ip: Vec<f32> = guide.par_iter().zip(src.par_iter()).map(|(i, p)| i * p).collect();
+    let corr_i = box_filter(&ii, width, height, radius);
+    let corr_ip = box_filter(&ip, width, height, radius);
+
+    let (a, b): (Vec<f32>, Vec<f32>) = (0..guide.len())
+        .into_par_iter()
+        .map(|k| {
+            let var_i = corr_i[k] - mean_i[k] * mean_i[k];
+            let cov_ip = corr_ip[k] - mean_i[k] * mean_p[k];
+            let a = cov_ip / (var_i + eps);
+            (a, mean_p[k] - a * mean_i[k])
+        })
+        .unzip();
+
+    let mean_a = box_filter(&a, width, height, radius);
+    let mean_b = box_filter(&b, width, height, radius);
+    guide
+        .par_iter()
+        .zip(mean_a.par_iter().zip(mean_b.par_iter()))
+        .map(|(i, (a, b))| a * i + b)
+        .collect()
+}
+
+/// Snaps a mask to the edges of `guide` (luminance in 0..1 at the mask
+/// resolution). `contrast` in 0..100 hardens the refined transition.
+pub fn refine_mask(mask: &GrayImage, guide: &[f32], radius: f32, contrast: f32) -> GrayImage {
+    let (width, height) = (mask.width() as usize, mask.height() as usize);
+    let radius = radius.round().max(1.0) as usize;
+    if guide.len() != width * height || width == 0 || height == 0 {
+        return mask.clone();
+    }
+
+    let src: Vec<f32> = mask.as_raw().iter().map(|&v| v as f32 / 255.0).collect();
+    let refined = guided_filter(guide, &src, width, height, radius, GUIDED_FILTER_EPS);
+
+    let gain = 1.0 + contrast.clamp(0.0, 100.0) / 25.0;
+    let data = refined
+        .par_iter()
+        .map(|v| ((((v - 0.5) * gain + 0.5).clamp(0.0, 1.0)) * 255.0).round() as u8)
+        .collect();
+    GrayImage::from_raw(mask.width(), mask.height(), data).unwrap_or_else(|| mask.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A sharp step in the guide at x = 8 and a soft ramp across it in the mask.
+    fn step_edge() -> (Vec<f32>, Vec<f32>) {
+        let guide = (0..16).map(|x| if x < 8 { 0.0 } else { 1.0 }).collect();
+        let mask = (0..16).map(|x| ((x as f32 - 7.5) / 8.0 + 0.5).clamp(0.0, 1.0)).collect();
+        (guide, mask)
+    }
+
+    #[test]
+    fn box_filter_keeps_constant_input() {
+        let out = box_filter(&[0.25; 20], 5, 4, 2);
+        assert!(out.iter().all(|v| (v - 0.25).abs() < 1e-6));
+    }
+
+    #[test]
+    fn box_filter_averages_inside_the_image_at_the_borders() {
+        let out = box_filter(&[9.0, 0.0, 0.0, 0.0, 3.0], 5, 1, 1);
+        let expected = [4.5, 3.0, 0.0, 1.0, 1.5];
+        for (v, e) in out.iter().zip(expected) {
+            assert!((v - e).abs() < 1e-6, "{:?}", out);
+        }
+    }
+
+    #[test]
+    fn guided_filter_sharpens_a_mask_along_the_guide_edge() {
+        let (guide, mask) = step_edge();
+        let out = guided_filter(&guide, &mask, 16, 1, 3, GUIDED_FILTER_EPS);
+        assert!(out[8] - out[7] > 3.0 * (mask[8] - mask[7]), "{:?}", out);
+        assert!(out[..7].windows(2).all(|w| (w[1] - w[0]).abs() < mask[8] - mask[7]));
+    }
+
+    #[test]
+    fn refine_mask_hardens_the_transition_and_ignores_a_mismatched_guide() {
+        let (guide, mask) = step_edge();
+        let mask = GrayImage::from_raw(16, 1, mask.iter().map(|v| (v * 255.0).round() as u8).collect()).unwrap();
+        let soft = refine_mask(&mask, &guide, 3.0, 0.0);
+        let hard = refine_mask(&mask, &guide, 3.0, 100.0);
+        assert!(hard[(7, 0)][0] <= soft[(7, 0)][0] && hard[(8, 0)][0] >= soft[(8, 0)][0]);
+        assert_eq!(refine_mask(&mask, &guide[..8], 3.0, 0.0), mask);
+    }
+}
@@ -0,0 +1,390 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::file_management::load_settings;
+
+const DEFAULT_BASE_URL: &str = "https://huggingface.co/CyberTimon/RapidRAW-Models/resolve/main";
+
+/// A model the app can download or import. `size` and `sha256` pin the
+/// published file. Only pinned models are downloaded, and only a file
+/// matching its pin counts as ready. A model without a pin has to be
+/// imported by the user and stays unverified; the server or a mirror is
+/// never trusted to say what the file should be.
+#[derive(Debug, Clone, Copy)]
+pub struct ModelSpec {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub filename: &'static str,
+    pub size: Option<u64>,
+    pub sha256: Option<&'static str>,
+}
+
+pub const SAM_ENCODER: ModelSpec = ModelSpec {
+    id: "sam-encoder",
+    name: "SAM Encoder",
+    filename: "vit_t_encoder.onnx",
+    size: None,
+    sha256: None,
+};
+
+pub const SAM_DECODER: ModelSpec = ModelSpec {
+    id: "sam-decoder",
+    name: "SAM Decoder",
+    filename: "vit_t_decoder.onnx",
+    size: None,
+    sha256: None,
+};
+
+pub const U2NET: ModelSpec = ModelSpec {
+    id: "u2net",
+    name: "Foreground Model",
+    filename: "u2net.onnx",
+    size: None,
+    sha256: None,
+};
+
+/// The super-resolution exports have no verified published copy to pin, so
+/// they have to be imported.
+pub const SUPER_RESOLUTION_X2: ModelSpec = ModelSpec {
+    id: "super-resolution-x2",
+    name: "Super-Resolution 2x",
+    filename: "RealESRGAN_x2plus.onnx",
+    size: None,
+    sha256: None,
+};
+
+pub const SUPER_RESOLUTION_X4: ModelSpec = ModelSpec {
+    id: "super-resolution-x4",
+    name: "Super-Resolution 4x",
+    filename: "RealESRGAN_x4plus.onnx",
+    size: None,
+    sha256: None,
+};
+
+pub const MODELS: [ModelSpec; 5] = [
+    SAM_ENCODER,
+    SAM_DECODER,
+    U2NET,
+    SUPER_RESOLUTION_X2,
+    SUPER_RESOLUTION_X4,
+];
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ModelState {
+    Missing,
+    /// An interrupted download that will be resumed.
+    Partial,
+    /// Present, but not matched against a pinned checksum.
+    Unverified,
+    /// Matches the pinned size and checksum.
+    Ready,
+    Corrupt,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelStatus {
+    pub id: String,
+    pub name: String,
+    pub filename: String,
+    pub state: ModelState,
+    pub size_on_disk: u64,
+    pub expected_size: Option<u64>,
+    pub sha256: Option<String>,
+    /// Size and checksum are pinned, so the model can be downloaded.
+    pub pinned: bool,
+}
+
+/// Checksum of an installed file, stored next to the model so status checks
+/// don't have to hash it again. `pinned` is set when it matched the pin of
+/// its spec.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct VerifiedRecord {
+    sha256: String,
+    size: u64,
+    #[serde(default)]
+    pinned: bool,
+}
+
+pub fn get_models_dir(app_handle: &AppHandle) -> Result<PathBuf> {
+    let models_dir = app_handle
+        .path()
+        .app_data_dir()?
+        .join("models");
+    if !models_dir.exists() {
+        fs::create_dir_all(&models_dir)?;
+    }
+    Ok(models_dir)
+}
+
+pub fn find_model(id: &str) -> Result<ModelSpec> {
+    MODELS
+        .iter()
+        .find(|spec| spec.id == id)
+        .copied()
+        .ok_or_else(|| anyhow!("Unknown model '{}'", id))
+}
+
+fn base_url(app_handle: &AppHandle) -> String {
+    load_settings(app_handle.clone())
+        .ok()
+        .and_then(|settings| settings.ai_model_mirror_url)
+        .map(|url| url.trim().trim_end_matches('/').to_string())
+        .filter(|url| !url.is_empty())
+        .unwrap_or_else(|| DEFAULT_BASE_URL.to_string())
+}
+
+fn partial_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".part");
+    PathBuf::from(name)
+}
+
+fn import_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".import");
+    PathBuf::from(name)
+}
+
+fn record_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".verified.json");
+    PathBuf::from(name)
+}
+
+fn read_record(path: &Path) -> Option<VerifiedRecord> {
+    let content = fs::read_to_string(record_path(path)).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+fn write_record(path: &Path, record: &VerifiedRecord) -> Result<()> {
+    fs::write(record_path(path), serde_json::to_string_pretty(record)?)?;
+    Ok(())
+}
+
+fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1 << 20];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Checks a file against the pinned size and checksum of its spec, if it
+/// has them.
+fn verify_file(spec: &ModelSpec, path: &Path) -> Result<VerifiedRecord> {
+    let size = fs::metadata(path)?.len();
+    if let Some(expected) = spec.size {
+        if size != expected {
+            bail!("{} has {} bytes, expected {}", spec.name, size, expected);
+        }
+    }
+
+    let sha256 = sha256_file(path)?;
+    if let Some(expected) = spec.sha256 {
+        if !sha256.eq_ignore_ascii_case(expected) {
+            bail!("{} failed checksum verification", spec.name);
+        }
+    }
+
+    Ok(VerifiedRecord { sha256, size, pinned: spec.sha256.is_some() })
+}
+
+fn model_status(spec: &ModelSpec, models_dir: &Path, full_check: bool) -> ModelStatus {
+    let path = models_dir.join(spec.filename);
+    let size_on_disk = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+    let record = read_record(&path);
+
+    let state = if !path.exists() {
+        if partial_path(&path).exists() { ModelState::Partial } else { ModelState::Missing }
+    } else if full_check {
+        match verify_file(spec, &path) {
+            Ok(r) if record.as_ref().is_some_and(|recorded| recorded.sha256 != r.sha256) => ModelState::Corrupt,
+            Ok(r) if r.pinned => ModelState::Ready,
+            Ok(_) => ModelState::Unverified,
+            Err(_) => ModelState::Corrupt,
+        }
+    } else {
+        match &record {
+            Some(r) if r.size != size_on_disk => ModelState::Corrupt,
+            Some(r) if spec.sha256.is_some_and(|pinned| !pinned.eq_ignore_ascii_case(&r.sha256)) => ModelState::Corrupt,
+            Some(r) if r.pinned && spec.sha256.is_some() => ModelState::Ready,
+            _ => ModelState::Unverified,
+        }
+    };
+
+    ModelStatus {
+        id: spec.id.to_string(),
+        name: spec.name.to_string(),
+        filename: spec.filename.to_string(),
+        state,
+        size_on_disk,
+        expected_size: spec.size.or(record.as_ref().map(|r| r.size)),
+        sha256: spec.sha256.map(str::to_string).or(record.map(|r| r.sha256)),
+        pinned: spec.sha256.is_some() && spec.size.is_some(),
+    }
+}
+
+fn model_url(app_handle: &AppHandle, spec: &ModelSpec) -> String {
+    format!("{}/{}?download=true", base_url(app_handle), spec.filename)
+}
+
+/// Downloads into a `.part` file, resuming an earlier attempt if the server
+/// supports ranges, and only moves the file into place once it verifies.
+async fn download_model(app_handle: &AppHandle, spec: &ModelSpec, dest: &Path) -> Result<VerifiedRecord> {
+    let url = model_url(app_handle, spec);
+    let part_path = partial_path(dest);
+    let resume_from = fs::metadata(&part_path).map(|m| m.len()).unwrap_or(0);
+
+    let client = reqwest::Client::new();
+    let mut request = client.get(&url);
+    if resume_from > 0 {
+        request = request.header(reqwest::header::RANGE, format!("bytes={}-", resume_from));
+    }
+    let mut response = request
+        .send()
+        .await
+        .with_context(|| format!("Could not reach {}. Import the model from a file to work offline.", url))?
+        .error_for_status()?;
+
+    let resumed = resume_from > 0 && response.status() == reqwest::StatusCode::PARTIAL_CONTENT;
+    let total = response
+        .content_length()
+        .map(|len| if resumed { len + resume_from } else { len })
+        .or(spec.size);
+
+    let mut file = if resumed {
+        fs::OpenOptions::new().append(true).open(&part_path)?
+    } else {
+        fs::File::create(&part_path)?
+    };
+    let mut downloaded = if resumed { resume_from } else { 0 };
+
+    while let Some(chunk) = response.chunk().await? {
+        file.write_all(&chunk)?;
+        downloaded += chunk.len() as u64;
+        let _ = app_handle.emit(
+            "ai-model-download-progress",
+            serde_json::json!({ "id": spec.id, "downloaded": downloaded, "total": total }),
+        );
+    }
+    file.sync_all()?;
+    drop(file);
+
+    match verify_file(spec, &part_path) {
+        Ok(record) => {
+            fs::rename(&part_path, dest)?;
+            write_record(dest, &record)?;
+            Ok(record)
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&part_path);
+            Err(e)
+        }
+    }
+}
+
+/// Returns the path of a model, downloading it first if needed. Only
+/// pinned models are downloaded; unpinned ones have to be imported.
+pub async fn ensure_model(app_handle: &AppHandle, spec: &ModelSpec) -> Result<PathBuf> {
+    let models_dir = get_models_dir(app_handle)?;
+    let path = models_dir.join(spec.filename);
+
+    match model_status(spec, &models_dir, false).state {
+        ModelState::Ready => return Ok(path),
+        // Imported by the user, with no pin to check it against.
+        ModelState::Unverified if spec.sha256.is_none() => return Ok(path),
+        ModelState::Unverified => {
+            // Files from older versions have no record yet.
+            if let Ok(record) = verify_file(spec, &path) {
+                write_record(&path, &record)?;
+                return Ok(path);
+            }
+            fs::remove_file(&path)?;
+        }
+        ModelState::Corrupt => {
+            fs::remove_file(&path)?;
+            let _ = fs::remove_file(record_path(&path));
+        }
+        ModelState::Missing | ModelState::Partial => {}
+    }
+
+    if spec.sha256.is_none() || spec.size.is_none() {
+        bail!(
+            "{} has no pinned checksum and is not downloaded automatically. Import a trusted copy of {} instead.",
+            spec.name,
+            spec.filename
+        );
+    }
+
+    let _ = app_handle.emit("ai-model-download-start", spec.name);
+    download_model(app_handle, spec, &path).await?;
+    let _ = app_handle.emit("ai-model-download-finish", spec.name);
+    Ok(path)
+}
+
+/// Path of a model that is already on disk, without downloading or
+/// verifying it.
+pub fn ready_model_path(app_handle: &AppHandle, spec: &ModelSpec) -> Option<PathBuf> {
+    let models_dir = get_models_dir(app_handle).ok()?;
+    match model_status(spec, &models_dir, false).state {
+        ModelState::Ready | ModelState::Unverified => Some(models_dir.join(spec.filename)),
+        _ => None,
+    }
+}
+
+#[tauri::command]
+pub fn get_ai_model_status(verify: Option<bool>, app_handle: AppHandle) -> Result<Vec<ModelStatus>, String> {
+    let models_dir = get_models_dir(&app_handle).map_err(|e| e.to_string())?;
+    Ok(MODELS
+        .iter()
+        .map(|spec| model_status(spec, &models_dir, verify.unwrap_or(false)))
+        .collect())
+}
+
+#[tauri::command]
+pub async fn download_ai_model(id: String, app_handle: AppHandle) -> Result<ModelStatus, String> {
+    let spec = find_model(&id).map_err(|e| e.to_string())?;
+    ensure_model(&app_handle, &spec).await.map_err(|e| e.to_string())?;
+    let models_dir = get_models_dir(&app_handle).map_err(|e| e.to_string())?;
+    Ok(model_status(&spec, &models_dir, false))
+}
+
+/// Installs a model from a local file, for machines without internet access
+/// and for models without a pin. The file must match the pin if there is
+/// one; otherwise it is installed as unverified.
+#[tauri::command]
+pub fn import_ai_model(id: String, file_path: String, app_handle: AppHandle) -> Result<ModelStatus, String> {
+    let spec = find_model(&id).map_err(|e| e.to_string())?;
+    let models_dir = get_models_dir(&app_handle).map_err(|e| e.to_string())?;
+    let dest = models_dir.join(spec.filename);
+    let temp_path = import_path(&dest);
+
+    fs::copy(&file_path, &temp_path).map_err(|e| format!("Failed to copy model: {}", e))?;
+    let record = match verify_file(&spec, &temp_path) {
+        Ok(record) => record,
+        Err(e) => {
+            let _ = fs::remove_file(&temp_path);
+            return Err(e.to_string());
+        }
+    };
+    let _ = fs::remove_file(record_path(&dest));
+    fs::rename(&temp_path, &dest).map_err(|e| e.to_string())?;
+    write_record(&dest, &record).map_err(|e| e.to_string())?;
+
+    Ok(model_status(&spec, &models_dir, false))
+}
@@ -0,0 +1,353 @@
+use std::fs;
+use std::io::Cursor;
+use std::sync::Arc;
+
+use base64::{engine::general_purpose, Engine as _};
+use image::{imageops, DynamicImage, GenericImageView, GrayImage, ImageFormat, Rgb, RgbImage, Rgba, Rgba32FImage, RgbaImage};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::formats::is_raw_file;
+use crate::image_processing::{get_all_adjustments_from_json, get_or_init_gpu_context, process_and_get_rgba32f};
+use crate::mask_generation::{generate_mask_bitmap, MaskDefinition};
+use crate::raw_processing::develop_camera_rgb;
+use crate::AppState;
+
+const DEFAULT_HIGHLIGHT_THRESHOLD: u8 = 254;
+const DEFAULT_SHADOW_THRESHOLD: u8 = 1;
+const DEFAULT_PEAKING_SENSITIVITY: f32 = 0.5;
+/// Camera RGB relative to the white level above which a photosite is
+/// treated as clipped. Slightly below 1.0 to allow for black level noise.
+const RAW_CLIP_LEVEL: f32 = 0.99;
+/// Distance beyond the gamut edge, in linear units of the target space, from
+/// which a color is flagged.
+const GAMUT_MARGIN: f32 = 0.002;
+/// Colors closer to neutral than this are left to the clipping overlay.
+const GAMUT_MIN_CHROMA: f32 = 0.05;
+const OVERLAY_ALPHA: u8 = 200;
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum OutputProfile {
+    Srgb,
+    DisplayP3,
+    AdobeRgb,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum AnalysisOverlay {
+    #[serde(rename_all = "camelCase")]
+    Clipping {
+        highlight_threshold: Option<u8>,
+        shadow_threshold: Option<u8>,
+    },
+    RawClipping,
+    #[serde(rename_all = "camelCase")]
+    Gamut { profile: OutputProfile },
+    #[serde(rename_all = "camelCase")]
+    FocusPeaking { sensitivity: Option<f32> },
+    SharpnessHeatmap,
+}
+
+/// Per-channel sensor clipping of the loaded raw file, at the resolution of
+/// the fast demosaic. Kept until another file is loaded.
+#[derive(Default)]
+pub struct OverlayCache {
+    raw_clipping: Option<(String, Arc<RgbImage>)>,
+}
+
+/// Highlights in the colors of the clipped channels, so a blown red channel
+/// shows red and a fully clipped pixel white. Crushed shadows likewise, with
+/// fully crushed pixels in blue so they stand out against black.
+pub fn clipping_overlay(image: &DynamicImage, highlight_threshold: u8, shadow_threshold: u8) -> RgbaImage {
+    let rgb = image.to_rgb8();
+    RgbaImage::from_fn(rgb.width(), rgb.height(), |x, y| {
+        let p = rgb.get_pixel(x, y);
+        let high = p.0.map(|c| c >= highlight_threshold);
+        let low = p.0.map(|c| c <= shadow_threshold);
+        if high.iter().any(|&c| c) {
+            let [r, g, b] = high.map(|c| if c { 255 } else { 0 });
+            Rgba([r, g, b, OVERLAY_ALPHA])
+        } else if low.iter().all(|&c| c) {
+            Rgba([0, 80, 255, OVERLAY_ALPHA])
+        } else if low.iter().any(|&c| c) {
+            let [r, g, b] = low.map(|c| if c { 255 } else { 0 });
+            Rgba([r, g, b, OVERLAY_ALPHA])
+        } else {
+            Rgba([0, 0, 0, 0])
+        }
+    })
+}
+
+/// Marks every pixel whose camera channels reached the sensor white level,
+/// in the color of the clipped channels. `clip_map` holds 255 for clipped
+/// channels and must already match the preview geometry.
+pub fn raw_clipping_overlay(clip_map: &RgbImage) -> RgbaImage {
+    RgbaImage::from_fn(clip_map.width(), clip_map.height(), |x, y| {
+        let clipped = clip_map.get_pixel(x, y).0.map(|c| c > 127);
+        if clipped.iter().any(|&c| c) {
+            let [r, g, b] = clipped.map(|c| if c { 255 } else { 0 });
+            Rgba([r, g, b, OVERLAY_ALPHA])
+        } else {
+            Rgba([0, 0, 0, 0])
+        }
+    })
+}
+
+/// Decodes the unclipped pipeline output, sRGB encoded and mirrored around zero.
+fn extended_srgb_to_linear(value: f32) -> f32 {
+    let v = value.abs();
+    let linear = if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) };
+    linear.copysign(value)
+}
+
+/// Linear sRGB to the linear RGB of `profile`. All three share the D65
+/// white point, so no adaptation is needed.
+fn srgb_to_profile_matrix(profile: OutputProfile) -> [[f32; 3]; 3] {
+    const SRGB_TO_XYZ: [[f32; 3]; 3] = [
+        [0.4124564, 0.3575761, 0.1804375],
+        [0.2126729, 0.7151522, 0.0721750],
+        [0.0193339, 0.119192, 0.9503041],
+    ];
+    let xyz_to_profile: [[f32; 3]; 3] = match profile {
+        OutputProfile::Srgb => return [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+        OutputProfile::DisplayP3 => [
+            [2.493497, -0.9313836, -0.4027108],
+            [-0.829489, 1.7626641, 0.0236247],
+            [0.0358458, -0.0761724, 0.9568845],
+        ],
+        OutputProfile::AdobeRgb => [
+            [2.041369, -0.5649464, -0.3446944],
+            [-0.969266, 1.8760108, 0.0415560],
+            [0.0134474, -0.1183897, 1.0154096],
+        ],
+    };
+    std::array::from_fn(|i| std::array::from_fn(|j| (0..3).map(|k| xyz_to_profile[i][k] * SRGB_TO_XYZ[k][j]).sum()))
+}
+
+/// Marks chromatic pixels outside the profile's gamut. `image` is the
+/// unclipped pipeline output, so colors beyond sRGB keep their values and are
+/// tested in the target space itself.
+pub fn gamut_overlay(image: &Rgba32FImage, profile: OutputProfile) -> RgbaImage {
+    let matrix = srgb_to_profile_matrix(profile);
+    RgbaImage::from_fn(image.width(), image.height(), |x, y| {
+        let p = image.get_pixel(x, y).0;
+        let linear = [p[0], p[1], p[2]].map(extended_srgb_to_linear);
+        let target: [f32; 3] = std::array::from_fn(|i| (0..3).map(|k| matrix[i][k] * linear[k]).sum());
+        let max = target.iter().cloned().fold(f32::MIN, f32::max);
+        let min = target.iter().cloned().fold(f32::MAX, f32::min);
+        let outside = min < -GAMUT_MARGIN || max > 1.0 + GAMUT_MARGIN;
+        if outside && max - min > GAMUT_MIN_CHROMA {
+            Rgba([255, 0, 255, OVERLAY_ALPHA])
+        } else {
+            Rgba([0, 0, 0, 0])
+        }
+    })
+}
+
+/// Sobel gradient magnitude of the luminance, normalized so that a hard edge
+/// from black to white is about 1.
+pub fn gradient_energy(image: &DynamicImage) -> (u32, u32, Vec<f32>) {
+    let luma = image.to_luma32f();
+    let (width, height) = luma.dimensions();
+    let at = |x: i64, y: i64| luma.get_pixel(x.clamp(0, width as i64 - 1) as u32, y.clamp(0, height as i64 - 1) as u32)[0];
+
+    let mut energy = vec![0.0f32; (width * height) as usize];
+    for y in 0..height as i64 {
+        for x in 0..width as i64 {
+            let gx = (at(x + 1, y - 1) + 2.0 * at(x + 1, y) + at(x + 1, y + 1))
+                - (at(x - 1, y - 1) + 2.0 * at(x - 1, y) + at(x - 1, y + 1));
+            let gy = (at(x - 1, y + 1) + 2.0 * at(x, y + 1) + at(x + 1, y + 1))
+                - (at(x - 1, y - 1) + 2.0 * at(x, y - 1) + at(x + 1, y - 1));
+            energy[(y as u32 * width + x as u32) as usize] = (gx * gx + gy * gy).sqrt() / 4.0;
+        }
+    }
+    (width, height, energy)
+}
+
+/// Marks edges whose contrast exceeds a threshold, as focus peaking does on
+/// a camera. A higher sensitivity marks softer edges.
+pub fn focus_peaking_overlay(image: &DynamicImage, sensitivity: f32) -> RgbaImage {
+    let threshold = 0.35 - 0.3 * sensitivity.clamp(0.0, 1.0);
+    let (width, height, energy) = gradient_energy(image);
+    RgbaImage::from_fn(width, height, |x, y| {
+        if energy[(y * width + x) as usize] > threshold {
+            Rgba([0, 255, 0, 255])
+        } else {
+            Rgba([0, 0, 0, 0])
+        }
+    })
+}
+
+/// Separable box blur with edge clamping.
+fn box_blur(data: &[f32], width: u32, height: u32, radius: u32) -> Vec<f32> {
+    let (w, h, r) = (width as usize, height as usize, radius as usize);
+    let blur_line = |line: &[f32], out: &mut [f32]| {
+        let n = line.len();
+        let mut prefix = vec![0.0f32; n + 1];
+        for i in 0..n {
+            prefix[i + 1] = prefix[i] + line[i];
+        }
+        for (i, value) in out.iter_mut().enumerate() {
+            let lo = i.saturating_sub(r);
+            let hi = (i + r + 1).min(n);
+            *value = (prefix[hi] - prefix[lo]) / (hi - lo) as f32;
+        }
+    };
+
+    let mut horizontal = vec![0.0f32; w * h];
+    for y in 0..h {
+        blur_line(&data[y * w..(y + 1) * w], &mut horizontal[y * w..(y + 1) * w]);
+    }
+    let mut result = vec![0.0f32; w * h];
+    let mut column = vec![0.0f32; h];
+    let mut blurred = vec![0.0f32; h];
+    for x in 0..w {
+        for y in 0..h {
+            column[y] = horizontal[y * w + x];
+        }
+        blur_line(&column, &mut blurred);
+        for y in 0..h {
+            result[y * w + x] = blurred[y];
+        }
+    }
+    result
+}
+
+/// Local gradient energy, smoothed into regions and colored from blue for
+/// soft areas to red for the sharpest ones in the frame.
+pub fn sharpness_heatmap_overlay(image: &DynamicImage) -> RgbaImage {
+    let (width, height, energy) = gradient_energy(image);
+    let squared: Vec<f32> = energy.iter().map(|e| e * e).collect();
+    let radius = (width.max(height) / 100).max(2);
+    let local = box_blur(&squared, width, height, radius);
+
+    let mut sorted = local.clone();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let reference = sorted.get(sorted.len() * 99 / 100).copied().unwrap_or(0.0).max(1e-6);
+
+    RgbaImage::from_fn(width, height, |x, y| {
+        let v = (local[(y * width + x) as usize] / reference).sqrt().clamp(0.0, 1.0);
+        let channel = |center: f32| ((1.5 - (4.0 * v - center).abs()).clamp(0.0, 1.0) * 255.0) as u8;
+        Rgba([channel(3.0), channel(2.0), channel(1.0), (60.0 + 140.0 * v) as u8])
+    })
+}
+
+fn raw_clip_map(path: &str) -> Result<RgbImage, String> {
+    let bytes = fs::read(path).map_err(|e| e.to_string())?;
+    let camera_rgb = develop_camera_rgb(&bytes).map_err(|e| e.to_string())?;
+    let image = camera_rgb.image;
+    Ok(RgbImage::from_fn(image.width(), image.height(), |x, y| {
+        Rgb(image.get_pixel(x, y).0.map(|c| if c >= RAW_CLIP_LEVEL { 255 } else { 0 }))
+    }))
+}
+
+/// The raw clip map warped into the geometry of the editor preview.
+fn raw_clip_map_for_preview(
+    state: &AppState,
+    js_adjustments: &Value,
+    preview_size: (u32, u32),
+) -> Result<Option<RgbImage>, String> {
+    let Some((path, full_width, full_height)) = state
+        .original_image
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|loaded| (loaded.path.clone(), loaded.full_width, loaded.full_height))
+    else {
+        return Ok(None);
+    };
+    if !is_raw_file(&path) {
+        return Ok(None);
+    }
+    let Some(scale) = state.cached_preview.lock().unwrap().as_ref().map(|cached| cached.scale) else {
+        return Ok(None);
+    };
+
+    let cached = state.overlay_cache.lock().unwrap().raw_clipping.clone();
+    let clip_map = match cached {
+        Some((cached_path, map)) if cached_path == path => map,
+        _ => {
+            let map = Arc::new(raw_clip_map(&path)?);
+            state.overlay_cache.lock().unwrap().raw_clipping = Some((path, map.clone()));
+            map
+        }
+    };
+
+    let scaled_width = ((full_width as f32 * scale).round() as u32).max(1);
+    let scaled_height = ((full_height as f32 * scale).round() as u32).max(1);
+    let scaled = imageops::resize(&*clip_map, scaled_width, scaled_height, imageops::FilterType::Nearest);
+    let (transformed, _) = crate::apply_all_transformations(&DynamicImage::ImageRgb8(scaled), js_adjustments, scale);
+    let mut transformed = transformed.to_rgb8();
+    if transformed.dimensions() != preview_size {
+        transformed = imageops::resize(&transformed, preview_size.0, preview_size.1, imageops::FilterType::Nearest);
+    }
+    Ok(Some(transformed))
+}
+
+/// The current editor preview rendered again without the final clip, see
+/// `PipelineOutput::Unclipped`.
+fn unclipped_preview(state: &tauri::State<AppState>, js_adjustments: &Value) -> Result<Option<Rgba32FImage>, String> {
+    let Some(cached) = state.cached_preview.lock().unwrap().clone() else {
+        return Ok(None);
+    };
+    let is_raw = crate::is_loaded_image_raw(state);
+    let context = get_or_init_gpu_context(state)?;
+
+    let (width, height) = cached.image.dimensions();
+    let scaled_crop_offset = (cached.unscaled_crop_offset.0 * cached.scale, cached.unscaled_crop_offset.1 * cached.scale);
+    let mask_definitions: Vec<MaskDefinition> = js_adjustments
+        .get("masks")
+        .and_then(|m| serde_json::from_value(m.clone()).ok())
+        .unwrap_or_default();
+    let mask_bitmaps: Vec<GrayImage> = mask_definitions
+        .iter()
+        .filter_map(|def| generate_mask_bitmap(def, &cached.image, width, height, cached.scale, scaled_crop_offset))
+        .collect();
+
+    let adjustments = get_all_adjustments_from_json(js_adjustments, is_raw);
+    process_and_get_rgba32f(&context, context.unclipped_pipeline(), &cached.image, adjustments, &mask_bitmaps).map(Some)
+}
+
+fn encode_overlay(overlay: &RgbaImage) -> Result<String, String> {
+    let mut buf = Cursor::new(Vec::new());
+    overlay.write_to(&mut buf, ImageFormat::Png).map_err(|e| e.to_string())?;
+    Ok(format!("data:image/png;base64,{}", general_purpose::STANDARD.encode(buf.get_ref())))
+}
+
+/// Renders an analysis overlay for the current processed preview as a PNG
+/// with transparency, sized like the preview. Returns an empty string when
+/// there is no preview yet or the overlay does not apply to the image.
+#[tauri::command]
+pub fn generate_analysis_overlay(
+    overlay: AnalysisOverlay,
+    js_adjustments: Value,
+    state: tauri::State<AppState>,
+) -> Result<String, String> {
+    let Some(preview) = state.processed_preview.lock().unwrap().clone() else {
+        return Ok(String::new());
+    };
+
+    let rgba = match overlay {
+        AnalysisOverlay::Clipping { highlight_threshold, shadow_threshold } => clipping_overlay(
+            &preview,
+            highlight_threshold.unwrap_or(DEFAULT_HIGHLIGHT_THRESHOLD),
+            shadow_threshold.unwrap_or(DEFAULT_SHADOW_THRESHOLD),
+        ),
+        AnalysisOverlay::RawClipping => match raw_clip_map_for_preview(&state, &js_adjustments, preview.dimensions())? {
+            Some(clip_map) => raw_clipping_overlay(&clip_map),
+            None => return Ok(String::new()),
+        },
+        AnalysisOverlay::Gamut { profile } => match unclipped_preview(&state, &js_adjustments)? {
+            Some(unclipped) => gamut_overlay(&unclipped, profile),
+            None => return Ok(String::new()),
+        },
+        AnalysisOverlay::FocusPeaking { sensitivity } => {
+            focus_peaking_overlay(&preview, sensitivity.unwrap_or(DEFAULT_PEAKING_SENSITIVITY))
+        }
+        AnalysisOverlay::SharpnessHeatmap => sharpness_heatmap_overlay(&preview),
+    };
+
+    encode_overlay(&rgba)
+}
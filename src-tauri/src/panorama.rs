@@ -0,0 +1,506 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::BufReader;
+use std::thread;
+
+use anyhow::{anyhow, Context, Result};
+use exif::{In, Reader as ExifReader, Tag};
+use image::{imageops, GrayImage, Luma, Rgb, Rgb32FImage};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use tauri::Emitter;
+
+use crate::alignment::{
+    brief_test_pairs, estimate_transform, frame_features, is_reliable_alignment, mat_invert,
+    mat_mul, match_features, sample_bilinear, transform_point, translation_matrix, Alignment, FeatureSet, Matrix3, MotionModel, IDENTITY,
+};
+use crate::file_management::{save_linear_dng, unique_sibling_path};
+use crate::image_loader::load_linear_image;
+use crate::pyramid::{
+    collapse, fill_empty_regions, gaussian_pyramid, laplacian_pyramid, level_count,
+    WeightImage,
+};
+
+const RANSAC_THRESHOLD_PX: f64 = 2.5;
+const DEFAULT_BLEND_LEVELS: usize = 6;
+const DEFAULT_FOCAL_LENGTH_35MM: f64 = 28.0;
+const FULL_FRAME_DIAGONAL_MM: f64 = 43.267;
+const MAX_CANVAS_DIMENSION: u32 = 65_000;
+const MAX_CANVAS_PIXELS: u64 = 600_000_000;
+
+type Region = (u32, u32, u32, u32);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum PanoramaProjection {
+    Planar,
+    Cylindrical,
+    Spherical,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PanoramaOptions {
+    pub projection: PanoramaProjection,
+    pub auto_crop: bool,
+    pub focal_length_35mm: Option<f64>,
+    pub blend_levels: Option<usize>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PanoramaResult {
+    pub path: String,
+    pub width: u32,
+    pub height: u32,
+    pub skipped: Vec<String>,
+}
+
+struct ProjectedFrame {
+    path: String,
+    image: Rgb32FImage,
+    coverage: GrayImage,
+}
+
+struct WarpedRegion {
+    x0: u32,
+    y0: u32,
+    image: Rgb32FImage,
+    coverage: WeightImage,
+    seam_weight: WeightImage,
+}
+
+fn focal_length_px(path: &str, width: u32, height: u32, override_35mm: Option<f64>) -> f64 {
+    let from_exif = || -> Option<f64> {
+        let file = fs::File::open(path).ok()?;
+        let exif = ExifReader::new().read_from_container(&mut BufReader::new(file)).ok()?;
+        let value = exif
+            .get_field(Tag::FocalLengthIn35mmFilm, In::PRIMARY)?
+            .value
+            .get_uint(0)?;
+        (value > 0).then_some(value as f64)
+    };
+    let focal_35mm = override_35mm
+        .filter(|f| *f > 0.0)
+        .or_else(from_exif)
+        .unwrap_or(DEFAULT_FOCAL_LENGTH_35MM);
+    let diagonal_px = ((width as f64).powi(2) + (height as f64).powi(2)).sqrt();
+    focal_35mm / FULL_FRAME_DIAGONAL_MM * diagonal_px
+}
+
+/// Re-projects a rectilinear frame onto a cylinder or sphere of radius `focal`
+/// centred on the optical axis. Rotations of the camera then become (close to)
+/// translations, which keeps wide pans from blowing up under a planar homography.
+fn project_frame(
+    path: String,
+    image: Rgb32FImage,
+    projection: PanoramaProjection,
+    focal: f64,
+) -> ProjectedFrame {
+    let (w, h) = image.dimensions();
+    if projection == PanoramaProjection::Planar {
+        return ProjectedFrame { path, image, coverage: GrayImage::from_pixel(w, h, Luma([255])) };
+    }
+
+    let (cx, cy) = ((w as f64 - 1.0) / 2.0, (h as f64 - 1.0) / 2.0);
+    let out_w = (2.0 * focal * (w as f64 / (2.0 * focal)).atan()).ceil() as u32;
+    let out_h = match projection {
+        PanoramaProjection::Spherical => (2.0 * focal * (h as f64 / (2.0 * focal)).atan()).ceil() as u32,
+        _ => h,
+    };
+    let (ocx, ocy) = ((out_w as f64 - 1.0) / 2.0, (out_h as f64 - 1.0) / 2.0);
+
+    let rows: Vec<(Vec<f32>, Vec<u8>)> = (0..out_h)
+        .into_par_iter()
+        .map(|v| {
+            let mut colors = vec![0.0f32; out_w as usize * 3];
+            let mut mask = vec![0u8; out_w as usize];
+            for u in 0..out_w {
+                let theta = (u as f64 - ocx) / focal;
+                let y_term = (v as f64 - ocy) / focal;
+                let (sx, sy) = match projection {
+                    PanoramaProjection::Spherical => {
+                        (focal * theta.tan() + cx, focal * y_term.tan() / theta.cos() + cy)
+                    }
+                    _ => (focal * theta.tan() + cx, focal * y_term / theta.cos() + cy),
+                };
+                if let Some(px) = sample_bilinear(&image, sx, sy) {
+                    let idx = u as usize;
+                    colors[idx * 3..idx * 3 + 3].copy_from_slice(&px);
+                    mask[idx] = 255;
+                }
+            }
+            (colors, mask)
+        })
+        .collect();
+
+    let (colors, mask): (Vec<Vec<f32>>, Vec<Vec<u8>>) = rows.into_iter().unzip();
+    ProjectedFrame {
+        path,
+        image: Rgb32FImage::from_raw(out_w, out_h, colors.concat()).unwrap(),
+        coverage: GrayImage::from_raw(out_w, out_h, mask.concat()).unwrap(),
+    }
+}
+
+/// Builds a maximum spanning tree over the pairwise match graph, rooted at the
+/// best-connected frame, and chains the pairwise transforms into each frame's
+/// mapping onto the reference frame.
+fn global_transforms(
+    count: usize,
+    edges: &[(usize, usize, Alignment)],
+) -> (usize, Vec<Option<Matrix3>>) {
+    let mut connectivity = vec![0usize; count];
+    for (i, j, a) in edges {
+        connectivity[*i] += a.inliers;
+        connectivity[*j] += a.inliers;
+    }
+    let reference = (0..count).max_by_key(|&i| connectivity[i]).unwrap_or(0);
+
+    let mut transforms: Vec<Option<Matrix3>> = vec![None; count];
+    transforms[reference] = Some(IDENTITY);
+    let mut in_tree: HashSet<usize> = HashSet::from([reference]);
+    // Edges whose transform can't be inverted; the frame may still join the
+    // tree through another edge.
+    let mut rejected: HashSet<usize> = HashSet::new();
+
+    loop {
+        let next = edges
+            .iter()
+            .enumerate()
+            .filter(|(n, (i, j, _))| !rejected.contains(n) && in_tree.contains(i) != in_tree.contains(j))
+            .max_by_key(|(_, (_, _, a))| a.inliers);
+        let Some((n, (i, j, alignment))) = next else { break };
+
+        let (parent, child, child_to_parent) = if in_tree.contains(i) {
+            match mat_invert(&alignment.matrix) {
+                Some(inv) => (*i, *j, inv),
+                None => {
+                    rejected.insert(n);
+                    continue;
+                }
+            }
+        } else {
+            (*j, *i, alignment.matrix)
+        };
+        let parent_to_ref = transforms[parent].unwrap();
+        transforms[child] = Some(mat_mul(&parent_to_ref, &child_to_parent));
+        in_tree.insert(child);
+    }
+
+    (reference, transforms)
+}
+
+fn frame_bounds(frame: &ProjectedFrame, to_canvas: &Matrix3) -> Result<(f64, f64, f64, f64)> {
+    let (w, h) = frame.image.dimensions();
+    let (w, h) = (w as f64 - 1.0, h as f64 - 1.0);
+    let steps = 16;
+    let mut bounds = (f64::MAX, f64::MAX, f64::MIN, f64::MIN);
+    for s in 0..=steps {
+        let t = s as f64 / steps as f64;
+        for (x, y) in [(t * w, 0.0), (t * w, h), (0.0, t * h), (w, t * h)] {
+            let depth = to_canvas[6] * x + to_canvas[7] * y + to_canvas[8];
+            if depth <= 1e-6 {
+                return Err(anyhow!(
+                    "The frames cover too wide a field of view for a planar projection. Try cylindrical or spherical."
+                ));
+            }
+            let (px, py) = transform_point(to_canvas, x, y);
+            bounds = (bounds.0.min(px), bounds.1.min(py), bounds.2.max(px), bounds.3.max(py));
+        }
+    }
+    Ok(bounds)
+}
+
+/// Resamples a frame into the canvas region `(x0, y0, width, height)`. Along with
+/// colour and coverage it returns a seam weight that falls off towards the frame
+/// edges, so the frame whose centre is closest wins each canvas pixel.
+fn warp_into_region(
+    frame: &ProjectedFrame,
+    canvas_to_frame: &Matrix3,
+    region: Region,
+) -> WarpedRegion {
+    let (x0, y0, width, height) = region;
+    let (fw, fh) = frame.image.dimensions();
+
+    let rows: Vec<(Vec<f32>, Vec<f32>, Vec<f32>)> = (0..height)
+        .into_par_iter()
+        .map(|row| {
+            let mut colors = vec![0.0f32; width as usize * 3];
+            let mut coverage = vec![0.0f32; width as usize];
+            let mut weight = vec![0.0f32; width as usize];
+            let cy = (y0 + row) as f64;
+            for col in 0..width {
+                let (sx, sy) = transform_point(canvas_to_frame, (x0 + col) as f64, cy);
+                let Some(px) = sample_bilinear(&frame.image, sx, sy) else { continue };
+                let (nx, ny) = (sx.round() as u32, sy.round() as u32);
+                if frame.coverage.get_pixel(nx.min(fw - 1), ny.min(fh - 1)).0[0] == 0 {
+                    continue;
+                }
+                let idx = col as usize;
+                colors[idx * 3..idx * 3 + 3].copy_from_slice(&px);
+                coverage[idx] = 1.0;
+                let wx = 1.0 - (2.0 * sx / (fw as f64 - 1.0).max(1.0) - 1.0).abs();
+                let wy = 1.0 - (2.0 * sy / (fh as f64 - 1.0).max(1.0) - 1.0).abs();
+                weight[idx] = (wx * wy) as f32 + 1e-4;
+            }
+            (colors, coverage, weight)
+        })
+        .collect();
+
+    let mut colors = Vec::with_capacity((width * height * 3) as usize);
+    let mut coverage = Vec::with_capacity((width * height) as usize);
+    let mut weight = Vec::with_capacity((width * height) as usize);
+    for (c, m, w) in rows {
+        colors.extend(c);
+        coverage.extend(m);
+        weight.extend(w);
+    }
+
+    WarpedRegion {
+        x0,
+        y0,
+        image: Rgb32FImage::from_raw(width, height, colors).unwrap(),
+        coverage: WeightImage::from_raw(width, height, coverage).unwrap(),
+        seam_weight: WeightImage::from_raw(width, height, weight).unwrap(),
+    }
+}
+
+/// Largest axis-aligned rectangle made only of covered pixels, found with the
+/// classic histogram-of-heights stack scan in a single pass over the rows.
+fn largest_inscribed_rectangle(covered: &[bool], width: u32, height: u32) -> Region {
+    let w = width as usize;
+    let mut heights = vec![0u32; w];
+    let mut best = (0u32, 0u32, width, height);
+    let mut best_area = 0u64;
+
+    for y in 0..height as usize {
+        for x in 0..w {
+            heights[x] = if covered[y * w + x] { heights[x] + 1 } else { 0 };
+        }
+        let mut stack: Vec<usize> = Vec::new();
+        for x in 0..=w {
+            let current = if x < w { heights[x] } else { 0 };
+            while let Some(&top) = stack.last() {
+                if heights[top] < current {
+                    break;
+                }
+                stack.pop();
+                let rect_h = heights[top];
+                let left = stack.last().map(|&l| l + 1).unwrap_or(0);
+                let rect_w = (x - left) as u32;
+                let area = rect_w as u64 * rect_h as u64;
+                if area > best_area {
+                    best_area = area;
+                    best = (left as u32, y as u32 + 1 - rect_h, rect_w, rect_h);
+                }
+            }
+            stack.push(x);
+        }
+    }
+    best
+}
+
+fn stitch(paths: &[String], options: &PanoramaOptions, app_handle: &tauri::AppHandle) -> Result<PanoramaResult> {
+    let total = paths.len();
+    let emit_progress = |stage: &str, current: usize| {
+        let _ = app_handle.emit(
+            "panorama-progress",
+            serde_json::json!({ "stage": stage, "current": current, "total": total }),
+        );
+    };
+
+    let mut frames = Vec::with_capacity(total);
+    for (i, path) in paths.iter().enumerate() {
+        emit_progress("loading", i);
+        let linear = load_linear_image(path)?;
+        let focal = focal_length_px(path, linear.width(), linear.height(), options.focal_length_35mm);
+        frames.push(project_frame(path.clone(), linear, options.projection, focal));
+    }
+
+    emit_progress("matching", 0);
+    let test_pairs = brief_test_pairs();
+    let features: Vec<FeatureSet> = frames
+        .par_iter()
+        .map(|f| frame_features(&f.image, Some(&f.coverage), &test_pairs))
+        .collect();
+
+    let model = match options.projection {
+        PanoramaProjection::Planar => MotionModel::Homography,
+        _ => MotionModel::Affine,
+    };
+    let pairs: Vec<(usize, usize)> = (0..total)
+        .flat_map(|i| ((i + 1)..total).map(move |j| (i, j)))
+        .collect();
+    let edges: Vec<(usize, usize, Alignment)> = pairs
+        .par_iter()
+        .filter_map(|&(i, j)| {
+            let matches = match_features(&features[i], &features[j]);
+            let threshold = RANSAC_THRESHOLD_PX / features[i].scale.min(features[j].scale);
+            let alignment = estimate_transform(&matches, model, threshold)?;
+            is_reliable_alignment(&alignment).then_some((i, j, alignment))
+        })
+        .collect();
+
+    let (reference, transforms) = global_transforms(total, &edges);
+    let mut skipped = Vec::new();
+    let mut placed: Vec<(usize, Matrix3)> = Vec::new();
+    for (i, t) in transforms.iter().enumerate() {
+        match t {
+            Some(m) => placed.push((i, *m)),
+            None => skipped.push(frames[i].path.clone()),
+        }
+    }
+    if placed.len() < 2 {
+        return Err(anyhow!("Could not find enough overlap between the selected images to stitch them."));
+    }
+
+    let mut bounds = (f64::MAX, f64::MAX, f64::MIN, f64::MIN);
+    for (i, m) in &placed {
+        let b = frame_bounds(&frames[*i], m)?;
+        bounds = (bounds.0.min(b.0), bounds.1.min(b.1), bounds.2.max(b.2), bounds.3.max(b.3));
+    }
+    let min_dim = placed
+        .iter()
+        .map(|(i, _)| frames[*i].image.width().min(frames[*i].image.height()))
+        .min()
+        .unwrap_or(1);
+    let levels = level_count(min_dim, min_dim, options.blend_levels.unwrap_or(DEFAULT_BLEND_LEVELS));
+    let align = 1u32 << (levels - 1);
+
+    let raw_w = (bounds.2 - bounds.0).ceil() as u64 + 1;
+    let raw_h = (bounds.3 - bounds.1).ceil() as u64 + 1;
+    if raw_w > MAX_CANVAS_DIMENSION as u64 || raw_h > MAX_CANVAS_DIMENSION as u64 || raw_w * raw_h > MAX_CANVAS_PIXELS {
+        return Err(anyhow!(
+            "The stitched panorama would be {}x{} pixels, which is too large. Try a cylindrical or spherical projection.",
+            raw_w, raw_h
+        ));
+    }
+    let canvas_w = (raw_w as u32).div_ceil(align) * align;
+    let canvas_h = (raw_h as u32).div_ceil(align) * align;
+    let to_canvas = translation_matrix(-bounds.0, -bounds.1);
+
+    let regions: Vec<(usize, Matrix3, Region)> = placed
+        .iter()
+        .filter_map(|(i, m)| {
+            let frame_to_canvas = mat_mul(&to_canvas, m);
+            let canvas_to_frame = mat_invert(&frame_to_canvas)?;
+            let b = frame_bounds(&frames[*i], &frame_to_canvas).ok()?;
+            let x0 = (b.0.floor().max(0.0) as u32 / align) * align;
+            let y0 = (b.1.floor().max(0.0) as u32 / align) * align;
+            let x1 = ((b.2.ceil() as u32 + 1).div_ceil(align) * align).min(canvas_w);
+            let y1 = ((b.3.ceil() as u32 + 1).div_ceil(align) * align).min(canvas_h);
+            Some((*i, canvas_to_frame, (x0, y0, x1 - x0, y1 - y0)))
+        })
+        .collect();
+
+    let canvas_len = (canvas_w as usize) * (canvas_h as usize);
+    let mut best_weight = vec![0.0f32; canvas_len];
+    let mut best_frame = vec![u16::MAX; canvas_len];
+    for (n, (i, canvas_to_frame, region)) in regions.iter().enumerate() {
+        emit_progress("seams", n);
+        let warped = warp_into_region(&frames[*i], canvas_to_frame, *region);
+        for (x, y, w) in warped.seam_weight.enumerate_pixels() {
+            let idx = (warped.y0 + y) as usize * canvas_w as usize + (warped.x0 + x) as usize;
+            if w.0[0] > best_weight[idx] {
+                best_weight[idx] = w.0[0];
+                best_frame[idx] = *i as u16;
+            }
+        }
+    }
+
+    let mut band_sums: Vec<Rgb32FImage> = (0..levels)
+        .map(|k| Rgb32FImage::new((canvas_w >> k).max(1), (canvas_h >> k).max(1)))
+        .collect();
+    let mut band_weights: Vec<WeightImage> = (0..levels)
+        .map(|k| WeightImage::new((canvas_w >> k).max(1), (canvas_h >> k).max(1)))
+        .collect();
+
+    for (n, (i, canvas_to_frame, region)) in regions.iter().enumerate() {
+        emit_progress("blending", n);
+        let mut warped = warp_into_region(&frames[*i], canvas_to_frame, *region);
+        fill_empty_regions(&mut warped.image, &warped.coverage);
+
+        let (rw, rh) = warped.image.dimensions();
+        let seam_mask = WeightImage::from_fn(rw, rh, |x, y| {
+            let idx = (warped.y0 + y) as usize * canvas_w as usize + (warped.x0 + x) as usize;
+            Luma([if best_frame[idx] == *i as u16 { 1.0 } else { 0.0 }])
+        });
+
+        let bands = laplacian_pyramid(&warped.image, levels);
+        let masks = gaussian_pyramid(&seam_mask, levels);
+        for k in 0..levels {
+            let (ox, oy) = (warped.x0 >> k, warped.y0 >> k);
+            let sum = &mut band_sums[k];
+            let weight = &mut band_weights[k];
+            for (x, y, m) in masks[k].enumerate_pixels() {
+                let m = m.0[0];
+                if m <= 0.0 || ox + x >= sum.width() || oy + y >= sum.height() {
+                    continue;
+                }
+                let band = bands[k].get_pixel(x, y).0;
+                let target = sum.get_pixel_mut(ox + x, oy + y);
+                for (t, b) in target.0.iter_mut().zip(band.iter()) {
+                    *t += b * m;
+                }
+                weight.get_pixel_mut(ox + x, oy + y).0[0] += m;
+            }
+        }
+    }
+
+    emit_progress("saving", 0);
+    for (sum, weight) in band_sums.iter_mut().zip(band_weights.iter()) {
+        for (p, w) in sum.pixels_mut().zip(weight.pixels()) {
+            if w.0[0] > 1e-6 {
+                p.0.iter_mut().for_each(|c| *c /= w.0[0]);
+            }
+        }
+    }
+    drop(band_weights);
+    let mut blended = collapse(band_sums);
+
+    let covered: Vec<bool> = best_weight.iter().map(|w| *w > 0.0).collect();
+    for (p, c) in blended.pixels_mut().zip(covered.iter()) {
+        if !*c {
+            *p = Rgb([0.0, 0.0, 0.0]);
+        }
+    }
+
+    let (crop_x, crop_y, crop_w, crop_h) = if options.auto_crop {
+        largest_inscribed_rectangle(&covered, canvas_w, canvas_h)
+    } else {
+        (0, 0, raw_w as u32, raw_h as u32)
+    };
+    let cropped = imageops::crop_imm(&blended, crop_x, crop_y, crop_w, crop_h).to_image();
+
+    let output_path = unique_sibling_path(&frames[reference].path, "Pano", "dng");
+    save_linear_dng(&cropped, &output_path).context("Failed to write panorama")?;
+
+    Ok(PanoramaResult {
+        path: output_path.to_string_lossy().into_owned(),
+        width: crop_w,
+        height: crop_h,
+        skipped,
+    })
+}
+
+#[tauri::command]
+pub fn stitch_panorama(
+    paths: Vec<String>,
+    options: PanoramaOptions,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    if paths.len() < 2 {
+        return Err("At least two images are needed to stitch a panorama.".to_string());
+    }
+
+    thread::spawn(move || match stitch(&paths, &options, &app_handle) {
+        Ok(result) => {
+            let _ = app_handle.emit("panorama-complete", result);
+        }
+        Err(e) => {
+            let _ = app_handle.emit("panorama-error", e.to_string());
+        }
+    });
+
+    Ok(())
+}
@@ -0,0 +1,157 @@
+use std::sync::Arc;
+
+use image::{imageops, DynamicImage, GenericImageView, ImageBuffer, Pixel};
+use serde::{Deserialize, Serialize};
+
+/// A rectangle in normalized coordinates of the transformed image, 0..1 on
+/// both axes.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct ViewportRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+/// Pixels of a viewport at a given scale, plus a margin around them so that
+/// neighbourhood effects see real image content at the viewport border.
+pub struct ViewportRegion {
+    pub image: DynamicImage,
+    /// Position of `image` in the scaled frame.
+    pub region_x: u32,
+    pub region_y: u32,
+    /// The requested viewport within the scaled frame.
+    pub visible_x: u32,
+    pub visible_y: u32,
+    pub visible_width: u32,
+    pub visible_height: u32,
+    /// Size of the whole image at `scale`.
+    pub frame_width: u32,
+    pub frame_height: u32,
+    pub scale: f32,
+}
+
+/// The developed, patched and transformed image at full resolution and in a
+/// series of halved levels down to a single pixel. Built once per image and
+/// geometry, so panning and zooming only resample the part on screen. Levels
+/// below the full resolution add a third of its size.
+pub struct PreviewPyramid {
+    pub image_id: u64,
+    pub transform_hash: u64,
+    /// Crop offset of level 0 in the unrotated original, as returned by
+    /// `apply_all_transformations`.
+    pub unscaled_crop_offset: (f32, f32),
+    levels: Vec<Arc<DynamicImage>>,
+}
+
+impl PreviewPyramid {
+    /// `base` is level 0 and may be shared with the loaded image.
+    pub fn new(image_id: u64, transform_hash: u64, base: Arc<DynamicImage>, unscaled_crop_offset: (f32, f32)) -> Self {
+        let mut levels = vec![base];
+        loop {
+            let last = levels.last().unwrap();
+            let (w, h) = last.dimensions();
+            if w <= 1 && h <= 1 {
+                break;
+            }
+            let next = last.resize_exact(w.div_ceil(2), h.div_ceil(2), imageops::FilterType::Triangle);
+            levels.push(Arc::new(next));
+        }
+        Self { image_id, transform_hash, unscaled_crop_offset, levels }
+    }
+
+    pub fn full_dimensions(&self) -> (u32, u32) {
+        self.levels[0].dimensions()
+    }
+
+    /// Cuts the viewport out of the image scaled by `scale`, grown by `apron`
+    /// pixels on every side where the image continues. Samples from the
+    /// smallest level that is at least as large as the scaled frame.
+    pub fn extract(&self, viewport: ViewportRect, scale: f32, apron: u32) -> Option<ViewportRegion> {
+        let (full_w, full_h) = self.full_dimensions();
+        let scale = scale.clamp(f32::MIN_POSITIVE, 1.0);
+        let frame_w = ((full_w as f32 * scale).round() as u32).clamp(1, full_w);
+        let frame_h = ((full_h as f32 * scale).round() as u32).clamp(1, full_h);
+
+        let x0 = viewport.x.clamp(0.0, 1.0);
+        let y0 = viewport.y.clamp(0.0, 1.0);
+        let x1 = (viewport.x + viewport.width).clamp(0.0, 1.0);
+        let y1 = (viewport.y + viewport.height).clamp(0.0, 1.0);
+        let visible_x = (x0 * frame_w as f32).floor() as u32;
+        let visible_y = (y0 * frame_h as f32).floor() as u32;
+        let visible_x1 = ((x1 * frame_w as f32).ceil() as u32).min(frame_w);
+        let visible_y1 = ((y1 * frame_h as f32).ceil() as u32).min(frame_h);
+        if visible_x1 <= visible_x || visible_y1 <= visible_y {
+            return None;
+        }
+
+        let region_x = visible_x.saturating_sub(apron);
+        let region_y = visible_y.saturating_sub(apron);
+        let region_w = (visible_x1 + apron).min(frame_w) - region_x;
+        let region_h = (visible_y1 + apron).min(frame_h) - region_y;
+
+        let level = self
+            .levels
+            .iter()
+            .rev()
+            .find(|l| l.width() >= frame_w && l.height() >= frame_h)
+            .unwrap_or(&self.levels[0]);
+
+        let image = if level.dimensions() == (frame_w, frame_h) {
+            level.crop_imm(region_x, region_y, region_w, region_h)
+        } else {
+            let step_x = level.width() as f32 / frame_w as f32;
+            let step_y = level.height() as f32 / frame_h as f32;
+            resample_region(level, (step_x, step_y), (region_x, region_y), (region_w, region_h))
+        };
+
+        Some(ViewportRegion {
+            image,
+            region_x,
+            region_y,
+            visible_x,
+            visible_y,
+            visible_width: visible_x1 - visible_x,
+            visible_height: visible_y1 - visible_y,
+            frame_width: frame_w,
+            frame_height: frame_h,
+            scale: frame_w as f32 / full_w as f32,
+        })
+    }
+}
+
+fn resample_buffer<I>(
+    source: &I,
+    step: (f32, f32),
+    origin: (u32, u32),
+    size: (u32, u32),
+) -> ImageBuffer<I::Pixel, Vec<<I::Pixel as Pixel>::Subpixel>>
+where
+    I: GenericImageView,
+    I::Pixel: 'static,
+{
+    let max_x = (source.width() - 1) as f32;
+    let max_y = (source.height() - 1) as f32;
+    ImageBuffer::from_fn(size.0, size.1, |x, y| {
+        let u = (((origin.0 + x) as f32 + 0.5) * step.0 - 0.5).clamp(0.0, max_x);
+        let v = (((origin.1 + y) as f32 + 0.5) * step.1 - 0.5).clamp(0.0, max_y);
+        imageops::interpolate_bilinear(source, u, v).unwrap_or_else(|| source.get_pixel(u as u32, v as u32))
+    })
+}
+
+/// Resamples the pixels of `size` at `origin` in the frame that `source`
+/// covers with `step` source pixels per frame pixel. The step is below two,
+/// as a pyramid level is never more than twice the frame, so bilinear
+/// sampling does not alias.
+fn resample_region(source: &DynamicImage, step: (f32, f32), origin: (u32, u32), size: (u32, u32)) -> DynamicImage {
+    match source {
+        DynamicImage::ImageRgb8(img) => DynamicImage::ImageRgb8(resample_buffer(img, step, origin, size)),
+        DynamicImage::ImageRgba8(img) => DynamicImage::ImageRgba8(resample_buffer(img, step, origin, size)),
+        DynamicImage::ImageRgb16(img) => DynamicImage::ImageRgb16(resample_buffer(img, step, origin, size)),
+        DynamicImage::ImageRgba16(img) => DynamicImage::ImageRgba16(resample_buffer(img, step, origin, size)),
+        DynamicImage::ImageRgb32F(img) => DynamicImage::ImageRgb32F(resample_buffer(img, step, origin, size)),
+        DynamicImage::ImageRgba32F(img) => DynamicImage::ImageRgba32F(resample_buffer(img, step, origin, size)),
+        other => DynamicImage::ImageRgba32F(resample_buffer(&other.to_rgba32f(), step, origin, size)),
+    }
+}
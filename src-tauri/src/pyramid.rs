@@ -0,0 +1,120 @@
+use image::{imageops, ImageBuffer, Luma, Pixel, Rgb32FImage};
+
+pub type FloatImage<P> = ImageBuffer<P, Vec<f32>>;
+pub type WeightImage = ImageBuffer<Luma<f32>, Vec<f32>>;
+
+pub fn reduce<P: Pixel<Subpixel = f32> + 'static>(image: &FloatImage<P>) -> FloatImage<P> {
+    let (w, h) = image.dimensions();
+    imageops::resize(image, w.div_ceil(2).max(1), h.div_ceil(2).max(1), imageops::FilterType::Triangle)
+}
+
+pub fn expand<P: Pixel<Subpixel = f32> + 'static>(
+    image: &FloatImage<P>,
+    width: u32,
+    height: u32,
+) -> FloatImage<P> {
+    imageops::resize(image, width, height, imageops::FilterType::Triangle)
+}
+
+/// Largest useful level count for an image of the given size, capped at `max_levels`.
+pub fn level_count(width: u32, height: u32, max_levels: usize) -> usize {
+    let min_dim = width.min(height).max(1);
+    let possible = (32 - min_dim.leading_zeros()).saturating_sub(3) as usize;
+    possible.clamp(1, max_levels.max(1))
+}
+
+pub fn gaussian_pyramid<P: Pixel<Subpixel = f32> + 'static>(
+    image: &FloatImage<P>,
+    levels: usize,
+) -> Vec<FloatImage<P>> {
+    let mut pyramid = vec![image.clone()];
+    for _ in 1..levels {
+        let next = reduce(pyramid.last().unwrap());
+        pyramid.push(next);
+    }
+    pyramid
+}
+
+/// Band-pass decomposition where the last level holds the low-pass residual.
+pub fn laplacian_pyramid<P: Pixel<Subpixel = f32> + 'static>(
+    image: &FloatImage<P>,
+    levels: usize,
+) -> Vec<FloatImage<P>> {
+    let gaussian = gaussian_pyramid(image, levels);
+    let mut laplacian = Vec::with_capacity(levels);
+    for k in 0..levels - 1 {
+        let (w, h) = gaussian[k].dimensions();
+        let upsampled = expand(&gaussian[k + 1], w, h);
+        let mut band = gaussian[k].clone();
+        band.iter_mut().zip(upsampled.iter()).for_each(|(v, u)| *v -= u);
+        laplacian.push(band);
+    }
+    laplacian.push(gaussian[levels - 1].clone());
+    laplacian
+}
+
+pub fn collapse<P: Pixel<Subpixel = f32> + 'static>(mut pyramid: Vec<FloatImage<P>>) -> FloatImage<P> {
+    let mut current = pyramid.pop().expect("pyramid must have at least one level");
+    while let Some(mut band) = pyramid.pop() {
+        let (w, h) = band.dimensions();
+        let upsampled = expand(&current, w, h);
+        band.iter_mut().zip(upsampled.iter()).for_each(|(v, u)| *v += u);
+        current = band;
+    }
+    current
+}
+
+/// Push-pull hole filling: pixels with zero weight take the weighted average of
+/// their surroundings from the coarsest level that has data. Used before building
+/// band-pass pyramids so empty regions don't bleed black into the blend.
+pub fn fill_empty_regions(image: &mut Rgb32FImage, weights: &WeightImage) {
+    let mut premultiplied = image.clone();
+    for (p, w) in premultiplied.pixels_mut().zip(weights.pixels()) {
+        p.0.iter_mut().for_each(|c| *c *= w.0[0]);
+    }
+
+    let mut colors = vec![premultiplied];
+    let mut coverage = vec![weights.clone()];
+    while {
+        let (w, h) = colors.last().unwrap().dimensions();
+        w > 1 || h > 1
+    } {
+        let next_color = reduce(colors.last().unwrap());
+        let next_weight = reduce(coverage.last().unwrap());
+        colors.push(next_color);
+        coverage.push(next_weight);
+    }
+
+    let mut filled: Rgb32FImage = {
+        let top_color = colors.pop().unwrap();
+        let top_weight = coverage.pop().unwrap();
+        normalize_by_weight(top_color, &top_weight, None)
+    };
+    while let (Some(color), Some(weight)) = (colors.pop(), coverage.pop()) {
+        let (w, h) = color.dimensions();
+        let fallback = expand(&filled, w, h);
+        filled = normalize_by_weight(color, &weight, Some(&fallback));
+    }
+
+    for ((out, src), w) in image.pixels_mut().zip(filled.pixels()).zip(weights.pixels()) {
+        if w.0[0] <= 0.0 {
+            *out = *src;
+        }
+    }
+}
+
+fn normalize_by_weight(
+    mut color: Rgb32FImage,
+    weight: &WeightImage,
+    fallback: Option<&Rgb32FImage>,
+) -> Rgb32FImage {
+    for (x, y, p) in color.enumerate_pixels_mut() {
+        let w = weight.get_pixel(x, y).0[0];
+        if w > 1e-6 {
+            p.0.iter_mut().for_each(|c| *c /= w);
+        } else if let Some(fb) = fallback {
+            *p = *fb.get_pixel(x, y);
+        }
+    }
+    color
+}
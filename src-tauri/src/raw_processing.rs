@@ -1,35 +1,520 @@
-use anyhow::Result;
-use image::DynamicImage;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError};
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+use image::{DynamicImage, ImageBuffer, Rgb32FImage};
 use rawler::{
     decoders::{Orientation, RawDecodeParams},
+    imgop::dcp::{neutral_from_wb, DcpProfile},
     imgop::develop::{DemosaicAlgorithm, Intermediate, ProcessingStep, RawDevelop},
-    rawimage::RawImage,
+    imgop::xyz::{FlatColorMatrix, Illuminant},
+    rawimage::{RawImage, RawPhotometricInterpretation},
     rawsource::RawSource,
 };
+use rayon::prelude::*;
+use serde::Serialize;
+use serde_json::Value;
+use crate::denoise::{denoise_linear_mono, denoise_linear_rgb};
 use crate::image_processing::apply_orientation;
+use crate::sensor_calibration::find_calibration;
+
+/// Channels at or above this fraction of the white level are left out of
+/// white balance estimates.
+pub const WHITE_BALANCE_CLIP_LEVEL: f32 = 0.98;
+/// Upper bound on the number of sensor cells the auto white balance reads.
+const AUTO_WHITE_BALANCE_SAMPLES: usize = 250_000;
+
+/// Named scene whites, in Kelvin and DNG tint.
+pub const WHITE_BALANCE_PRESETS: [WhiteBalancePreset; 6] = [
+    WhiteBalancePreset { id: "daylight", name: "Daylight", temperature: 5500.0, tint: 10.0 },
+    WhiteBalancePreset { id: "cloudy", name: "Cloudy", temperature: 6500.0, tint: 10.0 },
+    WhiteBalancePreset { id: "shade", name: "Shade", temperature: 7500.0, tint: 10.0 },
+    WhiteBalancePreset { id: "tungsten", name: "Tungsten", temperature: 2850.0, tint: 0.0 },
+    WhiteBalancePreset { id: "fluorescent", name: "Fluorescent", temperature: 3800.0, tint: 21.0 },
+    WhiteBalancePreset { id: "flash", name: "Flash", temperature: 5500.0, tint: 0.0 },
+];
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct WhiteBalancePreset {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub temperature: f32,
+    pub tint: f32,
+}
+
+/// An absolute scene white.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct WhiteBalanceValue {
+    /// Correlated color temperature in Kelvin.
+    pub temperature: f32,
+    /// DNG tint, positive towards magenta.
+    pub tint: f32,
+}
+
+/// How the sensor data is white balanced before color calibration.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RawWhiteBalance {
+    /// The coefficients recorded by the camera.
+    AsShot,
+    /// Estimated from the near-neutral parts of the sensor data.
+    Auto,
+    Custom(WhiteBalanceValue),
+}
+
+impl RawWhiteBalance {
+    fn from_adjustments(adjustments: &Value) -> Self {
+        match adjustments["rawWhiteBalance"].as_str().unwrap_or("asShot") {
+            "auto" => Self::Auto,
+            "custom" => match (adjustments["rawTemperature"].as_f64(), adjustments["rawTint"].as_f64()) {
+                (Some(temperature), tint) => Self::Custom(WhiteBalanceValue {
+                    temperature: (temperature as f32).clamp(1500.0, 50000.0),
+                    tint: (tint.unwrap_or(0.0) as f32).clamp(-150.0, 150.0),
+                }),
+                _ => Self::AsShot,
+            },
+            id => WHITE_BALANCE_PRESETS
+                .iter()
+                .find(|preset| preset.id == id)
+                .map_or(Self::AsShot, |preset| {
+                    Self::Custom(WhiteBalanceValue { temperature: preset.temperature, tint: preset.tint })
+                }),
+        }
+    }
+}
+
+/// Per-image settings that change how the raw file itself is developed.
+/// Changing any of these requires re-developing the base image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawDevelopSettings {
+    /// Path to a DCP camera profile, or `EMBEDDED_CAMERA_PROFILE`. `None`
+    /// uses the D65 matrix embedded in the raw file.
+    pub camera_profile: Option<String>,
+    /// Apply matching dark frames, flat fields and hot pixel maps.
+    pub sensor_calibration: bool,
+    /// Strength (0..100) of the wavelet denoise applied to the linear data
+    /// right after demosaicing.
+    pub denoise: u8,
+    pub white_balance: RawWhiteBalance,
+}
+
+impl Default for RawDevelopSettings {
+    fn default() -> Self {
+        Self::from_adjustments(&Value::Null)
+    }
+}
+
+impl RawDevelopSettings {
+    pub fn from_adjustments(adjustments: &Value) -> Self {
+        Self {
+            camera_profile: adjustments["cameraProfile"]
+                .as_str()
+                .filter(|s| !s.is_empty())
+                .map(str::to_string),
+            sensor_calibration: adjustments["sensorCalibration"].as_bool().unwrap_or(true),
+            denoise: adjustments["rawDenoise"].as_f64().unwrap_or(0.0).round().clamp(0.0, 100.0) as u8,
+            white_balance: RawWhiteBalance::from_adjustments(adjustments),
+        }
+    }
+}
+
+pub fn develop_raw_image(
+    file_bytes: &[u8],
+    fast_demosaic: bool,
+    settings: &RawDevelopSettings,
+) -> Result<DynamicImage> {
+    develop_internal(file_bytes, fast_demosaic, settings)
+}
+
+/// Demosaiced sensor data before white balance and color calibration.
+pub struct CameraRgbImage {
+    /// Camera RGB scaled so that 1.0 is the sensor white level, oriented for display.
+    pub image: Rgb32FImage,
+    pub make: String,
+    pub model: String,
+    pub wb_coeffs: [f32; 4],
+    pub color_matrix: HashMap<Illuminant, FlatColorMatrix>,
+}
+
+pub fn develop_camera_rgb(file_bytes: &[u8]) -> Result<CameraRgbImage> {
+    let source = RawSource::new_from_slice(file_bytes);
+    let decoder = rawler::get_decoder(&source)?;
+    let raw_image: RawImage = decoder.raw_image(&source, &RawDecodeParams::default(), false)?;
+    let metadata = decoder.raw_metadata(&source, &RawDecodeParams::default())?;
+    let orientation = metadata
+        .exif
+        .orientation
+        .map(Orientation::from_u16)
+        .unwrap_or(Orientation::Normal);
+
+    let mut developer = RawDevelop {
+        demosaic_algorithm: DemosaicAlgorithm::Speed,
+        ..Default::default()
+    };
+    developer.steps.retain(|&step| {
+        !matches!(step, ProcessingStep::WhiteBalance | ProcessingStep::Calibrate | ProcessingStep::SRgb)
+    });
+
+    let Intermediate::ThreeColor(pixels) = developer.develop_intermediate(&raw_image)? else {
+        anyhow::bail!("Only three-color sensors are supported");
+    };
+    let (width, height) = (pixels.width as u32, pixels.height as u32);
+    let image: Rgb32FImage = ImageBuffer::from_raw(width, height, pixels.flatten())
+        .context("Failed to build camera RGB buffer")?;
+
+    Ok(CameraRgbImage {
+        image: apply_orientation(DynamicImage::ImageRgb32F(image), orientation).into_rgb32f(),
+        make: raw_image.clean_make.clone(),
+        model: raw_image.clean_model.clone(),
+        wb_coeffs: raw_image.wb_coeffs,
+        color_matrix: raw_image.color_matrix.clone(),
+    })
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RawChannelHistogram {
+    /// "R", "G1", "G2" and "B" for Bayer sensors, one entry per color otherwise.
+    pub name: String,
+    /// Counts of black-subtracted values, linear from black to white level.
+    pub counts: Vec<u32>,
+    /// Photosites at or above the sensor white level.
+    pub clipped: u64,
+    pub total: u64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RawHistogramData {
+    pub channels: Vec<RawChannelHistogram>,
+    pub black_level: f32,
+    pub white_level: f32,
+}
+
+/// Channel of a photosite from its row, column and component.
+type ChannelLookup = Box<dyn Fn(usize, usize, usize) -> usize>;
+
+/// Histogram of the undemosaiced sensor data inside the active area, one
+/// channel per CFA position. Unlike a histogram of the rendered image it
+/// shows whether the sensor itself clipped.
+pub fn calculate_raw_histogram(file_bytes: &[u8], bins: usize) -> Result<RawHistogramData> {
+    let source = RawSource::new_from_slice(file_bytes);
+    let decoder = rawler::get_decoder(&source)?;
+    let raw_image: RawImage = decoder.raw_image(&source, &RawDecodeParams::default(), false)?;
+    Ok(raw_image_histogram(&raw_image, bins))
+}
+
+fn raw_image_histogram(raw_image: &RawImage, bins: usize) -> RawHistogramData {
+    let bins = bins.max(2);
+    let data = raw_image.data.as_f32();
+    let black = raw_image.blacklevel.as_bayer_array();
+    let white = raw_image.whitelevel.as_bayer_array();
+    let (width, height, cpp) = (raw_image.width, raw_image.height, raw_image.cpp);
+
+    // Maps a photosite to its channel. Black and white levels are indexed
+    // by the position in a 2x2 pattern, as rawler does when rescaling.
+    let (names, channel_at): (Vec<String>, ChannelLookup) = match &raw_image.photometric {
+        RawPhotometricInterpretation::Cfa(config) if cpp == 1 && config.cfa.width == 2 && config.cfa.height == 2 => {
+            let cfa = config.cfa.clone();
+            let mut greens = 0;
+            let names = (0..4)
+                .map(|i| match cfa.color_at(i / 2, i % 2) {
+                    0 => "R".to_string(),
+                    1 => {
+                        greens += 1;
+                        format!("G{}", greens)
+                    }
+                    2 => "B".to_string(),
+                    _ => "E".to_string(),
+                })
+                .collect();
+            (names, Box::new(|row, col, _| (row % 2) * 2 + col % 2))
+        }
+        RawPhotometricInterpretation::Cfa(config) if cpp == 1 => {
+            let cfa = config.cfa.clone();
+            let names = ["R", "G", "B", "E"].iter().map(|n| n.to_string()).collect();
+            (names, Box::new(move |row, col, _| cfa.color_at(row, col).min(3)))
+        }
+        _ => {
+            let names = ["R", "G", "B", "E"].iter().take(cpp.min(4)).map(|n| n.to_string()).collect();
+            (names, Box::new(|_, _, component| component.min(3)))
+        }
+    };
+
+    let mut channels: Vec<RawChannelHistogram> = names
+        .into_iter()
+        .map(|name| RawChannelHistogram { name, counts: vec![0; bins], clipped: 0, total: 0 })
+        .collect();
+
+    let area = raw_image.active_area.map(|a| (a.p.x, a.p.y, a.d.w, a.d.h)).unwrap_or((0, 0, width, height));
+    let (x0, y0) = (area.0.min(width), area.1.min(height));
+    let (x1, y1) = ((area.0 + area.2).min(width), (area.1 + area.3).min(height));
+
+    for row in y0..y1 {
+        for col in x0..x1 {
+            for component in 0..cpp {
+                let channel = channel_at(row, col, component);
+                let Some(histogram) = channels.get_mut(channel) else { continue };
+                let level = if cpp == 1 { (row % 2) * 2 + col % 2 } else { component.min(3) };
+                let value = data[(row * width + col) * cpp + component];
+                let range = (white[level] - black[level]).max(1.0);
+                let normalized = ((value - black[level]) / range).clamp(0.0, 1.0);
+                histogram.counts[((normalized * (bins - 1) as f32).round() as usize).min(bins - 1)] += 1;
+                histogram.total += 1;
+                if value >= white[level] {
+                    histogram.clipped += 1;
+                }
+            }
+        }
+    }
+
+    RawHistogramData { channels, black_level: black[0], white_level: white[0] }
+}
+
+/// `cameraProfile` value that interpolates the two calibration matrices
+/// embedded in the raw file by white balance, instead of using the D65 one.
+pub const EMBEDDED_CAMERA_PROFILE: &str = "embedded";
+
+fn load_camera_profile(settings: &RawDevelopSettings, color_matrix: &HashMap<Illuminant, FlatColorMatrix>) -> Result<Option<DcpProfile>> {
+    match settings.camera_profile.as_deref() {
+        None => Ok(None),
+        Some(EMBEDDED_CAMERA_PROFILE) => Ok(DcpProfile::from_color_matrices(color_matrix)),
+        Some(path) => DcpProfile::from_file(path)
+            .map(Some)
+            .with_context(|| format!("Failed to load camera profile {}", path)),
+    }
+}
+
+/// Tone curve flags of DCP files, keyed by path and modification time.
+type ToneCurveKey = (String, Option<SystemTime>);
+static TONE_CURVE_CACHE: OnceLock<Mutex<HashMap<ToneCurveKey, bool>>> = OnceLock::new();
+
+/// Whether the DCP file at `path` has a ProfileToneCurve. The develop step
+/// already tone maps with it, so the per-image tone mapper has to stay off.
+pub fn camera_profile_has_tone_curve(path: &str) -> bool {
+    if path == EMBEDDED_CAMERA_PROFILE {
+        return false;
+    }
+    let key = (path.to_string(), fs::metadata(path).and_then(|m| m.modified()).ok());
+    let cache = TONE_CURVE_CACHE.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Some(&has_curve) = cache.lock().unwrap_or_else(PoisonError::into_inner).get(&key) {
+        return has_curve;
+    }
+    let has_curve = DcpProfile::from_file(path).is_ok_and(|profile| profile.tone_curve.is_some());
+    cache.lock().unwrap_or_else(PoisonError::into_inner).insert(key, has_curve);
+    has_curve
+}
+
+/// Profile that relates camera neutrals to scene whites: the chosen DCP,
+/// the embedded calibration matrices, or the D65 matrix alone for cameras
+/// that only have that one.
+fn white_balance_profile(camera_profile: Option<DcpProfile>, color_matrix: &HashMap<Illuminant, FlatColorMatrix>) -> Option<DcpProfile> {
+    camera_profile.or_else(|| DcpProfile::from_color_matrices(color_matrix)).or_else(|| {
+        let m = color_matrix.get(&Illuminant::D65).filter(|m| m.len() == 9)?;
+        Some(DcpProfile {
+            illuminant1: Some(Illuminant::D65),
+            color_matrix1: Some([[m[0], m[1], m[2]], [m[3], m[4], m[5]], [m[6], m[7], m[8]]]),
+            ..Default::default()
+        })
+    })
+}
+
+/// Camera neutral for the white balance setting, or `None` to keep the
+/// recorded coefficients.
+fn white_balance_neutral(
+    white_balance: RawWhiteBalance,
+    auto_neutral: Option<[f32; 3]>,
+    color_matrix: &HashMap<Illuminant, FlatColorMatrix>,
+    camera_profile: Option<DcpProfile>,
+) -> Option<[f32; 3]> {
+    match white_balance {
+        RawWhiteBalance::AsShot => None,
+        RawWhiteBalance::Auto => auto_neutral,
+        RawWhiteBalance::Custom(white) => white_balance_profile(camera_profile, color_matrix)
+            .map(|profile| profile.neutral_for_white(white.temperature, white.tint)),
+    }
+}
 
-pub fn develop_raw_image(file_bytes: &[u8], fast_demosaic: bool) -> Result<DynamicImage> {
-    let (developed_image, orientation) = develop_internal(file_bytes, fast_demosaic)?;
-    Ok(apply_orientation(developed_image, orientation))
+/// White balance coefficients for a camera neutral, keeping the scale of the
+/// recorded green coefficient.
+fn wb_for_neutral(recorded: &[f32; 4], neutral: [f32; 3]) -> [f32; 4] {
+    let green = if recorded[1].is_normal() && recorded[1] > 0.0 { recorded[1] } else { 1.0 };
+    let fourth = if recorded[3].is_normal() { recorded[3] } else { green };
+    [green / neutral[0], green, green / neutral[2], fourth]
 }
 
-fn apply_tonemap_and_gamma(linear_val: f32) -> f32 {
+/// Camera RGB of unclipped sensor cells inside the active area, black
+/// subtracted and scaled to the white level. Bayer data is read in 2x2
+/// cells, all on a sparse grid.
+fn camera_rgb_samples(raw_image: &RawImage) -> Vec<[f32; 3]> {
+    let data = raw_image.data.as_f32();
+    let black = raw_image.blacklevel.as_bayer_array();
+    let white = raw_image.whitelevel.as_bayer_array();
+    let (width, height, cpp) = (raw_image.width, raw_image.height, raw_image.cpp);
+    let normalize = |value: f32, level: usize| (value - black[level]) / (white[level] - black[level]).max(1.0);
+
+    let area = raw_image.active_area.map(|a| (a.p.x, a.p.y, a.d.w, a.d.h)).unwrap_or((0, 0, width, height));
+    let (x0, y0) = (area.0.min(width), area.1.min(height));
+    let (x1, y1) = ((area.0 + area.2).min(width), (area.1 + area.3).min(height));
+    let step = (((x1 - x0) * (y1 - y0)) as f64 / AUTO_WHITE_BALANCE_SAMPLES as f64).sqrt().ceil().max(1.0) as usize;
+    let mut samples = Vec::new();
+
+    match &raw_image.photometric {
+        RawPhotometricInterpretation::Cfa(config)
+            if cpp == 1 && config.cfa.width == 2 && config.cfa.height == 2 && config.cfa.is_rgb() =>
+        {
+            let step = step.div_ceil(2) * 2;
+            for row in (y0..y1.saturating_sub(1)).step_by(step) {
+                for col in (x0..x1.saturating_sub(1)).step_by(step) {
+                    let mut sum = [0.0f32; 3];
+                    let mut count = [0u32; 3];
+                    let mut clipped = false;
+                    for (r, c) in [(row, col), (row, col + 1), (row + 1, col), (row + 1, col + 1)] {
+                        let value = normalize(data[r * width + c], (r % 2) * 2 + c % 2);
+                        clipped |= value >= WHITE_BALANCE_CLIP_LEVEL;
+                        let color = config.cfa.color_at(r, c);
+                        if color < 3 {
+                            sum[color] += value;
+                            count[color] += 1;
+                        }
+                    }
+                    if !clipped && count.iter().all(|&n| n > 0) {
+                        samples.push([0, 1, 2].map(|i| sum[i] / count[i] as f32));
+                    }
+                }
+            }
+        }
+        RawPhotometricInterpretation::LinearRaw if cpp == 3 => {
+            for row in (y0..y1).step_by(step) {
+                for col in (x0..x1).step_by(step) {
+                    let offset = (row * width + col) * 3;
+                    let rgb = [0, 1, 2].map(|i| normalize(data[offset + i], i));
+                    if rgb.iter().all(|&v| v < WHITE_BALANCE_CLIP_LEVEL) {
+                        samples.push(rgb);
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+    samples
+}
+
+fn median(values: &mut [f32]) -> f32 {
+    let mid = values.len() / 2;
+    *values.select_nth_unstable_by(mid, |a, b| a.total_cmp(b)).1
+}
+
+/// Gray world over the sensor cells that are close to neutral under the
+/// recorded white balance, so large colored areas do not pull the estimate.
+/// Returns a camera neutral normalized to green.
+fn estimate_auto_neutral(raw_image: &RawImage) -> Option<[f32; 3]> {
+    let as_shot = neutral_from_wb(&raw_image.wb_coeffs);
+    let balanced: Vec<([f32; 3], f32, f32)> = camera_rgb_samples(raw_image)
+        .into_iter()
+        .filter(|s| s[0] > 0.0 && s[1] > 0.01 && s[2] > 0.0)
+        .map(|s| (s, (s[0] / as_shot[0] / s[1]).ln(), (s[2] / as_shot[2] / s[1]).ln()))
+        .collect();
+    if balanced.len() < 100 {
+        return None;
+    }
+
+    let mut rg: Vec<f32> = balanced.iter().map(|b| b.1).collect();
+    let mut bg: Vec<f32> = balanced.iter().map(|b| b.2).collect();
+    let (median_rg, median_bg) = (median(&mut rg), median(&mut bg));
+    let tolerance = |values: &mut Vec<f32>, center: f32| {
+        values.iter_mut().for_each(|v| *v = (*v - center).abs());
+        (median(values) * 3.0 * 1.4826).clamp(0.05, 0.5)
+    };
+    let (tolerance_rg, tolerance_bg) = (tolerance(&mut rg, median_rg), tolerance(&mut bg, median_bg));
+
+    let mut sum = [0.0f64; 3];
+    let mut count = 0;
+    for (s, r, b) in &balanced {
+        if (r - median_rg).abs() <= tolerance_rg && (b - median_bg).abs() <= tolerance_bg && r.abs() < 1.0 && b.abs() < 1.0 {
+            (0..3).for_each(|i| sum[i] += s[i] as f64);
+            count += 1;
+        }
+    }
+    if count < 50 || sum[1] <= 0.0 {
+        return None;
+    }
+    Some([(sum[0] / sum[1]) as f32, 1.0, (sum[2] / sum[1]) as f32])
+}
+
+/// White balance values of a raw file for the white balance controls.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RawWhiteBalanceInfo {
+    pub as_shot: WhiteBalanceValue,
+    pub auto: Option<WhiteBalanceValue>,
+    pub presets: Vec<WhiteBalancePreset>,
+}
+
+fn white_for_neutral(profile: &DcpProfile, neutral: [f32; 3]) -> WhiteBalanceValue {
+    let white = profile.white_for_neutral(neutral);
+    WhiteBalanceValue { temperature: white.temperature, tint: white.tint }
+}
+
+pub fn raw_white_balance_info(file_bytes: &[u8], settings: &RawDevelopSettings) -> Result<RawWhiteBalanceInfo> {
+    let source = RawSource::new_from_slice(file_bytes);
+    let decoder = rawler::get_decoder(&source)?;
+    let raw_image: RawImage = decoder.raw_image(&source, &RawDecodeParams::default(), false)?;
+    let profile = white_balance_profile(load_camera_profile(settings, &raw_image.color_matrix)?, &raw_image.color_matrix)
+        .context("The raw file has no color matrix")?;
+
+    Ok(RawWhiteBalanceInfo {
+        as_shot: white_for_neutral(&profile, neutral_from_wb(&raw_image.wb_coeffs)),
+        auto: estimate_auto_neutral(&raw_image).map(|neutral| white_for_neutral(&profile, neutral)),
+        presets: WHITE_BALANCE_PRESETS.to_vec(),
+    })
+}
+
+/// Scene white that makes a camera RGB color neutral.
+pub fn white_balance_for_camera_color(
+    camera: &CameraRgbImage,
+    settings: &RawDevelopSettings,
+    color: [f32; 3],
+) -> Result<WhiteBalanceValue> {
+    if color.iter().any(|&c| c <= 0.0) {
+        anyhow::bail!("The picked color has no signal in every channel");
+    }
+    let profile = white_balance_profile(load_camera_profile(settings, &camera.color_matrix)?, &camera.color_matrix)
+        .context("The raw file has no color matrix")?;
+    Ok(white_for_neutral(&profile, [color[0] / color[1], 1.0, color[2] / color[1]]))
+}
+
+/// sRGB transfer curve without the upper clamp, so highlights above the
+/// white level survive into the float image. The GPU pipeline inverts it
+/// before any adjustment, which leaves raw data scene-linear until the
+/// per-image tone mapper runs.
+fn linear_to_extended_srgb(linear_val: f32) -> f32 {
     let x = linear_val.max(0.0);
-    let a = 2.51;
-    let b = 0.03;
-    let c = 2.43;
-    let d = 0.59;
-    let e = 0.14;
-    let tonemapped = ((x * (a * x + b)) / (x * (c * x + d) + e)).max(0.0).min(1.0);
-
-    if tonemapped <= 0.0031308 {
-        tonemapped * 12.92
+    if x <= 0.0031308 {
+        x * 12.92
     } else {
-        1.055 * tonemapped.powf(1.0 / 2.4) - 0.055
+        1.055 * x.powf(1.0 / 2.4) - 0.055
     }
 }
 
-fn develop_internal(file_bytes: &[u8], fast_demosaic: bool) -> Result<(DynamicImage, Orientation)> {
+/// Demosaiced camera RGB of a raw file, before denoise, white balance and
+/// color calibration, with what those later steps need from the file.
+struct DemosaicedRaw {
+    intermediate: Intermediate,
+    /// Brings the data developed against the raised white level back to 1.0
+    /// at the sensor white.
+    rescale_factor: f32,
+    /// Recorded white balance coefficients.
+    wb_coeffs: [f32; 4],
+    auto_neutral: Option<[f32; 3]>,
+    color_matrix: HashMap<Illuminant, FlatColorMatrix>,
+    orientation: Orientation,
+}
+
+fn demosaic_raw(file_bytes: &[u8], fast_demosaic: bool, sensor_calibration: bool) -> Result<DemosaicedRaw> {
     let source = RawSource::new_from_slice(file_bytes);
     let decoder = rawler::get_decoder(&source)?;
     let mut raw_image: RawImage = decoder.raw_image(&source, &RawDecodeParams::default(), false)?;
@@ -41,6 +526,9 @@ fn develop_internal(file_bytes: &[u8], fast_demosaic: bool) -> Result<(DynamicIm
         .map(Orientation::from_u16)
         .unwrap_or(Orientation::Normal);
 
+    // Estimated against the real white level, before it is raised below.
+    let auto_neutral = estimate_auto_neutral(&raw_image);
+
     let original_white_level = raw_image.whitelevel.0.get(0).cloned().unwrap_or(u16::MAX as u32) as f32;
     let original_black_level = raw_image.blacklevel.levels.get(0).map(|r| r.as_f32()).unwrap_or(0.0);
 
@@ -53,68 +541,195 @@ fn develop_internal(file_bytes: &[u8], fast_demosaic: bool) -> Result<(DynamicIm
     if fast_demosaic {
         developer.demosaic_algorithm = DemosaicAlgorithm::Speed;
     }
-    developer.steps.retain(|&step| step != ProcessingStep::SRgb);
+    developer
+        .steps
+        .retain(|&step| !matches!(step, ProcessingStep::Calibrate | ProcessingStep::SRgb));
+    if sensor_calibration {
+        developer.sensor_calibration = find_calibration(&raw_image, &metadata.exif);
+    }
 
-    let mut developed_intermediate = developer.develop_intermediate(&raw_image)?;
+    let intermediate = developer.develop_intermediate(&raw_image)?;
 
     let denominator = (original_white_level - original_black_level).max(1.0);
     let rescale_factor = (headroom_white_level - original_black_level) / denominator;
 
-    const HIGHLIGHT_COMPRESSION_POINT: f32 = 3.0; // FIXME: This is not a good solution yet
+    Ok(DemosaicedRaw {
+        intermediate,
+        rescale_factor,
+        wb_coeffs: raw_image.wb_coeffs,
+        auto_neutral,
+        color_matrix: raw_image.color_matrix.clone(),
+        orientation,
+    })
+}
+
+fn denoise_intermediate(intermediate: &mut Intermediate, strength: u8) {
+    let strength = strength as f32;
+    match intermediate {
+        Intermediate::Monochrome(pixels) => {
+            denoise_linear_mono(&mut pixels.data, pixels.width, pixels.height, strength);
+        }
+        Intermediate::ThreeColor(pixels) => {
+            denoise_linear_rgb(&mut pixels.data, pixels.width, pixels.height, strength);
+        }
+        Intermediate::FourColor(_) => {}
+    }
+}
+
+/// White balances, calibrates and renders `intermediate`, which is
+/// `raw.intermediate` or a denoised copy of it, into the oriented extended
+/// sRGB base image. Highlights are left for the per-image tone mapper. Every
+/// step here is a per-pixel pass, cheap enough to re-run while a white
+/// balance slider is dragged.
+fn finish_develop(raw: &DemosaicedRaw, intermediate: &Intermediate, settings: &RawDevelopSettings) -> Result<DynamicImage> {
+    let camera_profile = load_camera_profile(settings, &raw.color_matrix)?;
+    let wb_coeffs = white_balance_neutral(settings.white_balance, raw.auto_neutral, &raw.color_matrix, camera_profile.clone())
+        .map_or(raw.wb_coeffs, |neutral| wb_for_neutral(&raw.wb_coeffs, neutral));
+
+    let developer = RawDevelop { profile: camera_profile, ..Default::default() };
+
+    // Look tables and tone curves need scene-referred values, so they run
+    // after rescaling rather than inside the calibrate step.
+    let renderer = developer
+        .profile
+        .as_ref()
+        .filter(|profile| profile.has_rendering())
+        .map(|profile| profile.renderer(&profile.white_for_neutral(neutral_from_wb(&wb_coeffs))));
 
+    let rescale_factor = raw.rescale_factor;
+    let mut developed_intermediate = developer.calibrate(intermediate, &wb_coeffs, &raw.color_matrix)?;
     match &mut developed_intermediate {
         Intermediate::Monochrome(pixels) => {
-            pixels.data.iter_mut().for_each(|p| {
-                let linear_val = *p * rescale_factor;
-                *p = apply_tonemap_and_gamma(linear_val);
+            pixels.data.par_iter_mut().for_each(|p| {
+                *p = linear_to_extended_srgb(*p * rescale_factor);
             });
         }
         Intermediate::ThreeColor(pixels) => {
-            pixels.data.iter_mut().for_each(|p| {
-                let r = (p[0] * rescale_factor).max(0.0);
-                let g = (p[1] * rescale_factor).max(0.0);
-                let b = (p[2] * rescale_factor).max(0.0);
-
-                let max_c = r.max(g).max(b);
-
-                let (final_r, final_g, final_b) = if max_c > 1.0 {
-                    let min_c = r.min(g).min(b);
-                    let compression_factor = (1.0 - (max_c - 1.0) / (HIGHLIGHT_COMPRESSION_POINT - 1.0))
-                        .max(0.0)
-                        .min(1.0);
-                    let compressed_r = min_c + (r - min_c) * compression_factor;
-                    let compressed_g = min_c + (g - min_c) * compression_factor;
-                    let compressed_b = min_c + (b - min_c) * compression_factor;
-                    let compressed_max = compressed_r.max(compressed_g).max(compressed_b);
-
-                    if compressed_max > 1e-6 {
-                        let rescale = max_c / compressed_max;
-                        (compressed_r * rescale, compressed_g * rescale, compressed_b * rescale)
-                    } else {
-                        (max_c, max_c, max_c)
-                    }
-                } else {
-                    (r, g, b)
-                };
-
-                p[0] = apply_tonemap_and_gamma(final_r);
-                p[1] = apply_tonemap_and_gamma(final_g);
-                p[2] = apply_tonemap_and_gamma(final_b);
+            pixels.data.par_iter_mut().for_each(|p| {
+                let mut rgb = [p[0] * rescale_factor, p[1] * rescale_factor, p[2] * rescale_factor];
+                if let Some(renderer) = &renderer {
+                    rgb = renderer.apply(rgb);
+                }
+                *p = rgb.map(linear_to_extended_srgb);
             });
         }
         Intermediate::FourColor(pixels) => {
-            pixels.data.iter_mut().for_each(|p| {
+            pixels.data.par_iter_mut().for_each(|p| {
                 p.iter_mut().for_each(|c| {
-                    let linear_val = *c * rescale_factor;
-                    *c = apply_tonemap_and_gamma(linear_val);
+                    *c = linear_to_extended_srgb(*c * rescale_factor);
                 });
             });
         }
     }
 
-    let dynamic_image = developed_intermediate
-        .to_dynamic_image()
-        .ok_or_else(|| anyhow::anyhow!("Failed to convert developed image to DynamicImage"))?;
+    // `to_dynamic_image` would clamp to 16 bits, losing everything above white.
+    let dynamic_image = match developed_intermediate {
+        Intermediate::ThreeColor(pixels) => {
+            let (width, height) = (pixels.width as u32, pixels.height as u32);
+            let image: Rgb32FImage = ImageBuffer::from_raw(width, height, pixels.flatten())
+                .context("Failed to build developed image buffer")?;
+            DynamicImage::ImageRgb32F(image)
+        }
+        Intermediate::Monochrome(pixels) => {
+            let (width, height) = (pixels.width as u32, pixels.height as u32);
+            let data = pixels.data.iter().flat_map(|&v| [v, v, v]).collect();
+            let image: Rgb32FImage = ImageBuffer::from_raw(width, height, data)
+                .context("Failed to build developed image buffer")?;
+            DynamicImage::ImageRgb32F(image)
+        }
+        other => other
+            .to_dynamic_image()
+            .ok_or_else(|| anyhow::anyhow!("Failed to convert developed image to DynamicImage"))?,
+    };
+
+    Ok(apply_orientation(dynamic_image, raw.orientation))
+}
+
+fn develop_internal(
+    file_bytes: &[u8],
+    fast_demosaic: bool,
+    settings: &RawDevelopSettings,
+) -> Result<DynamicImage> {
+    let mut demosaiced = demosaic_raw(file_bytes, fast_demosaic, settings.sensor_calibration)?;
+    // Thumbnails are downscaled far enough to hide the noise.
+    if settings.denoise > 0 && !fast_demosaic {
+        denoise_intermediate(&mut demosaiced.intermediate, settings.denoise);
+    }
+    finish_develop(&demosaiced, &demosaiced.intermediate, settings)
+}
+
+/// Settings that apply before denoise, which the cached demosaic depends on.
+#[derive(Debug, Clone, PartialEq)]
+struct DevelopKey {
+    path: String,
+    modified: Option<SystemTime>,
+    sensor_calibration: bool,
+}
+
+struct DevelopCache {
+    key: DevelopKey,
+    demosaiced: Arc<DemosaicedRaw>,
+    /// The last denoise result and its strength.
+    denoised: Option<(u8, Arc<Intermediate>)>,
+}
+
+/// Only the raw file being edited is kept; it is several hundred megabytes.
+/// The lock is only held to swap entries, never while developing, and a
+/// lock poisoned by a panicking decoder is recovered, so one bad file
+/// can't break every later develop.
+static DEVELOP_CACHE: OnceLock<Mutex<Option<DevelopCache>>> = OnceLock::new();
+
+fn develop_cache() -> MutexGuard<'static, Option<DevelopCache>> {
+    DEVELOP_CACHE
+        .get_or_init(|| Mutex::new(None))
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+}
 
-    Ok((dynamic_image, orientation))
-}
\ No newline at end of file
+/// Develops the raw file open in the editor. The demosaiced data and the
+/// last denoise result are cached, so a new denoise strength, white balance
+/// or camera profile only re-runs the passes that come after them.
+pub fn develop_raw_file_cached(path: &str, settings: &RawDevelopSettings) -> Result<DynamicImage> {
+    let key = DevelopKey {
+        path: path.to_string(),
+        modified: fs::metadata(path).and_then(|m| m.modified()).ok(),
+        sensor_calibration: settings.sensor_calibration,
+    };
+
+    let cached = develop_cache()
+        .as_ref()
+        .filter(|cached| cached.key == key)
+        .map(|cached| (cached.demosaiced.clone(), cached.denoised.clone()));
+    let (demosaiced, denoised) = match cached {
+        Some(cached) => cached,
+        None => {
+            // Free the previous file before developing the next one.
+            *develop_cache() = None;
+            let file_bytes = fs::read(path).with_context(|| format!("Failed to read {}", path))?;
+            let demosaiced = Arc::new(demosaic_raw(&file_bytes, false, settings.sensor_calibration)?);
+            *develop_cache() = Some(DevelopCache { key: key.clone(), demosaiced: demosaiced.clone(), denoised: None });
+            (demosaiced, None)
+        }
+    };
+
+    if settings.denoise == 0 {
+        return finish_develop(&demosaiced, &demosaiced.intermediate, settings);
+    }
+    let denoised = match denoised.filter(|(strength, _)| *strength == settings.denoise) {
+        Some((_, denoised)) => denoised,
+        None => {
+            // Free the previous strength's result before computing the next.
+            if let Some(cached) = develop_cache().as_mut() {
+                cached.denoised = None;
+            }
+            let mut denoised = demosaiced.intermediate.clone();
+            denoise_intermediate(&mut denoised, settings.denoise);
+            let denoised = Arc::new(denoised);
+            if let Some(cached) = develop_cache().as_mut().filter(|cached| cached.key == key) {
+                cached.denoised = Some((settings.denoise, denoised.clone()));
+            }
+            denoised
+        }
+    };
+    finish_develop(&demosaiced, &denoised, settings)
+}
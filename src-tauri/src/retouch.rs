@@ -0,0 +1,778 @@
+use image::{imageops, Rgba32FImage};
+use rawler::Orientation;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Half size of the patches compared by PatchMatch (7x7).
+const PATCH_RADIUS: i32 = 3;
+const PATCHMATCH_ITERATIONS: usize = 4;
+const EM_ITERATIONS: usize = 3;
+const MEMBRANE_ITERATIONS: usize = 40;
+/// Long side of the downscaled image used to pick a source automatically.
+const SOURCE_SEARCH_SIZE: u32 = 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum RetouchMode {
+    /// Copies texture from the source and matches it to the surrounding tones.
+    Heal,
+    /// Copies the source as-is.
+    Clone,
+    /// Synthesizes the area from its surroundings (PatchMatch).
+    Fill,
+}
+
+/// Frame the coordinates of a retouch layer refer to.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum RetouchSpace {
+    /// The image as displayed, after its EXIF orientation.
+    #[default]
+    Image,
+    /// The sensor before orientation, shared by all frames of a camera.
+    /// Converted to image space when applied to a file.
+    Sensor,
+}
+
+/// Normalized image coordinates (0..1 of width and height).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct RetouchPoint {
+    pub x: f32,
+    pub y: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RetouchStroke {
+    /// A single point makes a spot.
+    pub points: Vec<RetouchPoint>,
+    /// Fraction of the image's long side.
+    pub radius: f32,
+    #[serde(default = "default_feather")]
+    pub feather: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RetouchParameters {
+    pub mode: RetouchMode,
+    pub strokes: Vec<RetouchStroke>,
+    /// Offset from the retouched area to its source, in normalized
+    /// coordinates. Picked once when the layer is created, so preview and
+    /// export copy the same area; heal and clone layers without one are
+    /// filled instead.
+    #[serde(default)]
+    pub source_offset: Option<RetouchPoint>,
+    #[serde(default = "default_opacity")]
+    pub opacity: f32,
+    #[serde(default)]
+    pub space: RetouchSpace,
+}
+
+impl RetouchParameters {
+    /// Converts sensor-space coordinates into the image space of a file with
+    /// the given orientation, matching `apply_orientation`.
+    pub fn resolve_sensor_space(&mut self, orientation: Orientation) {
+        if self.space != RetouchSpace::Sensor {
+            return;
+        }
+        for stroke in &mut self.strokes {
+            for point in &mut stroke.points {
+                *point = orient_point(*point, orientation);
+            }
+        }
+        if let Some(offset) = self.source_offset {
+            let origin = orient_point(RetouchPoint { x: 0.0, y: 0.0 }, orientation);
+            let moved = orient_point(offset, orientation);
+            self.source_offset = Some(RetouchPoint { x: moved.x - origin.x, y: moved.y - origin.y });
+        }
+        self.space = RetouchSpace::Image;
+    }
+}
+
+fn orient_point(p: RetouchPoint, orientation: Orientation) -> RetouchPoint {
+    let (x, y) = match orientation {
+        Orientation::Normal | Orientation::Unknown => (p.x, p.y),
+        Orientation::HorizontalFlip => (1.0 - p.x, p.y),
+        Orientation::Rotate180 => (1.0 - p.x, 1.0 - p.y),
+        Orientation::VerticalFlip => (p.x, 1.0 - p.y),
+        Orientation::Transpose => (1.0 - p.y, 1.0 - p.x),
+        Orientation::Rotate90 => (1.0 - p.y, p.x),
+        Orientation::Transverse => (p.y, p.x),
+        Orientation::Rotate270 => (p.y, 1.0 - p.x),
+    };
+    RetouchPoint { x, y }
+}
+
+fn default_feather() -> f32 {
+    0.5
+}
+
+fn default_opacity() -> f32 {
+    1.0
+}
+
+/// Start, end, radius and feather of one stroke segment, in pixels.
+type Segment = ((f32, f32), (f32, f32), f32, f32);
+
+/// Coverage of the strokes over a rectangle of the image, with a margin of
+/// uncovered pixels around them.
+struct Footprint {
+    x0: i32,
+    y0: i32,
+    width: usize,
+    height: usize,
+    alpha: Vec<f32>,
+}
+
+impl Footprint {
+    fn rasterize(strokes: &[RetouchStroke], width: u32, height: u32, margin: i32) -> Option<Self> {
+        let long_side = width.max(height) as f32;
+        let segments: Vec<Segment> = strokes
+            .iter()
+            .filter(|stroke| !stroke.points.is_empty())
+            .flat_map(|stroke| {
+                let radius = (stroke.radius * long_side).max(1.0);
+                let feather = stroke.feather.clamp(0.0, 1.0);
+                let points: Vec<(f32, f32)> = stroke
+                    .points
+                    .iter()
+                    .map(|p| (p.x * width as f32, p.y * height as f32))
+                    .collect();
+                let pairs: Vec<_> = if points.len() == 1 {
+                    vec![(points[0], points[0], radius, feather)]
+                } else {
+                    points.windows(2).map(|w| (w[0], w[1], radius, feather)).collect()
+                };
+                pairs
+            })
+            .collect();
+        if segments.is_empty() {
+            return None;
+        }
+
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (f32::MAX, f32::MAX, f32::MIN, f32::MIN);
+        for &(a, b, r, _) in &segments {
+            min_x = min_x.min(a.0.min(b.0) - r);
+            min_y = min_y.min(a.1.min(b.1) - r);
+            max_x = max_x.max(a.0.max(b.0) + r);
+            max_y = max_y.max(a.1.max(b.1) + r);
+        }
+        let x0 = (min_x.floor() as i32 - margin).max(0);
+        let y0 = (min_y.floor() as i32 - margin).max(0);
+        let x1 = (max_x.ceil() as i32 + margin).min(width as i32);
+        let y1 = (max_y.ceil() as i32 + margin).min(height as i32);
+        if x1 <= x0 || y1 <= y0 {
+            return None;
+        }
+
+        let (fw, fh) = ((x1 - x0) as usize, (y1 - y0) as usize);
+        let mut alpha = vec![0.0f32; fw * fh];
+        for &(a, b, radius, feather) in &segments {
+            let sx0 = ((a.0.min(b.0) - radius).floor() as i32).max(x0);
+            let sy0 = ((a.1.min(b.1) - radius).floor() as i32).max(y0);
+            let sx1 = ((a.0.max(b.0) + radius).ceil() as i32).min(x1);
+            let sy1 = ((a.1.max(b.1) + radius).ceil() as i32).min(y1);
+            let inner = radius * (1.0 - feather);
+            for y in sy0..sy1 {
+                for x in sx0..sx1 {
+                    let d = distance_to_segment((x as f32 + 0.5, y as f32 + 0.5), a, b);
+                    let value = if d <= inner {
+                        1.0
+                    } else if d >= radius {
+                        0.0
+                    } else {
+                        let t = (radius - d) / (radius - inner);
+                        t * t * (3.0 - 2.0 * t)
+                    };
+                    let index = (y - y0) as usize * fw + (x - x0) as usize;
+                    alpha[index] = alpha[index].max(value);
+                }
+            }
+        }
+
+        Some(Self { x0, y0, width: fw, height: fh, alpha })
+    }
+}
+
+fn distance_to_segment(p: (f32, f32), a: (f32, f32), b: (f32, f32)) -> f32 {
+    let (abx, aby) = (b.0 - a.0, b.1 - a.1);
+    let length_sq = abx * abx + aby * aby;
+    let t = if length_sq > 0.0 {
+        (((p.0 - a.0) * abx + (p.1 - a.1) * aby) / length_sq).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    ((p.0 - a.0 - t * abx).powi(2) + (p.1 - a.1 - t * aby).powi(2)).sqrt()
+}
+
+fn read_region(image: &Rgba32FImage, x0: i32, y0: i32, width: usize, height: usize) -> Vec<[f32; 3]> {
+    let (max_x, max_y) = (image.width() as i32 - 1, image.height() as i32 - 1);
+    let mut out = Vec::with_capacity(width * height);
+    for y in 0..height as i32 {
+        for x in 0..width as i32 {
+            let p = image.get_pixel((x0 + x).clamp(0, max_x) as u32, (y0 + y).clamp(0, max_y) as u32);
+            out.push([p[0], p[1], p[2]]);
+        }
+    }
+    out
+}
+
+fn write_footprint(image: &mut Rgba32FImage, fp: &Footprint, original: &[[f32; 3]], result: &[[f32; 3]], opacity: f32) {
+    for y in 0..fp.height {
+        for x in 0..fp.width {
+            let i = y * fp.width + x;
+            let t = fp.alpha[i] * opacity;
+            if t <= 0.0 {
+                continue;
+            }
+            let p = image.get_pixel_mut(fp.x0 as u32 + x as u32, fp.y0 as u32 + y as u32);
+            for c in 0..3 {
+                p[c] = original[i][c] + (result[i][c] - original[i][c]) * t;
+            }
+        }
+    }
+}
+
+/// Renders one retouch layer onto the image at its current resolution.
+pub fn apply_retouch(image: &mut Rgba32FImage, params: &RetouchParameters) {
+    let (width, height) = image.dimensions();
+    let opacity = params.opacity.clamp(0.0, 1.0);
+    if width == 0 || height == 0 || opacity <= 0.0 {
+        return;
+    }
+    let Some(fp) = Footprint::rasterize(&params.strokes, width, height, 2) else {
+        return;
+    };
+
+    match params.mode {
+        RetouchMode::Fill => fill(image, &fp, opacity),
+        RetouchMode::Heal | RetouchMode::Clone => {
+            let offset = params
+                .source_offset
+                .map(|o| ((o.x * width as f32).round() as i32, (o.y * height as f32).round() as i32));
+            let Some((dx, dy)) = offset else {
+                fill(image, &fp, opacity);
+                return;
+            };
+
+            let target = read_region(image, fp.x0, fp.y0, fp.width, fp.height);
+            let mut result = read_region(image, fp.x0 + dx, fp.y0 + dy, fp.width, fp.height);
+            if params.mode == RetouchMode::Heal {
+                let fixed: Vec<bool> = fp.alpha.iter().map(|&a| a <= 0.0).collect();
+                let mut correction: Vec<[f32; 3]> = target
+                    .iter()
+                    .zip(&result)
+                    .zip(&fixed)
+                    .map(|((t, s), &f)| if f { [t[0] - s[0], t[1] - s[1], t[2] - s[2]] } else { [0.0; 3] })
+                    .collect();
+                solve_membrane(&mut correction, &fixed, fp.width, fp.height);
+                for (r, c) in result.iter_mut().zip(&correction) {
+                    for ch in 0..3 {
+                        r[ch] += c[ch];
+                    }
+                }
+            }
+            write_footprint(image, &fp, &target, &result, opacity);
+        }
+    }
+}
+
+/// Smoothly interpolates the unfixed values from the fixed ones (Laplace
+/// equation), solved coarse to fine.
+fn solve_membrane(values: &mut [[f32; 3]], fixed: &[bool], width: usize, height: usize) {
+    if fixed.iter().all(|&f| f) || !fixed.iter().any(|&f| f) {
+        return;
+    }
+
+    let has_coarse_level = width > 16 && height > 16;
+    if has_coarse_level {
+        let (cw, ch) = (width.div_ceil(2), height.div_ceil(2));
+        let mut coarse = vec![[0.0f32; 3]; cw * ch];
+        let mut counts = vec![0u32; cw * ch];
+        for y in 0..height {
+            for x in 0..width {
+                if fixed[y * width + x] {
+                    let c = (y / 2) * cw + x / 2;
+                    for k in 0..3 {
+                        coarse[c][k] += values[y * width + x][k];
+                    }
+                    counts[c] += 1;
+                }
+            }
+        }
+        for (v, &n) in coarse.iter_mut().zip(&counts) {
+            if n > 0 {
+                v.iter_mut().for_each(|c| *c /= n as f32);
+            }
+        }
+        let coarse_fixed: Vec<bool> = counts.iter().map(|&n| n > 0).collect();
+        solve_membrane(&mut coarse, &coarse_fixed, cw, ch);
+        for y in 0..height {
+            for x in 0..width {
+                if !fixed[y * width + x] {
+                    values[y * width + x] = coarse[(y / 2) * cw + x / 2];
+                }
+            }
+        }
+    }
+
+    let iterations = if has_coarse_level { MEMBRANE_ITERATIONS } else { MEMBRANE_ITERATIONS * 8 };
+    for _ in 0..iterations {
+        for y in 0..height {
+            for x in 0..width {
+                let i = y * width + x;
+                if fixed[i] {
+                    continue;
+                }
+                let mut sum = [0.0f32; 3];
+                let mut count = 0.0;
+                let mut add = |j: usize| {
+                    for k in 0..3 {
+                        sum[k] += values[j][k];
+                    }
+                    count += 1.0;
+                };
+                if x > 0 {
+                    add(i - 1);
+                }
+                if x + 1 < width {
+                    add(i + 1);
+                }
+                if y > 0 {
+                    add(i - width);
+                }
+                if y + 1 < height {
+                    add(i + width);
+                }
+                values[i] = [sum[0] / count, sum[1] / count, sum[2] / count];
+            }
+        }
+    }
+}
+
+/// Picks the nearby area whose surroundings best match those of the
+/// retouched area and whose content has similar tones.
+pub fn find_source_offset(image: &Rgba32FImage, params: &RetouchParameters) -> Option<RetouchPoint> {
+    let (full_width, full_height) = image.dimensions();
+    let scale = SOURCE_SEARCH_SIZE as f32 / full_width.max(full_height) as f32;
+    let small = if scale < 1.0 {
+        let width = ((full_width as f32 * scale).round() as u32).max(1);
+        let height = ((full_height as f32 * scale).round() as u32).max(1);
+        imageops::thumbnail(image, width, height)
+    } else {
+        image.clone()
+    };
+    let (width, height) = small.dimensions();
+    let core = Footprint::rasterize(&params.strokes, width, height, 0)?;
+    let ring_width = ((core.width.max(core.height) / 4) as i32).clamp(2, 16);
+    let fp = Footprint::rasterize(&params.strokes, width, height, ring_width)?;
+
+    let target = read_region(&small, fp.x0, fp.y0, fp.width, fp.height);
+    let ring: Vec<usize> = (0..fp.alpha.len()).filter(|&i| fp.alpha[i] <= 0.0).collect();
+    let inside: Vec<usize> = (0..fp.alpha.len()).filter(|&i| fp.alpha[i] > 0.0).collect();
+    if ring.is_empty() || inside.is_empty() {
+        return None;
+    }
+    let ring_mean = mean_of(&target, &ring);
+
+    let score = |dx: i32, dy: i32| -> Option<f32> {
+        let (sx0, sy0) = (fp.x0 + dx, fp.y0 + dy);
+        if sx0 < 0 || sy0 < 0 || sx0 + fp.width as i32 > width as i32 || sy0 + fp.height as i32 > height as i32 {
+            return None;
+        }
+        // The source must not overlap the area it replaces.
+        if dx.abs() < core.width as i32 && dy.abs() < core.height as i32 {
+            return None;
+        }
+        let source = read_region(&small, sx0, sy0, fp.width, fp.height);
+        let ring_error = ring.iter().map(|&i| squared_distance(&target[i], &source[i])).sum::<f32>() / ring.len() as f32;
+        let tone_error = squared_distance(&mean_of(&source, &inside), &ring_mean);
+        let texture_error = inside
+            .iter()
+            .map(|&i| squared_distance(&source[i], &ring_mean))
+            .sum::<f32>()
+            / inside.len() as f32;
+        Some(ring_error + tone_error + 0.25 * texture_error)
+    };
+
+    let size = core.width.max(core.height) as f32;
+    let candidates: Vec<(i32, i32)> = [1.2f32, 1.6, 2.2, 3.0, 4.0]
+        .iter()
+        .flat_map(|&distance| {
+            (0..24).map(move |step| {
+                let angle = step as f32 / 24.0 * std::f32::consts::TAU;
+                ((angle.cos() * size * distance).round() as i32, (angle.sin() * size * distance).round() as i32)
+            })
+        })
+        .collect();
+    let (mut best, mut best_score) = candidates
+        .par_iter()
+        .filter_map(|&(dx, dy)| score(dx, dy).map(|s| ((dx, dy), s)))
+        .min_by(|a, b| a.1.total_cmp(&b.1))?;
+
+    for step in [4, 2, 1] {
+        for (ox, oy) in [(-step, 0), (step, 0), (0, -step), (0, step)] {
+            if let Some(s) = score(best.0 + ox, best.1 + oy) {
+                if s < best_score {
+                    best = (best.0 + ox, best.1 + oy);
+                    best_score = s;
+                }
+            }
+        }
+    }
+
+    Some(RetouchPoint { x: best.0 as f32 / width as f32, y: best.1 as f32 / height as f32 })
+}
+
+fn mean_of(values: &[[f32; 3]], indices: &[usize]) -> [f32; 3] {
+    let mut sum = [0.0f32; 3];
+    for &i in indices {
+        for k in 0..3 {
+            sum[k] += values[i][k];
+        }
+    }
+    sum.map(|s| s / indices.len().max(1) as f32)
+}
+
+fn squared_distance(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)
+}
+
+fn fill(image: &mut Rgba32FImage, fp: &Footprint, opacity: f32) {
+    let (width, height) = image.dimensions();
+    let context = (fp.width.max(fp.height) as i32).max(8 * PATCH_RADIUS);
+    let wx0 = (fp.x0 - context).max(0);
+    let wy0 = (fp.y0 - context).max(0);
+    let wx1 = (fp.x0 + fp.width as i32 + context).min(width as i32);
+    let wy1 = (fp.y0 + fp.height as i32 + context).min(height as i32);
+    let (ww, wh) = ((wx1 - wx0) as usize, (wy1 - wy0) as usize);
+
+    let window = read_region(image, wx0, wy0, ww, wh);
+    let mut hole = vec![false; ww * wh];
+    for y in 0..fp.height {
+        for x in 0..fp.width {
+            if fp.alpha[y * fp.width + x] > 0.0 {
+                let (wx, wy) = ((fp.x0 - wx0) as usize + x, (fp.y0 - wy0) as usize + y);
+                hole[wy * ww + wx] = true;
+            }
+        }
+    }
+
+    let filled = inpaint(&window, &hole, ww, wh);
+    let ox = (fp.x0 - wx0) as usize;
+    let oy = (fp.y0 - wy0) as usize;
+    let mut original = Vec::with_capacity(fp.width * fp.height);
+    let mut result = Vec::with_capacity(fp.width * fp.height);
+    for y in 0..fp.height {
+        for x in 0..fp.width {
+            let i = (oy + y) * ww + ox + x;
+            original.push(window[i]);
+            result.push(filled[i]);
+        }
+    }
+    write_footprint(image, fp, &original, &result, opacity);
+}
+
+struct Level {
+    pixels: Vec<[f32; 3]>,
+    hole: Vec<bool>,
+    width: usize,
+    height: usize,
+}
+
+impl Level {
+    fn downsample(&self) -> Level {
+        let (width, height) = (self.width.div_ceil(2), self.height.div_ceil(2));
+        let mut pixels = vec![[0.0f32; 3]; width * height];
+        let mut hole = vec![false; width * height];
+        let mut counts = vec![0u32; width * height];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let (i, c) = (y * self.width + x, (y / 2) * width + x / 2);
+                if self.hole[i] {
+                    hole[c] = true;
+                } else {
+                    for (sum, v) in pixels[c].iter_mut().zip(&self.pixels[i]) {
+                        *sum += v;
+                    }
+                    counts[c] += 1;
+                }
+            }
+        }
+        for (p, &n) in pixels.iter_mut().zip(&counts) {
+            if n > 0 {
+                p.iter_mut().for_each(|v| *v /= n as f32);
+            }
+        }
+        Level { pixels, hole, width, height }
+    }
+}
+
+/// Small deterministic generator so fills render identically every time.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn range(&mut self, lo: i32, hi: i32) -> i32 {
+        lo + (self.next() % (hi - lo + 1) as u64) as i32
+    }
+}
+
+/// Multi-scale PatchMatch inpainting (Wexler et al. with Barnes et al.'s
+/// nearest-neighbour search). Returns the window with its hole filled.
+fn inpaint(pixels: &[[f32; 3]], hole: &[bool], width: usize, height: usize) -> Vec<[f32; 3]> {
+    let mut levels = vec![Level { pixels: pixels.to_vec(), hole: hole.to_vec(), width, height }];
+    while levels.len() < 8 {
+        let last = levels.last().unwrap();
+        if last.width.min(last.height) < (8 * PATCH_RADIUS) as usize {
+            break;
+        }
+        let next = last.downsample();
+        levels.push(next);
+    }
+
+    let mut rng = XorShift(0x9E37_79B9_7F4A_7C15);
+    let mut estimate: Vec<[f32; 3]> = Vec::new();
+    let mut nnf: Vec<(i32, i32)> = Vec::new();
+    let mut previous_width = 0;
+
+    for level in levels.iter().rev() {
+        let (w, h) = (level.width, level.height);
+        let valid = valid_sources(&level.hole, w, h);
+        let valid_list: Vec<(i32, i32)> = (0..w * h)
+            .filter(|&i| valid[i])
+            .map(|i| ((i % w) as i32, (i / w) as i32))
+            .collect();
+
+        let mut current = level.pixels.clone();
+        if estimate.is_empty() {
+            let fixed: Vec<bool> = level.hole.iter().map(|&h| !h).collect();
+            solve_membrane(&mut current, &fixed, w, h);
+        } else {
+            for y in 0..h {
+                for x in 0..w {
+                    if level.hole[y * w + x] {
+                        current[y * w + x] = estimate[(y / 2) * previous_width + x / 2];
+                    }
+                }
+            }
+        }
+        if valid_list.is_empty() {
+            estimate = current;
+            nnf = Vec::new();
+            previous_width = w;
+            continue;
+        }
+
+        let targets = dilate(&level.hole, w, h, PATCH_RADIUS);
+        let mut next_nnf = vec![(0i32, 0i32); w * h];
+        for &t in &targets {
+            let (x, y) = ((t % w) as i32, (t / w) as i32);
+            let upsampled = if nnf.is_empty() {
+                None
+            } else {
+                let (qx, qy) = nnf[(y as usize / 2) * previous_width + x as usize / 2];
+                Some((qx * 2 + x % 2, qy * 2 + y % 2))
+            };
+            next_nnf[t] = match upsampled {
+                Some((qx, qy)) if is_valid(&valid, w, h, qx, qy) => (qx, qy),
+                _ => valid_list[rng.range(0, valid_list.len() as i32 - 1) as usize],
+            };
+        }
+        nnf = next_nnf;
+
+        for _ in 0..EM_ITERATIONS {
+            let distances = patch_match(&current, &valid, &targets, &mut nnf, w, h, &mut rng);
+            vote(&mut current, &level.hole, &targets, &nnf, &distances, w, h);
+        }
+        estimate = current;
+        previous_width = w;
+    }
+
+    estimate
+}
+
+/// Pixels whose whole patch lies inside the window and outside the hole.
+fn valid_sources(hole: &[bool], width: usize, height: usize) -> Vec<bool> {
+    let near_hole = dilate(hole, width, height, PATCH_RADIUS);
+    let mut blocked = vec![false; width * height];
+    for i in near_hole {
+        blocked[i] = true;
+    }
+    let r = PATCH_RADIUS as usize;
+    (0..width * height)
+        .map(|i| {
+            let (x, y) = (i % width, i / width);
+            x >= r && y >= r && x + r < width && y + r < height && !blocked[i]
+        })
+        .collect()
+}
+
+fn is_valid(valid: &[bool], width: usize, height: usize, x: i32, y: i32) -> bool {
+    x >= 0 && y >= 0 && (x as usize) < width && (y as usize) < height && valid[y as usize * width + x as usize]
+}
+
+/// Indices of all pixels within `radius` (square) of a set pixel.
+fn dilate(mask: &[bool], width: usize, height: usize, radius: i32) -> Vec<usize> {
+    let mut out = vec![false; width * height];
+    for y in 0..height as i32 {
+        for x in 0..width as i32 {
+            if !mask[y as usize * width + x as usize] {
+                continue;
+            }
+            for ny in (y - radius).max(0)..=(y + radius).min(height as i32 - 1) {
+                for nx in (x - radius).max(0)..=(x + radius).min(width as i32 - 1) {
+                    out[ny as usize * width + nx as usize] = true;
+                }
+            }
+        }
+    }
+    (0..width * height).filter(|&i| out[i]).collect()
+}
+
+fn patch_distance(pixels: &[[f32; 3]], width: usize, height: usize, p: (i32, i32), q: (i32, i32), limit: f32) -> f32 {
+    let mut sum = 0.0;
+    for dy in -PATCH_RADIUS..=PATCH_RADIUS {
+        let py = p.1 + dy;
+        if py < 0 || py >= height as i32 {
+            continue;
+        }
+        for dx in -PATCH_RADIUS..=PATCH_RADIUS {
+            let px = p.0 + dx;
+            if px < 0 || px >= width as i32 {
+                continue;
+            }
+            let a = &pixels[py as usize * width + px as usize];
+            let b = &pixels[(q.1 + dy) as usize * width + (q.0 + dx) as usize];
+            sum += squared_distance(a, b);
+        }
+        if sum >= limit {
+            return sum;
+        }
+    }
+    sum
+}
+
+fn patch_match(
+    pixels: &[[f32; 3]],
+    valid: &[bool],
+    targets: &[usize],
+    nnf: &mut [(i32, i32)],
+    width: usize,
+    height: usize,
+    rng: &mut XorShift,
+) -> Vec<f32> {
+    let mut is_target = vec![false; width * height];
+    for &t in targets {
+        is_target[t] = true;
+    }
+    let mut distances = vec![f32::MAX; width * height];
+    for &t in targets {
+        let p = ((t % width) as i32, (t / width) as i32);
+        distances[t] = patch_distance(pixels, width, height, p, nnf[t], f32::MAX);
+    }
+
+    for iteration in 0..PATCHMATCH_ITERATIONS {
+        let forward = iteration % 2 == 0;
+        let step: i32 = if forward { 1 } else { -1 };
+        let order: Box<dyn Iterator<Item = &usize>> =
+            if forward { Box::new(targets.iter()) } else { Box::new(targets.iter().rev()) };
+
+        for &t in order {
+            let p = ((t % width) as i32, (t / width) as i32);
+            let try_candidate = |q: (i32, i32), nnf: &mut [(i32, i32)], distances: &mut [f32]| {
+                if !is_valid(valid, width, height, q.0, q.1) || q == nnf[t] {
+                    return;
+                }
+                let d = patch_distance(pixels, width, height, p, q, distances[t]);
+                if d < distances[t] {
+                    distances[t] = d;
+                    nnf[t] = q;
+                }
+            };
+
+            for (nx, ny) in [(p.0 - step, p.1), (p.0, p.1 - step)] {
+                if nx < 0 || ny < 0 || nx >= width as i32 || ny >= height as i32 {
+                    continue;
+                }
+                let n = ny as usize * width + nx as usize;
+                if is_target[n] {
+                    let (qx, qy) = nnf[n];
+                    try_candidate((qx + p.0 - nx, qy + p.1 - ny), nnf, &mut distances);
+                }
+            }
+
+            let mut radius = width.max(height) as i32;
+            while radius >= 1 {
+                let (bx, by) = nnf[t];
+                let q = (bx + rng.range(-radius, radius), by + rng.range(-radius, radius));
+                try_candidate(q, nnf, &mut distances);
+                radius /= 2;
+            }
+        }
+    }
+
+    distances
+}
+
+/// Sets each hole pixel to the similarity-weighted average of the source
+/// pixels that the overlapping patches map it to.
+fn vote(
+    pixels: &mut [[f32; 3]],
+    hole: &[bool],
+    targets: &[usize],
+    nnf: &[(i32, i32)],
+    distances: &[f32],
+    width: usize,
+    height: usize,
+) {
+    let mut sorted: Vec<f32> = targets.iter().map(|&t| distances[t]).collect();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let sigma_sq = sorted.get(sorted.len() * 3 / 4).copied().unwrap_or(1.0).max(1e-6);
+
+    let mut is_target = vec![false; width * height];
+    for &t in targets {
+        is_target[t] = true;
+    }
+
+    let updates: Vec<(usize, [f32; 3])> = (0..width * height)
+        .into_par_iter()
+        .filter(|&i| hole[i])
+        .map(|i| {
+            let (x, y) = ((i % width) as i32, (i / width) as i32);
+            let mut sum = [0.0f32; 3];
+            let mut weight_sum = 0.0f32;
+            for dy in -PATCH_RADIUS..=PATCH_RADIUS {
+                for dx in -PATCH_RADIUS..=PATCH_RADIUS {
+                    let (px, py) = (x - dx, y - dy);
+                    if px < 0 || py < 0 || px >= width as i32 || py >= height as i32 {
+                        continue;
+                    }
+                    let p = py as usize * width + px as usize;
+                    if !is_target[p] {
+                        continue;
+                    }
+                    let (qx, qy) = nnf[p];
+                    let source = pixels[(qy + dy) as usize * width + (qx + dx) as usize];
+                    let weight = (-distances[p] / (2.0 * sigma_sq)).exp() + 1e-8;
+                    for k in 0..3 {
+                        sum[k] += source[k] * weight;
+                    }
+                    weight_sum += weight;
+                }
+            }
+            (i, if weight_sum > 0.0 { sum.map(|s| s / weight_sum) } else { pixels[i] })
+        })
+        .collect();
+
+    for (i, value) in updates {
+        pixels[i] = value;
+    }
+}
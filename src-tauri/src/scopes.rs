@@ -0,0 +1,186 @@
+use std::fs;
+use std::sync::Arc;
+
+use image::{imageops::FilterType, DynamicImage, GenericImageView};
+use serde::{Deserialize, Serialize};
+
+use crate::file_management::load_settings;
+use crate::formats::is_raw_file;
+use crate::image_processing::{calculate_waveform_with_size, WaveformData};
+use crate::raw_processing::{calculate_raw_histogram, RawHistogramData};
+use crate::AppState;
+
+pub const DEFAULT_SCOPE_RESOLUTION: u32 = 256;
+
+/// Scopes are read from a copy of the preview whose long edge is this many
+/// times the scope resolution, which keeps every bin well populated.
+const SCOPE_SAMPLE_FACTOR: u32 = 2;
+
+/// Direction of the skin tone line, counterclockwise from the +Cb axis. Skin
+/// of every complexion falls close to it, whatever its brightness.
+const SKIN_LINE_ANGLE_DEGREES: f32 = 123.0;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ScopeKind {
+    Vectorscope,
+    Parade,
+    RawHistogram,
+}
+
+/// Scopes the frontend currently shows. Only these are computed after each
+/// preview render, and the raw histogram is kept until another file loads.
+#[derive(Default)]
+pub struct ScopeState {
+    pub enabled: Vec<ScopeKind>,
+    raw_histogram: Option<(String, RawHistogramData)>,
+    last: Option<ScopeCache>,
+}
+
+/// Scopes already computed for a preview, so enabling another scope or
+/// changing the selection only computes what is missing.
+struct ScopeCache {
+    preview: Arc<DynamicImage>,
+    resolution: u32,
+    data: ScopesData,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct VectorscopeData {
+    /// Counts in a `size` by `size` grid, Cb to the right and Cr upwards,
+    /// with neutral colors in the center.
+    pub data: Vec<u32>,
+    pub size: u32,
+    pub skin_line_angle: f32,
+}
+
+#[derive(Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ScopesData {
+    pub vectorscope: Option<VectorscopeData>,
+    /// Per-channel waveforms, shown side by side as a parade.
+    pub parade: Option<WaveformData>,
+    pub raw_histogram: Option<RawHistogramData>,
+}
+
+pub fn calculate_vectorscope(image: &DynamicImage, size: u32) -> VectorscopeData {
+    let size = size.max(2);
+    let mut data = vec![0u32; (size * size) as usize];
+    let max = (size - 1) as f32;
+
+    for pixel in image.to_rgb8().pixels() {
+        let r = pixel[0] as f32 / 255.0;
+        let g = pixel[1] as f32 / 255.0;
+        let b = pixel[2] as f32 / 255.0;
+        // BT.709 color difference signals, both in -0.5..0.5.
+        let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        let cb = (b - y) / 1.8556;
+        let cr = (r - y) / 1.5748;
+        let x = ((cb + 0.5) * max).round().clamp(0.0, max) as u32;
+        let y = ((0.5 - cr) * max).round().clamp(0.0, max) as u32;
+        data[(y * size + x) as usize] += 1;
+    }
+
+    VectorscopeData { data, size, skin_line_angle: SKIN_LINE_ANGLE_DEGREES }
+}
+
+fn scope_resolution(app_handle: &tauri::AppHandle) -> u32 {
+    load_settings(app_handle.clone())
+        .unwrap_or_default()
+        .scope_resolution
+        .unwrap_or(DEFAULT_SCOPE_RESOLUTION)
+        .clamp(64, 1024)
+}
+
+fn raw_histogram(state: &AppState, bins: u32) -> Option<RawHistogramData> {
+    let path = state.original_image.lock().unwrap().as_ref().map(|loaded| loaded.path.clone())?;
+    if !is_raw_file(&path) {
+        return None;
+    }
+
+    if let Some((cached_path, histogram)) = &state.scopes.lock().unwrap().raw_histogram {
+        if *cached_path == path && histogram.channels.first().is_some_and(|c| c.counts.len() == bins as usize) {
+            return Some(histogram.clone());
+        }
+    }
+
+    let histogram = fs::read(&path)
+        .map_err(anyhow::Error::from)
+        .and_then(|bytes| calculate_raw_histogram(&bytes, bins as usize))
+        .map_err(|e| eprintln!("Failed to calculate raw histogram for {}: {}", path, e))
+        .ok()?;
+    state.scopes.lock().unwrap().raw_histogram = Some((path, histogram.clone()));
+    Some(histogram)
+}
+
+/// The preview reduced to what a scope of `resolution` can show.
+fn scope_sample(preview: &DynamicImage, resolution: u32) -> DynamicImage {
+    let max_dim = resolution * SCOPE_SAMPLE_FACTOR;
+    let (width, height) = preview.dimensions();
+    if width.max(height) <= max_dim {
+        return preview.clone();
+    }
+    preview.resize(max_dim, max_dim, FilterType::Triangle)
+}
+
+/// Computes the enabled scopes from a rendered preview. An edit usually
+/// changes every pixel, so a new preview is always measured in full, but
+/// from a copy reduced to the scope resolution. Scopes already computed for
+/// the same preview are reused.
+pub fn calculate_scopes(state: &AppState, app_handle: &tauri::AppHandle, preview: Option<&Arc<DynamicImage>>) -> ScopesData {
+    let resolution = scope_resolution(app_handle);
+    let (enabled, mut scopes) = {
+        let scope_state = state.scopes.lock().unwrap();
+        let cached = scope_state.last.as_ref().filter(|last| {
+            last.resolution == resolution && preview.is_some_and(|p| Arc::ptr_eq(p, &last.preview))
+        });
+        (scope_state.enabled.clone(), cached.map(|last| last.data.clone()).unwrap_or_default())
+    };
+
+    let mut sample = None;
+    for kind in &enabled {
+        match (kind, preview) {
+            (ScopeKind::Vectorscope, Some(image)) if scopes.vectorscope.is_none() => {
+                let sample = sample.get_or_insert_with(|| scope_sample(image, resolution));
+                scopes.vectorscope = Some(calculate_vectorscope(sample, resolution));
+            }
+            (ScopeKind::Parade, Some(image)) if scopes.parade.is_none() => {
+                let sample = sample.get_or_insert_with(|| scope_sample(image, resolution));
+                scopes.parade = calculate_waveform_with_size(sample, resolution, resolution).ok();
+            }
+            (ScopeKind::RawHistogram, _) => {
+                scopes.raw_histogram = raw_histogram(state, resolution);
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(preview) = preview {
+        state.scopes.lock().unwrap().last = Some(ScopeCache { preview: preview.clone(), resolution, data: scopes.clone() });
+    }
+
+    // Only the enabled scopes are sent.
+    ScopesData {
+        vectorscope: scopes.vectorscope.filter(|_| enabled.contains(&ScopeKind::Vectorscope)),
+        parade: scopes.parade.filter(|_| enabled.contains(&ScopeKind::Parade)),
+        raw_histogram: scopes.raw_histogram.filter(|_| enabled.contains(&ScopeKind::RawHistogram)),
+    }
+}
+
+pub fn has_enabled_scopes(state: &AppState) -> bool {
+    !state.scopes.lock().unwrap().enabled.is_empty()
+}
+
+/// Selects the scopes to keep up to date and returns them for the current
+/// preview right away.
+#[tauri::command]
+pub fn set_active_scopes(
+    scopes: Vec<ScopeKind>,
+    state: tauri::State<AppState>,
+    app_handle: tauri::AppHandle,
+) -> Result<ScopesData, String> {
+    state.scopes.lock().unwrap().enabled = scopes;
+    let preview = state.processed_preview.lock().unwrap().clone();
+    Ok(calculate_scopes(&state, &app_handle, preview.as_ref()))
+}
@@ -0,0 +1,479 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+
+use anyhow::{anyhow, bail, Context, Result};
+use rawler::{
+    cfa::CFA,
+    decoders::RawDecodeParams,
+    exif::Exif,
+    imgop::sensor::calibration::SensorCalibration,
+    rawimage::{RawImage, RawPhotometricInterpretation},
+    rawsource::RawSource,
+};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager};
+use uuid::Uuid;
+
+/// Hot pixels must exceed the median dark level by this many robust
+/// standard deviations, and by at least `HOT_PIXEL_MIN_ADU`.
+const HOT_PIXEL_SIGMA: f32 = 12.0;
+const HOT_PIXEL_MIN_ADU: f32 = 8.0;
+/// Never map more than this fraction of the sensor as defective.
+const MAX_HOT_PIXEL_FRACTION: f64 = 0.001;
+/// Exposure times within this ratio are considered equal when matching darks.
+const EXPOSURE_MATCH_RATIO: f32 = 1.5;
+
+static CALIBRATION_DIR: OnceLock<PathBuf> = OnceLock::new();
+static LOADED_FRAMES: OnceLock<Mutex<HashMap<String, Arc<LoadedFrame>>>> = OnceLock::new();
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum CalibrationKind {
+    Dark,
+    Flat,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CalibrationFrameInfo {
+    pub id: String,
+    pub kind: CalibrationKind,
+    pub make: String,
+    pub model: String,
+    pub serial: Option<String>,
+    pub iso: Option<u32>,
+    pub exposure_time: Option<f32>,
+    pub fnumber: Option<f32>,
+    pub lens_model: Option<String>,
+    pub width: usize,
+    pub height: usize,
+    pub frame_count: usize,
+    pub hot_pixel_count: usize,
+    pub created: String,
+}
+
+struct LoadedFrame {
+    data: Arc<Vec<f32>>,
+    hot_pixels: Arc<Vec<u32>>,
+}
+
+/// Shooting conditions of a raw file, used to build and match calibration frames.
+struct FrameConditions {
+    make: String,
+    model: String,
+    serial: Option<String>,
+    iso: Option<u32>,
+    exposure_time: Option<f32>,
+    fnumber: Option<f32>,
+    lens_model: Option<String>,
+    width: usize,
+    height: usize,
+}
+
+impl FrameConditions {
+    fn new(raw_image: &RawImage, exif: &Exif) -> Self {
+        let non_empty = |s: &Option<String>| s.as_ref().map(|s| s.trim().to_string()).filter(|s| !s.is_empty());
+        Self {
+            make: raw_image.clean_make.clone(),
+            model: raw_image.clean_model.clone(),
+            serial: non_empty(&exif.serial_number),
+            iso: exif.iso_speed.or(exif.iso_speed_ratings.map(u32::from)),
+            exposure_time: exif.exposure_time.filter(|r| r.d != 0).map(|r| r.as_f32()),
+            fnumber: exif.fnumber.filter(|r| r.d != 0).map(|r| r.as_f32()),
+            lens_model: non_empty(&exif.lens_model),
+            width: raw_image.width,
+            height: raw_image.height,
+        }
+    }
+
+    fn same_camera(&self, info: &CalibrationFrameInfo) -> bool {
+        let serial_matches = match (&self.serial, &info.serial) {
+            (Some(a), Some(b)) => a == b,
+            _ => true,
+        };
+        self.make == info.make
+            && self.model == info.model
+            && self.width == info.width
+            && self.height == info.height
+            && serial_matches
+    }
+
+    fn same_exposure(&self, info: &CalibrationFrameInfo) -> bool {
+        let iso_matches = match (self.iso, info.iso) {
+            (Some(a), Some(b)) => a == b,
+            _ => true,
+        };
+        let exposure_matches = match (self.exposure_time, info.exposure_time) {
+            (Some(a), Some(b)) if a > 0.0 && b > 0.0 => (a / b).max(b / a) <= EXPOSURE_MATCH_RATIO,
+            _ => true,
+        };
+        iso_matches && exposure_matches
+    }
+}
+
+pub fn init_calibration_dir(app_handle: &AppHandle) -> Result<(), String> {
+    let dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| e.to_string())?
+        .join("calibration");
+
+    if !dir.exists() {
+        fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    }
+
+    let _ = CALIBRATION_DIR.set(dir);
+    Ok(())
+}
+
+fn calibration_dir() -> Result<&'static Path> {
+    CALIBRATION_DIR
+        .get()
+        .map(PathBuf::as_path)
+        .ok_or_else(|| anyhow!("Calibration directory is not initialized"))
+}
+
+fn loaded_frames() -> &'static Mutex<HashMap<String, Arc<LoadedFrame>>> {
+    LOADED_FRAMES.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn read_infos(dir: &Path) -> Vec<CalibrationFrameInfo> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().and_then(|ext| ext.to_str()) == Some("json"))
+        .filter_map(|path| {
+            let content = fs::read_to_string(&path).ok()?;
+            match serde_json::from_str(&content) {
+                Ok(info) => Some(info),
+                Err(e) => {
+                    eprintln!("Skipping unreadable calibration frame {}: {}", path.display(), e);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+fn write_f32s(path: &Path, values: &[f32]) -> Result<()> {
+    let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+    fs::write(path, bytes).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn read_f32s(path: &Path) -> Result<Vec<f32>> {
+    let bytes = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect())
+}
+
+fn load_frame(dir: &Path, info: &CalibrationFrameInfo) -> Result<Arc<LoadedFrame>> {
+    if let Some(frame) = loaded_frames().lock().unwrap().get(&info.id) {
+        return Ok(frame.clone());
+    }
+
+    let data = read_f32s(&dir.join(format!("{}.bin", info.id)))?;
+    if data.len() != info.width * info.height {
+        bail!("Calibration frame {} is truncated", info.id);
+    }
+    let hot_path = dir.join(format!("{}.hot", info.id));
+    let hot_pixels = if hot_path.exists() {
+        let bytes = fs::read(&hot_path)?;
+        bytes
+            .chunks_exact(4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let frame = Arc::new(LoadedFrame {
+        data: Arc::new(data),
+        hot_pixels: Arc::new(hot_pixels),
+    });
+    loaded_frames().lock().unwrap().insert(info.id.clone(), frame.clone());
+    Ok(frame)
+}
+
+fn find_dark<'a>(infos: &'a [CalibrationFrameInfo], conditions: &FrameConditions) -> Option<&'a CalibrationFrameInfo> {
+    infos
+        .iter()
+        .filter(|info| info.kind == CalibrationKind::Dark)
+        .filter(|info| conditions.same_camera(info) && conditions.same_exposure(info))
+        .min_by(|a, b| {
+            let distance = |info: &CalibrationFrameInfo| match (conditions.exposure_time, info.exposure_time) {
+                (Some(x), Some(y)) if x > 0.0 && y > 0.0 => (x / y).ln().abs(),
+                _ => f32::MAX,
+            };
+            distance(a).total_cmp(&distance(b))
+        })
+}
+
+fn find_flat<'a>(infos: &'a [CalibrationFrameInfo], conditions: &FrameConditions) -> Option<&'a CalibrationFrameInfo> {
+    infos
+        .iter()
+        .filter(|info| info.kind == CalibrationKind::Flat && conditions.same_camera(info))
+        .filter(|info| match (&conditions.lens_model, &info.lens_model) {
+            (Some(a), Some(b)) => a == b,
+            _ => true,
+        })
+        .min_by(|a, b| {
+            let distance = |info: &CalibrationFrameInfo| match (conditions.fnumber, info.fnumber) {
+                (Some(x), Some(y)) if x > 0.0 && y > 0.0 => (x / y).ln().abs(),
+                _ => f32::MAX,
+            };
+            distance(a).total_cmp(&distance(b))
+        })
+}
+
+/// Looks up stored dark and flat frames matching the camera and shooting
+/// conditions of `raw_image`.
+pub fn find_calibration(raw_image: &RawImage, exif: &Exif) -> Option<SensorCalibration> {
+    let dir = calibration_dir().ok()?;
+    let infos = read_infos(dir);
+    if infos.is_empty() {
+        return None;
+    }
+
+    let conditions = FrameConditions::new(raw_image, exif);
+    let load = |info: Option<&CalibrationFrameInfo>| {
+        info.and_then(|info| match load_frame(dir, info) {
+            Ok(frame) => Some(frame),
+            Err(e) => {
+                eprintln!("Failed to load calibration frame {}: {}", info.id, e);
+                None
+            }
+        })
+    };
+    let dark = load(find_dark(&infos, &conditions));
+    let flat = load(find_flat(&infos, &conditions));
+
+    let calibration = SensorCalibration {
+        dark_frame: dark.as_ref().map(|frame| frame.data.clone()),
+        flat_field: flat.as_ref().map(|frame| frame.data.clone()),
+        hot_pixels: dark.map(|frame| frame.hot_pixels.clone()).unwrap_or_default(),
+    };
+    (!calibration.is_empty()).then_some(calibration)
+}
+
+fn decode_raw(path: &str) -> Result<(RawImage, Exif)> {
+    let file_bytes = fs::read(path).with_context(|| format!("Failed to read {}", path))?;
+    let source = RawSource::new_from_slice(&file_bytes);
+    let decoder = rawler::get_decoder(&source)?;
+    let raw_image = decoder.raw_image(&source, &RawDecodeParams::default(), false)?;
+    let metadata = decoder.raw_metadata(&source, &RawDecodeParams::default())?;
+    Ok((raw_image, metadata.exif))
+}
+
+fn cfa_of(raw_image: &RawImage) -> Result<CFA> {
+    match &raw_image.photometric {
+        RawPhotometricInterpretation::Cfa(config) if raw_image.cpp == 1 => Ok(config.cfa.clone()),
+        _ => bail!("Calibration frames are only supported for color filter array sensors"),
+    }
+}
+
+/// Averages the frames in ADU above black level.
+fn average_frames(
+    paths: &[String],
+    mut on_frame: impl FnMut(usize),
+) -> Result<(Vec<f32>, FrameConditions, CFA)> {
+    let mut sum: Vec<f64> = Vec::new();
+    let mut first: Option<(FrameConditions, CFA)> = None;
+
+    for (i, path) in paths.iter().enumerate() {
+        on_frame(i);
+        let (raw_image, exif) = decode_raw(path)?;
+        let cfa = cfa_of(&raw_image)?;
+        let conditions = FrameConditions::new(&raw_image, &exif);
+
+        match &first {
+            None => {
+                sum = vec![0.0; raw_image.width * raw_image.height];
+            }
+            Some((reference, _)) => {
+                if conditions.make != reference.make
+                    || conditions.model != reference.model
+                    || conditions.width != reference.width
+                    || conditions.height != reference.height
+                {
+                    bail!("{} was taken with a different camera", path);
+                }
+                if conditions.iso != reference.iso {
+                    bail!("{} has a different ISO than the other frames", path);
+                }
+            }
+        }
+
+        let black = raw_image.blacklevel.as_bayer_array();
+        let width = raw_image.width;
+        for (index, (acc, value)) in sum.iter_mut().zip(raw_image.data.as_f32().iter()).enumerate() {
+            let (row, col) = (index / width, index % width);
+            *acc += (value - black[(row % 2) * 2 + col % 2]) as f64;
+        }
+
+        if first.is_none() {
+            first = Some((conditions, cfa));
+        }
+    }
+
+    let (conditions, cfa) = first.ok_or_else(|| anyhow!("No frames given"))?;
+    let count = paths.len() as f64;
+    Ok((sum.iter().map(|v| (v / count) as f32).collect(), conditions, cfa))
+}
+
+/// Pixels that stand out from the dark level, sorted by index.
+fn detect_hot_pixels(dark: &[f32]) -> Vec<u32> {
+    let step = (dark.len() / 1_000_000).max(1);
+    let mut sample: Vec<f32> = dark.iter().step_by(step).copied().collect();
+    if sample.is_empty() {
+        return Vec::new();
+    }
+
+    let mid = sample.len() / 2;
+    let median = *sample.select_nth_unstable_by(mid, f32::total_cmp).1;
+    let mut deviations: Vec<f32> = sample.iter().map(|v| (v - median).abs()).collect();
+    let mad = *deviations.select_nth_unstable_by(mid, f32::total_cmp).1;
+
+    let percentile_index = (((sample.len() as f64) * (1.0 - MAX_HOT_PIXEL_FRACTION)) as usize).min(sample.len() - 1);
+    let percentile = *sample.select_nth_unstable_by(percentile_index, f32::total_cmp).1;
+    let threshold = (median + (HOT_PIXEL_SIGMA * 1.4826 * mad).max(HOT_PIXEL_MIN_ADU)).max(percentile);
+
+    dark.iter()
+        .enumerate()
+        .filter(|(_, &v)| v > threshold)
+        .map(|(i, _)| i as u32)
+        .collect()
+}
+
+/// Normalizes the flat so that each CFA color averages to 1.0.
+fn normalize_flat(flat: &mut [f32], width: usize, cfa: &CFA) -> Result<()> {
+    let mut sums: HashMap<usize, (f64, usize)> = HashMap::new();
+    for (index, value) in flat.iter().enumerate() {
+        let entry = sums.entry(cfa.color_at(index / width, index % width)).or_default();
+        entry.0 += *value as f64;
+        entry.1 += 1;
+    }
+
+    let means: HashMap<usize, f32> = sums
+        .into_iter()
+        .map(|(color, (sum, count))| (color, (sum / count.max(1) as f64) as f32))
+        .collect();
+    if means.values().any(|&mean| mean < 1.0) {
+        bail!("Flat frames are too dark to be used");
+    }
+
+    for (index, value) in flat.iter_mut().enumerate() {
+        *value /= means[&cfa.color_at(index / width, index % width)];
+    }
+    Ok(())
+}
+
+fn build_frame(
+    paths: &[String],
+    kind: CalibrationKind,
+    app_handle: &AppHandle,
+) -> Result<CalibrationFrameInfo> {
+    let dir = calibration_dir()?;
+    let total = paths.len();
+    let emit_progress = |stage: &str, current: usize| {
+        let _ = app_handle.emit(
+            "calibration-progress",
+            serde_json::json!({ "stage": stage, "current": current, "total": total }),
+        );
+    };
+
+    let (mut data, conditions, cfa) = average_frames(paths, |i| emit_progress("reading", i))?;
+    emit_progress("analyzing", total);
+
+    let hot_pixels = match kind {
+        CalibrationKind::Dark => detect_hot_pixels(&data),
+        CalibrationKind::Flat => {
+            let infos = read_infos(dir);
+            if let Some(dark) = find_dark(&infos, &conditions) {
+                let dark = load_frame(dir, dark)?;
+                data.iter_mut().zip(dark.data.iter()).for_each(|(v, d)| *v -= d);
+            }
+            normalize_flat(&mut data, conditions.width, &cfa)?;
+            Vec::new()
+        }
+    };
+
+    let info = CalibrationFrameInfo {
+        id: Uuid::new_v4().to_string(),
+        kind,
+        make: conditions.make,
+        model: conditions.model,
+        serial: conditions.serial,
+        iso: conditions.iso,
+        exposure_time: conditions.exposure_time,
+        fnumber: conditions.fnumber,
+        lens_model: conditions.lens_model,
+        width: conditions.width,
+        height: conditions.height,
+        frame_count: total,
+        hot_pixel_count: hot_pixels.len(),
+        created: chrono::Local::now().to_rfc3339(),
+    };
+
+    write_f32s(&dir.join(format!("{}.bin", info.id)), &data)?;
+    if !hot_pixels.is_empty() {
+        let bytes: Vec<u8> = hot_pixels.iter().flat_map(|v| v.to_le_bytes()).collect();
+        fs::write(dir.join(format!("{}.hot", info.id)), bytes)?;
+    }
+    let json = serde_json::to_string_pretty(&info)?;
+    fs::write(dir.join(format!("{}.json", info.id)), json)?;
+
+    Ok(info)
+}
+
+#[tauri::command]
+pub fn create_calibration_frame(
+    paths: Vec<String>,
+    kind: CalibrationKind,
+    app_handle: AppHandle,
+) -> Result<(), String> {
+    if paths.is_empty() {
+        return Err("At least one frame is needed.".to_string());
+    }
+
+    thread::spawn(move || match build_frame(&paths, kind, &app_handle) {
+        Ok(info) => {
+            let _ = app_handle.emit("calibration-complete", info);
+        }
+        Err(e) => {
+            let _ = app_handle.emit("calibration-error", e.to_string());
+        }
+    });
+
+    Ok(())
+}
+
+#[tauri::command]
+pub fn list_calibration_frames() -> Result<Vec<CalibrationFrameInfo>, String> {
+    let dir = calibration_dir().map_err(|e| e.to_string())?;
+    let mut infos = read_infos(dir);
+    infos.sort_by(|a, b| b.created.cmp(&a.created));
+    Ok(infos)
+}
+
+#[tauri::command]
+pub fn delete_calibration_frame(id: String) -> Result<(), String> {
+    if Uuid::parse_str(&id).is_err() {
+        return Err("Invalid calibration frame id".to_string());
+    }
+    let dir = calibration_dir().map_err(|e| e.to_string())?;
+    for extension in ["json", "bin", "hot"] {
+        let path = dir.join(format!("{}.{}", id, extension));
+        if path.exists() {
+            fs::remove_file(&path).map_err(|e| e.to_string())?;
+        }
+    }
+    loaded_frames().lock().unwrap().remove(&id);
+    Ok(())
+}
@@ -0,0 +1,383 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+
+use anyhow::{bail, Context, Result};
+use image::{ImageFormat, Rgb32FImage};
+use ndarray::{Array, ArrayD};
+use ort::{Environment, SessionBuilder, Value as OrtValue};
+use serde::Serialize;
+use serde_json::Value;
+use tauri::Emitter;
+
+use crate::file_management::{get_sidecar_path, unique_sibling_path};
+use crate::formats::is_raw_file;
+use crate::image_loader::{composite_patches_on_image, load_base_image_from_bytes};
+use crate::image_processing::ImageMetadata;
+use crate::model_registry::{ensure_model, SUPER_RESOLUTION_X2, SUPER_RESOLUTION_X4};
+use crate::raw_processing::RawDevelopSettings;
+
+/// Used when the model does not declare a fixed input size.
+const DEFAULT_TILE_SIZE: u32 = 192;
+/// Input pixels shared by neighbouring tiles, blended to hide seams.
+const TILE_OVERLAP: u32 = 16;
+const MAX_OUTPUT_PIXELS: u64 = 600_000_000;
+/// Values below this reach the model unchanged; highlights above it are
+/// compressed into the rest of the model's 0..1 range.
+const HEADROOM_KNEE: f32 = 0.8;
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct EnhanceResult {
+    pub path: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Evenly spaced tile origins covering `length` with at least `overlap`
+/// pixels shared between neighbours.
+fn tile_starts(length: u32, tile: u32, overlap: u32) -> Vec<u32> {
+    if length <= tile {
+        return vec![0];
+    }
+    let step = (tile - overlap).max(1);
+    let count = (length - overlap).div_ceil(step);
+    let span = (length - tile) as f64;
+    (0..count)
+        .map(|i| (span * i as f64 / (count - 1) as f64).round() as u32)
+        .collect()
+}
+
+/// Blend weight of a tile pixel: ramps up across edges shared with another
+/// tile and stays at one along the image border.
+fn edge_weight(position: u32, length: u32, ramp: f32, has_before: bool, has_after: bool) -> f32 {
+    let mut weight = 1.0f32;
+    if has_before {
+        weight = weight.min((position as f32 + 0.5) / ramp);
+    }
+    if has_after {
+        weight = weight.min((length - position) as f32 / ramp);
+    }
+    weight.clamp(1e-3, 1.0)
+}
+
+/// Linear compression of the range above `HEADROOM_KNEE` so that the
+/// brightest value of the image maps to 1.0. Raw files keep highlights above
+/// 1.0, which the model would otherwise clip; expanding its output restores
+/// them.
+#[derive(Clone, Copy)]
+struct Headroom {
+    peak: f32,
+}
+
+impl Headroom {
+    fn of(image: &Rgb32FImage) -> Self {
+        let peak = image.iter().copied().filter(|c| c.is_finite()).fold(1.0f32, f32::max);
+        Self { peak }
+    }
+
+    fn compress(&self, value: f32) -> f32 {
+        let value = value.max(0.0);
+        if value <= HEADROOM_KNEE || self.peak <= 1.0 {
+            return value.min(1.0);
+        }
+        HEADROOM_KNEE + (value.min(self.peak) - HEADROOM_KNEE) * (1.0 - HEADROOM_KNEE) / (self.peak - HEADROOM_KNEE)
+    }
+
+    fn expand(&self, value: f32) -> f32 {
+        if value <= HEADROOM_KNEE || self.peak <= 1.0 {
+            return value.max(0.0);
+        }
+        HEADROOM_KNEE + (value - HEADROOM_KNEE) * (self.peak - HEADROOM_KNEE) / (1.0 - HEADROOM_KNEE)
+    }
+}
+
+/// Output rows that are still receiving contributions from tiles.
+struct Band {
+    y0: u32,
+    width: u32,
+    color: Vec<f32>,
+    weight: Vec<f32>,
+}
+
+impl Band {
+    fn extend_to(&mut self, y_end: u32) {
+        let rows = (y_end.saturating_sub(self.y0)) as usize;
+        let pixels = rows * self.width as usize;
+        if pixels > self.weight.len() {
+            self.color.resize(pixels * 3, 0.0);
+            self.weight.resize(pixels, 0.0);
+        }
+    }
+
+    /// Moves the finished rows above `y_end` into the output.
+    fn flush(&mut self, y_end: u32, headroom: Headroom, output: &mut Rgb32FImage) {
+        let rows = y_end.saturating_sub(self.y0);
+        let pixels = (rows * self.width) as usize;
+        let start = (self.y0 * self.width) as usize * 3;
+        let target = &mut output.as_mut()[start..start + pixels * 3];
+        for ((out, color), weight) in target
+            .chunks_exact_mut(3)
+            .zip(self.color.chunks_exact(3))
+            .zip(&self.weight)
+        {
+            let w = weight.max(1e-6);
+            for (o, c) in out.iter_mut().zip(color) {
+                *o = headroom.expand(c / w);
+            }
+        }
+        self.color.drain(..pixels * 3);
+        self.weight.drain(..pixels);
+        self.y0 = y_end;
+    }
+}
+
+/// Upscales the image tile by tile with `run_tile`, which maps a 1x3xHxW
+/// tensor to its upscaled counterpart, and blends the overlapping tiles.
+/// Tiles are padded to `fixed_size` when the model requires one.
+fn upscale_tiled(
+    image: &Rgb32FImage,
+    scale: u32,
+    fixed_size: (Option<u32>, Option<u32>),
+    mut run_tile: impl FnMut(ArrayD<f32>) -> Result<ArrayD<f32>>,
+    on_tile: impl Fn(usize, usize),
+) -> Result<Rgb32FImage> {
+    let (width, height) = image.dimensions();
+    let (out_width, out_height) = (width * scale, height * scale);
+    if out_width as u64 * out_height as u64 > MAX_OUTPUT_PIXELS {
+        bail!("The enhanced image would be {}x{}, which is too large.", out_width, out_height);
+    }
+
+    let (fixed_width, fixed_height) = fixed_size;
+    let tile_width = fixed_width.unwrap_or(DEFAULT_TILE_SIZE);
+    let tile_height = fixed_height.unwrap_or(DEFAULT_TILE_SIZE);
+    if tile_width <= TILE_OVERLAP * 2 || tile_height <= TILE_OVERLAP * 2 {
+        bail!("The model's input size {}x{} is too small for tiling.", tile_width, tile_height);
+    }
+
+    let rows = tile_starts(height, tile_height, TILE_OVERLAP);
+    let cols = tile_starts(width, tile_width, TILE_OVERLAP);
+    let total = rows.len() * cols.len();
+    let ramp = (TILE_OVERLAP * scale) as f32;
+
+    let headroom = Headroom::of(image);
+    let mut output = Rgb32FImage::new(out_width, out_height);
+    let mut band = Band { y0: 0, width: out_width, color: Vec::new(), weight: Vec::new() };
+
+    for (row_index, &ty) in rows.iter().enumerate() {
+        let th = tile_height.min(height - ty);
+        band.extend_to((ty + th) * scale);
+
+        for (col_index, &tx) in cols.iter().enumerate() {
+            on_tile(row_index * cols.len() + col_index, total);
+            let tw = tile_width.min(width - tx);
+            let input_width = fixed_width.unwrap_or(tw);
+            let input_height = fixed_height.unwrap_or(th);
+
+            let mut input = Array::<f32, _>::zeros((1, 3, input_height as usize, input_width as usize));
+            for y in 0..input_height {
+                let sy = ty + y.min(th - 1);
+                for x in 0..input_width {
+                    let pixel = image.get_pixel(tx + x.min(tw - 1), sy).0;
+                    for c in 0..3 {
+                        input[[0, c, y as usize, x as usize]] = headroom.compress(pixel[c]);
+                    }
+                }
+            }
+
+            let tensor = run_tile(input.into_dyn())?;
+            let shape = tensor.shape();
+            if shape.len() != 4 || shape[1] < 3
+                || shape[2] != (input_height * scale) as usize
+                || shape[3] != (input_width * scale) as usize
+            {
+                bail!("Unexpected super-resolution output shape {:?} for a {}x model", shape, scale);
+            }
+
+            let (ow, oh) = (tw * scale, th * scale);
+            let (ox, oy) = (tx * scale, ty * scale);
+            for y in 0..oh {
+                let wy = edge_weight(y, oh, ramp, ty > 0, ty + th < height);
+                let row_offset = ((oy + y - band.y0) * out_width + ox) as usize;
+                for x in 0..ow {
+                    let weight = wy * edge_weight(x, ow, ramp, tx > 0, tx + tw < width);
+                    let index = row_offset + x as usize;
+                    for c in 0..3 {
+                        band.color[index * 3 + c] += tensor[[0, c, y as usize, x as usize]] * weight;
+                    }
+                    band.weight[index] += weight;
+                }
+            }
+        }
+
+        let finished = rows.get(row_index + 1).map_or(out_height, |&next| next * scale);
+        band.flush(finished, headroom, &mut output);
+    }
+
+    Ok(output)
+}
+
+fn upscale(
+    image: &Rgb32FImage,
+    model_path: &Path,
+    scale: u32,
+    on_tile: impl Fn(usize, usize),
+) -> Result<Rgb32FImage> {
+    let environment = Arc::new(Environment::builder().with_name("SuperResolution").build()?);
+    let session = SessionBuilder::new(&environment)?.with_model_from_file(model_path)?;
+
+    let declared_size = |index: usize| {
+        session.inputs.first()
+            .and_then(|input| input.dimensions.get(index).copied().flatten())
+            .filter(|&size| size > 0)
+    };
+    let fixed_size = (declared_size(3), declared_size(2));
+
+    let run_tile = |input: ArrayD<f32>| -> Result<ArrayD<f32>> {
+        let input_values = input.as_standard_layout();
+        let outputs = session.run(vec![OrtValue::from_array(session.allocator(), &input_values)?])?;
+        let tensor = outputs[0].try_extract::<f32>()?.view().to_owned();
+        Ok(tensor.into_dyn())
+    };
+
+    upscale_tiled(image, scale, fixed_size, run_tile, on_tile)
+}
+
+/// Pixel positions in the adjustments (crop and mask geometry) are stored in
+/// full-image coordinates and have to follow the new resolution. AI mask
+/// bitmaps are kept at the original size, so they are cleared to be
+/// regenerated.
+fn scale_adjustments(adjustments: &mut Value, scale: f64) {
+    let scale_keys = |object: &mut Value, keys: &[&str]| {
+        for key in keys {
+            if let Some(v) = object.get(*key).and_then(Value::as_f64) {
+                object[*key] = Value::from(v * scale);
+            }
+        }
+    };
+    let scale_points = |points: Option<&mut Value>| {
+        for point in points.and_then(Value::as_array_mut).into_iter().flatten() {
+            for key in ["x", "y"] {
+                if let Some(v) = point.get(key).and_then(Value::as_f64) {
+                    point[key] = Value::from(v * scale);
+                }
+            }
+        }
+    };
+
+    if let Some(crop) = adjustments.get_mut("crop").filter(|c| c.is_object()) {
+        scale_keys(crop, &["x", "y", "width", "height"]);
+    }
+
+    let masks = adjustments.get_mut("masks").and_then(Value::as_array_mut);
+    let sub_masks = masks
+        .into_iter()
+        .flatten()
+        .filter_map(|mask| mask.get_mut("subMasks").and_then(Value::as_array_mut))
+        .flatten();
+    for sub_mask in sub_masks {
+        let mask_type = sub_mask.get("type").and_then(Value::as_str).unwrap_or_default().to_string();
+        let Some(params) = sub_mask.get_mut("parameters").filter(|p| p.is_object()) else {
+            continue;
+        };
+        match mask_type.as_str() {
+            "radial" => scale_keys(params, &["centerX", "centerY", "radiusX", "radiusY"]),
+            "linear" => scale_keys(params, &["startX", "startY", "endX", "endY", "range"]),
+            "brush" => {
+                for line in params.get_mut("lines").and_then(Value::as_array_mut).into_iter().flatten() {
+                    scale_keys(line, &["brushSize"]);
+                    scale_points(line.get_mut("points"));
+                }
+            }
+            "color-range" => scale_points(params.get_mut("samples")),
+            "ai-subject" => {
+                scale_keys(params, &["startX", "startY", "endX", "endY"]);
+                params["maskDataBase64"] = Value::Null;
+            }
+            "ai-foreground" => params["maskDataBase64"] = Value::Null,
+            "depth" => params["depthDataBase64"] = Value::Null,
+            _ => {}
+        }
+    }
+}
+
+fn enhance(path: &str, model_path: &Path, scale: u32, app_handle: &tauri::AppHandle) -> Result<EnhanceResult> {
+    let emit_progress = |stage: &str, current: usize, total: usize| {
+        let _ = app_handle.emit(
+            "enhance-resolution-progress",
+            serde_json::json!({ "stage": stage, "current": current, "total": total }),
+        );
+    };
+
+    emit_progress("loading", 0, 1);
+    let mut metadata: ImageMetadata = fs::read_to_string(get_sidecar_path(path))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default();
+
+    // Patches are baked into the enhanced pixels rather than carried over.
+    let file_bytes = fs::read(path)?;
+    let raw_settings = RawDevelopSettings::from_adjustments(&metadata.adjustments);
+    let base_image = load_base_image_from_bytes(&file_bytes, path, false, &raw_settings)?;
+    let source = composite_patches_on_image(&base_image, &metadata.adjustments)?.to_rgb32f();
+
+    let output = upscale(&source, model_path, scale, |tile, total| emit_progress("upscaling", tile, total))?;
+    let (width, height) = output.dimensions();
+
+    emit_progress("saving", 0, 1);
+    let output_path = unique_sibling_path(path, "Enhanced", "tif");
+    // A float TIFF keeps the restored highlights editable.
+    output
+        .save_with_format(&output_path, ImageFormat::Tiff)
+        .context("Failed to write enhanced image")?;
+
+    let output_path_str = output_path.to_string_lossy().into_owned();
+    if let Some(adjustments) = metadata.adjustments.as_object_mut() {
+        adjustments.remove("aiPatches");
+    }
+    // The TIFF is no longer a raw file, so the tone mapper raws get by default
+    // has to be stated explicitly.
+    if is_raw_file(path) {
+        if metadata.adjustments.is_null() {
+            metadata.adjustments = serde_json::json!({});
+        }
+        if metadata.adjustments.get("toneMapper").is_none() {
+            metadata.adjustments["toneMapper"] = Value::from("aces");
+        }
+    }
+    if !metadata.adjustments.is_null() {
+        scale_adjustments(&mut metadata.adjustments, scale as f64);
+        let json_string = serde_json::to_string_pretty(&metadata)?;
+        fs::write(get_sidecar_path(&output_path_str), json_string)?;
+    }
+
+    Ok(EnhanceResult { path: output_path_str, width, height })
+}
+
+/// Upscales an image 2x or 4x into a new `-Enhanced.tif` next to it, with
+/// the original's adjustments carried over.
+#[tauri::command]
+pub async fn enhance_resolution(
+    path: String,
+    scale: u32,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    let spec = match scale {
+        2 => SUPER_RESOLUTION_X2,
+        4 => SUPER_RESOLUTION_X4,
+        _ => return Err(format!("Unsupported enhance scale {}x.", scale)),
+    };
+    let model_path = ensure_model(&app_handle, &spec).await.map_err(|e| e.to_string())?;
+
+    thread::spawn(move || match enhance(&path, &model_path, scale, &app_handle) {
+        Ok(result) => {
+            let _ = app_handle.emit("enhance-resolution-complete", result);
+        }
+        Err(e) => {
+            let _ = app_handle.emit("enhance-resolution-error", e.to_string());
+        }
+    });
+
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use std::fs;
+
+use image::{imageops, DynamicImage};
+use serde_json::Value;
+
+use crate::formats::is_raw_file;
+use crate::raw_processing::{
+    develop_camera_rgb, raw_white_balance_info, white_balance_for_camera_color, RawDevelopSettings,
+    RawWhiteBalanceInfo, WhiteBalanceValue, WHITE_BALANCE_CLIP_LEVEL,
+};
+use crate::AppState;
+
+/// Half the side of the area the eyedropper averages, relative to the
+/// shorter image side.
+const PICK_RADIUS: f32 = 0.01;
+
+fn loaded_raw_image(state: &AppState) -> Result<(String, u32, u32), String> {
+    let (path, full_width, full_height) = state
+        .original_image
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|loaded| (loaded.path.clone(), loaded.full_width, loaded.full_height))
+        .ok_or("No image loaded")?;
+    if !is_raw_file(&path) {
+        return Err("Raw white balance is only available for raw files".to_string());
+    }
+    Ok((path, full_width, full_height))
+}
+
+/// As shot, auto and preset white balance of the loaded raw file.
+#[tauri::command]
+pub fn get_raw_white_balance(
+    js_adjustments: Value,
+    state: tauri::State<AppState>,
+) -> Result<RawWhiteBalanceInfo, String> {
+    let (path, _, _) = loaded_raw_image(&state)?;
+    let bytes = fs::read(&path).map_err(|e| e.to_string())?;
+    let settings = RawDevelopSettings::from_adjustments(&js_adjustments);
+    raw_white_balance_info(&bytes, &settings).map_err(|e| e.to_string())
+}
+
+/// White balance that renders the clicked area neutral. `x` and `y` are
+/// relative to the transformed image shown in the editor.
+#[tauri::command]
+pub fn pick_raw_white_balance(
+    x: f32,
+    y: f32,
+    js_adjustments: Value,
+    state: tauri::State<AppState>,
+) -> Result<WhiteBalanceValue, String> {
+    let (path, full_width, full_height) = loaded_raw_image(&state)?;
+    let bytes = fs::read(&path).map_err(|e| e.to_string())?;
+    let mut camera = develop_camera_rgb(&bytes).map_err(|e| e.to_string())?;
+
+    // Warp the camera image into the editor's geometry so crop, rotation
+    // and flips line up with the click.
+    let camera_image = std::mem::take(&mut camera.image);
+    let scale = camera_image.width() as f32 / full_width.max(1) as f32;
+    let scaled_width = ((full_width as f32 * scale).round() as u32).max(1);
+    let scaled_height = ((full_height as f32 * scale).round() as u32).max(1);
+    let scaled = if camera_image.dimensions() == (scaled_width, scaled_height) {
+        camera_image
+    } else {
+        imageops::resize(&camera_image, scaled_width, scaled_height, imageops::FilterType::Triangle)
+    };
+    let (transformed, _) = crate::apply_all_transformations(&DynamicImage::ImageRgb32F(scaled), &js_adjustments, scale);
+    let transformed = transformed.to_rgba32f();
+    let (width, height) = transformed.dimensions();
+    if width == 0 || height == 0 {
+        return Err("The image is empty".to_string());
+    }
+
+    let center_x = ((x.clamp(0.0, 1.0) * width as f32) as u32).min(width - 1);
+    let center_y = ((y.clamp(0.0, 1.0) * height as f32) as u32).min(height - 1);
+    let radius = ((width.min(height) as f32 * PICK_RADIUS).round() as u32).max(1);
+
+    let mut sum = [0.0f64; 3];
+    let mut count = 0usize;
+    for py in center_y.saturating_sub(radius)..=(center_y + radius).min(height - 1) {
+        for px in center_x.saturating_sub(radius)..=(center_x + radius).min(width - 1) {
+            let pixel = transformed.get_pixel(px, py).0;
+            // Skip the empty corners of a rotated image and clipped photosites,
+            // whose ratios no longer describe the light.
+            if pixel[3] < 1.0 || pixel[..3].iter().any(|&c| c >= WHITE_BALANCE_CLIP_LEVEL) {
+                continue;
+            }
+            (0..3).for_each(|i| sum[i] += pixel[i] as f64);
+            count += 1;
+        }
+    }
+    if count == 0 {
+        return Err("The picked area is clipped".to_string());
+    }
+
+    let color = sum.map(|c| (c / count as f64) as f32);
+    let settings = RawDevelopSettings::from_adjustments(&js_adjustments);
+    white_balance_for_camera_color(&camera, &settings, color).map_err(|e| e.to_string())
+}
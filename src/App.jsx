@@ -6,7 +6,7 @@ import { homeDir } from '@tauri-apps/api/path';
 import debounce from 'lodash.debounce';
 import { centerCrop, makeAspectCrop } from 'react-image-crop';
 import clsx from 'clsx';
-import { Copy, ClipboardPaste, RotateCcw, Star, Trash2, Folder, Edit, Check, X, Undo, Redo, FolderPlus, FileEdit, CopyPlus, Aperture } from 'lucide-react';
+import { Copy, ClipboardPaste, RotateCcw, Star, Trash2, Folder, Edit, Check, X, Undo, Redo, FolderPlus, FileEdit, CopyPlus, Aperture, SunMedium, Images, Layers, SlidersHorizontal, ScanSearch, Maximize } from 'lucide-react';
 import TitleBar from './window/TitleBar';
 import MainLibrary from './components/panel/MainLibrary';
 import FolderTree from './components/panel/FolderTree';
@@ -26,6 +26,11 @@ import { ContextMenuProvider, useContextMenu } from './context/ContextMenuContex
 import CreateFolderModal from './components/modals/CreateFolderModal';
 import RenameFolderModal from './components/modals/RenameFolderModal';
 import ConfirmModal from './components/modals/ConfirmModal';
+import PanoramaModal from './components/modals/PanoramaModal';
+import FocusStackModal from './components/modals/FocusStackModal';
+import CalibrationFrameModal from './components/modals/CalibrationFrameModal';
+import DustDetectionModal from './components/modals/DustDetectionModal';
+import EnhanceResolutionModal from './components/modals/EnhanceResolutionModal';
 import { useHistoryState } from './hooks/useHistoryState';
 import Resizer from './components/ui/Resizer';
 import { INITIAL_ADJUSTMENTS, COPYABLE_ADJUSTMENT_KEYS, normalizeLoadedAdjustments } from './utils/adjustments';
@@ -66,6 +71,8 @@ function App() {
   const [histogram, setHistogram] = useState(null);
   const [waveform, setWaveform] = useState(null);
   const [isWaveformVisible, setIsWaveformVisible] = useState(false);
+  const [scopes, setScopes] = useState(null);
+  const [activeScope, setActiveScope] = useState(null);
   const [uiVisibility, setUiVisibility] = useState({
     folderTree: true,
     filmstrip: true,
@@ -94,11 +101,16 @@ function App() {
   const [copiedMask, setCopiedMask] = useState(null);
   const [isCopied, setIsCopied] = useState(false);
   const [isPasted, setIsPasted] = useState(false);
-  const [brushSettings, setBrushSettings] = useState({ size: 50, feather: 50, tool: 'brush' });
+  const [brushSettings, setBrushSettings] = useState({ size: 50, feather: 50, tool: 'brush', smart: false });
   const [isCreateFolderModalOpen, setIsCreateFolderModalOpen] = useState(false);
   const [isRenameFolderModalOpen, setIsRenameFolderModalOpen] = useState(false);
   const [folderActionTarget, setFolderActionTarget] = useState(null);
   const [confirmModalState, setConfirmModalState] = useState({ isOpen: false });
+  const [panoramaModalState, setPanoramaModalState] = useState({ isOpen: false, paths: [] });
+  const [focusStackModalState, setFocusStackModalState] = useState({ isOpen: false, paths: [] });
+  const [calibrationFrameModalState, setCalibrationFrameModalState] = useState({ isOpen: false, paths: [], kind: 'dark' });
+  const [dustDetectionModalState, setDustDetectionModalState] = useState({ isOpen: false, paths: [] });
+  const [enhanceResolutionModalState, setEnhanceResolutionModalState] = useState({ isOpen: false, path: null });
   const [customEscapeHandler, setCustomEscapeHandler] = useState(null);
   const [isGeneratingAiMask, setIsGeneratingAiMask] = useState(false);
   const [isComfyUiConnected, setIsComfyUiConnected] = useState(false);
@@ -106,6 +118,7 @@ function App() {
   const [aiTool, setAiTool] = useState(null);
   const [pendingAiAction, setPendingAiAction] = useState(null);
   const [isMaskControlHovered, setIsMaskControlHovered] = useState(false);
+  const [isWhiteBalancePicking, setIsWhiteBalancePicking] = useState(false);
   const { showContextMenu } = useContextMenu();
   const imagePathList = useMemo(() => imageList.map(f => f.path), [imageList]);
   const { thumbnails } = useThumbnails(imagePathList);
@@ -200,7 +213,7 @@ function App() {
     setPendingAiAction(null);
 
     try {
-      const newPatchBase64 = await invoke('invoke_generative_replace', {
+      const variants = await invoke('invoke_generative_replace', {
         path: selectedImage.path,
         maskDataBase64,
         prompt,
@@ -213,7 +226,9 @@ function App() {
           p.id === tempId 
             ? { 
                 ...p, 
-                patchDataBase64: newPatchBase64,
+                patchDataBase64: variants[0],
+                variants,
+                selectedVariant: 0,
                 maskDataBase64,
                 isLoading: false,
               } 
@@ -232,6 +247,37 @@ function App() {
     }
   }, [selectedImage?.path, isGeneratingAi, adjustments, setAdjustments]);
 
+  // Heal and clone layers get their source picked once, on the full image,
+  // so preview and export copy the same area.
+  const resolvingRetouchIds = useRef(new Set());
+  useEffect(() => {
+    if (!selectedImage?.isReady) return;
+    const patches = adjustments.aiPatches || [];
+    patches.forEach((patch, index) => {
+      const retouch = patch.retouch;
+      if (!retouch || retouch.mode === 'fill' || retouch.sourceOffset || retouch.space === 'sensor') return;
+      if (resolvingRetouchIds.current.has(patch.id)) return;
+      resolvingRetouchIds.current.add(patch.id);
+
+      const updateRetouch = (changes) => setAdjustments(prev => ({
+        ...prev,
+        aiPatches: (prev.aiPatches || []).map(p =>
+          p.id === patch.id ? { ...p, retouch: { ...p.retouch, ...changes } } : p
+        ),
+      }));
+      invoke('find_retouch_source', {
+        retouch,
+        currentAdjustments: { ...adjustments, aiPatches: patches.slice(0, index) },
+      })
+        .then(sourceOffset => updateRetouch({ sourceOffset }))
+        .catch(err => {
+          console.error("Failed to find a retouch source:", err);
+          updateRetouch({ mode: 'fill' });
+        })
+        .finally(() => resolvingRetouchIds.current.delete(patch.id));
+    });
+  }, [adjustments.aiPatches, selectedImage?.isReady]);
+
   const handleResetAiEdits = useCallback(() => {
     if (!adjustments?.aiPatches?.length > 0 || isGeneratingAi) return;
     setAdjustments(prev => ({ ...prev, aiPatches: [] }));
@@ -322,6 +368,40 @@ function App() {
     }
   };
 
+  const handleGenerateAiDepthMask = async (subMaskId) => {
+    if (!selectedImage?.path) {
+      console.error("Cannot generate AI mask: No image selected.");
+      return;
+    }
+    setIsGeneratingAiMask(true);
+    try {
+      const newParameters = await invoke('generate_ai_depth_mask', {
+        path: selectedImage.path,
+        rotation: adjustments.rotation,
+        flipHorizontal: adjustments.flipHorizontal,
+        flipVertical: adjustments.flipVertical,
+      });
+
+      setAdjustments(prev => ({
+        ...prev,
+        masks: prev.masks.map(container => ({
+          ...container,
+          subMasks: container.subMasks.map(sm =>
+            sm.id === subMaskId
+              ? { ...sm, parameters: { ...newParameters, near: sm.parameters.near, far: sm.parameters.far, feather: sm.parameters.feather } }
+              : sm
+          )
+        }))
+      }));
+
+    } catch (error) {
+      console.error("Failed to generate AI depth mask:", error);
+      setError(`AI Mask Failed: ${error}`);
+    } finally {
+      setIsGeneratingAiMask(false);
+    }
+  };
+
   const sortedImageList = useMemo(() => {
     const filteredList = imageList.filter(image => {
       if (filterCriteria.rating > 0) {
@@ -643,6 +723,7 @@ function App() {
     setHistogram(null);
     setWaveform(null);
     setIsWaveformVisible(false);
+    setScopes(null);
     setActiveMaskId(null);
     setActiveMaskContainerId(null);
     setAiTool(null);
@@ -702,8 +783,12 @@ function App() {
     const generate = async () => {
       setIsFullScreenLoading(true);
       try {
-        const url = await invoke('generate_fullscreen_preview', { jsAdjustments: adjustments });
-        setFullScreenUrl(url);
+        const result = await invoke('render_viewport', {
+          jsAdjustments: adjustments,
+          viewport: { x: 0, y: 0, width: 1, height: 1 },
+          displayWidth: Math.round(window.innerWidth * window.devicePixelRatio),
+        });
+        setFullScreenUrl(result.imageBase64);
       } catch (e) {
         console.error("Failed to generate fullscreen preview:", e);
         setError("Failed to generate full screen preview.");
@@ -739,10 +824,46 @@ function App() {
     setIsPasted(true);
   }, [copiedAdjustments, multiSelectedPaths, selectedImage, setAdjustments]);
 
+  // Dust spots are in sensor space; the backend orients them and picks a heal
+  // source per file, so the sidecars are reloaded for the open images.
+  const handleApplyDustSpots = useCallback((paths, spots) => {
+    const aiPatches = spots.map(spot => ({
+      id: uuidv4(),
+      prompt: 'Dust Spot',
+      visible: true,
+      retouch: spot.retouch,
+    }));
+    invoke('apply_adjustments_to_paths', { paths, adjustments: { aiPatches } })
+      .then(() => {
+        if (selectedImage && paths.includes(selectedImage.path)) {
+          invoke('load_metadata', { path: selectedImage.path })
+            .then(metadata => {
+              if (metadata.adjustments && !metadata.adjustments.is_null) {
+                const normalized = normalizeLoadedAdjustments(metadata.adjustments);
+                setLiveAdjustments(normalized);
+                resetAdjustmentsHistory(normalized);
+              }
+            });
+        }
+        if (libraryActivePath && paths.includes(libraryActivePath)) {
+          invoke('load_metadata', { path: libraryActivePath })
+            .then(metadata => {
+              if (metadata.adjustments && !metadata.adjustments.is_null) {
+                setLibraryActiveAdjustments(normalizeLoadedAdjustments(metadata.adjustments));
+              }
+            });
+        }
+      })
+      .catch(err => {
+        console.error("Failed to apply dust spots:", err);
+        setError(`Failed to apply dust spots: ${err}`);
+      });
+  }, [selectedImage, libraryActivePath, setLiveAdjustments, resetAdjustmentsHistory]);
+
   const handleAutoAdjustments = async () => {
     if (!selectedImage) return;
     try {
-      const autoAdjustments = await invoke('calculate_auto_adjustments');
+      const { adjustments: autoAdjustments } = await invoke('calculate_auto_adjustments');
       setAdjustments(prev => {
         const newAdjustments = { ...prev, ...autoAdjustments };
         newAdjustments.sectionVisibility = {
@@ -758,6 +879,22 @@ function App() {
     }
   };
 
+  const handleWhiteBalancePick = async (x, y) => {
+    setIsWhiteBalancePicking(false);
+    try {
+      const { temperature, tint } = await invoke('pick_raw_white_balance', { x, y, jsAdjustments: adjustments });
+      setAdjustments(prev => ({
+        ...prev,
+        rawWhiteBalance: 'custom',
+        rawTemperature: Math.round(temperature),
+        rawTint: Math.round(tint),
+      }));
+    } catch (err) {
+      console.error("Failed to pick white balance:", err);
+      setError(`Failed to pick white balance: ${err}`);
+    }
+  };
+
   const handleRate = useCallback((newRating) => {
     const pathsToRate = multiSelectedPaths.length > 0 ? multiSelectedPaths : (selectedImage ? [selectedImage.path] : []);
     if (pathsToRate.length === 0) return;
@@ -832,6 +969,7 @@ function App() {
     setFinalPreviewUrl(null);
     setUncroppedAdjustedPreviewUrl(null);
     setFullScreenUrl(null);
+    setIsWhiteBalancePicking(false);
     setLiveAdjustments(INITIAL_ADJUSTMENTS);
     resetAdjustmentsHistory(INITIAL_ADJUSTMENTS);
     setShowOriginal(false);
@@ -887,6 +1025,7 @@ function App() {
       listen('preview-update-uncropped', (event) => { if (isEffectActive) setUncroppedAdjustedPreviewUrl(event.payload); }),
       listen('histogram-update', (event) => { if (isEffectActive) setHistogram(event.payload); }),
       listen('waveform-update', (event) => { if (isEffectActive) setWaveform(event.payload); }),
+      listen('scopes-update', (event) => { if (isEffectActive) setScopes(event.payload); }),
       listen('thumbnail-generated', (event) => { if (isEffectActive) { const { path, rating } = event.payload; if (rating !== undefined) setImageRatings(prev => ({ ...prev, [path]: rating })); } }),
       listen('ai-model-download-start', (event) => { if (isEffectActive) setAiModelDownloadStatus(event.payload); }),
       listen('ai-model-download-finish', () => { if (isEffectActive) setAiModelDownloadStatus(null); }),
@@ -1060,6 +1199,13 @@ function App() {
     }
   }, [isWaveformVisible, selectedImage?.isReady, waveform]);
 
+  useEffect(() => {
+    const enabled = isWaveformVisible && activeScope && selectedImage?.isReady ? [activeScope] : [];
+    invoke('set_active_scopes', { scopes: enabled })
+      .then(setScopes)
+      .catch(err => console.error("Failed to update scopes:", err));
+  }, [isWaveformVisible, activeScope, selectedImage?.path, selectedImage?.isReady]);
+
   useEffect(() => {
     if (selectedImage && !selectedImage.isReady && selectedImage.path) {
       let isEffectActive = true;
@@ -1147,10 +1293,10 @@ function App() {
     const copyLabel = isSingleSelection ? 'Copy Image' : `Copy ${selectionCount} Images`;
     const autoAdjustLabel = isSingleSelection ? 'Auto Adjust Image' : `Auto Adjust ${selectionCount} Images`;
 
-    const handleApplyAutoAdjustmentsToSelection = () => {
+    const handleApplyAutoAdjustmentsToSelection = (referencePath = null) => {
       if (finalSelection.length === 0) return;
 
-      invoke('apply_auto_adjustments_to_paths', { paths: finalSelection })
+      invoke('apply_auto_adjustments_to_paths', { paths: finalSelection, referencePath })
         .then(() => {
           if (selectedImage && finalSelection.includes(selectedImage.path)) {
             invoke('load_metadata', { path: selectedImage.path })
@@ -1188,11 +1334,22 @@ function App() {
         },
       },
       { label: pasteLabel, icon: ClipboardPaste, disabled: copiedAdjustments === null, onClick: handlePasteAdjustments },
-      { label: autoAdjustLabel, icon: Aperture, onClick: handleApplyAutoAdjustmentsToSelection },
+      { label: autoAdjustLabel, icon: Aperture, onClick: () => handleApplyAutoAdjustmentsToSelection() },
+      ...(!isSingleSelection ? [{ label: 'Match Exposure to This Image', icon: SunMedium, onClick: () => handleApplyAutoAdjustmentsToSelection(path) }] : []),
       { type: 'separator' },
       { label: copyLabel, icon: Copy, onClick: () => { setCopiedFilePaths(finalSelection); setIsCopied(true); } },
       { label: 'Duplicate Image', icon: CopyPlus, disabled: !isSingleSelection, onClick: async () => { try { await invoke('duplicate_file', { path: finalSelection[0] }); handleLibraryRefresh(); } catch (err) { console.error("Failed to duplicate file:", err); setError(`Failed to duplicate file: ${err}`); } } },
       { type: 'separator' },
+      { label: 'Stitch Panorama', icon: Images, disabled: finalSelection.length < 2, onClick: () => setPanoramaModalState({ isOpen: true, paths: finalSelection }) },
+      { label: 'Focus Stack', icon: Layers, disabled: finalSelection.length < 2, onClick: () => setFocusStackModalState({ isOpen: true, paths: finalSelection }) },
+      { label: 'Enhance Resolution', icon: Maximize, disabled: !isSingleSelection, onClick: () => setEnhanceResolutionModalState({ isOpen: true, path: finalSelection[0] }) },
+      { label: 'Detect Dust Spots', icon: ScanSearch, onClick: () => setDustDetectionModalState({ isOpen: true, paths: finalSelection }) },
+      { label: 'Create Calibration Frame', icon: SlidersHorizontal, submenu: [
+          { label: 'Dark Frame', onClick: () => setCalibrationFrameModalState({ isOpen: true, paths: finalSelection, kind: 'dark' }) },
+          { label: 'Flat Field', onClick: () => setCalibrationFrameModalState({ isOpen: true, paths: finalSelection, kind: 'flat' }) },
+        ],
+      },
+      { type: 'separator' },
       { label: 'Set Rating', icon: Star, submenu: [0, 1, 2, 3, 4, 5].map(rating => ({ label: rating === 0 ? 'No Rating' : `${rating} Star${rating !== 1 ? 's' : ''}`, onClick: () => handleRate(rating) })) },
       { type: 'separator' },
       { label: 'Show in File Explorer', icon: Folder, disabled: !isSingleSelection, onClick: () => { invoke('show_in_finder', { path: finalSelection[0] }).catch(err => setError(`Could not show file in explorer: ${err}`)); } },
@@ -1297,13 +1454,6 @@ function App() {
     showContextMenu(event.clientX, event.clientY, options);
   };
 
-  const handleAppliedLutImage = (imageUrl) => {
-    console.log("App jsx: LUT applied to image:", imageUrl);
-    if (!selectedImage) return;
-    setSelectedImage(prev => ({ ...prev, originalUrl: imageUrl }));
-    setFinalPreviewUrl(imageUrl);
-  }
-
   const renderMainView = () => {
     if (selectedImage) {
       return (
@@ -1317,6 +1467,8 @@ function App() {
               setShowOriginal={setShowOriginal}
               isAdjusting={isAdjusting}
               waveform={waveform}
+              scopes={scopes}
+              onActiveScopeChange={setActiveScope}
               isWaveformVisible={isWaveformVisible}
               onCloseWaveform={() => setIsWaveformVisible(false)}
               onBackToLibrary={handleBackToLibrary}
@@ -1347,6 +1499,8 @@ function App() {
               aiTool={aiTool}
               onAiMaskDrawingComplete={handleAiMaskDrawingComplete}
               isMaskControlHovered={isMaskControlHovered}
+              isWhiteBalancePicking={isWhiteBalancePicking}
+              onWhiteBalancePick={handleWhiteBalancePick}
             />
             <Resizer onMouseDown={createResizeHandler(setBottomPanelHeight, bottomPanelHeight)} direction="horizontal" />
             <BottomBar
@@ -1386,7 +1540,7 @@ function App() {
               style={{ width: activeRightPanel ? `${rightPanelWidth}px` : '0px' }}
             >
               <div style={{ width: `${rightPanelWidth}px` }} className="h-full">
-                {renderedRightPanel === 'adjustments' && <Controls theme={theme} adjustments={adjustments} setAdjustments={setAdjustments} selectedImage={selectedImage} histogram={histogram} collapsibleState={collapsibleSectionsState} setCollapsibleState={setCollapsibleSectionsState} copiedSectionAdjustments={copiedSectionAdjustments} setCopiedSectionAdjustments={setCopiedSectionAdjustments} handleAutoAdjustments={handleAutoAdjustments} />}
+                {renderedRightPanel === 'adjustments' && <Controls theme={theme} adjustments={adjustments} setAdjustments={setAdjustments} selectedImage={selectedImage} histogram={histogram} collapsibleState={collapsibleSectionsState} setCollapsibleState={setCollapsibleSectionsState} copiedSectionAdjustments={copiedSectionAdjustments} setCopiedSectionAdjustments={setCopiedSectionAdjustments} handleAutoAdjustments={handleAutoAdjustments} isWhiteBalancePicking={isWhiteBalancePicking} setIsWhiteBalancePicking={setIsWhiteBalancePicking} />}
                 {renderedRightPanel === 'metadata' && <MetadataPanel selectedImage={selectedImage} />}
                 {renderedRightPanel === 'crop' && <CropPanel selectedImage={selectedImage} adjustments={adjustments} setAdjustments={setAdjustments} />}
                 {renderedRightPanel === 'masks' && <MasksPanel 
@@ -1405,7 +1559,8 @@ function App() {
                   histogram={histogram} 
                   isGeneratingAiMask={isGeneratingAiMask} 
                   aiModelDownloadStatus={aiModelDownloadStatus} 
-                  onGenerateAiForegroundMask={handleGenerateAiForegroundMask} 
+                  onGenerateAiForegroundMask={handleGenerateAiForegroundMask}
+                  onGenerateAiDepthMask={handleGenerateAiDepthMask} 
                   setIsMaskControlHovered={setIsMaskControlHovered}
                 />}
                 {renderedRightPanel === 'presets' && <PresetsPanel adjustments={adjustments} setAdjustments={setAdjustments} selectedImage={selectedImage} activePanel={activeRightPanel} />}
@@ -1425,10 +1580,9 @@ function App() {
                   onTogglePatchVisibility={handleToggleAiPatchVisibility}
                 />}
                 { renderedRightPanel === 'lut' && <LutPanel
-                    selectedImage={selectedImage}
+                    adjustments={adjustments}
+                    setAdjustments={setAdjustments}
                     activePanel={activeRightPanel}
-                    rootPath={rootPath}
-                    setFinalPreviewUrl={handleAppliedLutImage}
                   />
                 }
               </div>
@@ -1563,6 +1717,30 @@ function App() {
         {...confirmModalState}
         onClose={closeConfirmModal}
       />
+      <PanoramaModal
+        {...panoramaModalState}
+        onClose={() => setPanoramaModalState(prev => ({ ...prev, isOpen: false }))}
+        onComplete={handleLibraryRefresh}
+      />
+      <FocusStackModal
+        {...focusStackModalState}
+        onClose={() => setFocusStackModalState(prev => ({ ...prev, isOpen: false }))}
+        onComplete={handleLibraryRefresh}
+      />
+      <CalibrationFrameModal
+        {...calibrationFrameModalState}
+        onClose={() => setCalibrationFrameModalState(prev => ({ ...prev, isOpen: false }))}
+      />
+      <DustDetectionModal
+        {...dustDetectionModalState}
+        onClose={() => setDustDetectionModalState(prev => ({ ...prev, isOpen: false }))}
+        onApply={(spots) => handleApplyDustSpots(dustDetectionModalState.paths, spots)}
+      />
+      <EnhanceResolutionModal
+        {...enhanceResolutionModalState}
+        onClose={() => setEnhanceResolutionModalState(prev => ({ ...prev, isOpen: false }))}
+        onComplete={handleLibraryRefresh}
+      />
     </div>
   );
 }
@@ -1,11 +1,17 @@
 import Slider from '../ui/Slider';
 
-export default function BasicAdjustments({ adjustments, setAdjustments }) {
+export default function BasicAdjustments({ adjustments, setAdjustments, isForMask = false }) {
   const handleAdjustmentChange = (key, value) => {
     const numericValue = parseFloat(value);
     setAdjustments(prev => ({ ...prev, [key]: numericValue }));
   };
 
+  // An empty value leaves the choice to the backend: ACES for raw files,
+  // none for everything else.
+  const handleToneMapperChange = (value) => {
+    setAdjustments(prev => ({ ...prev, toneMapper: value || null }));
+  };
+
   return (
     <div>
       <Slider
@@ -56,6 +62,50 @@ export default function BasicAdjustments({ adjustments, setAdjustments }) {
         max="100"
         step="1"
       />
+      {!isForMask && (
+        <div className="mt-4 p-2 bg-bg-tertiary rounded-md">
+          <p className="text-md font-semibold mb-2 text-primary">Tone Mapping</p>
+          <select
+            value={adjustments.toneMapper || ''}
+            onChange={(e) => handleToneMapperChange(e.target.value)}
+            className="w-full bg-bg-primary border border-surface rounded-md p-2 text-sm text-text-primary focus:ring-accent focus:border-accent"
+          >
+            <option value="">Default</option>
+            <option value="none">None (Clip)</option>
+            <option value="aces">ACES</option>
+            <option value="filmic">Filmic</option>
+            <option value="agx">AgX</option>
+          </select>
+          {adjustments.toneMapper === 'filmic' && (
+            <div className="space-y-2 mt-2 pt-2 border-t border-bg-secondary">
+              <Slider
+                label="White Exposure"
+                value={adjustments.filmicWhiteExposure ?? 4}
+                onChange={(e) => handleAdjustmentChange('filmicWhiteExposure', e.target.value)}
+                min="1"
+                max="10"
+                step="0.1"
+              />
+              <Slider
+                label="Black Exposure"
+                value={adjustments.filmicBlackExposure ?? -8}
+                onChange={(e) => handleAdjustmentChange('filmicBlackExposure', e.target.value)}
+                min="-14"
+                max="-2"
+                step="0.1"
+              />
+              <Slider
+                label="Contrast"
+                value={adjustments.filmicContrast ?? 1.6}
+                onChange={(e) => handleAdjustmentChange('filmicContrast', e.target.value)}
+                min="0.5"
+                max="3"
+                step="0.05"
+              />
+            </div>
+          )}
+        </div>
+      )}
     </div>
   );
 }
\ No newline at end of file
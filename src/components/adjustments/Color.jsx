@@ -1,6 +1,10 @@
-import { useState } from 'react';
+import { useState, useEffect } from 'react';
+import { invoke } from '@tauri-apps/api/core';
+import { open } from '@tauri-apps/plugin-dialog';
+import { Pipette, Plus, Target } from 'lucide-react';
 import Slider from '../ui/Slider';
 import ColorWheel from '../ui/ColorWheel';
+import CalibrateProfileModal from '../modals/CalibrateProfileModal';
 import { INITIAL_ADJUSTMENTS } from '../../utils/adjustments';
 
 const ColorSwatch = ({ color, name, isActive, onClick }) => (
@@ -85,6 +89,167 @@ const ColorGradingPanel = ({ adjustments, setAdjustments }) => {
     );
 };
 
+// Interpolates the raw file's two calibration matrices by white balance.
+const EMBEDDED_PROFILE = 'embedded';
+
+const CameraProfilePanel = ({ adjustments, setAdjustments, selectedImage }) => {
+    const [profiles, setProfiles] = useState([]);
+    const [isCalibrating, setIsCalibrating] = useState(false);
+
+    const loadProfiles = () => {
+        invoke('list_camera_profiles')
+            .then(setProfiles)
+            .catch(err => console.error("Failed to list camera profiles:", err));
+    };
+
+    useEffect(loadProfiles, []);
+
+    const handleImport = async () => {
+        const filePath = await open({
+            title: 'Import Camera Profile',
+            multiple: false,
+            filters: [{ name: 'DNG Camera Profile', extensions: ['dcp'] }],
+        });
+        if (!filePath) return;
+        try {
+            const profile = await invoke('import_camera_profile', { filePath });
+            loadProfiles();
+            setAdjustments(prev => ({ ...prev, cameraProfile: profile.path }));
+        } catch (err) {
+            console.error("Failed to import camera profile:", err);
+        }
+    };
+
+    const cameraModel = selectedImage?.exif?.Model?.replace(/"/g, '').trim();
+    const isForCamera = (profile) => !profile.cameraModel || !cameraModel
+        || profile.cameraModel.toLowerCase().includes(cameraModel.toLowerCase());
+    const current = adjustments.cameraProfile || '';
+    const listed = profiles.filter(p => isForCamera(p) || p.path === current);
+    const isKnown = current === EMBEDDED_PROFILE || listed.some(p => p.path === current);
+    const hasToneCurve = profiles.some(p => p.path === current && p.hasToneCurve);
+
+    return (
+        <div>
+            <div className="flex items-center gap-2">
+                <select
+                    value={current}
+                    onChange={(e) => setAdjustments(prev => ({ ...prev, cameraProfile: e.target.value || null }))}
+                    className="flex-grow min-w-0 bg-bg-primary border border-surface rounded-md p-2 text-sm text-text-primary focus:ring-accent focus:border-accent"
+                >
+                    <option value="">Embedded Matrix</option>
+                    <option value={EMBEDDED_PROFILE}>Embedded Dual Illuminant</option>
+                    {listed.map(p => <option key={p.path} value={p.path}>{p.name}</option>)}
+                    {current && !isKnown && (
+                        <option value={current}>Missing Profile</option>
+                    )}
+                </select>
+                <button
+                    onClick={handleImport}
+                    className="p-2 rounded-md hover:bg-surface transition-colors"
+                    title="Import Camera Profile"
+                >
+                    <Plus size={16} />
+                </button>
+                <button
+                    onClick={() => setIsCalibrating(true)}
+                    className="p-2 rounded-md hover:bg-surface transition-colors"
+                    title="Calibrate from Color Chart"
+                >
+                    <Target size={16} />
+                </button>
+            </div>
+            <CalibrateProfileModal
+                isOpen={isCalibrating}
+                onClose={() => setIsCalibrating(false)}
+                selectedImage={selectedImage}
+                onComplete={(calibration) => {
+                    loadProfiles();
+                    setAdjustments(prev => ({ ...prev, cameraProfile: calibration.profile.path }));
+                }}
+            />
+            {hasToneCurve && (
+                <p className="text-xs text-text-secondary mt-2">
+                    This profile has its own tone curve, so the tone mapper is turned off.
+                </p>
+            )}
+        </div>
+    );
+};
+
+const RawWhiteBalancePanel = ({ adjustments, setAdjustments, selectedImage, isWhiteBalancePicking, setIsWhiteBalancePicking }) => {
+    const [info, setInfo] = useState(null);
+
+    useEffect(() => {
+        if (!selectedImage?.isReady) return;
+        let cancelled = false;
+        invoke('get_raw_white_balance', { jsAdjustments: { cameraProfile: adjustments.cameraProfile } })
+            .then(result => { if (!cancelled) setInfo(result); })
+            .catch(err => console.error("Failed to read raw white balance:", err));
+        return () => { cancelled = true; };
+    }, [selectedImage?.path, selectedImage?.isReady, adjustments.cameraProfile]);
+
+    const mode = adjustments.rawWhiteBalance || 'asShot';
+    const preset = info?.presets.find(p => p.id === mode);
+    let current = info?.asShot;
+    if (mode === 'auto') current = info?.auto || info?.asShot;
+    else if (preset) current = preset;
+    else if (mode === 'custom' && adjustments.rawTemperature != null) {
+        current = { temperature: adjustments.rawTemperature, tint: adjustments.rawTint ?? 0 };
+    }
+
+    const handleModeChange = (value) => {
+        setAdjustments(prev => ({ ...prev, rawWhiteBalance: value, rawTemperature: null, rawTint: null }));
+    };
+
+    const handleValueChange = (key, value) => {
+        setAdjustments(prev => ({
+            ...prev,
+            rawWhiteBalance: 'custom',
+            rawTemperature: Math.round(current?.temperature ?? 5500),
+            rawTint: Math.round(current?.tint ?? 0),
+            [key]: parseFloat(value),
+        }));
+    };
+
+    return (
+        <div>
+            <div className="flex items-center gap-2 mb-2">
+                <select
+                    value={mode}
+                    onChange={(e) => handleModeChange(e.target.value)}
+                    className="flex-grow bg-bg-primary border border-surface rounded-md p-2 text-sm text-text-primary focus:ring-accent focus:border-accent"
+                >
+                    <option value="asShot">As Shot</option>
+                    <option value="auto" disabled={info && !info.auto}>Auto</option>
+                    {info?.presets.map(p => <option key={p.id} value={p.id}>{p.name}</option>)}
+                    <option value="custom">Custom</option>
+                </select>
+                <button
+                    onClick={() => setIsWhiteBalancePicking(!isWhiteBalancePicking)}
+                    className={`p-2 rounded-md transition-colors ${isWhiteBalancePicking ? 'bg-accent text-button-text' : 'hover:bg-surface'}`}
+                    title="Pick a neutral area"
+                >
+                    <Pipette size={16} />
+                </button>
+            </div>
+            <Slider
+                label="Temperature"
+                value={Math.round(current?.temperature ?? 5500)}
+                onChange={(e) => handleValueChange('rawTemperature', e.target.value)}
+                min="2000" max="12000" step="50"
+                defaultValue={Math.round(info?.asShot.temperature ?? 5500)}
+            />
+            <Slider
+                label="Tint"
+                value={Math.round(current?.tint ?? 0)}
+                onChange={(e) => handleValueChange('rawTint', e.target.value)}
+                min="-150" max="150" step="1"
+                defaultValue={Math.round(info?.asShot.tint ?? 0)}
+            />
+        </div>
+    );
+};
+
 const HSL_COLORS = [
     { name: 'reds', color: '#f87171' },
     { name: 'oranges', color: '#fb923c' },
@@ -96,7 +261,7 @@ const HSL_COLORS = [
     { name: 'magentas', color: '#f472b6' },
 ];
 
-export default function ColorPanel({ adjustments, setAdjustments }) {
+export default function ColorPanel({ adjustments, setAdjustments, selectedImage, isWhiteBalancePicking, setIsWhiteBalancePicking }) {
     const [activeColor, setActiveColor] = useState('reds');
 
     const handleGlobalChange = (key, value) => {
@@ -117,23 +282,50 @@ export default function ColorPanel({ adjustments, setAdjustments }) {
     };
     
     const currentHsl = adjustments.hsl?.[activeColor] || { hue: 0, saturation: 0, luminance: 0 };
+    const isRaw = !!selectedImage?.isRaw && !!setIsWhiteBalancePicking;
+    // Raw files are balanced in camera space; the relative sliders stay for
+    // settings made before that, or pasted from non-raw images.
+    const showRelativeWhiteBalance = !!adjustments.temperature || !!adjustments.tint;
 
     return (
         <div> 
+            {isRaw && (
+                <div className="mb-4 p-2 bg-bg-tertiary rounded-md">
+                    <p className="text-md font-semibold mb-2 text-primary">Camera Profile</p>
+                    <CameraProfilePanel
+                        adjustments={adjustments}
+                        setAdjustments={setAdjustments}
+                        selectedImage={selectedImage}
+                    />
+                </div>
+            )}
             <div className="mb-4 p-2 bg-bg-tertiary rounded-md">
                 <p className="text-md font-semibold mb-2 text-primary">White Balance</p>
-                <Slider
-                    label="Temperature"
-                    value={adjustments.temperature || 0}
-                    onChange={(e) => handleGlobalChange('temperature', e.target.value)}
-                    min="-100" max="100" step="1"
-                />
-                <Slider
-                    label="Tint"
-                    value={adjustments.tint || 0}
-                    onChange={(e) => handleGlobalChange('tint', e.target.value)}
-                    min="-100" max="100" step="1"
-                />
+                {isRaw && (
+                    <RawWhiteBalancePanel
+                        adjustments={adjustments}
+                        setAdjustments={setAdjustments}
+                        selectedImage={selectedImage}
+                        isWhiteBalancePicking={isWhiteBalancePicking}
+                        setIsWhiteBalancePicking={setIsWhiteBalancePicking}
+                    />
+                )}
+                {(!isRaw || showRelativeWhiteBalance) && (
+                    <>
+                        <Slider
+                            label={isRaw ? "Temperature Offset" : "Temperature"}
+                            value={adjustments.temperature || 0}
+                            onChange={(e) => handleGlobalChange('temperature', e.target.value)}
+                            min="-100" max="100" step="1"
+                        />
+                        <Slider
+                            label={isRaw ? "Tint Offset" : "Tint"}
+                            value={adjustments.tint || 0}
+                            onChange={(e) => handleGlobalChange('tint', e.target.value)}
+                            min="-100" max="100" step="1"
+                        />
+                    </>
+                )}
             </div>
 
             <div className="mb-4 p-2 bg-bg-tertiary rounded-md">
@@ -54,6 +54,12 @@ export default function DetailsPanel({ adjustments, setAdjustments }) {
           onChange={(e) => handleAdjustmentChange('colorNoiseReduction', e.target.value)}
           min="0" max="100" step="1"
         />
+        <Slider
+          label="Raw Denoise"
+          value={adjustments.rawDenoise ?? 0}
+          onChange={(e) => handleAdjustmentChange('rawDenoise', e.target.value)}
+          min="0" max="100" step="1"
+        />
       </div>
     </div>
   );
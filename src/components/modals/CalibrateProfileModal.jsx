@@ -0,0 +1,167 @@
+import { useState, useEffect } from 'react';
+import { invoke } from '@tauri-apps/api/core';
+import { open } from '@tauri-apps/plugin-dialog';
+import ProcessingModal from './ProcessingModal';
+import Dropdown from '../ui/Dropdown';
+import Switch from '../ui/Switch';
+
+const CHARTS = [
+  { value: 'colorChecker24', label: 'ColorChecker 24' },
+  { value: 'colorCheckerSg', label: 'ColorChecker SG' },
+];
+
+const ILLUMINANTS = [
+  { value: 'D65', label: 'Daylight (D65)' },
+  { value: 'D55', label: 'Daylight (D55)' },
+  { value: 'D50', label: 'Horizon Daylight (D50)' },
+  { value: 'D75', label: 'Shade (D75)' },
+  { value: 'A', label: 'Tungsten (A)' },
+];
+
+const fileName = (path) => path.split(/[\\/]/).pop();
+
+export default function CalibrateProfileModal({ isOpen, onClose, selectedImage, onComplete }) {
+  const [chart, setChart] = useState('colorChecker24');
+  const [referenceFile, setReferenceFile] = useState(null);
+  const [illuminant, setIlluminant] = useState('D65');
+  const [hueSatCorrection, setHueSatCorrection] = useState(false);
+  const [name, setName] = useState('');
+  const [corners, setCorners] = useState([]);
+  const [isRunning, setIsRunning] = useState(false);
+  const [error, setError] = useState(null);
+  const [result, setResult] = useState(null);
+
+  useEffect(() => {
+    if (isOpen) {
+      setCorners([]);
+      setError(null);
+      setResult(null);
+    }
+  }, [isOpen, selectedImage?.path]);
+
+  const handleSelectReference = async () => {
+    const filePath = await open({
+      title: 'Select Chart Reference Data',
+      multiple: false,
+      filters: [{ name: 'CGATS Reference Data', extensions: ['txt', 'cie', 'cxf', 'it8'] }],
+    });
+    if (filePath) setReferenceFile(filePath);
+  };
+
+  const handleImageClick = (e) => {
+    if (isRunning) return;
+    const rect = e.currentTarget.getBoundingClientRect();
+    const point = [(e.clientX - rect.left) / rect.width, (e.clientY - rect.top) / rect.height];
+    setResult(null);
+    setCorners(prev => (prev.length >= 4 ? [point] : [...prev, point]));
+  };
+
+  const handleCalibrate = async () => {
+    setIsRunning(true);
+    setError(null);
+    setResult(null);
+    try {
+      const calibration = await invoke('calibrate_camera_profile', {
+        path: selectedImage.path,
+        options: {
+          chart,
+          corners: corners.length === 4 ? corners : null,
+          referenceFile,
+          illuminant,
+          hueSatCorrection,
+          name: name.trim() || null,
+          exportPath: null,
+        },
+      });
+      setResult(calibration);
+      setCorners(calibration.corners);
+      onComplete(calibration);
+    } catch (err) {
+      console.error("Camera profile calibration failed:", err);
+      setError(String(err));
+    } finally {
+      setIsRunning(false);
+    }
+  };
+
+  const previewUrl = selectedImage?.originalUrl || selectedImage?.thumbnailUrl;
+  const needsReference = chart === 'colorCheckerSg' && !referenceFile;
+
+  return (
+    <ProcessingModal
+      isOpen={isOpen}
+      onClose={onClose}
+      title="Calibrate Camera Profile"
+      isRunning={isRunning}
+      progress={{ stage: 'calibrating' }}
+      error={error}
+      footer={
+        <button
+          onClick={handleCalibrate}
+          disabled={isRunning || needsReference || (corners.length > 0 && corners.length < 4)}
+          className="px-4 py-2 rounded-md bg-accent text-primary font-semibold hover:bg-accent-hover disabled:bg-gray-500 disabled:text-white disabled:cursor-not-allowed transition-colors"
+        >
+          Calibrate
+        </button>
+      }
+    >
+      <p>
+        Build a profile from a shot of a color chart. The chart is found automatically, or click the centers of the
+        first patch, the last patch of the first row, the last patch and the first patch of the last row.
+      </p>
+      {previewUrl && (
+        <div className="relative cursor-crosshair select-none" onClick={handleImageClick}>
+          <img src={previewUrl} alt="" className="w-full rounded-md" draggable={false} />
+          {corners.map(([x, y], i) => (
+            <div
+              key={i}
+              className="absolute w-5 h-5 -ml-2.5 -mt-2.5 rounded-full border-2 border-accent bg-black/50 text-[10px] text-white flex items-center justify-center pointer-events-none"
+              style={{ left: `${x * 100}%`, top: `${y * 100}%` }}
+            >
+              {i + 1}
+            </div>
+          ))}
+        </div>
+      )}
+      {corners.length > 0 && !result && (
+        <button onClick={() => setCorners([])} className="text-xs text-accent hover:underline">
+          Clear corners and detect automatically
+        </button>
+      )}
+      <div>
+        <label className="block text-sm font-medium text-text-primary mb-2">Chart</label>
+        <Dropdown options={CHARTS} value={chart} onChange={setChart} />
+      </div>
+      <div>
+        <label className="block text-sm font-medium text-text-primary mb-2">Reference Data</label>
+        <div className="flex items-center gap-2">
+          <span className="flex-grow min-w-0 truncate">
+            {referenceFile ? fileName(referenceFile) : (chart === 'colorChecker24' ? 'Built-in reference' : 'Required for this chart')}
+          </span>
+          {referenceFile && (
+            <button onClick={() => setReferenceFile(null)} className="px-2 py-1 rounded-md hover:bg-bg-primary transition-colors">Clear</button>
+          )}
+          <button onClick={handleSelectReference} className="px-2 py-1 rounded-md bg-bg-primary hover:bg-card-active transition-colors">Browse</button>
+        </div>
+      </div>
+      <div>
+        <label className="block text-sm font-medium text-text-primary mb-2">Light Source</label>
+        <Dropdown options={ILLUMINANTS} value={illuminant} onChange={setIlluminant} />
+      </div>
+      <Switch label="Correct Hue and Saturation" checked={hueSatCorrection} onChange={setHueSatCorrection} disabled={isRunning} />
+      <input
+        type="text"
+        value={name}
+        onChange={(e) => setName(e.target.value)}
+        placeholder="Profile name (optional)"
+        className="w-full bg-bg-primary text-text-primary border border-border rounded-md px-3 py-2 focus:outline-none focus:ring-2 focus:ring-accent"
+      />
+      {result && (
+        <p className="text-text-primary">
+          Saved "{result.profile.name}" from {result.patchesUsed} patches{result.autoDetected ? ' (chart found automatically)' : ''}.
+          Mean error ΔE {result.meanDeltaE.toFixed(2)}, max {result.maxDeltaE.toFixed(2)}.
+        </p>
+      )}
+    </ProcessingModal>
+  );
+}
@@ -0,0 +1,55 @@
+import { useEffect } from 'react';
+import ProcessingModal from './ProcessingModal';
+import { useProcessingJob } from '../../hooks/useProcessingJob';
+
+const KIND_LABELS = { dark: 'Dark Frame', flat: 'Flat Field' };
+
+export default function CalibrationFrameModal({ isOpen, onClose, paths, kind }) {
+  const job = useProcessingJob('calibration');
+
+  useEffect(() => {
+    if (isOpen) job.reset();
+  }, [isOpen]);
+
+  const handleCreate = () => {
+    job.start('create_calibration_frame', { paths, kind });
+  };
+
+  const label = KIND_LABELS[kind] || 'Calibration Frame';
+  const info = job.result;
+
+  return (
+    <ProcessingModal
+      isOpen={isOpen}
+      onClose={onClose}
+      title={`Create ${label}`}
+      isRunning={job.isRunning}
+      progress={job.progress}
+      error={job.error}
+      footer={!info && (
+        <button
+          onClick={handleCreate}
+          disabled={job.isRunning || paths.length === 0}
+          className="px-4 py-2 rounded-md bg-accent text-primary font-semibold hover:bg-accent-hover disabled:bg-gray-500 disabled:text-white disabled:cursor-not-allowed transition-colors"
+        >
+          Create
+        </button>
+      )}
+    >
+      {info ? (
+        <div className="space-y-1 text-text-primary">
+          <p>Saved a {label.toLowerCase()} for the {info.make} {info.model} from {info.frameCount} frames.</p>
+          {info.iso && <p className="text-text-secondary">ISO {info.iso}{info.exposureTime ? `, ${info.exposureTime}s` : ''}</p>}
+          {kind === 'dark' && <p className="text-text-secondary">{info.hotPixelCount} hot pixels mapped.</p>}
+          <p className="text-text-secondary">It is applied automatically to matching raw files.</p>
+        </div>
+      ) : (
+        <p>
+          {kind === 'dark'
+            ? `Average ${paths.length} frames shot with the lens cap on into a dark frame. It is subtracted from raws with the same camera, ISO and a similar exposure time.`
+            : `Average ${paths.length} frames of an evenly lit surface into a flat field. It corrects vignetting and dust shadows on raws from the same camera and lens.`}
+        </p>
+      )}
+    </ProcessingModal>
+  );
+}
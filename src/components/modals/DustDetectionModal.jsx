@@ -0,0 +1,123 @@
+import { useState, useEffect } from 'react';
+import { Check } from 'lucide-react';
+import clsx from 'clsx';
+import ProcessingModal from './ProcessingModal';
+import { useProcessingJob } from '../../hooks/useProcessingJob';
+
+const percent = (value) => `${Math.round(value * 100)}%`;
+
+export default function DustDetectionModal({ isOpen, onClose, paths, onApply }) {
+  const job = useProcessingJob('dust-detection');
+  const [selectedSpots, setSelectedSpots] = useState(new Set());
+
+  useEffect(() => {
+    if (isOpen) job.reset();
+  }, [isOpen]);
+
+  useEffect(() => {
+    setSelectedSpots(new Set(job.result ? job.result.spots.map((_, i) => i) : []));
+  }, [job.result]);
+
+  const handleDetect = () => {
+    job.start('detect_dust_spots', { paths });
+  };
+
+  const toggleSpot = (index) => {
+    setSelectedSpots(prev => {
+      const next = new Set(prev);
+      if (next.has(index)) {
+        next.delete(index);
+      } else {
+        next.add(index);
+      }
+      return next;
+    });
+  };
+
+  const handleApply = () => {
+    const spots = job.result.spots.filter((_, i) => selectedSpots.has(i));
+    onApply(spots);
+    onClose();
+  };
+
+  const spots = job.result?.spots || [];
+  const allSelected = spots.length > 0 && selectedSpots.size === spots.length;
+
+  const footer = job.result ? (
+    <button
+      onClick={handleApply}
+      disabled={selectedSpots.size === 0}
+      className="px-4 py-2 rounded-md bg-accent text-primary font-semibold hover:bg-accent-hover disabled:bg-gray-500 disabled:text-white disabled:cursor-not-allowed transition-colors"
+    >
+      Apply to {paths.length} {paths.length === 1 ? 'Image' : 'Images'}
+    </button>
+  ) : (
+    <button
+      onClick={handleDetect}
+      disabled={job.isRunning || paths.length === 0}
+      className="px-4 py-2 rounded-md bg-accent text-primary font-semibold hover:bg-accent-hover disabled:bg-gray-500 disabled:text-white disabled:cursor-not-allowed transition-colors"
+    >
+      Detect
+    </button>
+  );
+
+  return (
+    <ProcessingModal
+      isOpen={isOpen}
+      onClose={onClose}
+      title="Detect Dust Spots"
+      isRunning={job.isRunning}
+      progress={job.progress}
+      error={job.error}
+      footer={footer}
+    >
+      {job.result ? (
+        spots.length === 0 ? (
+          <p className="text-text-primary">No dust spots were found in {job.result.frameCount} frames.</p>
+        ) : (
+          <div className="space-y-2">
+            <div className="flex items-center justify-between">
+              <p className="text-text-primary">
+                Found {spots.length} {spots.length === 1 ? 'spot' : 'spots'} in {job.result.frameCount} frames.
+              </p>
+              <button
+                onClick={() => setSelectedSpots(new Set(allSelected ? [] : spots.map((_, i) => i)))}
+                className="text-xs text-accent hover:underline"
+              >
+                {allSelected ? 'Select None' : 'Select All'}
+              </button>
+            </div>
+            <div className="max-h-64 overflow-y-auto space-y-1">
+              {spots.map((spot, index) => (
+                <button
+                  key={index}
+                  onClick={() => toggleSpot(index)}
+                  className="w-full flex items-center gap-3 p-2 rounded-md bg-bg-primary hover:bg-card-active transition-colors text-left"
+                >
+                  <div className={clsx(
+                    'w-4 h-4 flex-shrink-0 rounded border flex items-center justify-center',
+                    selectedSpots.has(index) ? 'bg-accent border-accent text-primary' : 'border-text-secondary'
+                  )}>
+                    {selectedSpots.has(index) && <Check size={12} />}
+                  </div>
+                  <span className="flex-grow text-text-primary">
+                    Spot at {percent(spot.x)}, {percent(spot.y)}
+                  </span>
+                  <span className="text-xs">
+                    {spot.detectedFrames} of {spot.checkedFrames} frames, score {spot.score.toFixed(2)}
+                  </span>
+                </button>
+              ))}
+            </div>
+            <p>The selected spots are added as heal patches. Positions are on the sensor, so rotated shots are matched too.</p>
+          </div>
+        )
+      ) : (
+        <p>
+          Look for sensor dust across {paths.length} {paths.length === 1 ? 'image' : 'images'}. Spots that show up in the
+          same place in several frames are the most reliable, so select shots with plain areas such as sky.
+        </p>
+      )}
+    </ProcessingModal>
+  );
+}
@@ -0,0 +1,63 @@
+import { useState, useEffect } from 'react';
+import ProcessingModal from './ProcessingModal';
+import Dropdown from '../ui/Dropdown';
+import { useProcessingJob } from '../../hooks/useProcessingJob';
+
+const SCALES = [
+  { value: 2, label: '2x' },
+  { value: 4, label: '4x' },
+];
+
+const fileName = (path) => path.split(/[\\/]/).pop();
+
+export default function EnhanceResolutionModal({ isOpen, onClose, path, onComplete }) {
+  const [scale, setScale] = useState(2);
+  const job = useProcessingJob('enhance-resolution');
+
+  useEffect(() => {
+    if (isOpen) job.reset();
+  }, [isOpen]);
+
+  useEffect(() => {
+    if (job.result) onComplete(job.result);
+  }, [job.result]);
+
+  const handleEnhance = () => {
+    job.start('enhance_resolution', { path, scale });
+  };
+
+  return (
+    <ProcessingModal
+      isOpen={isOpen}
+      onClose={onClose}
+      title="Enhance Resolution"
+      isRunning={job.isRunning}
+      progress={job.progress}
+      error={job.error}
+      footer={!job.result && (
+        <button
+          onClick={handleEnhance}
+          disabled={job.isRunning || !path}
+          className="px-4 py-2 rounded-md bg-accent text-primary font-semibold hover:bg-accent-hover disabled:bg-gray-500 disabled:text-white disabled:cursor-not-allowed transition-colors"
+        >
+          Enhance
+        </button>
+      )}
+    >
+      {job.result ? (
+        <p className="text-text-primary">
+          Saved {fileName(job.result.path)} ({job.result.width} x {job.result.height}).
+        </p>
+      ) : (
+        <>
+          <p>Upscale {path ? fileName(path) : 'the image'} into a new TIFF next to it. The edits are carried over.</p>
+          <div>
+            <label className="block text-sm font-medium text-text-primary mb-2">Scale</label>
+            <Dropdown options={SCALES} value={scale} onChange={setScale} />
+          </div>
+        </>
+      )}
+      {job.error && <p>Models that are not downloaded automatically can be imported under Settings, AI Models.</p>}
+    </ProcessingModal>
+  );
+}
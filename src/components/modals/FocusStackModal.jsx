@@ -0,0 +1,73 @@
+import { useState, useEffect } from 'react';
+import ProcessingModal from './ProcessingModal';
+import Dropdown from '../ui/Dropdown';
+import Switch from '../ui/Switch';
+import { useProcessingJob } from '../../hooks/useProcessingJob';
+
+const METHODS = [
+  { value: 'pyramid', label: 'Pyramid (fine detail)' },
+  { value: 'depthMap', label: 'Depth Map (smooth surfaces)' },
+];
+
+const fileName = (path) => path.split(/[\\/]/).pop();
+
+export default function FocusStackModal({ isOpen, onClose, paths, onComplete }) {
+  const [method, setMethod] = useState('pyramid');
+  const [align, setAlign] = useState(true);
+  const [generateDepthMap, setGenerateDepthMap] = useState(false);
+  const job = useProcessingJob('focus-stack');
+
+  useEffect(() => {
+    if (isOpen) job.reset();
+  }, [isOpen]);
+
+  useEffect(() => {
+    if (job.result) onComplete(job.result);
+  }, [job.result]);
+
+  const handleStack = () => {
+    job.start('focus_stack', { paths, options: { method, align, generateDepthMap } });
+  };
+
+  return (
+    <ProcessingModal
+      isOpen={isOpen}
+      onClose={onClose}
+      title="Focus Stack"
+      isRunning={job.isRunning}
+      progress={job.progress}
+      error={job.error}
+      footer={!job.result && (
+        <button
+          onClick={handleStack}
+          disabled={job.isRunning || paths.length < 2}
+          className="px-4 py-2 rounded-md bg-accent text-primary font-semibold hover:bg-accent-hover disabled:bg-gray-500 disabled:text-white disabled:cursor-not-allowed transition-colors"
+        >
+          Stack
+        </button>
+      )}
+    >
+      {job.result ? (
+        <div className="space-y-2">
+          <p className="text-text-primary">
+            Saved {fileName(job.result.path)} ({job.result.width} x {job.result.height}).
+          </p>
+          {job.result.depthMapPath && <p>Depth map saved as {fileName(job.result.depthMapPath)}.</p>}
+          {job.result.skipped.length > 0 && (
+            <p>Could not align {job.result.skipped.map(fileName).join(', ')}.</p>
+          )}
+        </div>
+      ) : (
+        <>
+          <p>Merge {paths.length} images focused at different distances into a linear DNG next to the source images.</p>
+          <div>
+            <label className="block text-sm font-medium text-text-primary mb-2">Method</label>
+            <Dropdown options={METHODS} value={method} onChange={setMethod} />
+          </div>
+          <Switch label="Align Frames" checked={align} onChange={setAlign} disabled={job.isRunning} />
+          <Switch label="Save Depth Map" checked={generateDepthMap} onChange={setGenerateDepthMap} disabled={job.isRunning} />
+        </>
+      )}
+    </ProcessingModal>
+  );
+}
@@ -0,0 +1,74 @@
+import { useState, useEffect } from 'react';
+import ProcessingModal from './ProcessingModal';
+import Dropdown from '../ui/Dropdown';
+import Switch from '../ui/Switch';
+import { useProcessingJob } from '../../hooks/useProcessingJob';
+
+const PROJECTIONS = [
+  { value: 'planar', label: 'Planar' },
+  { value: 'cylindrical', label: 'Cylindrical' },
+  { value: 'spherical', label: 'Spherical' },
+];
+
+const fileName = (path) => path.split(/[\\/]/).pop();
+
+export default function PanoramaModal({ isOpen, onClose, paths, onComplete }) {
+  const [projection, setProjection] = useState('cylindrical');
+  const [autoCrop, setAutoCrop] = useState(true);
+  const job = useProcessingJob('panorama');
+
+  useEffect(() => {
+    if (isOpen) job.reset();
+  }, [isOpen]);
+
+  useEffect(() => {
+    if (job.result) onComplete(job.result);
+  }, [job.result]);
+
+  const handleStitch = () => {
+    job.start('stitch_panorama', {
+      paths,
+      options: { projection, autoCrop, focalLength35mm: null, blendLevels: null },
+    });
+  };
+
+  return (
+    <ProcessingModal
+      isOpen={isOpen}
+      onClose={onClose}
+      title="Stitch Panorama"
+      isRunning={job.isRunning}
+      progress={job.progress}
+      error={job.error}
+      footer={!job.result && (
+        <button
+          onClick={handleStitch}
+          disabled={job.isRunning || paths.length < 2}
+          className="px-4 py-2 rounded-md bg-accent text-primary font-semibold hover:bg-accent-hover disabled:bg-gray-500 disabled:text-white disabled:cursor-not-allowed transition-colors"
+        >
+          Stitch
+        </button>
+      )}
+    >
+      {job.result ? (
+        <div className="space-y-2">
+          <p className="text-text-primary">
+            Saved {fileName(job.result.path)} ({job.result.width} x {job.result.height}).
+          </p>
+          {job.result.skipped.length > 0 && (
+            <p>Could not place {job.result.skipped.map(fileName).join(', ')}.</p>
+          )}
+        </div>
+      ) : (
+        <>
+          <p>Stitch {paths.length} images into a linear DNG next to the source images.</p>
+          <div>
+            <label className="block text-sm font-medium text-text-primary mb-2">Projection</label>
+            <Dropdown options={PROJECTIONS} value={projection} onChange={setProjection} />
+          </div>
+          <Switch label="Crop to Content" checked={autoCrop} onChange={setAutoCrop} disabled={job.isRunning} />
+        </>
+      )}
+    </ProcessingModal>
+  );
+}
@@ -0,0 +1,88 @@
+import { useState, useEffect } from 'react';
+import { createPortal } from 'react-dom';
+import { Loader2 } from 'lucide-react';
+
+function formatStage(stage) {
+  return stage ? stage.charAt(0).toUpperCase() + stage.slice(1) : 'Starting';
+}
+
+export default function ProcessingModal({ isOpen, onClose, title, isRunning, progress, error, children, footer }) {
+  const [isMounted, setIsMounted] = useState(false);
+  const [show, setShow] = useState(false);
+
+  useEffect(() => {
+    if (isOpen) {
+      setIsMounted(true);
+      const timer = setTimeout(() => setShow(true), 10);
+      return () => clearTimeout(timer);
+    } else {
+      setShow(false);
+      const timer = setTimeout(() => setIsMounted(false), 300);
+      return () => clearTimeout(timer);
+    }
+  }, [isOpen]);
+
+  if (!isMounted) {
+    return null;
+  }
+
+  const handleClose = () => {
+    if (!isRunning) onClose();
+  };
+
+  const fraction = progress?.total ? Math.min(1, progress.current / progress.total) : 0;
+
+  return createPortal(
+    <div
+      className={`
+        fixed inset-0 flex items-center justify-center z-50
+        bg-black/30 backdrop-blur-sm
+        transition-opacity duration-300 ease-in-out
+        ${show ? 'opacity-100' : 'opacity-0'}
+      `}
+      onClick={handleClose}
+      role="dialog"
+      aria-modal="true"
+    >
+      <div
+        className={`
+          bg-surface rounded-lg shadow-xl p-6 w-full max-w-md max-h-[85vh] overflow-y-auto
+          transform transition-all duration-300 ease-out
+          ${show ? 'scale-100 opacity-100 translate-y-0' : 'scale-95 opacity-0 -translate-y-4'}
+        `}
+        onClick={(e) => e.stopPropagation()}
+      >
+        <h3 className="text-lg font-semibold text-text-primary mb-4">{title}</h3>
+        <div className="space-y-4 text-sm text-text-secondary">
+          {children}
+          {isRunning && (
+            <div className="space-y-2">
+              <div className="flex items-center gap-2 text-text-primary">
+                <Loader2 size={16} className="animate-spin" />
+                <span>
+                  {formatStage(progress?.stage)}
+                  {progress?.total > 0 && ` (${Math.min(progress.current + 1, progress.total)} of ${progress.total})`}
+                </span>
+              </div>
+              <div className="w-full h-1.5 bg-bg-primary rounded-full overflow-hidden">
+                <div className="h-full bg-accent transition-all duration-300" style={{ width: `${fraction * 100}%` }} />
+              </div>
+            </div>
+          )}
+          {error && <p className="text-red-400 break-words">{error}</p>}
+        </div>
+        <div className="flex justify-end gap-3 mt-5">
+          <button
+            onClick={handleClose}
+            disabled={isRunning}
+            className="px-4 py-2 rounded-md text-text-secondary hover:bg-bg-primary disabled:opacity-50 disabled:cursor-not-allowed transition-colors"
+          >
+            Close
+          </button>
+          {footer}
+        </div>
+      </div>
+    </div>,
+    document.body
+  );
+}
@@ -9,7 +9,7 @@ import { AnimatePresence } from 'framer-motion';
 import { useImageRenderSize } from '../../hooks/useImageRenderSize';
 
 import FullScreenViewer from './editor/FullScreenViewer';
-import EditorToolbar from './editor/EditorToolbar';
+import EditorToolbar, { ANALYSIS_OVERLAYS } from './editor/EditorToolbar';
 import ImageCanvas from './editor/ImageCanvas';
 import Waveform from './editor/Waveform';
 
@@ -21,13 +21,16 @@ export default function Editor({
   onSelectMask, updateSubMask, transformWrapperRef, onZoomed, onContextMenu,
   onUndo, onRedo, canUndo, canRedo, brushSettings, 
   onGenerateAiMask, aiTool, onAiMaskDrawingComplete, isMaskControlHovered,
-  targetZoom, waveform, isWaveformVisible, onCloseWaveform,
+  isWhiteBalancePicking, onWhiteBalancePick,
+  targetZoom, waveform, scopes, onActiveScopeChange, isWaveformVisible, onCloseWaveform,
 }) {
   const [crop, setCrop] = useState();
   const prevCropParams = useRef(null);
   const [isMaskHovered, setIsMaskHovered] = useState(false);
   const [isLoaderVisible, setIsLoaderVisible] = useState(false);
   const [maskOverlayUrl, setMaskOverlayUrl] = useState(null);
+  const [analysisOverlayId, setAnalysisOverlayId] = useState(null);
+  const [analysisOverlayUrl, setAnalysisOverlayUrl] = useState(null);
   const [transformState, setTransformState] = useState({ scale: 1, positionX: 0, positionY: 0 });
   const imageContainerRef = useRef(null);
   const isInitialMount = useRef(true);
@@ -122,6 +125,27 @@ export default function Editor({
   }, [activeMaskContainerId, adjustments.masks, imageRenderSize, debouncedGenerateMaskOverlay]);
 
 
+  useEffect(() => {
+    setAnalysisOverlayId(null);
+  }, [selectedImage.path]);
+
+  // Overlays are computed from the processed preview, so they follow finalPreviewUrl.
+  useEffect(() => {
+    const option = ANALYSIS_OVERLAYS.find(o => o.id === analysisOverlayId);
+    if (!option || !finalPreviewUrl) {
+      setAnalysisOverlayUrl(null);
+      return;
+    }
+    let isCurrent = true;
+    invoke('generate_analysis_overlay', { overlay: option.overlay, jsAdjustments: adjustments })
+      .then(url => { if (isCurrent) setAnalysisOverlayUrl(url || null); })
+      .catch(e => {
+        console.error("Failed to generate analysis overlay:", e);
+        if (isCurrent) setAnalysisOverlayUrl(null);
+      });
+    return () => { isCurrent = false; };
+  }, [analysisOverlayId, finalPreviewUrl]);
+
   useEffect(() => {
     let timer;
     if (showSpinner) {
@@ -261,11 +285,12 @@ export default function Editor({
         thumbnailUrl={selectedImage.thumbnailUrl}
         transformState={transformState}
         onTransformChange={setTransformState}
+        adjustments={adjustments}
       />
 
       <div className="flex-1 bg-bg-secondary rounded-lg flex flex-col relative overflow-hidden p-2 gap-2 min-h-0">
         <AnimatePresence>
-          {isWaveformVisible && <Waveform waveformData={waveform} onClose={onCloseWaveform} />}
+          {isWaveformVisible && <Waveform waveformData={waveform} scopes={scopes} onActiveScopeChange={onActiveScopeChange} onClose={onCloseWaveform} />}
         </AnimatePresence>
         <EditorToolbar
           onBackToLibrary={onBackToLibrary}
@@ -279,6 +304,8 @@ export default function Editor({
           onRedo={onRedo}
           canUndo={canUndo}
           canRedo={canRedo}
+          analysisOverlayId={analysisOverlayId}
+          onAnalysisOverlayChange={setAnalysisOverlayId}
         />
 
         <div 
@@ -324,6 +351,7 @@ export default function Editor({
                 isAdjusting={isAdjusting}
                 uncroppedAdjustedPreviewUrl={uncroppedAdjustedPreviewUrl}
                 maskOverlayUrl={maskOverlayUrl}
+                analysisOverlayUrl={analysisOverlayUrl}
                 onSelectMask={onSelectMask}
                 activeMaskId={activeMaskId}
                 activeMaskContainerId={activeMaskContainerId}
@@ -335,6 +363,8 @@ export default function Editor({
                 aiTool={aiTool}
                 onAiMaskDrawingComplete={onAiMaskDrawingComplete}
                 isMaskControlHovered={isMaskControlHovered}
+                isWhiteBalancePicking={isWhiteBalancePicking}
+                onWhiteBalancePick={onWhiteBalancePick}
               />
             </TransformComponent>
           </TransformWrapper>
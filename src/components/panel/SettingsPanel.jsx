@@ -1,6 +1,7 @@
-import { useState } from 'react';
-import { ArrowLeft, Trash2, Wifi, WifiOff } from 'lucide-react';
+import { useState, useEffect } from 'react';
+import { ArrowLeft, Trash2, Wifi, WifiOff, Download, Upload, ShieldCheck } from 'lucide-react';
 import { invoke } from '@tauri-apps/api/core';
+import { open } from '@tauri-apps/plugin-dialog';
 import { relaunch } from '@tauri-apps/plugin-process';
 import Button from '../ui/Button';
 import ConfirmModal from '../modals/ConfirmModal';
@@ -17,6 +18,12 @@ const resolutions = [
   { value: 3840, label: '3840px' },
 ];
 
+const scopeResolutions = [
+  { value: 128, label: '128' },
+  { value: 256, label: '256' },
+  { value: 512, label: '512' },
+];
+
 const KeybindItem = ({ keys, description }) => (
   <div className="flex justify-between items-center py-2">
     <span className="text-text-secondary">{description}</span>
@@ -30,6 +37,129 @@ const KeybindItem = ({ keys, description }) => (
   </div>
 );
 
+const MODEL_STATE_LABELS = {
+  missing: 'Not installed',
+  partial: 'Download interrupted',
+  unverified: 'Installed, not verified',
+  ready: 'Installed and verified',
+  corrupt: 'Damaged, reinstall needed',
+};
+
+const formatBytes = (bytes) => `${(bytes / (1024 * 1024)).toFixed(1)} MB`;
+
+const AiModelsSection = ({ appSettings, onSettingsChange }) => {
+  const [models, setModels] = useState([]);
+  const [busyModelId, setBusyModelId] = useState(null);
+  const [isVerifying, setIsVerifying] = useState(false);
+  const [message, setMessage] = useState(null);
+
+  const loadModels = (verify = false) => invoke('get_ai_model_status', { verify })
+    .then(setModels)
+    .catch(err => console.error("Failed to read AI model status:", err));
+
+  useEffect(() => { loadModels(); }, []);
+
+  const runForModel = async (model, action) => {
+    setBusyModelId(model.id);
+    setMessage(null);
+    try {
+      await action();
+    } catch (err) {
+      console.error(`AI model action failed for ${model.id}:`, err);
+      setMessage({ success: false, text: String(err) });
+    } finally {
+      setBusyModelId(null);
+      loadModels();
+    }
+  };
+
+  const handleDownload = (model) => runForModel(model, () => invoke('download_ai_model', { id: model.id }));
+
+  const handleImport = (model) => runForModel(model, async () => {
+    const filePath = await open({
+      title: `Import ${model.name}`,
+      multiple: false,
+      filters: [{ name: 'ONNX Model', extensions: ['onnx'] }],
+    });
+    if (!filePath) return;
+    const status = await invoke('import_ai_model', { id: model.id, filePath });
+    setMessage({ success: true, text: `${status.name}: ${MODEL_STATE_LABELS[status.state] || status.state}.` });
+  });
+
+  const handleVerify = async () => {
+    setIsVerifying(true);
+    setMessage(null);
+    await loadModels(true);
+    setIsVerifying(false);
+  };
+
+  return (
+    <div className="p-6 bg-surface rounded-xl shadow-md">
+      <div className="flex items-center justify-between mb-4">
+        <h2 className="text-xl font-semibold text-accent">AI Models</h2>
+        <Button onClick={handleVerify} disabled={isVerifying || busyModelId !== null} className="bg-surface">
+          <ShieldCheck size={16} />
+          {isVerifying ? 'Verifying...' : 'Verify Files'}
+        </Button>
+      </div>
+      <div className="space-y-2">
+        {models.map(model => {
+          const isBusy = busyModelId === model.id;
+          return (
+            <div key={model.id} className="flex items-center justify-between gap-4 p-3 bg-bg-primary rounded-md">
+              <div className="min-w-0">
+                <p className="text-sm font-medium text-text-primary truncate">{model.name}</p>
+                <p className={`text-xs truncate ${model.state === 'corrupt' ? 'text-red-400' : 'text-text-secondary'}`}>
+                  {MODEL_STATE_LABELS[model.state] || model.state}
+                  {model.sizeOnDisk > 0 && ` · ${formatBytes(model.sizeOnDisk)}`}
+                  {!model.pinned && ' · no pinned checksum'}
+                </p>
+              </div>
+              <div className="flex items-center gap-1 flex-shrink-0">
+                <button
+                  onClick={() => handleDownload(model)}
+                  disabled={busyModelId !== null || !model.pinned || model.state === 'ready'}
+                  className="p-2 rounded-full text-text-secondary hover:bg-surface disabled:opacity-40 disabled:cursor-not-allowed"
+                  title={model.pinned ? 'Download' : 'Only models with a pinned checksum are downloaded automatically'}
+                >
+                  <Download size={16} className={isBusy ? 'animate-pulse' : ''} />
+                </button>
+                <button
+                  onClick={() => handleImport(model)}
+                  disabled={busyModelId !== null}
+                  className="p-2 rounded-full text-text-secondary hover:bg-surface disabled:opacity-40 disabled:cursor-not-allowed"
+                  title="Import from File"
+                >
+                  <Upload size={16} />
+                </button>
+              </div>
+            </div>
+          );
+        })}
+      </div>
+      {message && (
+        <p className={`text-sm mt-3 break-words ${message.success ? 'text-green-400' : 'text-red-400'}`}>{message.text}</p>
+      )}
+      <div className="mt-6">
+        <label htmlFor="ai-model-mirror" className="block text-sm font-medium text-text-primary mb-2">
+          Model Mirror
+        </label>
+        <Input
+          id="ai-model-mirror"
+          type="text"
+          placeholder="Default download location"
+          value={appSettings?.aiModelMirrorUrl || ''}
+          onChange={(e) => onSettingsChange({ ...appSettings, aiModelMirrorUrl: e.target.value })}
+        />
+        <p className="text-xs text-text-secondary mt-2">
+          Base URL to download the models from, e.g. a server on your network. Downloads are checked against the pinned checksums.
+          Models without one have to be imported from a trusted file.
+        </p>
+      </div>
+    </div>
+  );
+};
+
 export default function SettingsPanel({ onBack, appSettings, onSettingsChange, rootPath, onLibraryRefresh }) {
   const [isClearing, setIsClearing] = useState(false);
   const [clearMessage, setClearMessage] = useState('');
@@ -54,6 +184,38 @@ export default function SettingsPanel({ onBack, appSettings, onSettingsChange, r
 
   const effectiveRootPath = rootPath || appSettings?.lastRootPath;
 
+  const [calibrationFrames, setCalibrationFrames] = useState([]);
+
+  const loadCalibrationFrames = () => {
+    invoke('list_calibration_frames')
+      .then(setCalibrationFrames)
+      .catch(err => console.error("Failed to list calibration frames:", err));
+  };
+
+  useEffect(loadCalibrationFrames, []);
+
+  const handleDeleteCalibrationFrame = (frame) => {
+    setConfirmModalState({
+      isOpen: true,
+      title: 'Delete Calibration Frame',
+      message: `Delete the ${frame.kind === 'dark' ? 'dark frame' : 'flat field'} for the ${frame.make} ${frame.model}?\n\nRaw files will no longer be corrected with it.`,
+      onConfirm: () => invoke('delete_calibration_frame', { id: frame.id })
+        .then(loadCalibrationFrames)
+        .catch(err => console.error("Failed to delete calibration frame:", err)),
+      confirmText: 'Delete',
+      confirmVariant: 'destructive',
+    });
+  };
+
+  const handleSelectDepthModel = async () => {
+    const filePath = await open({
+      title: 'Select Depth Model',
+      multiple: false,
+      filters: [{ name: 'ONNX Model', extensions: ['onnx'] }],
+    });
+    if (filePath) onSettingsChange({ ...appSettings, depthModelPath: filePath });
+  };
+
   const executeClearSidecars = async () => {
     setIsClearing(true);
     setClearMessage('Deleting sidecar files, please wait...');
@@ -218,6 +380,20 @@ export default function SettingsPanel({ onBack, appSettings, onSettingsChange, r
                   Higher resolutions provide a sharper preview but may impact performance on less powerful systems.
                 </p>
               </div>
+
+              <div>
+                <label htmlFor="scope-resolution" className="block text-sm font-medium text-text-primary mb-2">
+                  Scope Resolution
+                </label>
+                <Dropdown
+                  options={scopeResolutions}
+                  value={appSettings?.scopeResolution || 256}
+                  onChange={(value) => onSettingsChange({ ...appSettings, scopeResolution: value })}
+                />
+                <p className="text-xs text-text-secondary mt-2">
+                  Detail of the parade, vectorscope and raw histogram.
+                </p>
+              </div>
             </div>
           </div>
 
@@ -255,6 +431,67 @@ export default function SettingsPanel({ onBack, appSettings, onSettingsChange, r
                 Enter the address and port of your running ComfyUI instance. Required for generative AI features.
               </p>
             </div>
+            <div className="mt-6">
+              <label htmlFor="depth-model-path" className="block text-sm font-medium text-text-primary mb-2">
+                Depth Model
+              </label>
+              <div className="flex items-center gap-2">
+                <Input
+                  id="depth-model-path"
+                  type="text"
+                  placeholder="No depth model selected"
+                  value={appSettings?.depthModelPath || ''}
+                  onChange={(e) => onSettingsChange({ ...appSettings, depthModelPath: e.target.value })}
+                  className="flex-grow"
+                />
+                <Button onClick={handleSelectDepthModel} className="w-32">
+                  Browse
+                </Button>
+              </div>
+              <p className="text-xs text-text-secondary mt-2">
+                A local depth estimation ONNX model, such as Depth Anything. Required for depth masks.
+              </p>
+            </div>
+          </div>
+
+          <AiModelsSection appSettings={appSettings} onSettingsChange={onSettingsChange} />
+
+          <div className="p-6 bg-surface rounded-xl shadow-md">
+            <h2 className="text-xl font-semibold mb-4 text-accent">Calibration Frames</h2>
+            {calibrationFrames.length === 0 ? (
+              <p className="text-sm text-text-secondary">
+                No dark frames or flat fields yet. Select the frames in the library and choose Create Calibration Frame from the context menu.
+              </p>
+            ) : (
+              <div className="space-y-2">
+                {calibrationFrames.map(frame => (
+                  <div key={frame.id} className="flex items-center justify-between gap-4 p-3 bg-bg-primary rounded-md">
+                    <div className="min-w-0">
+                      <p className="text-sm font-medium text-text-primary truncate">
+                        {frame.kind === 'dark' ? 'Dark Frame' : 'Flat Field'} - {frame.make} {frame.model}
+                      </p>
+                      <p className="text-xs text-text-secondary truncate">
+                        {[
+                          frame.iso && `ISO ${frame.iso}`,
+                          frame.exposureTime && `${frame.exposureTime}s`,
+                          frame.fnumber && `f/${frame.fnumber}`,
+                          frame.lensModel,
+                          `${frame.frameCount} frames`,
+                          new Date(frame.created).toLocaleDateString(),
+                        ].filter(Boolean).join(' · ')}
+                      </p>
+                    </div>
+                    <button
+                      onClick={() => handleDeleteCalibrationFrame(frame)}
+                      className="p-2 rounded-full text-text-secondary hover:text-red-500 hover:bg-red-500/10 flex-shrink-0"
+                      title="Delete Calibration Frame"
+                    >
+                      <Trash2 size={16} />
+                    </button>
+                  </div>
+                ))}
+              </div>
+            )}
           </div>
 
           <div className="p-6 bg-surface rounded-xl shadow-md">
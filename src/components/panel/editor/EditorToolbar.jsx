@@ -1,7 +1,59 @@
-import { memo } from 'react';
-import { Eye, EyeOff, ArrowLeft, Maximize, Loader2, Undo, Redo } from 'lucide-react';
+import { memo, useState } from 'react';
+import { Eye, EyeOff, ArrowLeft, Maximize, Loader2, Undo, Redo, ScanEye } from 'lucide-react';
+import clsx from 'clsx';
 
-const EditorToolbar = memo(({ onBackToLibrary, selectedImage, isLoading, onToggleShowOriginal, showOriginal, onToggleFullScreen, isFullScreenLoading, onUndo, onRedo, canUndo, canRedo }) => (
+export const ANALYSIS_OVERLAYS = [
+  { id: 'clipping', label: 'Highlight & Shadow Clipping', overlay: { type: 'clipping' } },
+  { id: 'rawClipping', label: 'Sensor Clipping', overlay: { type: 'rawClipping' }, rawOnly: true },
+  { id: 'gamutSrgb', label: 'Gamut Warning (sRGB)', overlay: { type: 'gamut', profile: 'srgb' } },
+  { id: 'gamutDisplayP3', label: 'Gamut Warning (Display P3)', overlay: { type: 'gamut', profile: 'displayP3' } },
+  { id: 'gamutAdobeRgb', label: 'Gamut Warning (Adobe RGB)', overlay: { type: 'gamut', profile: 'adobeRgb' } },
+  { id: 'focusPeaking', label: 'Focus Peaking', overlay: { type: 'focusPeaking' } },
+  { id: 'sharpnessHeatmap', label: 'Sharpness Heat Map', overlay: { type: 'sharpnessHeatmap' } },
+];
+
+const AnalysisOverlayMenu = ({ selectedImage, analysisOverlayId, onAnalysisOverlayChange }) => {
+  const [isOpen, setIsOpen] = useState(false);
+  const options = ANALYSIS_OVERLAYS.filter(option => !option.rawOnly || selectedImage.isRaw);
+
+  const select = (id) => {
+    onAnalysisOverlayChange(id);
+    setIsOpen(false);
+  };
+
+  return (
+    <div className="relative">
+      <button
+        onClick={() => setIsOpen(open => !open)}
+        className={clsx(
+          "p-2 rounded-full transition-colors",
+          analysisOverlayId ? "bg-accent text-black" : "bg-surface text-text-primary hover:bg-card-active"
+        )}
+        title="Analysis Overlays"
+      >
+        <ScanEye size={20} />
+      </button>
+      {isOpen && (
+        <div className="absolute right-0 top-full mt-2 w-60 bg-surface rounded-lg shadow-lg py-1 z-50 text-sm">
+          <button onClick={() => select(null)} className={clsx("w-full text-left px-3 py-1.5 hover:bg-card-active", !analysisOverlayId ? "text-accent" : "text-text-primary")}>
+            None
+          </button>
+          {options.map(option => (
+            <button
+              key={option.id}
+              onClick={() => select(option.id)}
+              className={clsx("w-full text-left px-3 py-1.5 hover:bg-card-active", analysisOverlayId === option.id ? "text-accent" : "text-text-primary")}
+            >
+              {option.label}
+            </button>
+          ))}
+        </div>
+      )}
+    </div>
+  );
+};
+
+const EditorToolbar = memo(({ onBackToLibrary, selectedImage, isLoading, onToggleShowOriginal, showOriginal, onToggleFullScreen, isFullScreenLoading, onUndo, onRedo, canUndo, canRedo, analysisOverlayId, onAnalysisOverlayChange }) => (
   <div className="relative flex-shrink-0 flex justify-between items-center px-4 h-14">
     <button onClick={onBackToLibrary} className="bg-surface text-text-primary p-2 rounded-full hover:bg-card-active transition-colors" title="Back to Library">
       <ArrowLeft size={20} />
@@ -20,6 +72,11 @@ const EditorToolbar = memo(({ onBackToLibrary, selectedImage, isLoading, onToggl
       <button onClick={onRedo} disabled={!canRedo} className="bg-surface text-text-primary p-2 rounded-full hover:bg-card-active transition-colors disabled:opacity-50 disabled:cursor-not-allowed" title="Redo (Ctrl+Y)">
         <Redo size={20} />
       </button>
+      <AnalysisOverlayMenu
+        selectedImage={selectedImage}
+        analysisOverlayId={analysisOverlayId}
+        onAnalysisOverlayChange={onAnalysisOverlayChange}
+      />
       <button onClick={onToggleShowOriginal} className="bg-surface text-text-primary p-2 rounded-full hover:bg-card-active transition-colors" title={showOriginal ? "Show Edited (.)" : "Show Original (.)"}>
         {showOriginal ? <EyeOff size={20} /> : <Eye size={20} />}
       </button>
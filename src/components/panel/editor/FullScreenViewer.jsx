@@ -1,7 +1,10 @@
-import { useState, useEffect, useCallback, useMemo, memo } from 'react';
+import { useState, useEffect, useCallback, useMemo, useRef, memo } from 'react';
 import { TransformWrapper, TransformComponent } from "react-zoom-pan-pinch";
 import { X } from 'lucide-react';
 import clsx from 'clsx';
+import { invoke } from '@tauri-apps/api/core';
+
+const VIEWPORT_RENDER_DELAY_MS = 150;
 
 const FullScreenViewer = memo(({
   isOpen,
@@ -9,11 +12,15 @@ const FullScreenViewer = memo(({
   url,
   thumbnailUrl,
   transformState,
-  onTransformChange
+  onTransformChange,
+  adjustments
 }) => {
   const [isMounted, setIsMounted] = useState(false);
   const [show, setShow] = useState(false);
   const [isFullResLoaded, setIsFullResLoaded] = useState(false);
+  const [detail, setDetail] = useState(null);
+  const imageRef = useRef(null);
+  const detailRequestRef = useRef(0);
 
   useEffect(() => {
     if (isOpen) {
@@ -37,6 +44,51 @@ const FullScreenViewer = memo(({
     }
   }, [isOpen, thumbnailUrl]);
 
+  useEffect(() => {
+    if (!isOpen || transformState.scale <= 1) {
+      detailRequestRef.current += 1;
+      setDetail(null);
+      return;
+    }
+
+    // Once zoomed in, render only the part of the image on screen, at the
+    // resolution it is displayed at.
+    const timer = setTimeout(async () => {
+      const image = imageRef.current;
+      if (!image) return;
+      const rect = image.getBoundingClientRect();
+      if (rect.width <= 0 || rect.height <= 0) return;
+
+      const left = Math.max(rect.left, 0);
+      const top = Math.max(rect.top, 0);
+      const right = Math.min(rect.right, window.innerWidth);
+      const bottom = Math.min(rect.bottom, window.innerHeight);
+      if (right <= left || bottom <= top) return;
+
+      const viewport = {
+        x: (left - rect.left) / rect.width,
+        y: (top - rect.top) / rect.height,
+        width: (right - left) / rect.width,
+        height: (bottom - top) / rect.height,
+      };
+      const requestId = ++detailRequestRef.current;
+      try {
+        const result = await invoke('render_viewport', {
+          jsAdjustments: adjustments,
+          viewport,
+          displayWidth: Math.round(rect.width * window.devicePixelRatio),
+        });
+        if (requestId === detailRequestRef.current) {
+          setDetail(result);
+        }
+      } catch (e) {
+        console.error('Failed to render viewport:', e);
+      }
+    }, VIEWPORT_RENDER_DELAY_MS);
+
+    return () => clearTimeout(timer);
+  }, [isOpen, transformState, adjustments]);
+
   const handleClose = useCallback(() => {
     if (onClose) {
       onClose();
@@ -112,6 +164,7 @@ const FullScreenViewer = memo(({
             <div onClick={(e) => e.stopPropagation()}>
               <div className="relative">
                 <img
+                  ref={imageRef}
                   src={thumbnailUrl}
                   alt="Preview"
                   className="w-auto h-[90vh] max-w-[95vw] object-contain"
@@ -129,6 +182,20 @@ const FullScreenViewer = memo(({
                     onContextMenu={(e) => e.preventDefault()}
                   />
                 )}
+                {detail && (
+                  <img
+                    src={detail.imageBase64}
+                    alt="Zoomed Preview"
+                    className="absolute max-w-none pointer-events-none"
+                    style={{
+                      left: `${detail.x * 100}%`,
+                      top: `${detail.y * 100}%`,
+                      width: `${detail.width * 100}%`,
+                      height: `${detail.height * 100}%`,
+                    }}
+                    onContextMenu={(e) => e.preventDefault()}
+                  />
+                )}
               </div>
             </div>
           </TransformComponent>
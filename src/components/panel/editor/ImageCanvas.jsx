@@ -182,6 +182,25 @@ const MaskOverlay = memo(({ subMask, scale, onUpdate, isSelected, onSelect, onMa
     );
   }
 
+  if (subMask.type === 'color-range') {
+    const { samples = [] } = subMask.parameters;
+    if (!isSelected) return null;
+    return (
+      <Group listening={false}>
+        {samples.map((sample, i) => (
+          <Circle
+            key={i}
+            x={(sample.x - cropX) * scale}
+            y={(sample.y - cropY) * scale}
+            radius={5}
+            stroke="#0ea5e9"
+            strokeWidth={2}
+          />
+        ))}
+      </Group>
+    );
+  }
+
   if (subMask.type === 'radial') {
     const { centerX, centerY, radiusX, radiusY, rotation } = subMask.parameters;
     return (
@@ -320,10 +339,11 @@ const MaskOverlay = memo(({ subMask, scale, onUpdate, isSelected, onSelect, onMa
 const ImageCanvas = memo(({
   isCropping, crop, setCrop, handleCropComplete, adjustments, selectedImage,
   isMasking, imageRenderSize, showOriginal, finalPreviewUrl, isAdjusting,
-  uncroppedAdjustedPreviewUrl, maskOverlayUrl,
+  uncroppedAdjustedPreviewUrl, maskOverlayUrl, analysisOverlayUrl,
   onSelectMask, activeMaskId, activeMaskContainerId,
   updateSubMask, setIsMaskHovered, isMaskControlHovered,
-  brushSettings, onGenerateAiMask, aiTool, onAiMaskDrawingComplete
+  brushSettings, onGenerateAiMask, aiTool, onAiMaskDrawingComplete,
+  isWhiteBalancePicking, onWhiteBalancePick
 }) => {
   const [isCropViewVisible, setIsCropViewVisible] = useState(false);
   const imagePathRef = useRef(null);
@@ -349,6 +369,7 @@ const ImageCanvas = memo(({
 
   const isBrushActive = isMasking && activeSubMask?.type === 'brush';
   const isAiSubjectActive = isMasking && activeSubMask?.type === 'ai-subject';
+  const isColorRangeActive = isMasking && activeSubMask?.type === 'color-range';
 
   const isGenerativeReplaceActive = aiTool === 'generative-replace';
 
@@ -449,6 +470,21 @@ const ImageCanvas = memo(({
   }, [isCropping]);
 
   const handleMouseDown = useCallback((e) => {
+    if (isWhiteBalancePicking && !isCropping) {
+      const pos = e.target.getStage().getPointerPosition();
+      if (pos) onWhiteBalancePick(pos.x / imageRenderSize.width, pos.y / imageRenderSize.height);
+      return;
+    }
+    if (isColorRangeActive && !isCropping) {
+      const pos = e.target.getStage().getPointerPosition();
+      if (!pos) return;
+      const { scale } = imageRenderSize;
+      const sample = { x: pos.x / scale + (adjustments.crop?.x || 0), y: pos.y / scale + (adjustments.crop?.y || 0) };
+      updateSubMask(activeMaskId, {
+        parameters: { ...activeSubMask.parameters, samples: [...(activeSubMask.parameters.samples || []), sample] }
+      });
+      return;
+    }
     const toolActive = isGenerativeReplaceActive || isBrushActive || isAiSubjectActive;
     if (toolActive) {
       e.evt.preventDefault();
@@ -473,7 +509,7 @@ const ImageCanvas = memo(({
         onSelectMask(null);
       }
     }
-  }, [isGenerativeReplaceActive, isBrushActive, isAiSubjectActive, brushSettings, onSelectMask]);
+  }, [isGenerativeReplaceActive, isBrushActive, isAiSubjectActive, isColorRangeActive, activeSubMask, activeMaskId, updateSubMask, adjustments.crop, brushSettings, onSelectMask, isWhiteBalancePicking, isCropping, onWhiteBalancePick, imageRenderSize]);
 
   const handleMouseMove = useCallback((e) => {
     const toolActive = isGenerativeReplaceActive || isBrushActive || isAiSubjectActive;
@@ -566,6 +602,7 @@ const ImageCanvas = memo(({
         tool: brushSettings.tool,
         brushSize: brushSettings.size / scale,
         feather: brushSettings.feather / 100,
+        smart: brushSettings.tool === 'brush' && !!brushSettings.smart,
         points: line.points.map(p => ({
           x: p.x / scale + cropX,
           y: p.y / scale + cropY,
@@ -682,6 +719,21 @@ const ImageCanvas = memo(({
                 }}
               />
             ))}
+            {!isCropping && analysisOverlayUrl && (
+              <img
+                src={analysisOverlayUrl}
+                alt="Analysis Overlay"
+                className="absolute object-contain pointer-events-none"
+                style={{
+                  width: `${imageRenderSize.width}px`,
+                  height: `${imageRenderSize.height}px`,
+                  left: `${imageRenderSize.offsetX}px`,
+                  top: `${imageRenderSize.offsetY}px`,
+                  opacity: showOriginal ? 0 : 1,
+                  transition: 'opacity 150ms ease-in-out',
+                }}
+              />
+            )}
             {isMasking && maskOverlayUrl && (
               <img
                 src={maskOverlayUrl}
@@ -711,7 +763,7 @@ const ImageCanvas = memo(({
             zIndex: 4,
             opacity: showOriginal ? 0 : 1,
             pointerEvents: showOriginal ? 'none' : 'auto',
-            cursor: isGenerativeReplaceActive ? 'none' : ((isBrushActive || isAiSubjectActive) ? 'crosshair' : 'default'),
+            cursor: isGenerativeReplaceActive ? 'none' : ((isBrushActive || isAiSubjectActive || isColorRangeActive || isWhiteBalancePicking) ? 'crosshair' : 'default'),
           }}
           onMouseDown={handleMouseDown}
           onMouseMove={handleMouseMove}
@@ -68,10 +68,120 @@ const RgbWaveformDisplay = ({ redData, greenData, blueData, width, height, maxVa
   return <canvas ref={canvasRef} width={width} height={height} className="absolute inset-0" />;
 };
 
-export default function Waveform({ waveformData, onClose }) {
+const ParadeDisplay = ({ data }) => {
+  const canvasRef = useRef(null);
+  const { red, green, blue, width, height } = data;
+
+  useEffect(() => {
+    if (!canvasRef.current) return;
+    const ctx = canvasRef.current.getContext('2d');
+    const imageData = ctx.createImageData(width * 3, height);
+    const pixels = imageData.data;
+
+    [red, green, blue].forEach((channel, c) => {
+      const maxVal = channel.reduce((max, v) => Math.max(max, v), 0);
+      const scale = maxVal > 0 ? 255 / Math.log(1 + maxVal) : 0;
+      for (let i = 0; i < channel.length; i++) {
+        if (channel[i] === 0) continue;
+        const x = (i % width) + c * width;
+        const y = Math.floor(i / width);
+        const pixelIndex = (y * width * 3 + x) * 4;
+        pixels[pixelIndex + c] = 255;
+        pixels[pixelIndex + 3] = Math.log(1 + channel[i]) * scale;
+      }
+    });
+    ctx.putImageData(imageData, 0, 0);
+  }, [red, green, blue, width, height]);
+
+  return <canvas ref={canvasRef} width={width * 3} height={height} className="absolute inset-0 w-full h-full" />;
+};
+
+const VectorscopeDisplay = ({ data }) => {
+  const canvasRef = useRef(null);
+  const { data: counts, size, skinLineAngle } = data;
+
+  useEffect(() => {
+    if (!canvasRef.current) return;
+    const ctx = canvasRef.current.getContext('2d');
+    const imageData = ctx.createImageData(size, size);
+    const pixels = imageData.data;
+
+    const maxVal = counts.reduce((max, v) => Math.max(max, v), 0);
+    const scale = maxVal > 0 ? 255 / Math.log(1 + maxVal) : 0;
+    for (let i = 0; i < counts.length; i++) {
+      if (counts[i] === 0) continue;
+      const pixelIndex = i * 4;
+      pixels[pixelIndex] = 255;
+      pixels[pixelIndex + 1] = 255;
+      pixels[pixelIndex + 2] = 255;
+      pixels[pixelIndex + 3] = Math.log(1 + counts[i]) * scale;
+    }
+    ctx.putImageData(imageData, 0, 0);
+
+    const center = size / 2;
+    ctx.strokeStyle = 'rgba(255, 255, 255, 0.25)';
+    ctx.lineWidth = Math.max(1, size / 256);
+    ctx.beginPath();
+    ctx.arc(center, center, center - 1, 0, Math.PI * 2);
+    ctx.moveTo(center, 0);
+    ctx.lineTo(center, size);
+    ctx.moveTo(0, center);
+    ctx.lineTo(size, center);
+    ctx.stroke();
+
+    const angle = (skinLineAngle * Math.PI) / 180;
+    ctx.strokeStyle = 'rgba(255, 190, 140, 0.7)';
+    ctx.beginPath();
+    ctx.moveTo(center, center);
+    ctx.lineTo(center + Math.cos(angle) * center, center - Math.sin(angle) * center);
+    ctx.stroke();
+  }, [counts, size, skinLineAngle]);
+
+  return <canvas ref={canvasRef} width={size} height={size} className="absolute inset-0 w-full h-full" />;
+};
+
+const RAW_CHANNEL_COLORS = { R: '#ef4444', G: '#22c55e', G1: '#22c55e', G2: '#86efac', B: '#3b82f6', E: '#a855f7' };
+
+const RawHistogramDisplay = ({ data }) => {
+  const canvasRef = useRef(null);
+  const width = 256;
+  const height = 256;
+
+  useEffect(() => {
+    if (!canvasRef.current) return;
+    const ctx = canvasRef.current.getContext('2d');
+    ctx.clearRect(0, 0, width, height);
+
+    const maxVal = data.channels.reduce((max, ch) => ch.counts.reduce((m, v) => Math.max(m, v), max), 0);
+    const scale = maxVal > 0 ? (height - 1) / Math.log(1 + maxVal) : 0;
+    data.channels.forEach((channel) => {
+      const bins = channel.counts.length;
+      ctx.strokeStyle = RAW_CHANNEL_COLORS[channel.name] || '#ffffff';
+      ctx.beginPath();
+      channel.counts.forEach((count, i) => {
+        const x = (i / (bins - 1)) * (width - 1);
+        const y = height - 1 - Math.log(1 + count) * scale;
+        if (i === 0) ctx.moveTo(x, y); else ctx.lineTo(x, y);
+      });
+      ctx.stroke();
+    });
+  }, [data]);
+
+  return <canvas ref={canvasRef} width={width} height={height} className="absolute inset-0 w-full h-full" />;
+};
+
+const SCOPE_FOR_MODE = { parade: 'parade', vectorscope: 'vectorscope', raw: 'rawHistogram' };
+
+export default function Waveform({ waveformData, scopes, onActiveScopeChange, onClose }) {
   const [displayMode, setDisplayMode] = useState('rgb');
   const nodeRef = useRef(null);
 
+  useEffect(() => {
+    onActiveScopeChange?.(SCOPE_FOR_MODE[displayMode] ?? null);
+  }, [displayMode, onActiveScopeChange]);
+
+  useEffect(() => () => onActiveScopeChange?.(null), [onActiveScopeChange]);
+
   const { red, green, blue, luma, width, height } = waveformData || {};
 
   const maxVals = waveformData ? {
@@ -116,7 +226,22 @@ export default function Waveform({ waveformData, onClose }) {
                 {displayMode === 'red' && <LumaWaveformDisplay data={red} width={width} height={height} maxVal={maxVals.red} color={[255, 0, 0]} />}
                 {displayMode === 'green' && <LumaWaveformDisplay data={green} width={width} height={height} maxVal={maxVals.green} color={[0, 255, 0]} />}
                 {displayMode === 'blue' && <LumaWaveformDisplay data={blue} width={width} height={height} maxVal={maxVals.blue} color={[0, 0, 255]} />}
+                {displayMode === 'parade' && scopes?.parade && <ParadeDisplay data={scopes.parade} />}
+                {displayMode === 'vectorscope' && scopes?.vectorscope && <VectorscopeDisplay data={scopes.vectorscope} />}
+                {displayMode === 'raw' && scopes?.rawHistogram && <RawHistogramDisplay data={scopes.rawHistogram} />}
+                {displayMode === 'raw' && scopes && !scopes.rawHistogram && (
+                  <p className="absolute inset-0 flex items-center justify-center text-xs">Only available for raw files</p>
+                )}
               </div>
+              {displayMode === 'raw' && scopes?.rawHistogram && (
+                <div className="flex justify-between mt-1 text-xs">
+                  {scopes.rawHistogram.channels.map((channel) => (
+                    <span key={channel.name} title="Photosites at the sensor white level">
+                      {channel.name} {channel.total > 0 ? ((channel.clipped / channel.total) * 100).toFixed(2) : '0.00'}%
+                    </span>
+                  ))}
+                </div>
+              )}
               <div className="flex justify-center gap-1 mt-2 p-1 bg-surface rounded-lg">
                 <button onClick={() => setDisplayMode('luma')} className={`${baseButtonClass} ${displayMode === 'luma' ? 'bg-accent text-black' : inactiveButtonClass}`}>Luma</button>
                 <button onClick={() => setDisplayMode('rgb')} className={`${baseButtonClass} ${displayMode === 'rgb' ? 'bg-accent text-black' : inactiveButtonClass}`}>RGB</button>
@@ -124,6 +249,11 @@ export default function Waveform({ waveformData, onClose }) {
                 <button onClick={() => setDisplayMode('green')} className={`${baseButtonClass} ${displayMode === 'green' ? 'bg-green-500 text-white' : inactiveButtonClass}`}>G</button>
                 <button onClick={() => setDisplayMode('blue')} className={`${baseButtonClass} ${displayMode === 'blue' ? 'bg-blue-500 text-white' : inactiveButtonClass}`}>B</button>
               </div>
+              <div className="flex justify-center gap-1 mt-1 p-1 bg-surface rounded-lg">
+                <button onClick={() => setDisplayMode('parade')} className={`${baseButtonClass} ${displayMode === 'parade' ? 'bg-accent text-black' : inactiveButtonClass}`}>Parade</button>
+                <button onClick={() => setDisplayMode('vectorscope')} className={`${baseButtonClass} ${displayMode === 'vectorscope' ? 'bg-accent text-black' : inactiveButtonClass}`}>Vector</button>
+                <button onClick={() => setDisplayMode('raw')} className={`${baseButtonClass} ${displayMode === 'raw' ? 'bg-accent text-black' : inactiveButtonClass}`}>Raw</button>
+              </div>
             </div>
           )}
         </motion.div>
@@ -19,6 +19,8 @@ export default function Controls({
   copiedSectionAdjustments,
   setCopiedSectionAdjustments,
   handleAutoAdjustments,
+  isWhiteBalancePicking,
+  setIsWhiteBalancePicking,
 }) {
   const { showContextMenu } = useContextMenu();
 
@@ -152,6 +154,9 @@ export default function Controls({
                   setAdjustments={setAdjustments}
                   histogram={histogram}
                   theme={theme}
+                  selectedImage={selectedImage}
+                  isWhiteBalancePicking={isWhiteBalancePicking}
+                  setIsWhiteBalancePicking={setIsWhiteBalancePicking}
                 />
               </CollapsibleSection>
             </div>
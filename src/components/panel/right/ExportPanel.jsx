@@ -10,6 +10,8 @@ const FILE_FORMATS = [
   { id: 'tiff', name: 'TIFF', extensions: ['tiff'] },
 ];
 
+const LUT_SIZES = [33, 65];
+
 const FILENAME_VARIABLES = [
   '{original_filename}',
   '{sequence}',
@@ -41,6 +43,8 @@ export default function ExportPanel({ selectedImage, adjustments, multiSelectedP
   const [keepMetadata, setKeepMetadata] = useState(true);
   const [stripGps, setStripGps] = useState(true);
   const [filenameTemplate, setFilenameTemplate] = useState('{original_filename}_edited');
+  const [lutSize, setLutSize] = useState(33);
+  const [lutExport, setLutExport] = useState(null);
   const filenameInputRef = useRef(null);
 
   const { status, progress, errorMessage } = exportState;
@@ -59,6 +63,10 @@ export default function ExportPanel({ selectedImage, adjustments, multiSelectedP
     }
   }, [selectedImage, multiSelectedPaths, isExporting, setExportState]);
 
+  useEffect(() => {
+    setLutExport(null);
+  }, [selectedImage?.path]);
+
   const handleVariableClick = (variable) => {
     if (!filenameInputRef.current) return;
 
@@ -135,6 +143,31 @@ export default function ExportPanel({ selectedImage, adjustments, multiSelectedP
     }
   };
 
+  const handleExportLut = async () => {
+    if (!selectedImage) return;
+    const originalFilename = selectedImage.path.split(/[\\/]/).pop();
+    const [name] = originalFilename.split('.');
+    const filePath = await save({
+      title: "Save Edit as LUT",
+      defaultPath: `${name}.cube`,
+      filters: [{ name: 'Cube LUT', extensions: ['cube'] }],
+    });
+    if (!filePath) return;
+
+    setLutExport({ status: 'exporting' });
+    try {
+      const result = await invoke('export_lut_cube', {
+        jsAdjustments: adjustments,
+        path: filePath,
+        size: lutSize,
+      });
+      setLutExport({ status: 'success', omitted: result.omitted });
+    } catch (error) {
+      console.error('Failed to export LUT:', error);
+      setLutExport({ status: 'error', errorMessage: typeof error === 'string' ? error : 'Failed to export LUT.' });
+    }
+  };
+
   const handleCancel = async () => {
     try {
       await invoke('cancel_export');
@@ -272,6 +305,56 @@ export default function ExportPanel({ selectedImage, adjustments, multiSelectedP
                 </div>
               )}
             </Section>
+
+            {isEditorContext && !isBatchMode && (
+              <Section title="Color LUT">
+                <p className="text-xs">
+                  Saves the global color part of this edit as a .cube file. Sharpening, noise reduction, clarity, dehaze, vignette, grain and masks are not included.
+                </p>
+                <div className="flex items-center gap-2">
+                  <div className="grid grid-cols-2 gap-2 flex-grow">
+                    {LUT_SIZES.map(size => (
+                      <button
+                        key={size}
+                        onClick={() => setLutSize(size)}
+                        disabled={isExporting}
+                        className={`px-2 py-1.5 text-sm rounded-md transition-colors ${
+                          lutSize === size
+                            ? 'bg-surface text-white'
+                            : 'bg-surface hover:bg-card-active'
+                        } disabled:opacity-50`}
+                      >
+                        {size}³
+                      </button>
+                    ))}
+                  </div>
+                  <button
+                    onClick={handleExportLut}
+                    disabled={isExporting || lutExport?.status === 'exporting'}
+                    className="px-3 py-1.5 text-sm bg-surface text-white rounded-md hover:bg-surface-hover disabled:opacity-50 transition-colors"
+                  >
+                    Export .cube
+                  </button>
+                </div>
+                {lutExport?.status === 'exporting' && (
+                  <div className="flex items-center gap-2 text-accent text-sm">
+                    <Loader size={16} className="animate-spin" />
+                    <span>Baking LUT...</span>
+                  </div>
+                )}
+                {lutExport?.status === 'success' && (
+                  <div className="text-sm text-green-400">
+                    <p>LUT saved.</p>
+                    {lutExport.omitted.length > 0 && (
+                      <p className="text-yellow-400 text-xs mt-1">Not included: {lutExport.omitted.join(', ')}</p>
+                    )}
+                  </div>
+                )}
+                {lutExport?.status === 'error' && (
+                  <p className="text-sm text-red-400">{lutExport.errorMessage}</p>
+                )}
+              </Section>
+            )}
           </>
         ) : (
           <p className="text-center text-text-tertiary mt-4">No image selected for export.</p>
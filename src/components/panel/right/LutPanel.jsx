@@ -1,29 +1,25 @@
-import { useState, useEffect, useCallback } from 'react';
+import { useState, useCallback } from 'react';
 import { invoke } from '@tauri-apps/api/core';
-import { open as openDialog, save as saveDialog } from '@tauri-apps/plugin-dialog';
-import { usePresets } from '../../../hooks/usePresets';
-import { useContextMenu } from '../../../context/ContextMenuContext';
-import { Plus, Loader2, FileUp, FileDown, Edit, Trash2, CopyPlus, RefreshCw } from 'lucide-react';
+import { open as openDialog } from '@tauri-apps/plugin-dialog';
+import { Plus, X } from 'lucide-react';
 import FolderTree from '../FolderTree';
+import Slider from '../../ui/Slider';
 
-export default function LutPanel({ rootPath, selectedImage, activePanel, setFinalPreviewUrl }) {
-    /*
-        Processing of luts (Breakdown)
-        - Open folders with luts and save the location
-        - list out all the files in the folder with the correct extension
-        - Allow favorite selection of luts
-        - Allow user to select a lut and apply it to the image
-        - Show files in a tree view 
-        - Save settings for the applied lut for exporting later on
+const LUT_EXTENSIONS = ['cube', '3dl', 'png', 'tif', 'tiff'];
 
-    */
+const DEFAULT_LUT_SETTINGS = {
+  intensity: 100,
+  interpolation: 'tetrahedral',
+  inputSpace: 'srgb',
+};
 
-  const [selectedFolders, setSelectedFolders] = useState([]);
-  const [folderTree, setFolderTree] = useState();
+export default function LutPanel({ adjustments, setAdjustments, activePanel }) {
+  const [selectedFolder, setSelectedFolder] = useState(null);
+  const [folderTree, setFolderTree] = useState(null);
   const [expandedFolders, setExpandedFolders] = useState(new Set());
-  const [cachedImage, setCachedImage] = useState(null);
-  const [lutApplied, setLutApplied] = useState(false);
-  
+  const [error, setError] = useState(null);
+
+  const lut = adjustments.lut;
 
   const handleToggleFolder = useCallback((path) => {
     setExpandedFolders(prev => {
@@ -35,96 +31,73 @@ export default function LutPanel({ rootPath, selectedImage, activePanel, setFina
       }
       return newSet;
     });
-  }, []);  
-
-  async function selectFolder(){
-    const outputFolder = await openDialog({
-        title: `Select LUT Folder`,
-        directory: true,
-        multiple: false,
-        canCreateDirectories: false
-      });
+  }, []);
 
-    if (outputFolder) {
-        setSelectedFolders(prev => [...prev, outputFolder]);
-    }
+  async function selectFolder() {
+    const folder = await openDialog({
+      title: 'Select LUT Folder',
+      directory: true,
+      multiple: false,
+      canCreateDirectories: false,
+    });
+    if (!folder) return;
 
-     try {
-        const treeData = await invoke('get_file_tree', { path: outputFolder });
-        setFolderTree(treeData);
+    setSelectedFolder(folder);
+    try {
+      const treeData = await invoke('get_file_tree', { path: folder });
+      setFolderTree(treeData);
+      setError(null);
     } catch (err) {
-        console.error("Failed to load folder tree:", err);
-        setError(`Failed to load folder tree: ${err}. Some sub-folders might be inaccessible.`);
+      console.error("Failed to load folder tree:", err);
+      setError(`Failed to load folder tree: ${err}. Some sub-folders might be inaccessible.`);
     }
   }
 
   function findNodeByPath(node, path) {
-  if (!node) return null;
-  if (node.path === path) return node;
-  if (node.children) {
-    for (const child of node.children) {
-      const result = findNodeByPath(child, path);
-      if (result) return result;
+    if (!node) return null;
+    if (node.path === path) return node;
+    if (node.children) {
+      for (const child of node.children) {
+        const result = findNodeByPath(child, path);
+        if (result) return result;
+      }
     }
+    return null;
   }
-  return null;
-}
 
-const handleClick = useCallback(async (e, path, is_dir) => {
-    if (lutApplied) {
-      selectedImage.originalUrl = cachedImage;
-    }
-    else {
-      setCachedImage(selectedImage.originalUrl);
+  const handleSelectPath = useCallback((path) => {
+    const node = findNodeByPath(folderTree, path);
+    if (!node) return;
+    if (node.is_dir) {
+      handleToggleFolder(path);
+      return;
     }
+    const extension = path.split('.').pop().toLowerCase();
+    if (!LUT_EXTENSIONS.includes(extension)) return;
+    setAdjustments(prev => ({
+      ...prev,
+      lut: { ...DEFAULT_LUT_SETTINGS, ...(prev.lut || {}), path },
+    }));
+  }, [folderTree, handleToggleFolder, setAdjustments]);
 
-    if (!is_dir) {
-      const reader = new FileReader();
-      let lutType = path.endsWith('.cube') || path.endsWith('.3dl') ? 'cube' : 'hald';
-      // read lut as text when lutType is cube
-      if (lutType === 'cube') {
-        let lutData = await invoke('read_file_data', { path });        
-        await applyLutToImage(lutData, selectedImage.originalUrl, lutType);
-      } else {
-        let lutData = await invoke('load_file_data', { path });
-        let pathEnd = path.split('.').pop().toLowerCase();
-        let url = `data:image/${pathEnd};base64,${lutData}`;
-        await applyLutToImage(url, selectedImage.originalUrl, lutType);
-      }
-    } else {
-    handleToggleFolder(path);
-  }
-}, [selectedImage, handleToggleFolder]);
-
-  async function applyLutToImage(lut_data, image_data, lutType) {
-      if (lut_data) {
-        try {
-          const result = await invoke('apply_lut_type_gpu', {
-            imageData: image_data,
-            lutData: lut_data,
-            lutType: lutType,
-          });
-          let imageUrl = URL.createObjectURL(new Blob([result], { type: 'image/png' }));
+  const updateLut = (key, value) => {
+    setAdjustments(prev => ({ ...prev, lut: { ...prev.lut, [key]: value } }));
+  };
 
-          // Handle the processed image result
-          setFinalPreviewUrl(result);
+  const handleRemoveLut = () => {
+    setAdjustments(prev => ({ ...prev, lut: null }));
+  };
 
-          setLutApplied(true);
-        } catch (error) {
-          console.error('Error applying LUT:', error);
-          alert(`An error occurred while applying the LUT: ${error}`);
-        }
-      }
-    }
+  const lutName = lut?.path ? lut.path.split(/[\\/]/).pop() : null;
 
   return (
     <div className="flex flex-col h-full">
       <div className="p-4 flex justify-between items-center flex-shrink-0 border-b border-surface">
-        <h2 className="text-xl font-bold text-primary text-shadow-shiny">LUT Processing</h2>
+        <h2 className="text-xl font-bold text-primary text-shadow-shiny">LUTs</h2>
         <div className="flex items-center gap-1">
-          <button 
-            onClick={() => selectFolder()} 
-            title="Add LUT Folder" 
+          <button
+            onClick={selectFolder}
+            title="Open LUT Folder"
             className="p-2 rounded-full hover:bg-surface transition-colors"
           >
             <Plus size={18} />
@@ -132,46 +105,67 @@ const handleClick = useCallback(async (e, path, is_dir) => {
         </div>
       </div>
 
+      {lut?.path && (
+        <div className="p-4 border-b border-surface space-y-2 flex-shrink-0">
+          <div className="flex items-center justify-between gap-2">
+            <p className="text-sm font-semibold text-text-primary truncate" title={lut.path}>{lutName}</p>
+            <button
+              onClick={handleRemoveLut}
+              title="Remove LUT"
+              className="p-1 rounded-full hover:bg-surface transition-colors"
+            >
+              <X size={16} />
+            </button>
+          </div>
+          <Slider
+            label="Intensity"
+            value={lut.intensity ?? 100}
+            onChange={(e) => updateLut('intensity', parseInt(e.target.value, 10))}
+            min="0" max="100" step="1"
+          />
+          <div className="flex gap-2">
+            <select
+              value={lut.interpolation || 'tetrahedral'}
+              onChange={(e) => updateLut('interpolation', e.target.value)}
+              className="w-full bg-bg-primary border border-surface rounded-md p-2 text-sm text-text-primary focus:ring-accent focus:border-accent"
+            >
+              <option value="tetrahedral">Tetrahedral</option>
+              <option value="trilinear">Trilinear</option>
+            </select>
+            <select
+              value={lut.inputSpace || 'srgb'}
+              onChange={(e) => updateLut('inputSpace', e.target.value)}
+              className="w-full bg-bg-primary border border-surface rounded-md p-2 text-sm text-text-primary focus:ring-accent focus:border-accent"
+            >
+              <option value="srgb">sRGB Input</option>
+              <option value="linear">Linear Input</option>
+            </select>
+          </div>
+        </div>
+      )}
+
       <div className="flex-grow overflow-y-auto p-4">
+        {error && <p className="text-sm text-red-400 mb-2">{error}</p>}
         {folderTree ? (
-            <FolderTree
-                tree={folderTree}
-                onFolderSelect={(path) => {
-                  const node = findNodeByPath(folderTree, path);
-                  if (!node) {
-                    console.warn("Clicked path not found in tree:", path);
-                    return;
-                  }
-                  handleClick(null, path, node.is_dir); // pass the expected args
-                }}
-                selectedPath={selectedFolders[0]}
-                isVisible={activePanel === 'lut'}
-                setIsVisible={() => {}}
-                style={{ width: '100%' }}
-                isResizing={false}
-                onContextMenu={(e, path) => null}
-                expandedFolders={expandedFolders}
-                onToggleFolder={handleToggleFolder}
-                fileTree={true}
-            />
-        ) : 
-        <div className="text-center text-text-secondary">
-          <p>No LUT folders selected. Please add a folder to start.</p>
-        </div>
-        }
+          <FolderTree
+            tree={folderTree}
+            onFolderSelect={handleSelectPath}
+            selectedPath={lut?.path || selectedFolder}
+            isVisible={activePanel === 'lut'}
+            setIsVisible={() => {}}
+            style={{ width: '100%' }}
+            isResizing={false}
+            onContextMenu={() => null}
+            expandedFolders={expandedFolders}
+            onToggleFolder={handleToggleFolder}
+            fileTree={true}
+          />
+        ) : (
+          <div className="text-center text-text-secondary">
+            <p>Open a folder with .cube, .3dl or HaldCLUT files to start.</p>
+          </div>
+        )}
       </div>
-      
-      {/* <AddPresetModal
-        isOpen={isAddModalOpen}
-        onClose={() => setIsAddModalOpen(false)}
-        onSave={handleSaveCurrentSettingsAsPreset}
-      />
-      <RenamePresetModal
-        isOpen={renameModalState.isOpen}
-        onClose={() => setRenameModalState({ isOpen: false, preset: null })}
-        onSave={handleRenameSave}
-        currentName={renameModalState.preset?.name}
-      /> */}
     </div>
   );
-}
\ No newline at end of file
+}
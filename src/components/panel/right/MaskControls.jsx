@@ -2,8 +2,8 @@ import { useState, useEffect, useRef } from 'react';
 import { v4 as uuidv4 } from 'uuid';
 import { motion, AnimatePresence } from 'framer-motion';
 import {
-  RotateCcw, Copy, ClipboardPaste, Circle, TriangleRight, Brush, Droplet, Sparkles, User,
-  Trash2, Eye, EyeOff, Plus, Minus
+  RotateCcw, Copy, ClipboardPaste, Circle, TriangleRight, Brush, Droplet, Contrast, Mountain, Sparkles, User,
+  Trash2, Eye, EyeOff, Plus, Minus, SquaresIntersect
 } from 'lucide-react';
 
 import CollapsibleSection from '../../ui/CollapsibleSection';
@@ -24,12 +24,22 @@ const MASK_TYPES = [
   { id: 'brush', name: 'Brush', icon: Brush, type: 'brush', disabled: false },
   { id: 'linear', name: 'Linear', icon: TriangleRight, type: 'linear', disabled: false },
   { id: 'radial', name: 'Radial', icon: Circle, type: 'radial', disabled: false },
-  { id: 'color', name: 'Color', icon: Droplet, type: 'color', disabled: true },
+  { id: 'color-range', name: 'Color', icon: Droplet, type: 'color-range', disabled: false },
+  { id: 'luminance-range', name: 'Luminance', icon: Contrast, type: 'luminance-range', disabled: false },
+  { id: 'depth', name: 'Depth', icon: Mountain, type: 'depth', disabled: false },
+];
+
+const SUB_MASK_MODES = [
+  { mode: 'additive', label: 'Add', icon: Plus },
+  { mode: 'subtractive', label: 'Subtract', icon: Minus },
+  { mode: 'intersect', label: 'Intersect', icon: SquaresIntersect },
 ];
 
 function formatMaskTypeName(type) {
   if (type === 'ai-subject') return 'AI Subject';
   if (type === 'ai-foreground') return 'AI Foreground';
+  if (type === 'color-range') return 'Color Range';
+  if (type === 'luminance-range') return 'Luminance Range';
   return type.charAt(0).toUpperCase() + type.slice(1);
 }
 
@@ -37,16 +47,41 @@ const SUB_MASK_CONFIG = {
   radial: { parameters: [{ key: 'feather', label: 'Feather', min: 0, max: 100, step: 1, multiplier: 100, defaultValue: 50 }] },
   brush: { showBrushTools: true },
   linear: { parameters: [] },
-  color: { parameters: [] },
-  luminance: { parameters: [] },
+  'color-range': {
+    showColorRangeTools: true,
+    parameters: [
+      { key: 'tolerance', label: 'Tolerance', min: 0, max: 100, step: 1, defaultValue: 20 },
+      { key: 'smoothness', label: 'Smoothness', min: 0, max: 100, step: 1, defaultValue: 20 },
+    ],
+  },
+  'luminance-range': {
+    parameters: [
+      { key: 'low', label: 'Low', min: 0, max: 100, step: 1, multiplier: 100, defaultValue: 50 },
+      { key: 'high', label: 'High', min: 0, max: 100, step: 1, multiplier: 100, defaultValue: 100 },
+      { key: 'feather', label: 'Feather', min: 0, max: 50, step: 1, multiplier: 100, defaultValue: 10 },
+    ],
+  },
+  depth: {
+    parameters: [
+      { key: 'near', label: 'Near', min: 0, max: 100, step: 1, multiplier: 100, defaultValue: 0 },
+      { key: 'far', label: 'Far', min: 0, max: 100, step: 1, multiplier: 100, defaultValue: 50 },
+      { key: 'feather', label: 'Feather', min: 0, max: 50, step: 1, multiplier: 100, defaultValue: 10 },
+    ],
+  },
   'ai-subject': { parameters: [] },
   'ai-foreground': { parameters: [] },
 };
 
+const REFINE_PARAMETERS = [
+  { key: 'refineRadius', label: 'Refine Edges', min: 0, max: 50, step: 1, defaultValue: 0 },
+  { key: 'refineContrast', label: 'Edge Contrast', min: 0, max: 100, step: 1, defaultValue: 0 },
+];
+
 const BrushTools = ({ settings, onSettingsChange }) => (
   <div className="space-y-4 pt-4 border-t border-surface mt-4">
     <Slider label="Brush Size" value={settings.size} onChange={(e) => onSettingsChange(s => ({ ...s, size: Number(e.target.value) }))} min="1" max="200" step="1" defaultValue="100" />
     <Slider label="Brush Feather" value={settings.feather} onChange={(e) => onSettingsChange(s => ({ ...s, feather: Number(e.target.value) }))} min="0" max="100" step="1" defaultValue="50" />
+    <Switch label="Smart Brush" checked={!!settings.smart} onChange={(checked) => onSettingsChange(s => ({ ...s, smart: checked }))} />
     <div className="grid grid-cols-2 gap-2 pt-2">
       <button onClick={() => onSettingsChange(s => ({ ...s, tool: 'brush' }))} className={`p-2 rounded-md text-sm font-medium transition-colors flex items-center justify-center gap-2 ${settings.tool === 'brush' ? 'text-primary bg-surface' : 'bg-surface text-text-secondary hover:bg-card-active'}`}>Brush</button>
       <button onClick={() => onSettingsChange(s => ({ ...s, tool: 'eraser' }))} className={`p-2 rounded-md text-sm font-medium transition-colors flex items-center justify-center gap-2 ${settings.tool === 'eraser' ? 'text-primary bg-surface' : 'bg-surface text-text-secondary hover:bg-card-active'}`}>Eraser</button>
@@ -54,10 +89,27 @@ const BrushTools = ({ settings, onSettingsChange }) => (
   </div>
 );
 
+const ColorRangeTools = ({ parameters, onParameterChange }) => {
+  const sampleCount = (parameters.samples || []).length;
+  const colorSpace = parameters.colorSpace || 'lab';
+  return (
+    <div className="space-y-3">
+      <div className="text-sm text-text-secondary p-2 bg-surface rounded-md text-center">
+        {sampleCount === 0 ? 'Click the image to sample a color.' : `${sampleCount} color${sampleCount === 1 ? '' : 's'} sampled. Click to add more.`}
+      </div>
+      <div className="grid grid-cols-3 gap-2">
+        <button onClick={() => onParameterChange('colorSpace', 'lab')} className={`p-2 rounded-md text-sm font-medium transition-colors ${colorSpace === 'lab' ? 'text-primary bg-surface' : 'bg-surface text-text-secondary hover:bg-card-active'}`}>Lab</button>
+        <button onClick={() => onParameterChange('colorSpace', 'hsl')} className={`p-2 rounded-md text-sm font-medium transition-colors ${colorSpace === 'hsl' ? 'text-primary bg-surface' : 'bg-surface text-text-secondary hover:bg-card-active'}`}>HSL</button>
+        <button onClick={() => onParameterChange('samples', [])} disabled={sampleCount === 0} className="p-2 rounded-md text-sm font-medium transition-colors bg-surface text-text-secondary hover:bg-card-active disabled:opacity-50 disabled:cursor-not-allowed">Clear</button>
+      </div>
+    </div>
+  );
+};
+
 export default function MaskControls({
   editingMask, activeSubMask, updateMask, updateSubMask,
   brushSettings, setBrushSettings, histogram, isGeneratingAiMask, aiModelDownloadStatus,
-  setAdjustments, selectedImage, onSelectMask, activeMaskId, onGenerateAiForegroundMask,
+  setAdjustments, selectedImage, onSelectMask, activeMaskId, onGenerateAiForegroundMask, onGenerateAiDepthMask,
   setIsMaskControlHovered
 }) {
   const { showContextMenu } = useContextMenu();
@@ -84,13 +136,16 @@ export default function MaskControls({
 
   const createSubMask = (type) => {
     const { width, height } = selectedImage;
-    const common = { id: uuidv4(), visible: true, mode: 'additive', type };
+    const common = { id: uuidv4(), visible: true, mode: 'additive', opacity: 100, type };
     switch (type) {
       case 'radial': return { ...common, parameters: { centerX: width / 2, centerY: height / 2, radiusX: width / 4, radiusY: width / 4, rotation: 0, feather: 0.5 } };
       case 'linear': return { ...common, parameters: { startX: width * 0.25, startY: height / 2, endX: width * 0.75, endY: height / 2, range: 50 } };
       case 'brush': return { ...common, parameters: { lines: [] } };
       case 'ai-subject': return { ...common, parameters: { startX: 0, startY: 0, endX: 0, endY: 0, maskDataBase64: null } };
       case 'ai-foreground': return { ...common, parameters: { maskDataBase64: null } };
+      case 'color-range': return { ...common, parameters: { samples: [], targetColors: [], colorSpace: 'lab', tolerance: 20, smoothness: 20 } };
+      case 'luminance-range': return { ...common, parameters: { low: 0.5, high: 1, feather: 0.1 } };
+      case 'depth': return { ...common, parameters: { depthDataBase64: null, near: 0, far: 0.5, feather: 0.1 } };
       default: return { ...common, parameters: {} };
     }
   };
@@ -106,6 +161,8 @@ export default function MaskControls({
     onSelectMask(subMask.id);
     if (type === 'ai-foreground') {
       onGenerateAiForegroundMask(subMask.id);
+    } else if (type === 'depth') {
+      onGenerateAiDepthMask(subMask.id);
     }
   };
 
@@ -201,7 +258,7 @@ export default function MaskControls({
     showContextMenu(event.clientX, event.clientY, options);
   };
 
-  const isAiMask = activeSubMask && (activeSubMask.type === 'ai-subject' || activeSubMask.type === 'ai-foreground');
+  const isAiMask = activeSubMask && (activeSubMask.type === 'ai-subject' || activeSubMask.type === 'ai-foreground' || activeSubMask.type === 'depth');
   const sectionVisibility = editingMask.adjustments.sectionVisibility || INITIAL_MASK_ADJUSTMENTS.sectionVisibility;
 
   return (
@@ -235,6 +292,9 @@ export default function MaskControls({
           <AnimatePresence>
             {editingMask.subMasks.filter(sm => sm.id !== deletingItemId).map((subMask) => {
               const MaskIcon = MASK_TYPES.find(mt => mt.type === subMask.type)?.icon || Circle;
+              const modeIndex = Math.max(0, SUB_MASK_MODES.findIndex(m => m.mode === subMask.mode));
+              const ModeIcon = SUB_MASK_MODES[modeIndex].icon;
+              const nextMode = SUB_MASK_MODES[(modeIndex + 1) % SUB_MASK_MODES.length];
               return (
                 <motion.div
                   key={subMask.id}
@@ -251,8 +311,8 @@ export default function MaskControls({
                     </span>
                   </div>
                   <div className="flex items-center gap-1">
-                    <button onClick={(e) => { e.stopPropagation(); updateSubMask(subMask.id, { mode: subMask.mode === 'additive' ? 'subtractive' : 'additive' }); }} className="p-1.5 rounded-full text-text-secondary hover:bg-bg-primary" title={subMask.mode === 'additive' ? 'Set to Subtract' : 'Set to Add'}>
-                      {subMask.mode === 'additive' ? <Plus size={14} /> : <Minus size={14} />}
+                    <button onClick={(e) => { e.stopPropagation(); updateSubMask(subMask.id, { mode: nextMode.mode }); }} className="p-1.5 rounded-full text-text-secondary hover:bg-bg-primary" title={`Set to ${nextMode.label}`}>
+                      <ModeIcon size={14} />
                     </button>
                     <button onClick={(e) => { e.stopPropagation(); updateSubMask(subMask.id, { visible: !subMask.visible }); }} className="p-1.5 rounded-full text-text-secondary hover:bg-bg-primary" title={subMask.visible ? "Hide" : "Show"}>
                       {subMask.visible ? <Eye size={16} /> : <EyeOff size={16} />}
@@ -280,9 +340,16 @@ export default function MaskControls({
                     {showAnalyzingMessage && !aiModelDownloadStatus && <div className="text-sm text-text-secondary p-2 bg-surface rounded-md text-center animate-pulse">Analyzing Image...</div>}
                   </>
                 )}
+                <Slider label="Opacity" value={activeSubMask.opacity ?? 100} onChange={(e) => updateSubMask(activeSubMask.id, { opacity: Number(e.target.value) })} min={0} max={100} step={1} defaultValue={100} />
+                {subMaskConfig.showColorRangeTools && (
+                  <ColorRangeTools parameters={activeSubMask.parameters} onParameterChange={handleSubMaskParameterChange} />
+                )}
                 {subMaskConfig.parameters?.map(param => (
                   <Slider key={param.key} label={param.label} value={(activeSubMask.parameters[param.key] || 0) * (param.multiplier || 1)} onChange={(e) => handleSubMaskParameterChange(param.key, parseFloat(e.target.value) / (param.multiplier || 1))} min={param.min} max={param.max} step={param.step} defaultValue={param.defaultValue} />
                 ))}
+                {REFINE_PARAMETERS.filter(param => param.key === 'refineRadius' || activeSubMask.parameters.refineRadius > 0).map(param => (
+                  <Slider key={param.key} label={param.label} value={activeSubMask.parameters[param.key] || 0} onChange={(e) => handleSubMaskParameterChange(param.key, parseFloat(e.target.value))} min={param.min} max={param.max} step={param.step} defaultValue={param.defaultValue} />
+                ))}
                 {subMaskConfig.showBrushTools && brushSettings && setBrushSettings && (
                   <BrushTools settings={brushSettings} onSettingsChange={setBrushSettings} />
                 )}
@@ -301,7 +368,7 @@ export default function MaskControls({
             const title = sectionName.charAt(0).toUpperCase() + sectionName.slice(1);
             return (
               <CollapsibleSection key={sectionName} title={title} isOpen={collapsibleState[sectionName]} onToggle={() => handleToggleSection(sectionName)} onContextMenu={(e) => handleSectionContextMenu(e, sectionName)} isContentVisible={sectionVisibility[sectionName]} onToggleVisibility={() => handleToggleVisibility(sectionName)}>
-                <SectionComponent adjustments={editingMask.adjustments} setAdjustments={setMaskContainerAdjustments} histogram={histogram} isForMask={sectionName === 'effects'} />
+                <SectionComponent adjustments={editingMask.adjustments} setAdjustments={setMaskContainerAdjustments} histogram={histogram} isForMask={sectionName === 'effects' || sectionName === 'basic'} />
               </CollapsibleSection>
             );
           })}
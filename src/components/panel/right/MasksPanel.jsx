@@ -3,7 +3,7 @@ import { v4 as uuidv4 } from 'uuid';
 import { motion, AnimatePresence } from 'framer-motion';
 import {
   Trash2, RotateCcw, ArrowLeft, Eye, EyeOff, Edit, Copy, ClipboardPaste, PlusSquare,
-  ChevronsRight, FileEdit, Sparkles, User, Brush, TriangleRight, Circle, Droplet
+  ChevronsRight, FileEdit, Sparkles, User, Brush, TriangleRight, Circle, Droplet, Contrast, Mountain
 } from 'lucide-react';
 import MaskControls from './MaskControls';
 import { INITIAL_MASK_ADJUSTMENTS, INITIAL_MASK_CONTAINER } from '../../../utils/adjustments';
@@ -15,7 +15,9 @@ const MASK_TYPES = [
   { id: 'brush', name: 'Brush', icon: Brush, type: 'brush', disabled: false },
   { id: 'linear', name: 'Linear', icon: TriangleRight, type: 'linear', disabled: false },
   { id: 'radial', name: 'Radial', icon: Circle, type: 'radial', disabled: false },
-  { id: 'color', name: 'Color', icon: Droplet, type: 'color', disabled: true },
+  { id: 'color-range', name: 'Color', icon: Droplet, type: 'color-range', disabled: false },
+  { id: 'luminance-range', name: 'Luminance', icon: Contrast, type: 'luminance-range', disabled: false },
+  { id: 'depth', name: 'Depth', icon: Mountain, type: 'depth', disabled: false },
 ];
 
 const itemVariants = {
@@ -32,7 +34,8 @@ export default function MasksPanel({
   adjustments, setAdjustments, selectedImage, onSelectMask, activeMaskId,
   activeMaskContainerId, onSelectContainer, setIsMaskControlHovered,
   brushSettings, setBrushSettings, copiedMask, setCopiedMask, histogram,
-  setCustomEscapeHandler, isGeneratingAiMask, aiModelDownloadStatus, onGenerateAiForegroundMask
+  setCustomEscapeHandler, isGeneratingAiMask, aiModelDownloadStatus, onGenerateAiForegroundMask,
+  onGenerateAiDepthMask
 }) {
   const [deletingItemId, setDeletingItemId] = useState(null);
   const [renamingContainerId, setRenamingContainerId] = useState(null);
@@ -75,13 +78,16 @@ export default function MasksPanel({
 
   const createSubMask = (type) => {
     const { width, height } = selectedImage;
-    const common = { id: uuidv4(), visible: true, mode: 'additive', type };
+    const common = { id: uuidv4(), visible: true, mode: 'additive', opacity: 100, type };
     switch (type) {
       case 'radial': return { ...common, parameters: { centerX: width / 2, centerY: height / 2, radiusX: width / 4, radiusY: width / 4, rotation: 0, feather: 0.5 } };
       case 'linear': return { ...common, parameters: { startX: width * 0.25, startY: height / 2, endX: width * 0.75, endY: height / 2, range: 50 } };
       case 'brush': return { ...common, parameters: { lines: [] } };
       case 'ai-subject': return { ...common, parameters: { startX: 0, startY: 0, endX: 0, endY: 0, maskDataBase64: null } };
       case 'ai-foreground': return { ...common, parameters: { maskDataBase64: null } };
+      case 'color-range': return { ...common, parameters: { samples: [], targetColors: [], colorSpace: 'lab', tolerance: 20, smoothness: 20 } };
+      case 'luminance-range': return { ...common, parameters: { low: 0.5, high: 1, feather: 0.1 } };
+      case 'depth': return { ...common, parameters: { depthDataBase64: null, near: 0, far: 0.5, feather: 0.1 } };
       default: return { ...common, parameters: {} };
     }
   };
@@ -99,6 +105,8 @@ export default function MasksPanel({
     onSelectMask(subMask.id);
     if (type === 'ai-foreground') {
       onGenerateAiForegroundMask(subMask.id);
+    } else if (type === 'depth') {
+      onGenerateAiDepthMask(subMask.id);
     }
   };
 
@@ -271,6 +279,7 @@ export default function MasksPanel({
             onSelectMask={onSelectMask}
             activeMaskId={activeMaskId}
             onGenerateAiForegroundMask={onGenerateAiForegroundMask}
+            onGenerateAiDepthMask={onGenerateAiDepthMask}
             setIsMaskControlHovered={setIsMaskControlHovered}
           />
         </div>
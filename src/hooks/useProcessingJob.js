@@ -0,0 +1,33 @@
+import { useState, useEffect, useCallback } from 'react';
+import { invoke } from '@tauri-apps/api/core';
+import { listen } from '@tauri-apps/api/event';
+
+const IDLE_JOB = { isRunning: false, progress: null, result: null, error: null };
+
+// Tracks a background command that reports through `<prefix>-progress`,
+// `<prefix>-complete` and `<prefix>-error` events.
+export function useProcessingJob(eventPrefix) {
+  const [job, setJob] = useState(IDLE_JOB);
+
+  useEffect(() => {
+    const listeners = [
+      listen(`${eventPrefix}-progress`, (event) => setJob(prev => prev.isRunning ? { ...prev, progress: event.payload } : prev)),
+      listen(`${eventPrefix}-complete`, (event) => setJob({ ...IDLE_JOB, result: event.payload })),
+      listen(`${eventPrefix}-error`, (event) => setJob({ ...IDLE_JOB, error: String(event.payload) })),
+    ];
+    return () => listeners.forEach(p => p.then(unlisten => unlisten()));
+  }, [eventPrefix]);
+
+  const start = useCallback(async (command, args) => {
+    setJob({ ...IDLE_JOB, isRunning: true });
+    try {
+      await invoke(command, args);
+    } catch (err) {
+      setJob({ ...IDLE_JOB, error: String(err) });
+    }
+  }, []);
+
+  const reset = useCallback(() => setJob(IDLE_JOB), []);
+
+  return { ...job, start, reset };
+}
@@ -44,8 +44,10 @@ export const INITIAL_MASK_CONTAINER = {
 export const INITIAL_ADJUSTMENTS = {
   rating: 0,
   exposure: 0, contrast: 0, highlights: 0, shadows: 0, whites: 0, blacks: 0,
+  toneMapper: null, filmicWhiteExposure: 4, filmicBlackExposure: -8, filmicContrast: 1.6,
   saturation: 0, temperature: 0, tint: 0, vibrance: 0,
-  sharpness: 0, lumaNoiseReduction: 0, colorNoiseReduction: 0,
+  rawWhiteBalance: 'asShot', rawTemperature: null, rawTint: null, cameraProfile: null,
+  sharpness: 0, lumaNoiseReduction: 0, colorNoiseReduction: 0, rawDenoise: 0,
   clarity: 0, dehaze: 0, structure: 0,
   vignetteAmount: 0, vignetteMidpoint: 50, vignetteRoundness: 0, vignetteFeather: 50,
   grainAmount: 0, grainSize: 25, grainRoughness: 50,
@@ -68,6 +70,7 @@ export const INITIAL_ADJUSTMENTS = {
   crop: null, aspectRatio: null, rotation: 0, flipHorizontal: false, flipVertical: false,
   masks: [],
   aiPatches: [],
+  lut: null,
   sectionVisibility: {
     basic: true,
     curves: true,
@@ -85,6 +88,7 @@ export const normalizeLoadedAdjustments = (loadedAdjustments) => {
     const normalizedSubMasks = (maskContainer.subMasks || []).map(subMask => ({
       visible: true,
       mode: 'additive',
+      opacity: 100,
       ...subMask,
     }));
 
@@ -129,20 +133,28 @@ export const normalizeLoadedAdjustments = (loadedAdjustments) => {
 
 export const COPYABLE_ADJUSTMENT_KEYS = [
   'exposure', 'contrast', 'highlights', 'shadows', 'whites', 'blacks',
+  'toneMapper', 'filmicWhiteExposure', 'filmicBlackExposure', 'filmicContrast',
   'saturation', 'temperature', 'tint', 'vibrance',
-  'sharpness', 'lumaNoiseReduction', 'colorNoiseReduction',
+  'rawWhiteBalance', 'rawTemperature', 'rawTint', 'cameraProfile',
+  'sharpness', 'lumaNoiseReduction', 'colorNoiseReduction', 'rawDenoise',
   'clarity', 'dehaze', 'structure',
   'vignetteAmount', 'vignetteMidpoint', 'vignetteRoundness', 'vignetteFeather',
   'grainAmount', 'grainSize', 'grainRoughness',
   'enableNegativeConversion', 'filmBaseColor', 'negativeRedBalance', 'negativeGreenBalance', 'negativeBlueBalance',
-  'hsl', 'curves', 'colorGrading', 'sectionVisibility',
+  'hsl', 'curves', 'colorGrading', 'lut', 'sectionVisibility',
 ];
 
 export const ADJUSTMENT_SECTIONS = {
-  basic: ['exposure', 'contrast', 'highlights', 'shadows', 'whites', 'blacks'],
+  basic: [
+    'exposure', 'contrast', 'highlights', 'shadows', 'whites', 'blacks',
+    'toneMapper', 'filmicWhiteExposure', 'filmicBlackExposure', 'filmicContrast',
+  ],
   curves: ['curves'],
-  color: ['saturation', 'temperature', 'tint', 'vibrance', 'hsl', 'colorGrading'],
-  details: ['sharpness', 'lumaNoiseReduction', 'colorNoiseReduction'],
+  color: [
+    'saturation', 'temperature', 'tint', 'vibrance', 'rawWhiteBalance', 'rawTemperature', 'rawTint',
+    'cameraProfile', 'hsl', 'colorGrading',
+  ],
+  details: ['sharpness', 'lumaNoiseReduction', 'colorNoiseReduction', 'rawDenoise'],
   effects: [
     'clarity', 'dehaze', 'structure',
     'vignetteAmount', 'vignetteMidpoint', 'vignetteRoundness', 'vignetteFeather',